@@ -0,0 +1,151 @@
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Listen address used when neither the flags nor the config name one
+pub const DEFAULT_LISTEN: &str = "0.0.0.0:6881";
+
+/// Defaults read from the config file, e.g. `~/.config/x402/config.toml`
+///
+/// Every field is optional; command line flags take precedence over it.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: Vec<String>,
+    pub price: Option<u64>,
+    pub torrents_dir: Option<PathBuf>,
+    pub trackers: Vec<String>,
+}
+
+/// Settings for `serve` after merging flags over the config
+#[derive(Debug, PartialEq)]
+pub struct ServeSettings {
+    pub listen: Vec<String>,
+    pub price: u64,
+    pub torrents_dir: Option<PathBuf>,
+    pub trackers: Vec<String>,
+}
+
+impl Config {
+    /// Where the config lives: `$XDG_CONFIG_HOME/x402/config.toml`, falling
+    /// back to `~/.config/x402/config.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(base.join("x402").join("config.toml"))
+    }
+
+    /// Parse a config from TOML
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        toml::from_str(contents).map_err(|e| format!("Invalid config: {}", e))
+    }
+
+    /// Read a config file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+        Self::parse(&contents).map_err(|e| format!("{} in {}", e, path.display()))
+    }
+
+    /// Read the config at `path`, or at the default location if `None`
+    ///
+    /// A missing file at the default location yields an empty config, while
+    /// an explicitly given path has to exist.
+    pub fn load_or_default(path: Option<&Path>) -> Result<Self, String> {
+        if let Some(path) = path {
+            return Self::load(path);
+        }
+        let Some(path) = Self::default_path() else {
+            return Ok(Self::default());
+        };
+        match fs::metadata(&path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            _ => Self::load(&path),
+        }
+    }
+
+    /// Merge `serve` flags over this config
+    ///
+    /// An empty `listen` means the flag was not given.
+    pub fn serve_settings(
+        self,
+        listen: Vec<String>,
+        price: Option<u64>,
+        torrents_dir: Option<PathBuf>,
+    ) -> ServeSettings {
+        let listen = if !listen.is_empty() {
+            listen
+        } else if !self.listen.is_empty() {
+            self.listen
+        } else {
+            vec![DEFAULT_LISTEN.to_string()]
+        };
+        ServeSettings {
+            listen,
+            price: price.or(self.price).unwrap_or(0),
+            torrents_dir: torrents_dir.or(self.torrents_dir),
+            trackers: self.trackers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+listen = ["0.0.0.0:7000", "[::]:7000"]
+price = 250
+torrents_dir = "/srv/torrents"
+trackers = ["http://tracker.example.com/announce"]
+"#;
+
+    #[test]
+    fn test_load_and_merge() {
+        let path = env::temp_dir().join(format!("x402-config-{}.toml", std::process::id()));
+        fs::write(&path, SAMPLE).unwrap();
+        let config = Config::load_or_default(Some(&path));
+        fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        assert_eq!(config.price, Some(250));
+        assert_eq!(config.torrents_dir, Some(PathBuf::from("/srv/torrents")));
+
+        // Flags override the config; anything not given falls back to it
+        let settings = config.serve_settings(vec![], Some(10), None);
+        assert_eq!(
+            settings,
+            ServeSettings {
+                listen: vec!["0.0.0.0:7000".to_string(), "[::]:7000".to_string()],
+                price: 10,
+                torrents_dir: Some(PathBuf::from("/srv/torrents")),
+                trackers: vec!["http://tracker.example.com/announce".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_empty_config_uses_defaults() {
+        let settings = Config::parse("").unwrap().serve_settings(
+            vec!["127.0.0.1:9000".to_string()],
+            None,
+            None,
+        );
+        assert_eq!(settings.listen, vec!["127.0.0.1:9000".to_string()]);
+        assert_eq!(settings.price, 0);
+        assert_eq!(settings.torrents_dir, None);
+
+        let settings = Config::default().serve_settings(vec![], None, None);
+        assert_eq!(settings.listen, vec![DEFAULT_LISTEN.to_string()]);
+    }
+
+    #[test]
+    fn test_rejects_unknown_keys() {
+        assert!(Config::parse("prize = 5").is_err());
+        assert!(Config::load(Path::new("/nonexistent/x402/config.toml")).is_err());
+    }
+}
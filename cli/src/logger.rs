@@ -0,0 +1,29 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Prints the library's log records to stderr
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Info
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            Level::Info => eprintln!("{}", record.args()),
+            level => eprintln!("{}: {}", level, record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Print log records of level info and above to stderr
+pub fn init() {
+    // Only fails if a logger is already set, in which case that one stays
+    let _ = log::set_logger(&StderrLogger);
+    log::set_max_level(LevelFilter::Info);
+}
@@ -1,45 +1,193 @@
-use clap::{Parser, Subcommand};
+mod config;
+mod logger;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use config::Config;
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long `probe` waits to connect and for the peer's handshake
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Parser)]
 #[command(name = "x402")]
 #[command(about = "x402 P2P protocol CLI")]
 struct Cli {
+    /// Config file with defaults for the flags [default: ~/.config/x402/config.toml]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     Inspect {
         file: String,
+
+        #[arg(long, value_enum, default_value = "text")]
+        format: OutputFormat,
+
+        /// Price per piece, to show the total cost of the torrent
+        #[arg(long)]
+        price: Option<u64>,
+
+        /// Dump the raw bencode structure instead, e.g. for torrents that fail to parse
+        #[arg(long)]
+        raw: bool,
+    },
+    /// Print only the info hash of a torrent file, e.g. for scripts
+    Infohash {
+        file: PathBuf,
+
+        /// Print the base32 form used by some magnet links instead of hex
+        #[arg(long)]
+        base32: bool,
     },
     Serve {
-        #[arg(long, default_value = "0")]
-        price: u64,
+        /// Price per piece [default: from the config, or 0]
+        #[arg(long)]
+        price: Option<u64>,
+
+        /// Address to listen on; repeat to listen on several, e.g. IPv4 and IPv6
+        #[arg(long)]
+        listen: Vec<String>,
+
+        /// Fixed peer ID to announce, as 40 hex characters
+        #[arg(long)]
+        peer_id: Option<String>,
+
+        /// File with tracker URLs to announce to, one per line
+        #[arg(long)]
+        trackers_file: Option<PathBuf>,
+
+        /// ipfilter.dat style list of IP ranges to refuse connections from
+        #[arg(long)]
+        ip_filter: Option<PathBuf>,
+
+        /// File of info hashes to serve, one in hex per line
+        #[arg(long)]
+        allowlist: Option<PathBuf>,
+
+        /// JSON file keeping the upload and payment totals across restarts
+        #[arg(long)]
+        stats_file: Option<PathBuf>,
 
+        /// Directory of .torrent files to serve
         #[arg(long)]
-        listen: Option<String>,
+        torrents_dir: Option<PathBuf>,
+
+        /// Torrent file to serve, with its content given by --data
+        #[arg(long, requires = "data")]
+        torrent: Option<PathBuf>,
+
+        /// Content of the --torrent: the file, or the directory of a multi-file torrent
+        #[arg(long, requires = "torrent")]
+        data: Option<PathBuf>,
+
+        /// Info hash to accept connections for, as 40 hex characters
+        #[arg(long)]
+        info_hash: Option<String>,
+
+        /// Port to serve live statistics on as JSON over HTTP
+        #[arg(long)]
+        status_port: Option<u16>,
     },
     Download {
         source: String, // magnet link o .torrent
+
+        /// Peer to download from; repeat to download from several
+        #[arg(long)]
+        peer: Vec<std::net::SocketAddr>,
+
+        /// Print the announce URL for each tracker instead of downloading
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check that a local file matches a magnet link
+    Check {
+        magnet: String,
+
+        file: PathBuf,
+
+        /// Torrent file with the magnet's metadata, to verify every piece
+        #[arg(long)]
+        torrent: Option<PathBuf>,
+    },
+    /// Handshake with a peer and print the extensions it advertises
+    Probe {
+        addr: std::net::SocketAddr,
+
+        /// Info hash to handshake for, as 40 hex characters
+        info_hash: String,
     },
 }
 
 fn main() {
     let cli = Cli::parse();
+    logger::init();
 
     match cli.command {
-        Commands::Inspect { file } => {
+        Commands::Inspect {
+            file,
+            format,
+            price,
+            raw,
+        } => {
             // Check if it's a magnet link or a .torrent file
-            if file.starts_with("magnet:?") {
+            let kind = x402_core::SourceKind::detect(&file);
+            if kind == x402_core::SourceKind::Unknown {
+                eprintln!("{} is neither a magnet link nor a torrent file", file);
+                std::process::exit(1);
+            }
+            if raw {
+                if kind == x402_core::SourceKind::Magnet {
+                    eprintln!("Raw output is only supported for torrent files");
+                    std::process::exit(1);
+                }
+                match fs::read(&file) {
+                    Ok(data) => match x402_core::dump_bencode(&data) {
+                        Ok(dump) => println!("{}", dump),
+                        Err(e) => {
+                            eprintln!("Error decoding bencode: {}", e);
+                            std::process::exit(1);
+                        }
+                    },
+                    Err(e) => {
+                        eprintln!("Error reading file {}: {}", file, e);
+                        std::process::exit(1);
+                    }
+                }
+            } else if kind == x402_core::SourceKind::Magnet {
+                if format == OutputFormat::Json {
+                    eprintln!("JSON output is only supported for torrent files");
+                    std::process::exit(1);
+                }
+
                 println!("Inspecting magnet link...");
-                match x402_core::MagnetLink::parse(&file) {
+                match x402_core::load_magnet(&file) {
                     Ok(magnet) => {
-                        println!("Info Hash: {}", magnet.info_hash);
+                        if !magnet.info_hash.is_empty() {
+                            println!("Info Hash: {}", magnet.info_hash);
+                        }
+                        if let Some(info_hash_v2) = &magnet.info_hash_v2 {
+                            println!("Info Hash (v2): {}", info_hash_v2);
+                        }
                         if let Some(name) = &magnet.display_name {
                             println!("Name: {}", name);
                         }
+                        if !magnet.keywords.is_empty() {
+                            println!("Keywords: {}", magnet.keywords.join(" "));
+                        }
                         if !magnet.trackers.is_empty() {
                             println!("Trackers:");
                             for tracker in &magnet.trackers {
@@ -49,12 +197,50 @@ fn main() {
                         if let Some(length) = magnet.exact_length {
                             println!("Size: {} bytes", length);
                         }
+                        if let Some(price) = magnet.price {
+                            match magnet.price_unit {
+                                Some(unit) => println!("Price: {} {}", price, unit),
+                                None => println!("Price: {}", price),
+                            }
+                        }
                     }
                     Err(e) => {
                         eprintln!("Error parsing magnet link: {}", e);
                         std::process::exit(1);
                     }
                 }
+            } else if format == OutputFormat::Json {
+                match fs::read(&file) {
+                    Ok(data) => match x402_core::summarize_torrent(&data) {
+                        Ok(summary) => {
+                            let mut json = match serde_json::to_value(&summary) {
+                                Ok(json) => json,
+                                Err(e) => {
+                                    eprintln!("Error serializing summary: {}", e);
+                                    std::process::exit(1);
+                                }
+                            };
+                            if let Some(cost) = total_cost(&data, price) {
+                                json["total_cost"] = cost.into();
+                            }
+                            match serde_json::to_string_pretty(&json) {
+                                Ok(json) => println!("{}", json),
+                                Err(e) => {
+                                    eprintln!("Error serializing summary: {}", e);
+                                    std::process::exit(1);
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("Error decoding torrent: {}", e);
+                            std::process::exit(1);
+                        }
+                    },
+                    Err(e) => {
+                        eprintln!("Error reading file {}: {}", file, e);
+                        std::process::exit(1);
+                    }
+                }
             } else {
                 println!("Inspecting torrent file: {}", file);
                 // Read the torrent file
@@ -64,6 +250,9 @@ fn main() {
                             eprintln!("Error decoding torrent: {}", e);
                             std::process::exit(1);
                         }
+                        if let (Some(price), Some(cost)) = (price, total_cost(&data, price)) {
+                            println!("Total Cost: {} ({} per piece)", cost, price);
+                        }
                     }
                     Err(e) => {
                         eprintln!("Error reading file {}: {}", file, e);
@@ -72,34 +261,491 @@ fn main() {
                 }
             }
         }
-        Commands::Serve { price, listen } => {
-            let address = listen.unwrap_or_else(|| "0.0.0.0:6881".to_string());
-            let parts: Vec<&str> = address.split(':').collect();
-            
-            let (addr, port) = if parts.len() == 2 {
-                (parts[0].to_string(), parts[1].parse::<u16>().unwrap_or(6881))
-            } else {
-                ("0.0.0.0".to_string(), 6881)
+        Commands::Infohash { file, base32 } => {
+            let data = match fs::read(&file) {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("Error reading file {}: {}", file.display(), e);
+                    std::process::exit(1);
+                }
             };
+            let info_hash = x402_core::parse_torrent(&data)
+                .and_then(|torrent| torrent.info_hash_hex())
+                .and_then(|hex| {
+                    if base32 {
+                        x402_core::hex_to_base32(&hex)
+                    } else {
+                        Ok(hex)
+                    }
+                });
+            match info_hash {
+                Ok(info_hash) => println!("{}", info_hash),
+                Err(e) => {
+                    eprintln!("Error decoding torrent: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Serve {
+            price,
+            listen,
+            peer_id,
+            trackers_file,
+            ip_filter,
+            allowlist,
+            stats_file,
+            torrents_dir,
+            torrent,
+            data,
+            info_hash,
+            status_port,
+        } => {
+            let config = match Config::load_or_default(cli.config.as_deref()) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("Error loading config: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let settings = config.serve_settings(listen, price, torrents_dir);
+            let (listen, price) = (settings.listen, settings.price);
+
+            let (addr, port) = match parse_listen_address(&listen[0]) {
+                Ok(address) => address,
+                Err(e) => {
+                    eprintln!("Invalid --listen address: {}", e);
+                    std::process::exit(1);
+                }
+            };
+
+            println!(
+                "Starting x402 seeder on {}:{} with price {}",
+                addr, port, price
+            );
+
+            // Triggered by SIGINT or SIGTERM, so the stats are saved on the way out
+            let cancel = x402_core::CancellationToken::new();
+            let handler_cancel = cancel.clone();
+            if let Err(e) = ctrlc::set_handler(move || handler_cancel.cancel()) {
+                eprintln!("Error installing signal handler: {}", e);
+                std::process::exit(1);
+            }
+
+            let mut seeder = x402_core::Seeder::new(addr, port)
+                .with_price(price)
+                .with_cancellation(cancel);
+            if listen.len() > 1 {
+                let addrs: Result<Vec<std::net::SocketAddr>, _> =
+                    listen.iter().map(|addr| addr.parse()).collect();
+                match addrs {
+                    Ok(addrs) => seeder = seeder.with_listen_addrs(addrs),
+                    Err(e) => {
+                        eprintln!("Invalid --listen address: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            if let Some(status_port) = status_port {
+                seeder = seeder.with_status_port(status_port);
+            }
+            if let Some(peer_id) = peer_id {
+                match x402_core::parse_peer_id_hex(&peer_id) {
+                    Ok(peer_id) => seeder = seeder.with_peer_id(peer_id),
+                    Err(e) => {
+                        eprintln!("Invalid --peer-id: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            if !settings.trackers.is_empty() {
+                seeder.set_trackers(settings.trackers);
+            }
+            if let Some(path) = trackers_file {
+                if let Err(e) = seeder.load_trackers_file(&path) {
+                    eprintln!("Error loading trackers: {}", e);
+                    std::process::exit(1);
+                }
+                println!("Loaded {} trackers", seeder.trackers().len());
+            }
+            if let Some(path) = ip_filter
+                && let Err(e) = seeder.load_ip_filter_file(&path)
+            {
+                eprintln!("Error loading IP filter: {}", e);
+                std::process::exit(1);
+            }
+            if let Some(path) = allowlist {
+                match seeder.load_allowlist(&path) {
+                    Ok(count) => println!("Loaded {} info hashes from {}", count, path.display()),
+                    Err(e) => {
+                        eprintln!("Error loading allowlist: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            if let Some(path) = stats_file
+                && let Err(e) = seeder.load_stats_file(&path)
+            {
+                eprintln!("Error loading stats: {}", e);
+                std::process::exit(1);
+            }
+            if let Some(dir) = settings.torrents_dir {
+                match load_torrents_dir(&mut seeder, &dir) {
+                    Ok(count) => println!("Loaded {} torrents from {}", count, dir.display()),
+                    Err(e) => {
+                        eprintln!("Error loading torrents: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            if let (Some(torrent), Some(data)) = (torrent, data) {
+                match add_torrent_file(&mut seeder, &torrent, &data) {
+                    Ok((info_hash, bitfield)) => println!(
+                        "Serving {} ({}/{} pieces)",
+                        hex::encode(info_hash),
+                        bitfield.count(),
+                        bitfield.num_pieces()
+                    ),
+                    Err(e) => {
+                        eprintln!("Error loading torrent {}: {}", torrent.display(), e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            if let Some(info_hash) = info_hash
+                && let Err(e) = seeder.add_torrent_hex(&info_hash)
+            {
+                eprintln!("Invalid --info-hash: {}", e);
+                std::process::exit(1);
+            }
+
+            match seeder.bind_all() {
+                Ok(addrs) => {
+                    for addr in addrs {
+                        println!("Listening on {}", addr);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error starting seeder: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            let announcer = seeder.announcer().start();
 
-            println!("Starting x402 seeder on {}:{} with price {}", addr, port, price);
-            
-            let mut seeder = x402_core::Seeder::new(addr, port);
-            
-            // TODO: Load torrents from config/database
-            // For now, you need to add torrents manually
-            println!("Note: Add torrents to seed using seeder.add_torrent_hex()");
-            
-            if let Err(e) = seeder.listen() {
+            let result = seeder.listen();
+            // Sends the stopped announces; `exit` below would skip the drop
+            announcer.stop();
+            if let Err(e) = seeder.flush_stats() {
+                log::warn!("Failed to save stats: {}", e);
+            }
+            if let Err(e) = result {
                 eprintln!("Error starting seeder: {}", e);
                 std::process::exit(1);
             }
         }
-        Commands::Download { source } => {
+        Commands::Check {
+            magnet,
+            file,
+            torrent,
+        } => {
+            let magnet = match x402_core::MagnetLink::parse(&magnet) {
+                Ok(magnet) => magnet,
+                Err(e) => {
+                    eprintln!("Error parsing magnet link: {}", e);
+                    std::process::exit(1);
+                }
+            };
+
+            let torrent = torrent.map(|path| {
+                let result = fs::read(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|data| x402_core::parse_torrent(&data));
+                match result {
+                    Ok(torrent) => torrent,
+                    Err(e) => {
+                        eprintln!("Error reading torrent {}: {}", path.display(), e);
+                        std::process::exit(1);
+                    }
+                }
+            });
+
+            match x402_core::check_file(&magnet, &file, torrent.as_ref()) {
+                Ok(result) => {
+                    println!("{}", result);
+                    if !result.is_pass() {
+                        std::process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error checking {}: {}", file.display(), e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Probe { addr, info_hash } => {
+            let peer_id = x402_core::generate_peer_id();
+            let handshake = match x402_core::Handshake::from_hex(&info_hash, peer_id) {
+                Ok(handshake) => handshake,
+                Err(e) => {
+                    eprintln!("Invalid info hash: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let result = std::net::TcpStream::connect_timeout(&addr, PROBE_TIMEOUT)
+                .and_then(|stream| {
+                    stream.set_read_timeout(Some(PROBE_TIMEOUT))?;
+                    Ok(stream)
+                })
+                .map_err(|e| format!("Failed to connect to {}: {}", addr, e))
+                .and_then(|mut stream| {
+                    x402_core::Handshake::exchange(&mut stream, handshake.info_hash, peer_id)
+                });
+            let response = match result {
+                Ok(response) => response,
+                Err(e) => {
+                    eprintln!("Handshake with {} failed: {}", addr, e);
+                    std::process::exit(1);
+                }
+            };
+
+            let capabilities = response.capabilities();
+            let flag = |enabled: bool| if enabled { "yes" } else { "no" };
+            println!("Peer ID: {}", response.peer_id_hex());
+            println!(
+                "Client: {}",
+                x402_core::identify_client(&x402_core::peer_id_bytes(&response.peer_id))
+                    .unwrap_or_else(|| "unknown".to_string())
+            );
+            println!("Capabilities:");
+            println!("  DHT: {}", flag(capabilities.dht));
+            println!("  Fast Extension: {}", flag(capabilities.fast_extension));
+            println!(
+                "  Extension Protocol: {}",
+                flag(capabilities.extension_protocol)
+            );
+            println!("  x402: {}", flag(capabilities.x402));
+        }
+        Commands::Download {
+            source,
+            peer,
+            dry_run,
+        } => {
             println!(
                 "Downloading files using x402 protocol from source: {}",
                 source
             );
+
+            let kind = x402_core::SourceKind::detect(&source);
+            if kind == x402_core::SourceKind::Unknown {
+                eprintln!("{} is neither a magnet link nor a torrent file", source);
+                std::process::exit(1);
+            }
+            if kind == x402_core::SourceKind::Magnet {
+                let magnet = match x402_core::load_magnet(&source) {
+                    Ok(magnet) => magnet,
+                    Err(e) => {
+                        eprintln!("Error parsing magnet link: {}", e);
+                        std::process::exit(1);
+                    }
+                };
+                // The name from `dn` is provisional until the metadata arrives
+                let name = magnet.output_name();
+                println!("Output: {} (temporary file {}.part)", name, name);
+                eprintln!("Fetching metadata from magnet links is not supported yet");
+                std::process::exit(1);
+            }
+
+            let torrent = match fs::read(&source)
+                .map_err(|e| e.to_string())
+                .and_then(|data| x402_core::parse_torrent(&data))
+            {
+                Ok(torrent) => torrent,
+                Err(e) => {
+                    eprintln!("Error reading torrent {}: {}", source, e);
+                    std::process::exit(1);
+                }
+            };
+            let info_hash = match torrent.info_hash() {
+                Ok(info_hash) => info_hash,
+                Err(e) => {
+                    eprintln!("Error computing info hash: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            if dry_run {
+                let request = x402_core::AnnounceRequest {
+                    info_hash,
+                    peer_id: x402_core::peer_id_bytes(&x402_core::generate_peer_id()),
+                    port: 6881,
+                    uploaded: 0,
+                    downloaded: 0,
+                    left: torrent.info.total_length() as u64,
+                    event: Some(x402_core::AnnounceEvent::Started),
+                    compact: true,
+                    numwant: None,
+                    key: Some(x402_core::AnnounceSession::new().key),
+                    tracker_id: None,
+                };
+                let trackers: Vec<String> = torrent
+                    .trackers()
+                    .into_iter()
+                    .filter(|tracker| !tracker.is_empty())
+                    .collect();
+                if trackers.is_empty() {
+                    eprintln!("Torrent lists no trackers");
+                    std::process::exit(1);
+                }
+                for tracker in trackers {
+                    println!("{}", x402_core::build_announce_url(&tracker, &request));
+                }
+                return;
+            }
+
+            let name = match torrent.info.output_name() {
+                Some(name) => name,
+                None => {
+                    eprintln!("Torrent has no usable name");
+                    std::process::exit(1);
+                }
+            };
+            println!("Output: {} (temporary file {}.part)", name, name);
+            if torrent.info.files.is_some() {
+                eprintln!("Downloading multi-file torrents is not supported yet");
+                std::process::exit(1);
+            }
+            if peer.is_empty() {
+                eprintln!("No peers given; pass them with --peer");
+                std::process::exit(1);
+            }
+
+            let part_path = PathBuf::from(format!("{}.part", name));
+            let mut output = match fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(false)
+                .open(&part_path)
+            {
+                Ok(file) => file,
+                Err(e) => {
+                    eprintln!("Error opening {}: {}", part_path.display(), e);
+                    std::process::exit(1);
+                }
+            };
+
+            // Triggered by SIGINT or SIGTERM, so the resume state is saved and
+            // the peers are told we stopped
+            let cancel = x402_core::CancellationToken::new();
+            let handler_cancel = cancel.clone();
+            if let Err(e) = ctrlc::set_handler(move || handler_cancel.cancel()) {
+                eprintln!("Error installing signal handler: {}", e);
+                std::process::exit(1);
+            }
+
+            // No completed announce: the CLI doesn't seed what it downloads, so
+            // there is no port trackers could send peers to
+            let downloader = x402_core::Downloader::new(info_hash, torrent.info, peer)
+                .with_resume_file(PathBuf::from(format!("{}.resume", name)))
+                .with_cancellation(cancel)
+                .with_progress(|progress| eprint!("\r{}", progress_bar(&progress)));
+            let result = downloader.download(&mut output);
+            eprintln!();
+            match result {
+                Ok(x402_core::DownloadOutcome::Complete) => {
+                    if let Err(e) = fs::rename(&part_path, &name) {
+                        eprintln!("Error renaming {} to {}: {}", part_path.display(), name, e);
+                        std::process::exit(1);
+                    }
+                    println!("Downloaded {}", name);
+                }
+                Ok(x402_core::DownloadOutcome::Cancelled) => {
+                    println!("Download cancelled; run again to resume");
+                }
+                Err(e) => {
+                    eprintln!("Error downloading {}: {}", name, e);
+                    std::process::exit(1);
+                }
+            }
         }
     }
 }
+
+/// Split a listen address into host and port
+///
+/// A bare host listens on port 6881; a port that isn't a number from 0 to
+/// 65535 is an error rather than a silent fallback to the default.
+fn parse_listen_address(address: &str) -> Result<(String, u16), String> {
+    const DEFAULT_PORT: u16 = 6881;
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) if address.parse::<std::net::Ipv6Addr>().is_err() => (host, port),
+        _ => return Ok((address.to_string(), DEFAULT_PORT)),
+    };
+    let port = port.parse::<u16>().map_err(|_| {
+        format!(
+            "port '{}' in {} is not a number from 0 to 65535",
+            port, address
+        )
+    })?;
+    Ok((host.trim_matches(['[', ']']).to_string(), port))
+}
+
+/// Register the info hash of every `.torrent` file in `dir`
+fn load_torrents_dir(seeder: &mut x402_core::Seeder, dir: &Path) -> Result<usize, String> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read directory {}: {}", dir.display(), e))?;
+    let mut count = 0;
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().is_none_or(|ext| ext != "torrent") {
+            continue;
+        }
+        let torrent = fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| x402_core::parse_torrent(&data))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        seeder.add_torrent(torrent.info_hash()?);
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Serve a torrent file's content from `data`, advertising the pieces that verify
+fn add_torrent_file(
+    seeder: &mut x402_core::Seeder,
+    torrent: &Path,
+    data: &Path,
+) -> Result<([u8; 20], x402_core::Bitfield), String> {
+    let torrent = fs::read(torrent)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| x402_core::parse_torrent(&bytes))?;
+    let info_hash = torrent.info_hash()?;
+    let store = x402_core::MmapStore::open(&torrent.info, data)?;
+    let bitfield = seeder.add_torrent_with_data(info_hash, &torrent.info, Arc::new(store), true)?;
+    Ok((info_hash, bitfield))
+}
+
+/// Render download progress as a single-line text progress bar
+fn progress_bar(progress: &x402_core::Progress) -> String {
+    const WIDTH: usize = 30;
+    let filled = (progress.completed_pieces * WIDTH)
+        .checked_div(progress.total_pieces)
+        .unwrap_or(WIDTH);
+    let mut bar = format!(
+        "[{}{}] {}/{} pieces, {}/{} bytes",
+        "#".repeat(filled),
+        " ".repeat(WIDTH - filled),
+        progress.completed_pieces,
+        progress.total_pieces,
+        progress.downloaded_bytes,
+        progress.total_bytes
+    );
+    if let Some(eta) = progress.eta {
+        let secs = eta.as_secs();
+        bar.push_str(&format!(", ETA {}:{:02}", secs / 60, secs % 60));
+    }
+    bar
+}
+
+/// Total cost of a torrent at a per-piece price, if a price was given
+fn total_cost(data: &[u8], price: Option<u64>) -> Option<u64> {
+    let torrent = x402_core::parse_torrent(data).ok()?;
+    Some(x402_core::PricePolicy::PerPiece(price?).total_cost(&torrent.info))
+}
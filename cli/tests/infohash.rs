@@ -0,0 +1,32 @@
+use std::fs;
+use std::process::Command;
+
+use x402_core::{hex_to_base32, parse_torrent};
+
+const SAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../sample.torrent");
+
+fn infohash(args: &[&str]) -> (String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_cli"))
+        .arg("infohash")
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn test_infohash_prints_a_single_line() {
+    let torrent = parse_torrent(&fs::read(SAMPLE).unwrap()).unwrap();
+    let hex = torrent.info_hash_hex().unwrap();
+
+    let (stdout, stderr) = infohash(&[SAMPLE]);
+    assert_eq!(stdout, format!("{}\n", hex));
+    assert!(stderr.is_empty());
+
+    let (stdout, _) = infohash(&["--base32", SAMPLE]);
+    assert_eq!(stdout, format!("{}\n", hex_to_base32(&hex).unwrap()));
+}
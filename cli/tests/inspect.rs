@@ -0,0 +1,25 @@
+use std::fs;
+use std::process::Command;
+
+const MAGNET: &str = "magnet:?xt=urn:btih:d2474e86c95b19b8bcfdb92bc12c9d44667cfa36\
+    &dn=ubuntu.iso&tr=http%3A%2F%2Ftracker.example%2Fannounce";
+
+#[test]
+fn test_inspect_magnet_file() {
+    let path = std::env::temp_dir().join(format!("x402-inspect-{}.magnet", std::process::id()));
+    fs::write(&path, format!("{}\n", MAGNET)).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cli"))
+        .arg("inspect")
+        .arg(&path)
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Inspecting magnet link..."));
+    assert!(stdout.contains("Info Hash: d2474e86c95b19b8bcfdb92bc12c9d44667cfa36"));
+    assert!(stdout.contains("Name: ubuntu.iso"));
+    assert!(stdout.contains("  - http://tracker.example/announce"));
+}
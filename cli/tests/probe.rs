@@ -0,0 +1,33 @@
+use std::process::Command;
+use std::thread;
+
+use x402_core::{Capabilities, Seeder};
+
+#[test]
+fn test_probe_prints_advertised_capabilities() {
+    let info_hash = [0x5a; 20];
+    let mut seeder = Seeder::new("127.0.0.1".to_string(), 0).with_capabilities(Capabilities {
+        fast_extension: true,
+        extension_protocol: false,
+        x402: true,
+        dht: true,
+    });
+    seeder.add_torrent(info_hash);
+    let addr = seeder.bind().unwrap();
+    thread::spawn(move || seeder.listen());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_cli"))
+        .arg("probe")
+        .arg(addr.to_string())
+        .arg(hex::encode(info_hash))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Client: unknown"));
+    assert!(stdout.contains("  DHT: yes"));
+    assert!(stdout.contains("  Fast Extension: yes"));
+    assert!(stdout.contains("  Extension Protocol: no"));
+    assert!(stdout.contains("  x402: yes"));
+}
@@ -0,0 +1,92 @@
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::net::{SocketAddr, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+use x402_core::torrent::verify::hash_piece;
+use x402_core::{Handshake, generate_peer_id, parse_torrent};
+
+/// Kills the server when the test ends, even if it panics
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn test_serve_torrent_completes_handshake() {
+    let dir = std::env::temp_dir().join(format!("x402-serve-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let data: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    let data_path = dir.join("content.bin");
+    fs::write(&data_path, &data).unwrap();
+
+    let mut torrent = b"d8:announce0:4:infod6:lengthi1000e4:name11:content.bin\
+        12:piece lengthi16384e6:pieces20:"
+        .to_vec();
+    torrent.extend_from_slice(&hash_piece(&data));
+    torrent.extend_from_slice(b"ee");
+    let torrent_path = dir.join("content.torrent");
+    fs::write(&torrent_path, &torrent).unwrap();
+    let info_hash = parse_torrent(&torrent).unwrap().info_hash().unwrap();
+
+    let child = Command::new(env!("CARGO_BIN_EXE_cli"))
+        .arg("serve")
+        .arg("--torrent")
+        .arg(&torrent_path)
+        .arg("--data")
+        .arg(&data_path)
+        .args(["--price", "100", "--listen", "127.0.0.1:0"])
+        // Keep a config in the user's home from leaking into the test
+        .env("XDG_CONFIG_HOME", &dir)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut server = Server(child);
+
+    let mut stdout = BufReader::new(server.0.stdout.take().unwrap()).lines();
+    let addr: SocketAddr = stdout
+        .by_ref()
+        .map(|line| line.unwrap())
+        .find_map(|line| {
+            line.strip_prefix("Listening on ")
+                .map(|a| a.parse().unwrap())
+        })
+        .expect("server reports its address");
+    // Keep reading so the server's logging doesn't hit a closed pipe
+    thread::spawn(move || stdout.for_each(drop));
+
+    // A paid seeder only talks to peers advertising x402 support
+    let mut stream = TcpStream::connect(addr).unwrap();
+    Handshake::new(info_hash, generate_peer_id())
+        .enable_x402()
+        .send(&mut stream)
+        .unwrap();
+    let response = Handshake::receive(&mut stream).unwrap();
+    assert_eq!(response.info_hash, info_hash);
+    assert!(response.supports_x402());
+
+    drop(server);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_serve_rejects_out_of_range_port() {
+    let config_home = std::env::temp_dir().join("x402-no-config");
+    let output = Command::new(env!("CARGO_BIN_EXE_cli"))
+        .args(["serve", "--listen", "127.0.0.1:99999"])
+        .env("XDG_CONFIG_HOME", &config_home)
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stdout.contains("6881"));
+    assert!(stderr.contains("port '99999' in 127.0.0.1:99999 is not a number from 0 to 65535"));
+}
@@ -0,0 +1,388 @@
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+
+use crate::peer::address::{COMPACT_PEER_LENGTH, PeerAddress};
+
+/// Length of a compact node info record (20-byte id + 4-byte IPv4 + 2-byte port)
+const COMPACT_NODE_LENGTH: usize = 26;
+
+/// A DHT node as returned in the `nodes` field of a KRPC response
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeInfo {
+    /// 20-byte node ID
+    pub id: [u8; 20],
+    /// UDP address of the node
+    pub addr: SocketAddr,
+}
+
+/// A KRPC query (BEP 5)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    Ping { id: [u8; 20] },
+    GetPeers { id: [u8; 20], info_hash: [u8; 20] },
+}
+
+/// The body of a KRPC response
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Response {
+    /// ID of the responding node
+    pub id: [u8; 20],
+    /// Closer nodes returned by `get_peers`
+    pub nodes: Vec<NodeInfo>,
+    /// Peers for the requested info hash returned by `get_peers`
+    pub values: Vec<SocketAddr>,
+    /// Opaque token to be used in a later `announce_peer`
+    pub token: Option<Vec<u8>>,
+}
+
+/// A KRPC message: a query, a response or an error
+#[derive(Debug, Clone, PartialEq)]
+pub enum KrpcMessage {
+    Query {
+        transaction_id: Vec<u8>,
+        query: Query,
+    },
+    Response {
+        transaction_id: Vec<u8>,
+        response: Response,
+    },
+    Error {
+        transaction_id: Vec<u8>,
+        code: i64,
+        message: String,
+    },
+}
+
+/// Wire representation of a KRPC message, as a bencoded dictionary
+#[derive(Debug, Deserialize, Serialize)]
+struct RawMessage {
+    t: ByteBuf,
+    y: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    q: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    a: Option<RawArguments>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    r: Option<RawResponse>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    e: Option<(i64, String)>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct RawArguments {
+    id: ByteBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    info_hash: Option<ByteBuf>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct RawResponse {
+    id: ByteBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nodes: Option<ByteBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    values: Option<Vec<ByteBuf>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    token: Option<ByteBuf>,
+}
+
+impl KrpcMessage {
+    /// Get the transaction ID of the message
+    pub fn transaction_id(&self) -> &[u8] {
+        match self {
+            KrpcMessage::Query { transaction_id, .. }
+            | KrpcMessage::Response { transaction_id, .. }
+            | KrpcMessage::Error { transaction_id, .. } => transaction_id,
+        }
+    }
+
+    /// Encode the message as a bencoded dictionary
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        let raw = match self {
+            KrpcMessage::Query {
+                transaction_id,
+                query,
+            } => {
+                let (name, args) = match query {
+                    Query::Ping { id } => (
+                        "ping",
+                        RawArguments {
+                            id: ByteBuf::from(id.to_vec()),
+                            info_hash: None,
+                        },
+                    ),
+                    Query::GetPeers { id, info_hash } => (
+                        "get_peers",
+                        RawArguments {
+                            id: ByteBuf::from(id.to_vec()),
+                            info_hash: Some(ByteBuf::from(info_hash.to_vec())),
+                        },
+                    ),
+                };
+                RawMessage {
+                    t: ByteBuf::from(transaction_id.clone()),
+                    y: "q".to_string(),
+                    q: Some(name.to_string()),
+                    a: Some(args),
+                    r: None,
+                    e: None,
+                }
+            }
+            KrpcMessage::Response {
+                transaction_id,
+                response,
+            } => RawMessage {
+                t: ByteBuf::from(transaction_id.clone()),
+                y: "r".to_string(),
+                q: None,
+                a: None,
+                r: Some(RawResponse {
+                    id: ByteBuf::from(response.id.to_vec()),
+                    nodes: if response.nodes.is_empty() {
+                        None
+                    } else {
+                        Some(ByteBuf::from(encode_compact_nodes(&response.nodes)?))
+                    },
+                    values: if response.values.is_empty() {
+                        None
+                    } else {
+                        Some(
+                            response
+                                .values
+                                .iter()
+                                .map(|addr| encode_compact_peer(addr).map(ByteBuf::from))
+                                .collect::<Result<_, _>>()?,
+                        )
+                    },
+                    token: response.token.clone().map(ByteBuf::from),
+                }),
+                e: None,
+            },
+            KrpcMessage::Error {
+                transaction_id,
+                code,
+                message,
+            } => RawMessage {
+                t: ByteBuf::from(transaction_id.clone()),
+                y: "e".to_string(),
+                q: None,
+                a: None,
+                r: None,
+                e: Some((*code, message.clone())),
+            },
+        };
+
+        serde_bencode::to_bytes(&raw).map_err(|e| format!("Failed to encode KRPC message: {}", e))
+    }
+
+    /// Decode a bencoded KRPC message
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        let raw: RawMessage = serde_bencode::from_bytes(data)
+            .map_err(|e| format!("Failed to decode KRPC message: {}", e))?;
+        let transaction_id = raw.t.into_vec();
+
+        match raw.y.as_str() {
+            "q" => {
+                let name = raw.q.ok_or("KRPC query is missing 'q'")?;
+                let args = raw.a.ok_or("KRPC query is missing 'a'")?;
+                let id = to_id(&args.id, "id")?;
+                let query = match name.as_str() {
+                    "ping" => Query::Ping { id },
+                    "get_peers" => {
+                        let info_hash = args
+                            .info_hash
+                            .ok_or("get_peers query is missing 'info_hash'")?;
+                        Query::GetPeers {
+                            id,
+                            info_hash: to_id(&info_hash, "info_hash")?,
+                        }
+                    }
+                    other => return Err(format!("Unsupported KRPC query: {}", other)),
+                };
+                Ok(KrpcMessage::Query {
+                    transaction_id,
+                    query,
+                })
+            }
+            "r" => {
+                let r = raw.r.ok_or("KRPC response is missing 'r'")?;
+                let nodes = match r.nodes {
+                    Some(nodes) => decode_compact_nodes(&nodes)?,
+                    None => Vec::new(),
+                };
+                let values = match r.values {
+                    Some(values) => values
+                        .iter()
+                        .map(|v| decode_compact_peer(v))
+                        .collect::<Result<_, _>>()?,
+                    None => Vec::new(),
+                };
+                Ok(KrpcMessage::Response {
+                    transaction_id,
+                    response: Response {
+                        id: to_id(&r.id, "id")?,
+                        nodes,
+                        values,
+                        token: r.token.map(ByteBuf::into_vec),
+                    },
+                })
+            }
+            "e" => {
+                let (code, message) = raw.e.ok_or("KRPC error is missing 'e'")?;
+                Ok(KrpcMessage::Error {
+                    transaction_id,
+                    code,
+                    message,
+                })
+            }
+            other => Err(format!("Invalid KRPC message type: {}", other)),
+        }
+    }
+}
+
+/// Convert a byte string into a 20-byte ID
+fn to_id(bytes: &[u8], field: &str) -> Result<[u8; 20], String> {
+    bytes.try_into().map_err(|_| {
+        format!(
+            "Invalid '{}' length: expected 20, got {}",
+            field,
+            bytes.len()
+        )
+    })
+}
+
+/// Encode an IPv4 peer address into its 6-byte compact form
+fn encode_compact_peer(addr: &SocketAddr) -> Result<Vec<u8>, String> {
+    match addr {
+        SocketAddr::V4(_) => Ok(PeerAddress(*addr).to_compact()),
+        SocketAddr::V6(_) => Err(format!("IPv6 address not supported: {}", addr)),
+    }
+}
+
+/// Decode a 6-byte compact peer address
+fn decode_compact_peer(data: &[u8]) -> Result<SocketAddr, String> {
+    if data.len() != COMPACT_PEER_LENGTH {
+        return Err(format!(
+            "Invalid compact peer length: expected {}, got {}",
+            COMPACT_PEER_LENGTH,
+            data.len()
+        ));
+    }
+    PeerAddress::from_compact(data).map(SocketAddr::from)
+}
+
+/// Encode a list of nodes into the concatenated compact node info form
+fn encode_compact_nodes(nodes: &[NodeInfo]) -> Result<Vec<u8>, String> {
+    let mut buf = Vec::with_capacity(nodes.len() * COMPACT_NODE_LENGTH);
+    for node in nodes {
+        buf.extend_from_slice(&node.id);
+        buf.extend_from_slice(&encode_compact_peer(&node.addr)?);
+    }
+    Ok(buf)
+}
+
+/// Decode concatenated compact node info records
+fn decode_compact_nodes(data: &[u8]) -> Result<Vec<NodeInfo>, String> {
+    if !data.len().is_multiple_of(COMPACT_NODE_LENGTH) {
+        return Err(format!(
+            "Invalid compact nodes length: {} is not a multiple of {}",
+            data.len(),
+            COMPACT_NODE_LENGTH
+        ));
+    }
+
+    data.chunks_exact(COMPACT_NODE_LENGTH)
+        .map(|chunk| {
+            Ok(NodeInfo {
+                id: to_id(&chunk[..20], "node id")?,
+                addr: decode_compact_peer(&chunk[20..])?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_ping_query() {
+        // Example from BEP 5
+        let message = KrpcMessage::Query {
+            transaction_id: b"aa".to_vec(),
+            query: Query::Ping {
+                id: *b"abcdefghij0123456789",
+            },
+        };
+
+        let encoded = message.encode().unwrap();
+        assert_eq!(
+            encoded,
+            b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe".to_vec()
+        );
+        assert_eq!(KrpcMessage::decode(&encoded).unwrap(), message);
+    }
+
+    #[test]
+    fn test_get_peers_query_roundtrip() {
+        let message = KrpcMessage::Query {
+            transaction_id: b"aa".to_vec(),
+            query: Query::GetPeers {
+                id: *b"abcdefghij0123456789",
+                info_hash: *b"mnopqrstuvwxyz123456",
+            },
+        };
+
+        let encoded = message.encode().unwrap();
+        assert_eq!(
+            encoded,
+            b"d1:ad2:id20:abcdefghij01234567899:info_hash20:mnopqrstuvwxyz123456e1:q9:get_peers1:t2:aa1:y1:qe".to_vec()
+        );
+        assert_eq!(KrpcMessage::decode(&encoded).unwrap(), message);
+    }
+
+    #[test]
+    fn test_get_peers_response_roundtrip() {
+        let message = KrpcMessage::Response {
+            transaction_id: b"aa".to_vec(),
+            response: Response {
+                id: [7u8; 20],
+                nodes: vec![NodeInfo {
+                    id: [9u8; 20],
+                    addr: "10.0.0.1:6881".parse().unwrap(),
+                }],
+                values: vec![
+                    "192.168.1.2:51413".parse().unwrap(),
+                    "127.0.0.1:6881".parse().unwrap(),
+                ],
+                token: Some(b"aoeusnth".to_vec()),
+            },
+        };
+
+        let encoded = message.encode().unwrap();
+        assert_eq!(KrpcMessage::decode(&encoded).unwrap(), message);
+    }
+
+    #[test]
+    fn test_decode_error_message() {
+        let data = b"d1:eli201e23:A Generic Error Ocurrede1:t2:aa1:y1:ee";
+        let message = KrpcMessage::decode(data).unwrap();
+
+        assert_eq!(
+            message,
+            KrpcMessage::Error {
+                transaction_id: b"aa".to_vec(),
+                code: 201,
+                message: "A Generic Error Ocurred".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid_nodes_length() {
+        let result = decode_compact_nodes(&[0u8; 27]);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,2 @@
+pub mod krpc;
+pub mod node;
@@ -0,0 +1,292 @@
+use std::collections::HashSet;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use svix_ksuid::KsuidLike;
+
+use crate::dht::krpc::{KrpcMessage, NodeInfo, Query, Response};
+use crate::peer::handshake::generate_peer_id;
+
+/// Well-known nodes used to join the DHT
+pub const BOOTSTRAP_NODES: &[&str] = &[
+    "router.bittorrent.com:6881",
+    "dht.transmissionbt.com:6881",
+    "router.utorrent.com:6881",
+];
+
+/// Number of closest nodes kept during an iterative lookup
+const LOOKUP_WIDTH: usize = 8;
+/// Upper bound on the number of queries sent by a single lookup
+const MAX_LOOKUP_QUERIES: usize = 64;
+/// Maximum size of a KRPC datagram
+const MAX_DATAGRAM_SIZE: usize = 1500;
+
+/// Result of an iterative `get_peers` lookup
+#[derive(Debug, Clone, Default)]
+pub struct GetPeersResult {
+    /// Peers announced for the info hash
+    pub peers: Vec<SocketAddr>,
+    /// Closest nodes to the info hash that were found during the lookup
+    pub nodes: Vec<NodeInfo>,
+}
+
+/// A minimal DHT node able to bootstrap and look up peers (BEP 5)
+pub struct DhtNode {
+    /// The UDP socket used for KRPC traffic
+    socket: UdpSocket,
+    /// Our node ID
+    id: [u8; 20],
+    /// Nodes learned from bootstrapping
+    known_nodes: Vec<NodeInfo>,
+    /// Counter used to generate transaction IDs
+    next_transaction_id: u16,
+    /// How long to wait for a response to a single query
+    timeout: Duration,
+}
+
+impl DhtNode {
+    /// Bind a DHT node to a local UDP address
+    pub fn bind(address: &str) -> io::Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        let mut id = [0u8; 20];
+        id.copy_from_slice(generate_peer_id().bytes());
+
+        Ok(DhtNode {
+            socket,
+            id,
+            known_nodes: Vec::new(),
+            next_transaction_id: 0,
+            timeout: Duration::from_secs(2),
+        })
+    }
+
+    /// Set how long to wait for a response to a single query
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Get our node ID
+    pub fn id(&self) -> [u8; 20] {
+        self.id
+    }
+
+    /// Get the local address the node is bound to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Get the nodes learned from bootstrapping
+    pub fn known_nodes(&self) -> &[NodeInfo] {
+        &self.known_nodes
+    }
+
+    /// Ping the well-known bootstrap nodes and remember the ones that respond
+    pub fn bootstrap(&mut self) -> Result<usize, String> {
+        self.bootstrap_from(BOOTSTRAP_NODES)
+    }
+
+    /// Ping the given bootstrap nodes and remember the ones that respond
+    pub fn bootstrap_from(&mut self, nodes: &[&str]) -> Result<usize, String> {
+        for node in nodes {
+            let addrs = match node.to_socket_addrs() {
+                Ok(addrs) => addrs,
+                Err(e) => {
+                    log::warn!("Failed to resolve bootstrap node {}: {}", node, e);
+                    continue;
+                }
+            };
+
+            for addr in addrs.filter(SocketAddr::is_ipv4) {
+                match self.ping(addr) {
+                    Ok(id) => self.remember(NodeInfo { id, addr }),
+                    Err(e) => log::warn!("Bootstrap node {} did not respond: {}", addr, e),
+                }
+            }
+        }
+
+        if self.known_nodes.is_empty() {
+            return Err("No bootstrap node responded".to_string());
+        }
+
+        Ok(self.known_nodes.len())
+    }
+
+    /// Ping a node, returning its node ID
+    pub fn ping(&mut self, addr: SocketAddr) -> Result<[u8; 20], String> {
+        let response = self.query(addr, Query::Ping { id: self.id })?;
+        Ok(response.id)
+    }
+
+    /// Iteratively look up peers for an info hash, starting from the known nodes
+    pub fn get_peers(&mut self, info_hash: [u8; 20]) -> Result<GetPeersResult, String> {
+        if self.known_nodes.is_empty() {
+            return Err("No known nodes: bootstrap the DHT node first".to_string());
+        }
+
+        let mut candidates = self.known_nodes.clone();
+        let mut queried: HashSet<SocketAddr> = HashSet::new();
+        let mut responded: Vec<NodeInfo> = Vec::new();
+        let mut peers: Vec<SocketAddr> = Vec::new();
+
+        while queried.len() < MAX_LOOKUP_QUERIES {
+            candidates.sort_by_key(|node| distance(&node.id, &info_hash));
+            candidates.truncate(LOOKUP_WIDTH);
+
+            let next = match candidates.iter().find(|n| !queried.contains(&n.addr)) {
+                Some(node) => node.clone(),
+                // Every one of the closest nodes has been queried
+                None => break,
+            };
+            queried.insert(next.addr);
+
+            let response = match self.query(
+                next.addr,
+                Query::GetPeers {
+                    id: self.id,
+                    info_hash,
+                },
+            ) {
+                Ok(response) => response,
+                Err(_) => {
+                    candidates.retain(|n| n.addr != next.addr);
+                    continue;
+                }
+            };
+
+            responded.push(next);
+            for peer in response.values {
+                if !peers.contains(&peer) {
+                    peers.push(peer);
+                }
+            }
+            for node in response.nodes {
+                if !candidates.iter().any(|n| n.addr == node.addr) {
+                    candidates.push(node);
+                }
+            }
+        }
+
+        responded.sort_by_key(|node| distance(&node.id, &info_hash));
+        responded.truncate(LOOKUP_WIDTH);
+
+        Ok(GetPeersResult {
+            peers,
+            nodes: responded,
+        })
+    }
+
+    /// Send a query and wait for the matching response
+    fn query(&mut self, addr: SocketAddr, query: Query) -> Result<Response, String> {
+        let transaction_id = self.next_transaction_id.to_be_bytes().to_vec();
+        self.next_transaction_id = self.next_transaction_id.wrapping_add(1);
+
+        let message = KrpcMessage::Query {
+            transaction_id: transaction_id.clone(),
+            query,
+        };
+        self.socket
+            .send_to(&message.encode()?, addr)
+            .map_err(|e| format!("Failed to send query to {}: {}", addr, e))?;
+
+        let deadline = Instant::now() + self.timeout;
+        let mut buf = [0u8; MAX_DATAGRAM_SIZE];
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(format!("Query to {} timed out", addr));
+            }
+            self.socket
+                .set_read_timeout(Some(remaining))
+                .map_err(|e| format!("Failed to set read timeout: {}", e))?;
+
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Err(format!("Query to {} timed out", addr));
+                }
+                Err(e) => return Err(format!("Failed to receive response: {}", e)),
+            };
+
+            // Ignore stray or malformed datagrams from other nodes
+            if from != addr {
+                continue;
+            }
+            let reply = match KrpcMessage::decode(&buf[..len]) {
+                Ok(reply) if reply.transaction_id() == transaction_id.as_slice() => reply,
+                _ => continue,
+            };
+
+            return match reply {
+                KrpcMessage::Response { response, .. } => Ok(response),
+                KrpcMessage::Error { code, message, .. } => Err(format!(
+                    "Node {} returned error {}: {}",
+                    addr, code, message
+                )),
+                KrpcMessage::Query { .. } => continue,
+            };
+        }
+    }
+
+    /// Add a node to the known nodes if it isn't already there
+    fn remember(&mut self, node: NodeInfo) {
+        if !self.known_nodes.iter().any(|n| n.addr == node.addr) {
+            self.known_nodes.push(node);
+        }
+    }
+}
+
+/// XOR distance between two IDs, comparable as a big-endian number
+fn distance(a: &[u8; 20], b: &[u8; 20]) -> [u8; 20] {
+    let mut result = [0u8; 20];
+    for (i, byte) in result.iter_mut().enumerate() {
+        *byte = a[i] ^ b[i];
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distance() {
+        let a = [0u8; 20];
+        let mut b = [0u8; 20];
+        b[19] = 1;
+        let mut c = [0u8; 20];
+        c[0] = 1;
+
+        assert!(distance(&a, &b) < distance(&a, &c));
+        assert_eq!(distance(&b, &b), [0u8; 20]);
+    }
+
+    #[test]
+    fn test_ping_local_node() {
+        let mut client = DhtNode::bind("127.0.0.1:0").unwrap();
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let mut buf = [0u8; MAX_DATAGRAM_SIZE];
+            let (len, from) = server.recv_from(&mut buf).unwrap();
+            let query = KrpcMessage::decode(&buf[..len]).unwrap();
+            let reply = KrpcMessage::Response {
+                transaction_id: query.transaction_id().to_vec(),
+                response: Response {
+                    id: [5u8; 20],
+                    ..Default::default()
+                },
+            };
+            server.send_to(&reply.encode().unwrap(), from).unwrap();
+        });
+
+        assert_eq!(client.ping(server_addr).unwrap(), [5u8; 20]);
+        handle.join().unwrap();
+    }
+}
@@ -1,8 +1,65 @@
+#[cfg(feature = "dht")]
+pub mod dht;
+pub mod payment;
 pub mod peer;
 pub mod torrent;
+pub mod tracker;
 
 // Re-export only public API
-pub use peer::handshake::{generate_peer_id, Handshake};
-pub use peer::serve::Seeder;
-pub use torrent::magnet::MagnetLink;
-pub use torrent::parser::decode_torrent;
+pub use payment::exchange::{ExchangeMode, PaymentGate};
+pub use payment::price::{PricePolicy, PriceUnit};
+pub use peer::address::{COMPACT_PEER_LENGTH, COMPACT_PEER6_LENGTH, PeerAddress};
+pub use peer::availability::Availability;
+pub use peer::bitfield::Bitfield;
+pub use peer::cancel::CancellationToken;
+pub use peer::download::{DownloadError, DownloadOutcome, Downloader, Progress};
+pub use peer::extension::ExtendedHandshake;
+pub use peer::handshake::{
+    Capabilities, Handshake, HandshakeError, generate_peer_id, identify_client, parse_peer_id_hex,
+    peer_id_bytes,
+};
+pub use peer::ipfilter::{IpRange, load_ipfilter, parse_ipfilter};
+pub use peer::leecher::Leecher;
+pub use peer::message::{Message, MessageError};
+pub use peer::proxy::{ProxyAuth, ProxyConfig};
+pub use peer::resume::ResumeState;
+pub use peer::serve::{
+    BindError, ConnectionError, LegacyPeerPolicy, ReadError, Seeder, Settlement,
+};
+pub use peer::state::PeerState;
+pub use peer::stats::Stats;
+pub use peer::throughput::ThroughputEstimator;
+pub use peer::transport::{PeerListener, PeerStream, TcpTransport, Transport};
+pub use torrent::check::{CheckResult, VerifyReport, check_file, verify_files_parallel};
+pub use torrent::create::{hash_pieces, make_torrent};
+pub use torrent::filetree::FileTree;
+pub use torrent::index::{Duplicate, IndexError, TorrentIndex};
+pub use torrent::infohash::{
+    Hasher, HybridInfoHash, Sha1Hasher, base32_to_hex, derive_infohash, derive_infohash_with,
+    hex_to_base32, truncate_v2,
+};
+pub use torrent::magnet::{HashEncoding, MagnetError, MagnetLink, UrlOptions};
+pub use torrent::mmap::MmapStore;
+pub use torrent::naming::sanitize_filename;
+pub use torrent::parser::{
+    DEFAULT_MAX_PIECES, ParseError, decode_torrent, dump_bencode, parse_torrent,
+    parse_torrent_strict, parse_torrent_with_max_pieces, summarize_torrent,
+};
+pub use torrent::raw::RawTorrent;
+pub use torrent::source::{SourceKind, TorrentSource, load_magnet};
+pub use torrent::store::{MemoryStore, PieceStore};
+pub use torrent::summary::TorrentSummary;
+pub use torrent::types::{FileEntry, Info, PieceLayers, Torrent, TorrentDiff, TorrentFormat};
+pub use torrent::verify::{MERKLE_BLOCK_SIZE, StreamingVerifier, hash_piece_v2, verify_piece_v2};
+pub use torrent::writer::PieceWriter;
+pub use tracker::announce::{
+    AnnounceEvent, AnnounceRequest, AnnounceResponse, AnnounceSession, DEFAULT_NUMWANT, PeerEntry,
+    announce, announce_via, build_announce_url,
+};
+pub use tracker::announcer::{AnnounceHandle, AnnounceStatus, Announcer};
+pub use tracker::scrape::{ScrapeStats, scrape, scrape_via};
+
+#[cfg(feature = "dht")]
+pub use dht::node::DhtNode;
+#[cfg(feature = "mse")]
+pub use peer::mse::{EncryptionPolicy, MseListener, MseStream, MseTransport};
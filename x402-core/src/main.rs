@@ -1,5 +1,3 @@
-mod torrent;
-
 fn main() {
     println!("x402 peer starting...");
 }
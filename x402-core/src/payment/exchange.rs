@@ -0,0 +1,137 @@
+/// When a seeder requires payment relative to the blocks it serves
+///
+/// Every mode trades off who bears the risk of the other side cheating:
+/// whoever moves first can lose what they sent. This assumes payments are
+/// checked: without a payment verifier on the seeder, a payment is only the
+/// peer's claim, and one claiming a huge amount is served for free whatever
+/// the mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExchangeMode {
+    /// Every block is paid for before it is sent
+    ///
+    /// With verified payments the seeder risks nothing; the leecher risks
+    /// losing a payment for a block the seeder never sends.
+    #[default]
+    PayFirst,
+    /// Up to `block_credit` blocks may be served ahead of payment
+    ///
+    /// Both sides risk at most `block_credit` blocks worth of value, and
+    /// transfers keep flowing while payments are in transit.
+    Interleaved { block_credit: u32 },
+    /// Blocks are served right away and paid for afterwards
+    ///
+    /// The leecher risks nothing; the seeder can lose everything it served,
+    /// so this only suits trusted peers.
+    PayAfter,
+}
+
+/// Tracks payments against served blocks for a single peer
+///
+/// Payments are credited as reported; checking their proofs is up to the
+/// caller, before `on_payment`.
+#[derive(Debug, Clone)]
+pub struct PaymentGate {
+    mode: ExchangeMode,
+    /// Price of a single block
+    block_price: u64,
+    /// Total amount the peer has paid
+    paid: u64,
+    /// Total price of the blocks served so far
+    charged: u64,
+}
+
+impl PaymentGate {
+    pub fn new(mode: ExchangeMode, block_price: u64) -> Self {
+        PaymentGate {
+            mode,
+            block_price,
+            paid: 0,
+            charged: 0,
+        }
+    }
+
+    /// Whether the next block may be served now
+    pub fn may_serve(&self) -> bool {
+        let credit = match self.mode {
+            ExchangeMode::PayFirst => 0,
+            ExchangeMode::Interleaved { block_credit } => {
+                self.block_price.saturating_mul(block_credit as u64)
+            }
+            ExchangeMode::PayAfter => return true,
+        };
+        self.paid.saturating_add(credit) >= self.charged.saturating_add(self.block_price)
+    }
+
+    /// Record a payment received from the peer
+    pub fn on_payment(&mut self, amount: u64) {
+        self.paid = self.paid.saturating_add(amount);
+    }
+
+    /// Record that a block was served
+    pub fn on_block_served(&mut self) {
+        self.charged = self.charged.saturating_add(self.block_price);
+    }
+
+    /// Total amount the peer has paid
+    pub fn paid(&self) -> u64 {
+        self.paid
+    }
+
+    /// Total price of the blocks served so far
+    pub fn charged(&self) -> u64 {
+        self.charged
+    }
+
+    /// Amount owed for blocks already served
+    pub fn outstanding(&self) -> u64 {
+        self.charged.saturating_sub(self.paid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pay_first() {
+        let mut gate = PaymentGate::new(ExchangeMode::PayFirst, 10);
+        assert!(!gate.may_serve());
+
+        gate.on_payment(10);
+        assert!(gate.may_serve());
+        gate.on_block_served();
+        assert!(!gate.may_serve());
+        assert_eq!(gate.outstanding(), 0);
+    }
+
+    #[test]
+    fn test_interleaved() {
+        let mut gate = PaymentGate::new(ExchangeMode::Interleaved { block_credit: 1 }, 10);
+
+        // One block is served on credit, then each block needs a payment
+        assert!(gate.may_serve());
+        gate.on_block_served();
+        assert!(!gate.may_serve());
+        assert_eq!(gate.outstanding(), 10);
+
+        gate.on_payment(10);
+        assert!(gate.may_serve());
+        gate.on_block_served();
+        assert!(!gate.may_serve());
+    }
+
+    #[test]
+    fn test_pay_after() {
+        let mut gate = PaymentGate::new(ExchangeMode::PayAfter, 10);
+        for _ in 0..3 {
+            assert!(gate.may_serve());
+            gate.on_block_served();
+        }
+        assert_eq!(gate.outstanding(), 30);
+        assert_eq!(gate.charged(), 30);
+
+        gate.on_payment(30);
+        assert_eq!(gate.outstanding(), 0);
+        assert_eq!(gate.paid(), 30);
+    }
+}
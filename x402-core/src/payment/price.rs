@@ -0,0 +1,121 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::torrent::types::Info;
+
+/// Currency unit a price is expressed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceUnit {
+    /// Lamports, the smallest unit of SOL
+    Lamports,
+    /// The smallest unit of USDC (one millionth)
+    Usdc,
+}
+
+impl FromStr for PriceUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lamports" => Ok(PriceUnit::Lamports),
+            "usdc" => Ok(PriceUnit::Usdc),
+            _ => Err(format!(
+                "Unknown price unit '{}': expected 'lamports' or 'usdc'",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PriceUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PriceUnit::Lamports => write!(f, "lamports"),
+            PriceUnit::Usdc => write!(f, "usdc"),
+        }
+    }
+}
+
+/// How a seeder charges for the content it serves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PricePolicy {
+    /// Everything is served for free
+    #[default]
+    Free,
+    /// A fixed price for every piece, including the short last piece
+    PerPiece(u64),
+    /// A price for every byte of content
+    PerByte(u64),
+    /// A single price for the whole torrent
+    Flat(u64),
+}
+
+impl PricePolicy {
+    /// Total price of downloading every piece of a torrent
+    ///
+    /// Saturates instead of overflowing for absurd prices.
+    pub fn total_cost(&self, info: &Info) -> u64 {
+        match *self {
+            PricePolicy::Free => 0,
+            PricePolicy::PerPiece(price) => price.saturating_mul(info.num_pieces() as u64),
+            PricePolicy::PerByte(price) => price.saturating_mul(info.total_length() as u64),
+            PricePolicy::Flat(price) => price,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three pieces of 16 KiB, the last one 1000 bytes long
+    fn test_info() -> Info {
+        Info {
+            name: "test.bin".to_string(),
+            plength: 16384,
+            pieces: serde_bytes::ByteBuf::from(vec![0u8; 60]),
+            length: Some(2 * 16384 + 1000),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_price_unit_from_str() {
+        assert_eq!("lamports".parse::<PriceUnit>(), Ok(PriceUnit::Lamports));
+        assert_eq!("usdc".parse::<PriceUnit>(), Ok(PriceUnit::Usdc));
+        assert!(
+            "btc"
+                .parse::<PriceUnit>()
+                .unwrap_err()
+                .contains("Unknown price unit 'btc'")
+        );
+    }
+
+    #[test]
+    fn test_total_cost_free() {
+        assert_eq!(PricePolicy::Free.total_cost(&test_info()), 0);
+    }
+
+    #[test]
+    fn test_total_cost_per_piece() {
+        assert_eq!(PricePolicy::PerPiece(100).total_cost(&test_info()), 300);
+    }
+
+    #[test]
+    fn test_total_cost_per_byte() {
+        assert_eq!(PricePolicy::PerByte(2).total_cost(&test_info()), 2 * 33768);
+    }
+
+    #[test]
+    fn test_total_cost_flat() {
+        assert_eq!(PricePolicy::Flat(5000).total_cost(&test_info()), 5000);
+    }
+
+    #[test]
+    fn test_total_cost_saturates() {
+        assert_eq!(
+            PricePolicy::PerPiece(u64::MAX).total_cost(&test_info()),
+            u64::MAX
+        );
+    }
+}
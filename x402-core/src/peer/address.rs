@@ -0,0 +1,115 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+/// Length of a compact IPv4 peer: 4 address bytes and a 2 byte port
+pub const COMPACT_PEER_LENGTH: usize = 6;
+/// Length of a compact IPv6 peer: 16 address bytes and a 2 byte port
+pub const COMPACT_PEER6_LENGTH: usize = 18;
+
+/// Address of a peer, with the compact form trackers, DHT nodes and peers exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PeerAddress(pub SocketAddr);
+
+impl PeerAddress {
+    /// Encode as the address bytes followed by the big-endian port
+    ///
+    /// IPv4 addresses take 6 bytes and IPv6 addresses 18.
+    pub fn to_compact(&self) -> Vec<u8> {
+        let mut buf = match self.0.ip() {
+            IpAddr::V4(ip) => {
+                let mut buf = Vec::with_capacity(COMPACT_PEER_LENGTH);
+                buf.extend_from_slice(&ip.octets());
+                buf
+            }
+            IpAddr::V6(ip) => {
+                let mut buf = Vec::with_capacity(COMPACT_PEER6_LENGTH);
+                buf.extend_from_slice(&ip.octets());
+                buf
+            }
+        };
+        buf.extend_from_slice(&self.0.port().to_be_bytes());
+        buf
+    }
+
+    /// Decode a 6-byte IPv4 or 18-byte IPv6 compact peer
+    pub fn from_compact(data: &[u8]) -> Result<Self, String> {
+        let ip = match data.len() {
+            COMPACT_PEER_LENGTH => IpAddr::V4(Ipv4Addr::new(data[0], data[1], data[2], data[3])),
+            COMPACT_PEER6_LENGTH => {
+                let octets: [u8; 16] = data[..16].try_into().unwrap();
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            len => {
+                return Err(format!(
+                    "Invalid compact peer length: expected {} or {}, got {}",
+                    COMPACT_PEER_LENGTH, COMPACT_PEER6_LENGTH, len
+                ));
+            }
+        };
+        let port = u16::from_be_bytes([data[data.len() - 2], data[data.len() - 1]]);
+        Ok(PeerAddress(SocketAddr::new(ip, port)))
+    }
+
+    /// Decode concatenated compact peers of `record_length` bytes each
+    pub fn from_compact_list(data: &[u8], record_length: usize) -> Result<Vec<Self>, String> {
+        if !data.len().is_multiple_of(record_length) {
+            return Err(format!("Invalid compact peers length: {}", data.len()));
+        }
+        data.chunks(record_length).map(Self::from_compact).collect()
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.0
+    }
+}
+
+impl From<SocketAddr> for PeerAddress {
+    fn from(addr: SocketAddr) -> Self {
+        PeerAddress(addr)
+    }
+}
+
+impl From<PeerAddress> for SocketAddr {
+    fn from(addr: PeerAddress) -> Self {
+        addr.0
+    }
+}
+
+impl FromStr for PeerAddress {
+    type Err = String;
+
+    /// Parse `ip:port`, with IPv6 addresses in brackets, as in a magnet's `x.pe`
+    fn from_str(s: &str) -> Result<Self, String> {
+        s.parse::<SocketAddr>()
+            .map(PeerAddress)
+            .map_err(|_| format!("Invalid peer address: {}", s))
+    }
+}
+
+impl fmt::Display for PeerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_roundtrip() {
+        let v4: PeerAddress = "127.0.0.1:6881".parse().unwrap();
+        assert_eq!(v4.to_compact(), vec![127, 0, 0, 1, 0x1a, 0xe1]);
+        assert_eq!(PeerAddress::from_compact(&v4.to_compact()).unwrap(), v4);
+
+        let v6: PeerAddress = "[2001:db8::1]:51413".parse().unwrap();
+        let compact = v6.to_compact();
+        assert_eq!(compact.len(), COMPACT_PEER6_LENGTH);
+        assert_eq!(&compact[16..], &[0xc8, 0xd5]);
+        assert_eq!(PeerAddress::from_compact(&compact).unwrap(), v6);
+
+        assert!(PeerAddress::from_compact(&compact[..10]).is_err());
+        assert!(PeerAddress::from_compact_list(&compact[..10], COMPACT_PEER_LENGTH).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use crate::peer::bitfield::Bitfield;
+use crate::peer::message::Message;
+
+/// Tracks how many connected peers have each piece, for rarest-first selection
+#[derive(Debug, Clone)]
+pub struct Availability {
+    /// Number of peers that have each piece
+    counts: Vec<u32>,
+    /// What each connected peer has announced so far
+    peers: HashMap<SocketAddr, Bitfield>,
+}
+
+impl Availability {
+    pub fn new(num_pieces: usize) -> Self {
+        Availability {
+            counts: vec![0; num_pieces],
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Record a peer's Bitfield message, replacing anything it announced before
+    pub fn on_bitfield(&mut self, peer: SocketAddr, bitfield: &Bitfield) -> Result<(), String> {
+        if bitfield.num_pieces() != self.counts.len() {
+            return Err(format!(
+                "Bitfield covers {} pieces, expected {}",
+                bitfield.num_pieces(),
+                self.counts.len()
+            ));
+        }
+
+        self.on_disconnect(peer);
+        for index in bitfield.pieces() {
+            self.counts[index] += 1;
+        }
+        self.peers.insert(peer, bitfield.clone());
+        Ok(())
+    }
+
+    /// Record a peer's Have message
+    pub fn on_have(&mut self, peer: SocketAddr, index: u32) -> Result<(), String> {
+        let index = index as usize;
+        if index >= self.counts.len() {
+            return Err(format!("Have index {} out of range", index));
+        }
+
+        let num_pieces = self.counts.len();
+        let bitfield = self
+            .peers
+            .entry(peer)
+            .or_insert_with(|| Bitfield::new(num_pieces));
+        if !bitfield.has(index) {
+            bitfield.set(index);
+            self.counts[index] += 1;
+        }
+        Ok(())
+    }
+
+    /// Forget a peer and everything it announced
+    pub fn on_disconnect(&mut self, peer: SocketAddr) {
+        if let Some(bitfield) = self.peers.remove(&peer) {
+            for index in bitfield.pieces() {
+                self.counts[index] -= 1;
+            }
+        }
+    }
+
+    /// Update availability from any message received from a peer
+    pub fn on_message(&mut self, peer: SocketAddr, message: &Message) -> Result<(), String> {
+        match message {
+            Message::Have(index) => self.on_have(peer, *index),
+            Message::Bitfield(_) | Message::HaveAll | Message::HaveNone => {
+                let bitfield = Bitfield::from_message(message, self.counts.len())?;
+                self.on_bitfield(peer, &bitfield)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of peers that have a piece
+    pub fn count(&self, index: usize) -> u32 {
+        self.counts.get(index).copied().unwrap_or(0)
+    }
+
+    /// What a peer has announced, if it announced anything
+    pub fn peer_pieces(&self, peer: &SocketAddr) -> Option<&Bitfield> {
+        self.peers.get(peer)
+    }
+
+    /// Pick the rarest of the candidate pieces that at least one peer has
+    pub fn rarest<I: IntoIterator<Item = usize>>(&self, candidates: I) -> Option<usize> {
+        candidates
+            .into_iter()
+            .filter(|&index| self.count(index) > 0)
+            .min_by_key(|&index| self.count(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_overlapping_peers() {
+        let mut availability = Availability::new(4);
+
+        let bitfield = Bitfield::from_bytes(&[0b1100_0000], 4).unwrap();
+        availability.on_bitfield(peer(1), &bitfield).unwrap();
+        availability.on_have(peer(2), 1).unwrap();
+        availability.on_have(peer(2), 2).unwrap();
+        // A repeated Have must not be counted twice
+        availability.on_have(peer(2), 2).unwrap();
+
+        assert_eq!(availability.count(0), 1);
+        assert_eq!(availability.count(1), 2);
+        assert_eq!(availability.count(2), 1);
+        assert_eq!(availability.count(3), 0);
+
+        availability.on_disconnect(peer(1));
+
+        assert_eq!(availability.count(0), 0);
+        assert_eq!(availability.count(1), 1);
+        assert_eq!(availability.count(2), 1);
+    }
+
+    #[test]
+    fn test_on_message() {
+        let mut availability = Availability::new(3);
+        availability
+            .on_message(peer(1), &Message::Bitfield(vec![0b1010_0000]))
+            .unwrap();
+        availability.on_message(peer(1), &Message::Have(1)).unwrap();
+        availability.on_message(peer(1), &Message::Unchoke).unwrap();
+
+        assert!(availability.peer_pieces(&peer(1)).unwrap().is_complete());
+    }
+
+    #[test]
+    fn test_have_out_of_range() {
+        let mut availability = Availability::new(2);
+        assert!(availability.on_have(peer(1), 2).is_err());
+    }
+
+    #[test]
+    fn test_rarest() {
+        let mut availability = Availability::new(3);
+        availability
+            .on_bitfield(peer(1), &Bitfield::full(3))
+            .unwrap();
+        availability.on_have(peer(2), 0).unwrap();
+        availability.on_have(peer(2), 1).unwrap();
+
+        assert_eq!(availability.rarest(0..3), Some(2));
+        assert_eq!(availability.rarest([0, 1]), Some(0));
+    }
+}
@@ -0,0 +1,191 @@
+use crate::peer::message::Message;
+
+/// The set of pieces a peer has, as carried by the Bitfield message
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bitfield {
+    /// Packed bits, high bit of the first byte is piece 0
+    bits: Vec<u8>,
+    /// Number of pieces in the torrent
+    num_pieces: usize,
+}
+
+impl Bitfield {
+    /// Create an empty bitfield for a torrent with `num_pieces` pieces
+    pub fn new(num_pieces: usize) -> Self {
+        Bitfield {
+            bits: vec![0u8; num_pieces.div_ceil(8)],
+            num_pieces,
+        }
+    }
+
+    /// Create a bitfield with every piece set
+    pub fn full(num_pieces: usize) -> Self {
+        let mut bitfield = Self::new(num_pieces);
+        for index in 0..num_pieces {
+            bitfield.set(index);
+        }
+        bitfield
+    }
+
+    /// Create a bitfield from the payload of a Bitfield message
+    pub fn from_bytes(bytes: &[u8], num_pieces: usize) -> Result<Self, String> {
+        let expected = num_pieces.div_ceil(8);
+        if bytes.len() != expected {
+            return Err(format!(
+                "Invalid bitfield length: expected {}, got {}",
+                expected,
+                bytes.len()
+            ));
+        }
+
+        let bitfield = Bitfield {
+            bits: bytes.to_vec(),
+            num_pieces,
+        };
+
+        // Spare bits at the end must be cleared
+        if (num_pieces..expected * 8).any(|index| bitfield.bit(index)) {
+            return Err("Invalid bitfield: spare bits are set".to_string());
+        }
+
+        Ok(bitfield)
+    }
+
+    /// Encode as the message announcing our pieces
+    ///
+    /// With the Fast Extension negotiated, a full or empty bitfield is sent as
+    /// the one-byte Have All or Have None; otherwise as a Bitfield message.
+    pub fn to_message(&self, fast: bool) -> Message {
+        if fast && self.is_complete() {
+            Message::HaveAll
+        } else if fast && self.count() == 0 {
+            Message::HaveNone
+        } else {
+            Message::Bitfield(self.bits.clone())
+        }
+    }
+
+    /// Decode a Bitfield, Have All or Have None message
+    pub fn from_message(message: &Message, num_pieces: usize) -> Result<Self, String> {
+        match message {
+            Message::Bitfield(bytes) => Self::from_bytes(bytes, num_pieces),
+            Message::HaveAll => Ok(Self::full(num_pieces)),
+            Message::HaveNone => Ok(Self::new(num_pieces)),
+            other => Err(format!("Expected a bitfield, got {:?}", other)),
+        }
+    }
+
+    /// Get the packed bytes, as sent in a Bitfield message
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bits
+    }
+
+    /// Number of pieces the bitfield covers
+    pub fn num_pieces(&self) -> usize {
+        self.num_pieces
+    }
+
+    /// Check whether a piece is set
+    pub fn has(&self, index: usize) -> bool {
+        index < self.num_pieces && self.bit(index)
+    }
+
+    /// Mark a piece as present, ignoring out-of-range indices
+    pub fn set(&mut self, index: usize) {
+        if index < self.num_pieces {
+            self.bits[index / 8] |= 0x80 >> (index % 8);
+        }
+    }
+
+    /// Mark a piece as missing, ignoring out-of-range indices
+    pub fn clear(&mut self, index: usize) {
+        if index < self.num_pieces {
+            self.bits[index / 8] &= !(0x80 >> (index % 8));
+        }
+    }
+
+    /// Number of pieces set
+    pub fn count(&self) -> usize {
+        self.bits.iter().map(|b| b.count_ones() as usize).sum()
+    }
+
+    /// Whether every piece is set
+    pub fn is_complete(&self) -> bool {
+        self.count() == self.num_pieces
+    }
+
+    /// Iterate over the indices of the pieces that are set
+    pub fn pieces(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.num_pieces).filter(|&index| self.bit(index))
+    }
+
+    fn bit(&self, index: usize) -> bool {
+        self.bits[index / 8] & (0x80 >> (index % 8)) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_and_has() {
+        let mut bitfield = Bitfield::new(10);
+        bitfield.set(0);
+        bitfield.set(9);
+
+        assert!(bitfield.has(0));
+        assert!(!bitfield.has(1));
+        assert!(bitfield.has(9));
+        assert!(!bitfield.has(10));
+        assert_eq!(bitfield.as_bytes(), &[0b1000_0000, 0b0100_0000]);
+        assert_eq!(bitfield.count(), 2);
+    }
+
+    #[test]
+    fn test_full() {
+        let bitfield = Bitfield::full(10);
+        assert!(bitfield.is_complete());
+        assert_eq!(bitfield.as_bytes(), &[0xFF, 0b1100_0000]);
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_spare_bits() {
+        assert!(Bitfield::from_bytes(&[0xFF, 0b1100_0000], 10).is_ok());
+        assert!(Bitfield::from_bytes(&[0xFF, 0b1110_0000], 10).is_err());
+        assert!(Bitfield::from_bytes(&[0xFF], 10).is_err());
+    }
+
+    #[test]
+    fn test_pieces_iterator() {
+        let bitfield = Bitfield::from_bytes(&[0b1010_0000], 3).unwrap();
+        assert_eq!(bitfield.pieces().collect::<Vec<_>>(), vec![0, 2]);
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        let full = Bitfield::full(1_000_000);
+        assert_eq!(full.to_message(true), Message::HaveAll);
+        assert_eq!(
+            Bitfield::from_message(&Message::HaveAll, 1_000_000).unwrap(),
+            full
+        );
+
+        let empty = Bitfield::new(10);
+        assert_eq!(empty.to_message(true), Message::HaveNone);
+        assert_eq!(
+            Bitfield::from_message(&Message::HaveNone, 10).unwrap(),
+            empty
+        );
+
+        // Without the Fast Extension, or when partial, fall back to a Bitfield
+        let message = full.to_message(false);
+        assert!(matches!(&message, Message::Bitfield(bytes) if bytes.len() == 125_000));
+        let partial = Bitfield::from_bytes(&[0b1010_0000], 3).unwrap();
+        let message = partial.to_message(true);
+        assert_eq!(message, Message::Bitfield(vec![0b1010_0000]));
+        assert_eq!(Bitfield::from_message(&message, 3).unwrap(), partial);
+
+        assert!(Bitfield::from_message(&Message::Interested, 3).is_err());
+    }
+}
@@ -0,0 +1,1256 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io::{Seek, SeekFrom, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use svix_ksuid::KsuidMs;
+
+use crate::peer::availability::Availability;
+use crate::peer::bitfield::Bitfield;
+use crate::peer::cancel::CancellationToken;
+use crate::peer::handshake::{generate_peer_id, peer_id_bytes};
+use crate::peer::leecher::{BLOCK_SIZE, Leecher};
+use crate::peer::message::Message;
+use crate::peer::proxy::ProxyConfig;
+use crate::peer::resume::ResumeState;
+use crate::peer::serve::Seeder;
+use crate::peer::throughput::ThroughputEstimator;
+use crate::torrent::store::PieceStore;
+use crate::torrent::types::Info;
+use crate::torrent::verify::verify_piece;
+use crate::tracker::announce::{AnnounceEvent, AnnounceRequest, announce_via};
+
+/// Default cap on the block requests kept outstanding per peer
+const DEFAULT_MAX_IN_FLIGHT: usize = 16;
+/// Block requests kept outstanding with a peer before its speed is known
+const INITIAL_IN_FLIGHT: usize = 4;
+/// How much transfer time the requests in flight with a peer should cover
+const REQUEST_QUEUE_TIME: Duration = Duration::from_secs(1);
+/// Default number of missing pieces at which endgame mode starts
+const DEFAULT_ENDGAME_THRESHOLD: usize = 4;
+/// How often the download checks for cancellation while waiting for pieces
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How a download finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadOutcome {
+    /// Every piece was downloaded and verified
+    Complete,
+    /// The download was cancelled; finished pieces are in the resume state
+    Cancelled,
+}
+
+/// Errors that end a download early
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadError {
+    /// The deadline passed, or every peer was dropped for stalling, before
+    /// the download completed; finished pieces are in the resume state
+    DownloadTimeout {
+        completed_pieces: usize,
+        total_pieces: usize,
+    },
+    /// Any other failure
+    Other(String),
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::DownloadTimeout {
+                completed_pieces,
+                total_pieces,
+            } => write!(
+                f,
+                "Download timed out with {} of {} pieces",
+                completed_pieces, total_pieces
+            ),
+            DownloadError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for DownloadError {
+    fn from(message: String) -> Self {
+        DownloadError::Other(message)
+    }
+}
+
+/// Download progress, reported after each verified piece is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub completed_pieces: usize,
+    pub total_pieces: usize,
+    /// Bytes of the completed pieces, including those restored from the resume state
+    pub downloaded_bytes: u64,
+    pub total_bytes: u64,
+    /// Estimated time to finish at the recent download speed, once known
+    pub eta: Option<Duration>,
+}
+
+/// Downloads a torrent's pieces from several peers concurrently
+pub struct Downloader {
+    /// Info hash of the torrent
+    info_hash: [u8; 20],
+    /// Info dictionary describing the pieces
+    info: Info,
+    /// Peers to download from
+    peers: Vec<SocketAddr>,
+    /// Our peer ID
+    peer_id: KsuidMs,
+    /// Cap on the outstanding block requests per peer
+    max_in_flight: usize,
+    /// Number of missing pieces at which idle peers start duplicating pieces in progress
+    endgame_threshold: usize,
+    /// Cancels the download when triggered
+    cancel: CancellationToken,
+    /// How long the whole download may take
+    deadline: Option<Duration>,
+    /// How long a peer may take to deliver a piece before it is dropped
+    stall_timeout: Option<Duration>,
+    /// Where the completed pieces are saved when the download stops early
+    resume_path: Option<PathBuf>,
+    /// Called with the progress after each verified piece
+    progress: Option<Box<dyn Fn(Progress) + Send + Sync>>,
+    /// Proxy to connect to peers through
+    proxy: Option<ProxyConfig>,
+    /// Trackers told when the download completes
+    trackers: Vec<String>,
+    /// Port we accept peer connections on, reported to the trackers
+    port: u16,
+    /// Seeder that starts serving the torrent from the store once it's complete
+    seed: Option<(Arc<Seeder>, Arc<dyn PieceStore>)>,
+}
+
+impl Downloader {
+    pub fn new(info_hash: [u8; 20], info: Info, peers: Vec<SocketAddr>) -> Self {
+        Downloader {
+            info_hash,
+            info,
+            peers,
+            peer_id: generate_peer_id(),
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            endgame_threshold: DEFAULT_ENDGAME_THRESHOLD,
+            cancel: CancellationToken::new(),
+            deadline: None,
+            stall_timeout: None,
+            resume_path: None,
+            progress: None,
+            proxy: None,
+            trackers: Vec::new(),
+            port: 0,
+            seed: None,
+        }
+    }
+
+    /// Use a fixed peer ID instead of a freshly generated one
+    pub fn with_peer_id(mut self, peer_id: KsuidMs) -> Self {
+        self.peer_id = peer_id;
+        self
+    }
+
+    /// Set the maximum number of outstanding block requests per peer
+    ///
+    /// Below it, the number kept outstanding follows each peer's measured speed.
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight.max(1);
+        self
+    }
+
+    /// Start endgame mode once at most `threshold` pieces are missing
+    ///
+    /// In endgame mode a peer with nothing left to fetch also requests pieces
+    /// other peers are still downloading, so a slow peer can't stall the end
+    /// of the download. Whichever peer delivers a piece first wins, and the
+    /// others cancel their requests for it. A threshold of 0 disables it.
+    pub fn with_endgame_threshold(mut self, threshold: usize) -> Self {
+        self.endgame_threshold = threshold;
+        self
+    }
+
+    /// Stop the download when `cancel` is triggered
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Give up once the download has taken longer than `deadline`
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Drop a peer that takes longer than `timeout` to deliver a piece
+    ///
+    /// The piece goes back to the other peers.
+    pub fn with_stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = Some(timeout);
+        self
+    }
+
+    /// Save completed pieces to `path` if the download stops early, and skip
+    /// the pieces already saved there when starting
+    pub fn with_resume_file(mut self, path: PathBuf) -> Self {
+        self.resume_path = Some(path);
+        self
+    }
+
+    /// Connect to every peer through `proxy`
+    pub fn with_proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Send a `completed` announce to `trackers` when the download finishes
+    ///
+    /// `port` is the port we accept peer connections on; a bound seeder given
+    /// to `with_seeder` reports its own listening port instead. Nothing is sent
+    /// when every piece was already in the resume state.
+    pub fn with_trackers(mut self, trackers: Vec<String>, port: u16) -> Self {
+        self.trackers = trackers;
+        self.port = port;
+        self
+    }
+
+    /// Start seeding the torrent with `seeder` once the download completes
+    ///
+    /// `store` must read from the output the download writes to. The pieces
+    /// were verified as they arrived, so they aren't hashed again.
+    pub fn with_seeder(mut self, seeder: Arc<Seeder>, store: Arc<dyn PieceStore>) -> Self {
+        self.seed = Some((seeder, store));
+        self
+    }
+
+    /// Report the progress to `progress` after each verified piece is written
+    pub fn with_progress<F: Fn(Progress) + Send + Sync + 'static>(mut self, progress: F) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Download every piece, writing each verified piece at its offset in `output`
+    ///
+    /// One worker runs per peer. Pieces a worker fails to complete, because the
+    /// peer disconnected or sent bad data, are handed back to the other workers.
+    ///
+    /// On cancellation, workers finish the piece they are on, cancel their
+    /// outstanding requests and disconnect; everything received is written and
+    /// flushed and the resume state is saved before returning `Cancelled`.
+    /// Running out of time stops the workers the same way, except that they
+    /// drop their current piece, and returns `DownloadTimeout`.
+    pub fn download<W: Write + Seek>(
+        &self,
+        output: &mut W,
+    ) -> Result<DownloadOutcome, DownloadError> {
+        let num_pieces = self.info.num_pieces();
+        let mut resume = match &self.resume_path {
+            Some(path) => ResumeState::load(path, self.info_hash, num_pieces)?,
+            None => None,
+        }
+        .unwrap_or_else(|| ResumeState::new(self.info_hash, num_pieces));
+
+        let mut downloaded_bytes: u64 = resume
+            .completed
+            .pieces()
+            .map(|index| self.info.piece_size(index) as u64)
+            .sum();
+        let resumed_bytes = downloaded_bytes;
+        let already_complete = resume.completed.is_complete();
+
+        let queue = PieceQueue::remaining(&resume.completed, self.endgame_threshold);
+        let (sender, receiver) = mpsc::channel::<(usize, Vec<u8>)>();
+        let mut write_error = None;
+        let deadline = self.deadline.map(|deadline| Instant::now() + deadline);
+        let timed_out = AtomicBool::new(false);
+        let mut throughput = ThroughputEstimator::new();
+        let mut last_piece = Instant::now();
+
+        thread::scope(|scope| {
+            for &addr in &self.peers {
+                let sender = sender.clone();
+                let queue = &queue;
+                let timed_out = &timed_out;
+                scope.spawn(move || {
+                    if let Err(e) = self.run_peer(addr, queue, sender, deadline, timed_out) {
+                        log::warn!("Peer {} failed: {}", addr, e);
+                    }
+                });
+            }
+            drop(sender);
+
+            // The channel closes once every worker has finished
+            loop {
+                if self.cancel.is_cancelled() {
+                    queue.abort();
+                }
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    timed_out.store(true, Ordering::SeqCst);
+                    queue.abort();
+                }
+
+                let (index, data) = match receiver.recv_timeout(CANCEL_POLL_INTERVAL) {
+                    Ok(piece) => piece,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+
+                let offset = (index * self.info.plength) as u64;
+                let result = output
+                    .seek(SeekFrom::Start(offset))
+                    .and_then(|_| output.write_all(&data));
+                match result {
+                    Ok(()) => {
+                        resume.completed.set(index);
+                        downloaded_bytes += data.len() as u64;
+                        throughput.record(data.len() as u64, last_piece.elapsed());
+                        last_piece = Instant::now();
+                        if let Some(progress) = &self.progress {
+                            let total_bytes = self.info.total_length() as u64;
+                            let remaining = total_bytes.saturating_sub(downloaded_bytes);
+                            progress(Progress {
+                                completed_pieces: resume.completed.count(),
+                                total_pieces: num_pieces,
+                                downloaded_bytes,
+                                total_bytes,
+                                eta: (throughput.bytes_per_sec() > 0.0)
+                                    .then(|| throughput.eta(remaining)),
+                            });
+                        }
+                    }
+                    Err(e) => {
+                        write_error = Some(format!("Failed to write piece {}: {}", index, e));
+                        // Stop the workers; they cancel their outstanding requests
+                        queue.abort();
+                        break;
+                    }
+                }
+            }
+        });
+
+        output
+            .flush()
+            .map_err(|e| format!("Failed to flush output: {}", e))?;
+
+        if resume.completed.is_complete() {
+            if let Some(path) = &self.resume_path {
+                let _ = fs::remove_file(path);
+            }
+            if !already_complete {
+                self.announce_completed(downloaded_bytes - resumed_bytes);
+            }
+            if let Some((seeder, store)) = &self.seed {
+                seeder.add_torrent_with_data(self.info_hash, &self.info, store.clone(), false)?;
+            }
+            return Ok(DownloadOutcome::Complete);
+        }
+
+        if let Some(path) = &self.resume_path {
+            resume.save(path)?;
+        }
+        if let Some(e) = write_error {
+            return Err(e.into());
+        }
+        if self.cancel.is_cancelled() {
+            return Ok(DownloadOutcome::Cancelled);
+        }
+        if timed_out.load(Ordering::SeqCst) {
+            return Err(DownloadError::DownloadTimeout {
+                completed_pieces: resume.completed.count(),
+                total_pieces: num_pieces,
+            });
+        }
+
+        Err(format!(
+            "Download incomplete: {} of {} pieces",
+            resume.completed.count(),
+            num_pieces
+        )
+        .into())
+    }
+
+    /// Tell every tracker the download completed, so it counts us as a seeder
+    fn announce_completed(&self, downloaded: u64) {
+        let port = self
+            .seed
+            .as_ref()
+            .and_then(|(seeder, _)| seeder.listening_port())
+            .unwrap_or(self.port);
+        let request = AnnounceRequest {
+            info_hash: self.info_hash,
+            peer_id: peer_id_bytes(&self.peer_id),
+            port,
+            uploaded: 0,
+            downloaded,
+            left: 0,
+            event: Some(AnnounceEvent::Completed),
+            compact: true,
+            numwant: Some(0),
+            key: None,
+            tracker_id: None,
+        };
+        for tracker in self.trackers.iter().filter(|tracker| !tracker.is_empty()) {
+            if let Err(e) = announce_via(tracker, &request, self.proxy.as_ref()) {
+                log::warn!("Completed announce to {} failed: {}", tracker, e);
+            }
+        }
+    }
+
+    /// Download pieces from a single peer until nothing it has is left to fetch
+    fn run_peer(
+        &self,
+        addr: SocketAddr,
+        queue: &PieceQueue,
+        sender: mpsc::Sender<(usize, Vec<u8>)>,
+        deadline: Option<Instant>,
+        timed_out: &AtomicBool,
+    ) -> Result<(), String> {
+        let mut leecher = Leecher::connect_via(
+            addr,
+            self.info_hash,
+            self.peer_id,
+            self.info.num_pieces(),
+            self.proxy.as_ref(),
+        )?;
+        // Wake up to notice running out of time even when the peer goes silent
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if let Some(timeout) = [self.stall_timeout, remaining].into_iter().flatten().min() {
+            leecher.set_read_timeout(timeout.max(Duration::from_millis(1)))?;
+        }
+        leecher.interested()?;
+
+        let result = self.download_pieces(&mut leecher, queue, sender, deadline, timed_out);
+        queue.on_disconnect(addr);
+        // Don't leave the peer working on requests we no longer want
+        let _ = leecher.cancel_all();
+        if self.cancel.is_cancelled() {
+            let _ = leecher.send(&Message::NotInterested);
+        }
+        result
+    }
+
+    fn download_pieces(
+        &self,
+        leecher: &mut Leecher,
+        queue: &PieceQueue,
+        sender: mpsc::Sender<(usize, Vec<u8>)>,
+        deadline: Option<Instant>,
+        timed_out: &AtomicBool,
+    ) -> Result<(), String> {
+        let mut depth = RequestDepth::new(self.max_in_flight);
+        while !self.cancel.is_cancelled() {
+            for message in leecher.take_announced() {
+                queue.on_message(leecher.addr(), &message)?;
+            }
+            let Some(index) = queue.next_piece(leecher.bitfield()) else {
+                break;
+            };
+            let stall_at = self.stall_timeout.map(|timeout| Instant::now() + timeout);
+            let out_of_time = || {
+                [stall_at, deadline]
+                    .into_iter()
+                    .flatten()
+                    .any(|at| Instant::now() >= at)
+            };
+            let started = Instant::now();
+            let data = match leecher.download_piece_until(
+                index as u32,
+                self.info.piece_size(index),
+                depth.depth(),
+                || queue.is_done(index) || out_of_time(),
+            ) {
+                Ok(Some(data)) => {
+                    depth.record(data.len() as u64, started.elapsed());
+                    data
+                }
+                // Another peer delivered the piece first in endgame mode
+                Ok(None) if queue.is_done(index) => continue,
+                Ok(None) => {
+                    queue.requeue(index);
+                    timed_out.store(true, Ordering::SeqCst);
+                    return Err(format!("Timed out downloading piece {}", index));
+                }
+                Err(e) => {
+                    queue.requeue(index);
+                    if out_of_time() {
+                        timed_out.store(true, Ordering::SeqCst);
+                    }
+                    return Err(e);
+                }
+            };
+
+            if !verify_piece(&self.info, index, &data) {
+                queue.requeue(index);
+                return Err(format!("Piece {} failed hash verification", index));
+            }
+
+            if !queue.complete(index) {
+                continue;
+            }
+            if sender.send((index, data)).is_err() {
+                return Err("Download was abandoned".to_string());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Number of block requests to keep outstanding with one peer
+///
+/// Enough requests to cover `REQUEST_QUEUE_TIME` at the peer's measured speed
+/// keep the pipeline full, while a slow peer isn't handed more than it can
+/// deliver in that time.
+struct RequestDepth {
+    throughput: ThroughputEstimator,
+    max: usize,
+    depth: usize,
+}
+
+impl RequestDepth {
+    fn new(max: usize) -> Self {
+        RequestDepth {
+            throughput: ThroughputEstimator::new(),
+            max,
+            depth: INITIAL_IN_FLIGHT.min(max),
+        }
+    }
+
+    /// Record `bytes` received from the peer over `elapsed` and resize the pipeline
+    fn record(&mut self, bytes: u64, elapsed: Duration) {
+        self.throughput.record(bytes, elapsed);
+        let queued = self.throughput.bytes_per_sec() * REQUEST_QUEUE_TIME.as_secs_f64();
+        self.depth = ((queued / BLOCK_SIZE as f64).ceil() as usize).clamp(1, self.max);
+    }
+
+    /// Current number of requests to keep in flight
+    fn depth(&self) -> usize {
+        self.depth
+    }
+}
+
+/// Pieces still to be downloaded, shared between the peer workers
+struct PieceQueue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+struct QueueState {
+    /// Pieces nobody is working on yet
+    pending: VecDeque<usize>,
+    /// Pieces being downloaded, with the number of workers on each
+    in_progress: HashMap<usize, usize>,
+    /// Pieces downloaded and verified since the queue was created
+    done: HashSet<usize>,
+    /// Number of missing pieces at which pieces in progress are handed out again
+    endgame_threshold: usize,
+    /// Set when the download is shutting down
+    aborted: bool,
+    /// What the connected peers have, so the rarest pieces go first
+    availability: Availability,
+}
+
+impl QueueState {
+    fn in_endgame(&self) -> bool {
+        self.pending.len() + self.in_progress.len() <= self.endgame_threshold
+    }
+}
+
+impl PieceQueue {
+    #[cfg(test)]
+    fn new(num_pieces: usize) -> Self {
+        Self::remaining(&Bitfield::new(num_pieces), 0)
+    }
+
+    /// A queue of every piece not yet in `completed`
+    fn remaining(completed: &Bitfield, endgame_threshold: usize) -> Self {
+        PieceQueue {
+            state: Mutex::new(QueueState {
+                pending: (0..completed.num_pieces())
+                    .filter(|&index| !completed.has(index))
+                    .collect(),
+                in_progress: HashMap::new(),
+                done: HashSet::new(),
+                endgame_threshold,
+                aborted: false,
+                availability: Availability::new(completed.num_pieces()),
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Take the rarest pending piece the peer has
+    ///
+    /// Pieces no connected peer has announced go in queue order. In endgame mode, when no pending piece is left for the peer, the piece
+    /// in progress with the fewest workers is handed out again. Otherwise
+    /// waits while other workers still hold pieces that might be handed back,
+    /// and returns `None` once nothing this peer has can still be needed.
+    fn next_piece(&self, available: &Bitfield) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.aborted {
+                return None;
+            }
+            let candidates = state.pending.iter().copied().filter(|&i| available.has(i));
+            let rarest = state
+                .availability
+                .rarest(candidates)
+                .or_else(|| state.pending.iter().copied().find(|&i| available.has(i)));
+            if let Some(index) = rarest {
+                state.pending.retain(|&i| i != index);
+                state.in_progress.insert(index, 1);
+                return Some(index);
+            }
+            if state.in_endgame() {
+                let duplicate = state
+                    .in_progress
+                    .iter_mut()
+                    .filter(|(index, _)| available.has(**index))
+                    .min_by_key(|(index, workers)| (**workers, **index));
+                if let Some((&index, workers)) = duplicate {
+                    *workers += 1;
+                    return Some(index);
+                }
+            }
+            if state.in_progress.is_empty() {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Record a piece announcement from a connected peer
+    fn on_message(&self, peer: SocketAddr, message: &Message) -> Result<(), String> {
+        self.state
+            .lock()
+            .unwrap()
+            .availability
+            .on_message(peer, message)
+    }
+
+    /// Forget what a peer announced once its worker exits
+    fn on_disconnect(&self, peer: SocketAddr) {
+        self.state.lock().unwrap().availability.on_disconnect(peer);
+    }
+
+    /// Mark a piece as downloaded and verified
+    ///
+    /// Returns false if another worker already completed it in endgame mode,
+    /// in which case this copy must be discarded.
+    fn complete(&self, index: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let first = state.done.insert(index);
+        state.in_progress.remove(&index);
+        drop(state);
+        self.changed.notify_all();
+        first
+    }
+
+    /// Whether a piece has been completed, possibly by another worker
+    fn is_done(&self, index: usize) -> bool {
+        self.state.lock().unwrap().done.contains(&index)
+    }
+
+    /// Stop handing out pieces
+    fn abort(&self) {
+        self.state.lock().unwrap().aborted = true;
+        self.changed.notify_all();
+    }
+
+    /// Hand a piece back so another worker can download it
+    ///
+    /// A piece other workers are still downloading in endgame mode stays with them.
+    fn requeue(&self, index: usize) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(workers) = state.in_progress.get_mut(&index) {
+            *workers -= 1;
+            if *workers == 0 {
+                state.in_progress.remove(&index);
+                state.pending.push_back(index);
+            }
+        }
+        drop(state);
+        self.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use crate::peer::extension::ExtendedHandshake;
+    use crate::peer::handshake::Handshake;
+    use crate::peer::message::BlockRequest;
+    use crate::torrent::store::MemoryStore;
+    use crate::torrent::verify::hash_piece;
+
+    const PIECE_LENGTH: usize = 32 * 1024;
+
+    fn test_data() -> Vec<u8> {
+        (0..PIECE_LENGTH * 4 + 1000)
+            .map(|i| (i % 251) as u8)
+            .collect()
+    }
+
+    fn test_info(data: &[u8]) -> Info {
+        let pieces: Vec<u8> = data.chunks(PIECE_LENGTH).flat_map(hash_piece).collect();
+        Info {
+            name: "test.bin".to_string(),
+            plength: PIECE_LENGTH,
+            pieces: serde_bytes::ByteBuf::from(pieces),
+            length: Some(data.len()),
+            ..Default::default()
+        }
+    }
+
+    fn start_seeder(info_hash: [u8; 20], data: &[u8], pieces: &[usize]) -> SocketAddr {
+        let mut bitfield = Bitfield::new(data.len().div_ceil(PIECE_LENGTH));
+        for &index in pieces {
+            bitfield.set(index);
+        }
+
+        let mut seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        seeder.add_torrent_data(
+            info_hash,
+            Arc::new(MemoryStore::new(data.to_vec(), PIECE_LENGTH)),
+            bitfield,
+        );
+        let addr = seeder.bind().unwrap();
+        thread::spawn(move || seeder.listen());
+        addr
+    }
+
+    /// A peer that has every piece and unchokes, but never sends any data
+    ///
+    /// It keeps the connection alive and reports every message it receives.
+    /// With `reqq`, it advertises that request queue depth in an extended
+    /// handshake.
+    fn start_stalled_peer(
+        info_hash: [u8; 20],
+        num_pieces: usize,
+        reqq: Option<u32>,
+    ) -> (SocketAddr, mpsc::Receiver<Message>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            Handshake::receive(&mut stream).unwrap();
+            let handshake = Handshake::new(info_hash, generate_peer_id());
+            match reqq {
+                Some(reqq) => {
+                    handshake
+                        .with_extension_protocol()
+                        .send(&mut stream)
+                        .unwrap();
+                    let extended = ExtendedHandshake {
+                        reqq: Some(reqq),
+                        ..Default::default()
+                    };
+                    extended.to_message().unwrap().send(&mut stream).unwrap();
+                }
+                None => handshake.send(&mut stream).unwrap(),
+            }
+            Message::Bitfield(Bitfield::full(num_pieces).as_bytes().to_vec())
+                .send(&mut stream)
+                .unwrap();
+            Message::Unchoke.send(&mut stream).unwrap();
+
+            let mut keep_alive = stream.try_clone().unwrap();
+            thread::spawn(move || {
+                while Message::KeepAlive.send(&mut keep_alive).is_ok() {
+                    thread::sleep(Duration::from_millis(20));
+                }
+            });
+            while let Ok(Some(message)) = Message::try_receive(&mut stream) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        (addr, receiver)
+    }
+
+    #[test]
+    fn test_pipelines_requests_up_to_queue_depth() {
+        let info_hash = [15u8; 20];
+        let piece_length = 8 * BLOCK_SIZE as usize;
+        let info = Info {
+            name: "test.bin".to_string(),
+            plength: piece_length,
+            pieces: serde_bytes::ByteBuf::from(vec![0u8; 20]),
+            length: Some(piece_length),
+            ..Default::default()
+        };
+
+        // Our own limit, then a peer advertising a smaller one
+        for (reqq, expected) in [(None, 3), (Some(2), 2)] {
+            let (stalled, messages) = start_stalled_peer(info_hash, 1, reqq);
+            let result = Downloader::new(info_hash, info.clone(), vec![stalled])
+                .with_max_in_flight(3)
+                .with_deadline(Duration::from_millis(300))
+                .download(&mut Cursor::new(Vec::new()));
+            assert!(matches!(result, Err(DownloadError::DownloadTimeout { .. })));
+
+            // The peer never answers, so every request sent was in flight at once
+            let mut requests = Vec::new();
+            while let Ok(message) = messages.recv_timeout(Duration::from_millis(200)) {
+                if let Message::Request { begin, .. } = message {
+                    requests.push(begin);
+                }
+            }
+            let blocks: Vec<u32> = (0..expected).map(|i| i * BLOCK_SIZE).collect();
+            assert_eq!(requests, blocks);
+        }
+    }
+
+    #[test]
+    fn test_request_depth_follows_peer_speed() {
+        // A peer delivering a piece every 10ms can take more requests
+        let mut fast = RequestDepth::new(16);
+        assert_eq!(fast.depth(), INITIAL_IN_FLIGHT);
+        let mut depths = Vec::new();
+        for _ in 0..5 {
+            fast.record(PIECE_LENGTH as u64, Duration::from_millis(10));
+            depths.push(fast.depth());
+        }
+        assert!(depths[0] > INITIAL_IN_FLIGHT);
+        assert!(depths.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(fast.depth(), 16);
+
+        // One delivering 1 KiB/s gets a single request at a time
+        let mut slow = RequestDepth::new(16);
+        for _ in 0..5 {
+            slow.record(BLOCK_SIZE as u64, Duration::from_secs(16));
+            assert_eq!(slow.depth(), 1);
+        }
+    }
+
+    #[test]
+    fn test_endgame_cancels_redundant_requests() {
+        let data = test_data();
+        let info = test_info(&data);
+        let info_hash = [12u8; 20];
+
+        let (stalled, messages) = start_stalled_peer(info_hash, 5, None);
+
+        // The good peer only answers the handshake once the stalled peer has
+        // been asked for a piece, so that piece has to come from endgame mode
+        let seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        seeder.add_torrent_data(
+            info_hash,
+            Arc::new(MemoryStore::new(data.clone(), PIECE_LENGTH)),
+            Bitfield::full(5),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let good = listener.local_addr().unwrap();
+        let (requested_stalled, wait) = mpsc::channel();
+        thread::spawn(move || {
+            wait.recv().unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            seeder.handle_connection(stream)
+        });
+        // Ends once the stalled peer's connection is closed
+        let relay = thread::spawn(move || {
+            let mut received = Vec::new();
+            for message in messages {
+                if matches!(message, Message::Request { .. }) {
+                    let _ = requested_stalled.send(());
+                }
+                received.push(message);
+            }
+            received
+        });
+
+        let downloader =
+            Downloader::new(info_hash, info, vec![stalled, good]).with_endgame_threshold(5);
+        let mut output = Cursor::new(Vec::new());
+        let outcome = downloader.download(&mut output).unwrap();
+        assert_eq!(outcome, DownloadOutcome::Complete);
+        assert_eq!(output.into_inner(), data);
+
+        // Whatever the stalled peer was asked for came from the good peer
+        // instead, and every redundant request was cancelled
+        let messages = relay.join().unwrap();
+        let requested: HashSet<BlockRequest> = messages
+            .iter()
+            .filter_map(|message| match *message {
+                Message::Request {
+                    index,
+                    begin,
+                    length,
+                } => Some(BlockRequest {
+                    index,
+                    begin,
+                    length,
+                }),
+                _ => None,
+            })
+            .collect();
+        let cancelled: HashSet<BlockRequest> = messages
+            .iter()
+            .filter_map(|message| match *message {
+                Message::Cancel {
+                    index,
+                    begin,
+                    length,
+                } => Some(BlockRequest {
+                    index,
+                    begin,
+                    length,
+                }),
+                _ => None,
+            })
+            .collect();
+        assert!(!requested.is_empty());
+        assert_eq!(requested, cancelled);
+    }
+
+    #[test]
+    fn test_stalled_peer_times_out() {
+        let data = test_data();
+        let info = test_info(&data);
+        let info_hash = [13u8; 20];
+        let (stalled, _messages) = start_stalled_peer(info_hash, 5, None);
+
+        let started = Instant::now();
+        let result = Downloader::new(info_hash, info, vec![stalled])
+            .with_stall_timeout(Duration::from_millis(200))
+            .with_deadline(Duration::from_secs(10))
+            .download(&mut Cursor::new(Vec::new()));
+        assert_eq!(
+            result,
+            Err(DownloadError::DownloadTimeout {
+                completed_pieces: 0,
+                total_pieces: 5,
+            })
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_deadline_stops_download() {
+        let data = test_data();
+        let info = test_info(&data);
+        let info_hash = [14u8; 20];
+        let (stalled, _messages) = start_stalled_peer(info_hash, 5, None);
+
+        let started = Instant::now();
+        let result = Downloader::new(info_hash, info, vec![stalled])
+            .with_deadline(Duration::from_millis(300))
+            .download(&mut Cursor::new(Vec::new()));
+        assert!(matches!(
+            result,
+            Err(DownloadError::DownloadTimeout {
+                completed_pieces: 0,
+                ..
+            })
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_download_from_disjoint_seeders() {
+        let data = test_data();
+        let info = test_info(&data);
+        let info_hash = [7u8; 20];
+
+        let first = start_seeder(info_hash, &data, &[0, 2, 4]);
+        let second = start_seeder(info_hash, &data, &[1, 3]);
+
+        let downloader =
+            Downloader::new(info_hash, info, vec![first, second]).with_max_in_flight(3);
+        let mut output = Cursor::new(Vec::new());
+        let outcome = downloader.download(&mut output).unwrap();
+        assert_eq!(outcome, DownloadOutcome::Complete);
+
+        assert_eq!(output.into_inner(), data);
+    }
+
+    #[test]
+    fn test_progress_reported_per_piece() {
+        let data = test_data();
+        let info = test_info(&data);
+        let info_hash = [11u8; 20];
+        let seeder = start_seeder(info_hash, &data, &[0, 1, 2, 3, 4]);
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let recorded = reports.clone();
+        let downloader = Downloader::new(info_hash, info, vec![seeder])
+            .with_progress(move |progress| recorded.lock().unwrap().push(progress));
+        let outcome = downloader.download(&mut Cursor::new(Vec::new())).unwrap();
+        assert_eq!(outcome, DownloadOutcome::Complete);
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 5);
+        for (i, progress) in reports.iter().enumerate() {
+            assert_eq!(progress.completed_pieces, i + 1);
+            assert_eq!(progress.total_pieces, 5);
+            assert_eq!(progress.total_bytes, data.len() as u64);
+        }
+        assert!(
+            reports
+                .windows(2)
+                .all(|pair| pair[0].downloaded_bytes < pair[1].downloaded_bytes)
+        );
+        assert_eq!(reports[4].downloaded_bytes, data.len() as u64);
+    }
+
+    /// A tracker that answers every announce, reporting each request line
+    fn start_mock_tracker() -> (String, mpsc::Receiver<String>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = std::io::Read::read(&mut stream, &mut buffer).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..n]);
+                }
+
+                let request = String::from_utf8_lossy(&request);
+                let request_line = request.lines().next().unwrap_or_default().to_string();
+                stream
+                    .write_all(b"HTTP/1.0 200 OK\r\n\r\nd8:intervali1800e5:peers0:e")
+                    .unwrap();
+                if sender.send(request_line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        (url, receiver)
+    }
+
+    #[test]
+    fn test_completed_announce_sent_once_then_seeds() {
+        let data = test_data();
+        let info = test_info(&data);
+        let info_hash = [12u8; 20];
+        let peer = start_seeder(info_hash, &data, &[0, 1, 2, 3, 4]);
+        let (tracker, requests) = start_mock_tracker();
+
+        let mut seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        let seeding_port = seeder.bind().unwrap().port();
+        let seeder = Arc::new(seeder);
+        let store = Arc::new(MemoryStore::new(data.clone(), PIECE_LENGTH));
+        let downloader = Downloader::new(info_hash, info, vec![peer])
+            .with_trackers(vec![tracker], 51413)
+            .with_seeder(seeder.clone(), store);
+        let outcome = downloader.download(&mut Cursor::new(Vec::new())).unwrap();
+        assert_eq!(outcome, DownloadOutcome::Complete);
+
+        let request = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(request.contains("event=completed"));
+        assert!(request.contains("left=0"));
+        // The seeder's port, where peers can actually reach us
+        assert!(request.contains(&format!("port={}", seeding_port)));
+        assert!(requests.recv_timeout(Duration::from_millis(200)).is_err());
+        assert_eq!(seeder.info_hashes(), vec![info_hash]);
+    }
+
+    #[test]
+    fn test_download_requeues_from_failed_peer() {
+        let data = test_data();
+        let info = test_info(&data);
+        let info_hash = [8u8; 20];
+
+        let good = start_seeder(info_hash, &data, &[0, 1, 2, 3, 4]);
+        // A peer serving corrupted data gets dropped and its pieces re-queued
+        let mut corrupted = data.clone();
+        corrupted[0] ^= 0xFF;
+        let bad = start_seeder(info_hash, &corrupted, &[0, 1, 2, 3, 4]);
+
+        let downloader = Downloader::new(info_hash, info, vec![bad, good]);
+        let mut output = Cursor::new(Vec::new());
+        let outcome = downloader.download(&mut output).unwrap();
+        assert_eq!(outcome, DownloadOutcome::Complete);
+
+        assert_eq!(output.into_inner(), data);
+    }
+
+    #[test]
+    fn test_download_incomplete_without_peers() {
+        let data = test_data();
+        let info = test_info(&data);
+        let info_hash = [9u8; 20];
+
+        let partial = start_seeder(info_hash, &data, &[0, 1]);
+
+        let downloader = Downloader::new(info_hash, info, vec![partial]);
+        let mut output = Cursor::new(Vec::new());
+        let result = downloader.download(&mut output);
+
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Download incomplete")
+        );
+    }
+
+    /// An output that cancels the download as soon as the first piece is written
+    struct CancellingOutput {
+        inner: Cursor<Vec<u8>>,
+        cancel: CancellationToken,
+    }
+
+    impl Write for CancellingOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.cancel.cancel();
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    impl Seek for CancellingOutput {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_cancel_saves_resume_state() {
+        // Many small pieces, so the download is still running when cancelled
+        let data: Vec<u8> = (0..16 * 1024 * 256).map(|i| (i % 253) as u8).collect();
+        let pieces: Vec<u8> = data.chunks(16 * 1024).flat_map(hash_piece).collect();
+        let info = Info {
+            name: "test.bin".to_string(),
+            plength: 16 * 1024,
+            pieces: serde_bytes::ByteBuf::from(pieces),
+            length: Some(data.len()),
+            ..Default::default()
+        };
+        let info_hash = [10u8; 20];
+
+        let mut bitfield = Bitfield::full(256);
+        let mut seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        seeder.add_torrent_data(
+            info_hash,
+            Arc::new(MemoryStore::new(data.clone(), 16 * 1024)),
+            bitfield.clone(),
+        );
+        let addr = seeder.bind().unwrap();
+        thread::spawn(move || seeder.listen());
+
+        let resume_path =
+            std::env::temp_dir().join(format!("x402-download-{}.resume", std::process::id()));
+        let cancel = CancellationToken::new();
+        let downloader = Downloader::new(info_hash, info, vec![addr])
+            .with_cancellation(cancel.clone())
+            .with_resume_file(resume_path.clone());
+        let mut output = CancellingOutput {
+            inner: Cursor::new(Vec::new()),
+            cancel,
+        };
+
+        let outcome = downloader.download(&mut output).unwrap();
+        let resume = ResumeState::load(&resume_path, info_hash, 256);
+        let _ = fs::remove_file(&resume_path);
+
+        assert_eq!(outcome, DownloadOutcome::Cancelled);
+        let completed = resume.unwrap().expect("resume state was saved").completed;
+        assert!(completed.has(0));
+        assert!(!completed.is_complete());
+
+        // Every piece marked as completed was written
+        let written = output.inner.into_inner();
+        for index in completed.pieces() {
+            let range = index * 16 * 1024..(index + 1) * 16 * 1024;
+            assert_eq!(written[range.clone()], data[range]);
+            bitfield.clear(index);
+        }
+        assert!(bitfield.count() > 0);
+    }
+
+    #[test]
+    fn test_piece_queue_waits_for_requeue() {
+        let queue = PieceQueue::new(2);
+        let full = Bitfield::full(2);
+
+        assert_eq!(queue.next_piece(&full), Some(0));
+        assert_eq!(queue.next_piece(&full), Some(1));
+
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| queue.next_piece(&full));
+            thread::sleep(std::time::Duration::from_millis(20));
+            queue.complete(0);
+            queue.requeue(1);
+            assert_eq!(waiter.join().unwrap(), Some(1));
+        });
+
+        queue.complete(1);
+        assert_eq!(queue.next_piece(&full), None);
+    }
+
+    #[test]
+    fn test_piece_queue_endgame() {
+        let mut completed = Bitfield::new(4);
+        completed.set(0);
+        let queue = PieceQueue::remaining(&completed, 3);
+        let full = Bitfield::full(4);
+
+        assert_eq!(queue.next_piece(&full), Some(1));
+        assert_eq!(queue.next_piece(&full), Some(2));
+        // Three pieces are missing, so endgame starts after the last one...
+        assert_eq!(queue.next_piece(&full), Some(3));
+        // ...and then the pieces in progress again, fewest workers first
+        assert_eq!(queue.next_piece(&full), Some(1));
+        assert_eq!(queue.next_piece(&full), Some(2));
+
+        // The first worker to finish a piece wins
+        assert!(queue.complete(1));
+        assert!(queue.is_done(1));
+        assert!(!queue.complete(1));
+
+        assert_eq!(queue.next_piece(&full), Some(3));
+
+        // A failed duplicate doesn't put the piece back while another worker has it
+        queue.requeue(2);
+        assert!(queue.state.lock().unwrap().pending.is_empty());
+        queue.requeue(2);
+        assert_eq!(queue.state.lock().unwrap().pending, VecDeque::from([2]));
+    }
+
+    #[test]
+    fn test_piece_queue_rarest_first() {
+        let queue = PieceQueue::new(3);
+        let full = Bitfield::full(3);
+        let seeder = SocketAddr::from(([127, 0, 0, 1], 1));
+        let leecher = SocketAddr::from(([127, 0, 0, 1], 2));
+
+        queue.on_message(seeder, &Message::HaveAll).unwrap();
+        queue.on_message(leecher, &Message::Have(0)).unwrap();
+        queue.on_message(leecher, &Message::Have(2)).unwrap();
+        assert_eq!(queue.next_piece(&full), Some(1));
+
+        // Once the leecher is gone, every piece is as rare as the others
+        queue.on_disconnect(leecher);
+        assert_eq!(queue.next_piece(&full), Some(0));
+        assert_eq!(queue.next_piece(&full), Some(2));
+    }
+
+    #[test]
+    fn test_piece_queue_abort() {
+        let queue = PieceQueue::new(2);
+        queue.abort();
+        assert_eq!(queue.next_piece(&Bitfield::full(2)), None);
+    }
+}
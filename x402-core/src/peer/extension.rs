@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::peer::message::Message;
+
+/// Extended message ID of the Extension Protocol handshake (BEP 10)
+pub const EXTENDED_HANDSHAKE_ID: u8 = 0;
+
+/// The Extension Protocol handshake, sent right after the BitTorrent handshake
+/// when both sides set the extension bit
+///
+/// Only the keys we use are kept; others are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtendedHandshake {
+    /// Extension names mapped to the extended message IDs the sender expects
+    #[serde(default)]
+    pub m: BTreeMap<String, i64>,
+    /// Client name and version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub v: Option<String>,
+    /// Number of outstanding requests the sender queues without dropping any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reqq: Option<u32>,
+}
+
+impl ExtendedHandshake {
+    /// Parse the payload of an extended message with ID 0
+    pub fn from_payload(payload: &[u8]) -> Result<Self, String> {
+        serde_bencode::from_bytes(payload)
+            .map_err(|e| format!("Failed to parse extended handshake: {}", e))
+    }
+
+    /// Build the extended message carrying this handshake
+    pub fn to_message(&self) -> Result<Message, String> {
+        let payload = serde_bencode::to_bytes(self)
+            .map_err(|e| format!("Failed to encode extended handshake: {}", e))?;
+        Ok(Message::Extended {
+            id: EXTENDED_HANDSHAKE_ID,
+            payload,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_handshake_with_reqq() {
+        let payload = b"d1:md11:ut_metadatai3ee1:pi6881e4:reqqi250e1:v14:Transmission 4e";
+        let handshake = ExtendedHandshake::from_payload(payload).unwrap();
+        assert_eq!(handshake.reqq, Some(250));
+        assert_eq!(handshake.v.as_deref(), Some("Transmission 4"));
+        assert_eq!(handshake.m.get("ut_metadata"), Some(&3));
+
+        let message = handshake.to_message().unwrap();
+        let Message::Extended { id, payload } = message else {
+            panic!("expected an extended message");
+        };
+        assert_eq!(id, EXTENDED_HANDSHAKE_ID);
+        assert_eq!(
+            ExtendedHandshake::from_payload(&payload).unwrap(),
+            handshake
+        );
+
+        assert_eq!(
+            ExtendedHandshake::from_payload(b"de").unwrap(),
+            ExtendedHandshake::default()
+        );
+        assert!(ExtendedHandshake::from_payload(b"not bencode").is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use std::net::Ipv4Addr;
+
+use sha1::{Digest, Sha1};
+
+/// Number of allowed fast pieces offered to each peer
+pub const ALLOWED_FAST_COUNT: usize = 10;
+
+/// Compute the allowed fast set for a peer, as specified by BEP 6
+///
+/// The set only depends on the peer's /24 network and the info hash, so a
+/// peer can't collect extra free pieces by reconnecting from another address
+/// in the same network.
+pub fn allowed_fast_set(
+    ip: Ipv4Addr,
+    info_hash: &[u8; 20],
+    num_pieces: usize,
+    k: usize,
+) -> Vec<u32> {
+    let k = k.min(num_pieces);
+    let mut set = Vec::with_capacity(k);
+
+    let mut x = Vec::with_capacity(24);
+    x.extend_from_slice(&(u32::from(ip) & 0xFFFF_FF00).to_be_bytes());
+    x.extend_from_slice(info_hash);
+
+    while set.len() < k {
+        x = Sha1::digest(&x).to_vec();
+        for chunk in x.chunks_exact(4) {
+            if set.len() >= k {
+                break;
+            }
+            let y = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            let index = y % num_pieces as u32;
+            if !set.contains(&index) {
+                set.push(index);
+            }
+        }
+    }
+
+    set
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowed_fast_set() {
+        // Example from BEP 6
+        let ip = Ipv4Addr::new(80, 4, 4, 200);
+        let info_hash = [0xAA; 20];
+
+        assert_eq!(
+            allowed_fast_set(ip, &info_hash, 1313, 7),
+            vec![1059, 431, 808, 1217, 287, 376, 1188]
+        );
+        assert_eq!(
+            allowed_fast_set(ip, &info_hash, 1313, 9),
+            vec![1059, 431, 808, 1217, 287, 376, 1188, 353, 508]
+        );
+    }
+
+    #[test]
+    fn test_allowed_fast_set_same_network() {
+        let info_hash = [0xAA; 20];
+        let first = allowed_fast_set(Ipv4Addr::new(80, 4, 4, 200), &info_hash, 1313, 10);
+        let second = allowed_fast_set(Ipv4Addr::new(80, 4, 4, 1), &info_hash, 1313, 10);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_allowed_fast_set_small_torrent() {
+        let set = allowed_fast_set(Ipv4Addr::new(10, 0, 0, 1), &[1u8; 20], 3, 10);
+        let mut sorted = set.clone();
+        sorted.sort();
+        assert_eq!(sorted, vec![0, 1, 2]);
+    }
+}
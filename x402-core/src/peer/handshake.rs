@@ -1,18 +1,140 @@
+use std::fmt;
 use std::io::{Read, Write};
-use std::net::TcpStream;
 
 use svix_ksuid::{KsuidLike, KsuidMs};
 
 const PROTOCOL_STRING: &[u8] = b"BitTorrent protocol";
-const HANDSHAKE_LENGTH: usize = 68;
+/// Length of a handshake after the protocol string: reserved bytes, info hash and peer ID
+const HANDSHAKE_TAIL_LENGTH: usize = 48;
+/// Reserved byte and bit advertising the Extension Protocol (BEP 10)
+const EXTENSION_PROTOCOL_BYTE: usize = 5;
+const EXTENSION_PROTOCOL_BIT: u8 = 0x10;
+/// Reserved byte and bit advertising the Fast Extension (BEP 6)
+const FAST_EXTENSION_BYTE: usize = 7;
+const FAST_EXTENSION_BIT: u8 = 0x04;
+/// Reserved byte and bit advertising a DHT node (BEP 5)
+const DHT_BYTE: usize = 7;
+const DHT_BIT: u8 = 0x01;
+/// Reserved byte and bit advertising support for x402 payments
+///
+/// Byte 0 is not assigned by any BEP. Azureus uses its 0x80 bit and BitComet
+/// writes `e` (0x65) into it, so 0x08 is set by neither.
+const X402_BYTE: usize = 0;
+const X402_BIT: u8 = 0x08;
+
+/// Clients by the two-letter code of their Azureus-style peer IDs
+const CLIENT_CODES: &[(&str, &str)] = &[
+    ("AZ", "Vuze"),
+    ("BC", "BitComet"),
+    ("BT", "BitTorrent"),
+    ("DE", "Deluge"),
+    ("KT", "KTorrent"),
+    ("LT", "libtorrent (Rakshasa)"),
+    ("lt", "libtorrent (Rasterbar)"),
+    ("qB", "qBittorrent"),
+    ("TR", "Transmission"),
+    ("TX", "Tixati"),
+    ("UM", "µTorrent Mac"),
+    ("UT", "µTorrent"),
+];
+
+/// Errors from a handshake exchange that callers may want to tell apart
+#[derive(Debug, Clone, PartialEq)]
+pub enum HandshakeError {
+    /// The remote answered with a different peer ID than the one we expected
+    PeerIdMismatch {
+        expected: [u8; 20],
+        actual: [u8; 20],
+    },
+    /// Any other failure: I/O, malformed handshake or wrong info hash
+    Other(String),
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::PeerIdMismatch { expected, actual } => write!(
+                f,
+                "Peer ID mismatch in handshake response: expected {}, got {}",
+                hex::encode(expected),
+                hex::encode(actual)
+            ),
+            HandshakeError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for HandshakeError {
+    fn from(message: String) -> Self {
+        HandshakeError::Other(message)
+    }
+}
+
+impl From<HandshakeError> for String {
+    fn from(error: HandshakeError) -> Self {
+        error.to_string()
+    }
+}
+
+/// Protocol extensions advertised in the handshake's reserved bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The Fast Extension (BEP 6)
+    pub fast_extension: bool,
+    /// The Extension Protocol (BEP 10)
+    pub extension_protocol: bool,
+    /// Paying for blocks with x402
+    pub x402: bool,
+    /// Running a DHT node, whose port is sent in a Port message (BEP 5)
+    pub dht: bool,
+}
+
+impl Capabilities {
+    /// Read the capabilities advertised in a handshake's reserved bytes
+    pub fn from_reserved(reserved: &[u8; 8]) -> Self {
+        Capabilities {
+            fast_extension: reserved[FAST_EXTENSION_BYTE] & FAST_EXTENSION_BIT != 0,
+            extension_protocol: reserved[EXTENSION_PROTOCOL_BYTE] & EXTENSION_PROTOCOL_BIT != 0,
+            x402: reserved[X402_BYTE] & X402_BIT != 0,
+            dht: reserved[DHT_BYTE] & DHT_BIT != 0,
+        }
+    }
+
+    /// Reserved bytes advertising exactly these capabilities
+    pub fn to_reserved(self) -> [u8; 8] {
+        let mut reserved = [0u8; 8];
+        if self.fast_extension {
+            reserved[FAST_EXTENSION_BYTE] |= FAST_EXTENSION_BIT;
+        }
+        if self.extension_protocol {
+            reserved[EXTENSION_PROTOCOL_BYTE] |= EXTENSION_PROTOCOL_BIT;
+        }
+        if self.x402 {
+            reserved[X402_BYTE] |= X402_BIT;
+        }
+        if self.dht {
+            reserved[DHT_BYTE] |= DHT_BIT;
+        }
+        reserved
+    }
+
+    /// The capabilities both sides advertised, which are the ones a connection uses
+    pub fn intersect(self, other: Capabilities) -> Self {
+        Capabilities {
+            fast_extension: self.fast_extension && other.fast_extension,
+            extension_protocol: self.extension_protocol && other.extension_protocol,
+            x402: self.x402 && other.x402,
+            dht: self.dht && other.dht,
+        }
+    }
+}
 
 /// Represents a BitTorrent handshake message
 #[derive(Debug, Clone, PartialEq)]
 pub struct Handshake {
-    /// Protocol string length (always 19 for BitTorrent)
-    pstrlen: u8,
-    /// Protocol string (always "BitTorrent protocol")
-    pstr: [u8; 19],
+    /// Protocol string, "BitTorrent protocol" unless overridden; its
+    /// length is sent as the leading `pstrlen` byte
+    pstr: Vec<u8>,
     /// 8 reserved bytes for extensions
     pub reserved: [u8; 8],
     /// 20-byte SHA1 hash of the info dictionary
@@ -24,12 +146,8 @@ pub struct Handshake {
 impl Handshake {
     /// Create a new handshake message
     pub fn new(info_hash: [u8; 20], peer_id: KsuidMs) -> Self {
-        let mut pstr = [0u8; 19];
-        pstr.copy_from_slice(PROTOCOL_STRING);
-
         Handshake {
-            pstrlen: 19,
-            pstr,
+            pstr: PROTOCOL_STRING.to_vec(),
             reserved: [0u8; 8],
             info_hash,
             peer_id,
@@ -54,101 +172,224 @@ impl Handshake {
         Ok(Self::new(info_hash, peer_id))
     }
 
+    /// Use a protocol string other than "BitTorrent protocol", e.g. for a fork
+    ///
+    /// The string must be 1 to 255 bytes long so its length fits `pstrlen`.
+    pub fn with_protocol(mut self, protocol: &[u8]) -> Result<Self, String> {
+        if protocol.is_empty() || protocol.len() > u8::MAX as usize {
+            return Err(format!(
+                "Invalid protocol string length: {}",
+                protocol.len()
+            ));
+        }
+        self.pstr = protocol.to_vec();
+        Ok(self)
+    }
+
+    /// The protocol string sent in the handshake
+    pub fn protocol(&self) -> &[u8] {
+        &self.pstr
+    }
+
     /// Serialize the handshake to bytes
     pub fn serialize(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(HANDSHAKE_LENGTH);
-        buf.push(self.pstrlen);
+        let mut buf = Vec::with_capacity(1 + self.pstr.len() + HANDSHAKE_TAIL_LENGTH);
+        buf.push(self.pstr.len() as u8);
         buf.extend_from_slice(&self.pstr);
         buf.extend_from_slice(&self.reserved);
         buf.extend_from_slice(&self.info_hash);
-        buf.extend_from_slice(&self.peer_id.bytes().as_ref());
+        buf.extend_from_slice(self.peer_id.bytes().as_ref());
         buf
     }
 
     /// Deserialize a handshake from bytes
+    ///
+    /// Bytes after the handshake are ignored; use `deserialize_from` to find
+    /// out where they start.
     pub fn deserialize(data: &[u8]) -> Result<Self, String> {
-        if data.len() < HANDSHAKE_LENGTH {
+        Self::deserialize_from(data).map(|(handshake, _)| handshake)
+    }
+
+    /// Deserialize a handshake from the start of a buffer that may hold more data
+    ///
+    /// Returns the handshake and the number of bytes it took, so whatever
+    /// follows, like the first message, can be read from the rest.
+    pub fn deserialize_from(data: &[u8]) -> Result<(Self, usize), String> {
+        Self::deserialize_from_protocol(data, PROTOCOL_STRING)
+    }
+
+    /// Deserialize a handshake like `deserialize_from`, expecting a custom
+    /// protocol string instead of "BitTorrent protocol"
+    pub fn deserialize_from_protocol(
+        data: &[u8],
+        protocol: &[u8],
+    ) -> Result<(Self, usize), String> {
+        let Some(&pstrlen) = data.first() else {
+            return Err("Handshake too short: empty".to_string());
+        };
+        if pstrlen == 0 {
+            return Err(format!("Invalid protocol string length: {}", pstrlen));
+        }
+
+        let pstr_end = 1 + pstrlen as usize;
+        let length = pstr_end + HANDSHAKE_TAIL_LENGTH;
+        if data.len() < length {
             return Err(format!(
                 "Handshake too short: expected {}, got {}",
-                HANDSHAKE_LENGTH,
+                length,
                 data.len()
             ));
         }
 
-        let pstrlen = data[0];
-        if pstrlen != 19 {
-            return Err(format!("Invalid protocol string length: {}", pstrlen));
-        }
-
-        let mut pstr = [0u8; 19];
-        pstr.copy_from_slice(&data[1..20]);
-
-        if &pstr != PROTOCOL_STRING {
+        let pstr = data[1..pstr_end].to_vec();
+        if pstr != protocol {
             return Err("Invalid protocol string".to_string());
         }
 
         let mut reserved = [0u8; 8];
-        reserved.copy_from_slice(&data[20..28]);
+        reserved.copy_from_slice(&data[pstr_end..pstr_end + 8]);
 
         let mut info_hash = [0u8; 20];
-        info_hash.copy_from_slice(&data[28..48]);
+        info_hash.copy_from_slice(&data[pstr_end + 8..pstr_end + 28]);
 
         let mut peer_id_bytes = [0u8; 20];
-        peer_id_bytes.copy_from_slice(&data[48..68]);
+        peer_id_bytes.copy_from_slice(&data[pstr_end + 28..length]);
 
         let peer_id = KsuidMs::from_bytes(peer_id_bytes);
 
-        Ok(Handshake {
-            pstrlen,
+        let handshake = Handshake {
             pstr,
             reserved,
             info_hash,
             peer_id,
-        })
+        };
+        Ok((handshake, length))
+    }
+
+    /// Replace the reserved bytes, e.g. with `Capabilities::to_reserved`
+    pub fn with_reserved(mut self, reserved: [u8; 8]) -> Self {
+        self.reserved = reserved;
+        self
+    }
+
+    /// Capabilities the sender advertised in its reserved bytes
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::from_reserved(&self.reserved)
+    }
+
+    /// Advertise support for the Fast Extension
+    pub fn with_fast_extension(mut self) -> Self {
+        self.reserved[FAST_EXTENSION_BYTE] |= FAST_EXTENSION_BIT;
+        self
+    }
+
+    /// Whether the sender supports the Fast Extension
+    pub fn supports_fast_extension(&self) -> bool {
+        self.reserved[FAST_EXTENSION_BYTE] & FAST_EXTENSION_BIT != 0
+    }
+
+    /// Advertise support for the Extension Protocol
+    pub fn with_extension_protocol(mut self) -> Self {
+        self.reserved[EXTENSION_PROTOCOL_BYTE] |= EXTENSION_PROTOCOL_BIT;
+        self
+    }
+
+    /// Whether the sender supports the Extension Protocol
+    pub fn supports_extension_protocol(&self) -> bool {
+        self.reserved[EXTENSION_PROTOCOL_BYTE] & EXTENSION_PROTOCOL_BIT != 0
+    }
+
+    /// Advertise support for paying for blocks with x402
+    pub fn enable_x402(mut self) -> Self {
+        self.reserved[X402_BYTE] |= X402_BIT;
+        self
+    }
+
+    /// Whether the sender supports paying for blocks with x402
+    pub fn supports_x402(&self) -> bool {
+        self.reserved[X402_BYTE] & X402_BIT != 0
     }
 
     pub fn peer_id_hex(&self) -> String {
         hex::encode(self.peer_id.bytes())
     }
 
-    /// Send handshake over a TCP stream
-    pub fn send(&self, stream: &mut TcpStream) -> std::io::Result<()> {
+    /// Send handshake over a stream
+    pub fn send<W: Write>(&self, stream: &mut W) -> std::io::Result<()> {
         let data = self.serialize();
         stream.write_all(&data)?;
         stream.flush()?;
         Ok(())
     }
 
-    /// Receive handshake from a TCP stream
-    pub fn receive(stream: &mut TcpStream) -> Result<Self, String> {
-        let mut buf = [0u8; HANDSHAKE_LENGTH];
+    /// Receive handshake from a stream
+    pub fn receive<R: Read>(stream: &mut R) -> Result<Self, String> {
+        Self::receive_protocol(stream, PROTOCOL_STRING)
+    }
+
+    /// Receive a handshake that uses a custom protocol string
+    ///
+    /// Reads exactly as many bytes as the sender's `pstrlen` announces.
+    pub fn receive_protocol<R: Read>(stream: &mut R, protocol: &[u8]) -> Result<Self, String> {
+        let mut pstrlen = [0u8; 1];
         stream
-            .read_exact(&mut buf)
+            .read_exact(&mut pstrlen)
             .map_err(|e| format!("Failed to read handshake: {}", e))?;
-        Self::deserialize(&buf)
+
+        let mut buf = vec![0u8; 1 + pstrlen[0] as usize + HANDSHAKE_TAIL_LENGTH];
+        buf[0] = pstrlen[0];
+        stream
+            .read_exact(&mut buf[1..])
+            .map_err(|e| format!("Failed to read handshake: {}", e))?;
+        Self::deserialize_from_protocol(&buf, protocol).map(|(handshake, _)| handshake)
     }
 
     /// Perform a complete handshake exchange (send then receive)
-    pub fn exchange(
-        stream: &mut TcpStream,
+    pub fn exchange<S: Read + Write>(
+        stream: &mut S,
         info_hash: [u8; 20],
         peer_id: KsuidMs,
     ) -> Result<Self, String> {
-        let handshake = Self::new(info_hash, peer_id);
-        handshake
-            .send(stream)
+        Self::new(info_hash, peer_id).exchange_on(stream)
+    }
+
+    /// Send this handshake and receive the remote's, which must be for the same torrent
+    pub fn exchange_on<S: Read + Write>(&self, stream: &mut S) -> Result<Self, String> {
+        self.send(stream)
             .map_err(|e| format!("Failed to send handshake: {}", e))?;
 
         let response = Self::receive(stream)?;
 
         // Verify the info hash matches
-        if response.info_hash != info_hash {
+        if response.info_hash != self.info_hash {
             return Err("Info hash mismatch in handshake response".to_string());
         }
 
         Ok(response)
     }
 
+    /// Perform a handshake exchange, also checking the remote's peer ID
+    ///
+    /// Use this when the peer ID is known in advance, e.g. from a tracker, so
+    /// a different client answering on that address is rejected.
+    pub fn exchange_expecting<S: Read + Write>(
+        stream: &mut S,
+        info_hash: [u8; 20],
+        peer_id: KsuidMs,
+        expected_peer_id: Option<[u8; 20]>,
+    ) -> Result<Self, HandshakeError> {
+        let response = Self::exchange(stream, info_hash, peer_id)?;
+
+        if let Some(expected) = expected_peer_id {
+            let actual = *response.peer_id.bytes();
+            if actual != expected {
+                return Err(HandshakeError::PeerIdMismatch { expected, actual });
+            }
+        }
+
+        Ok(response)
+    }
+
     /// Get the info hash as a hex string
     pub fn info_hash_hex(&self) -> String {
         self.info_hash
@@ -163,9 +404,144 @@ pub fn generate_peer_id() -> KsuidMs {
     svix_ksuid::KsuidMs::new(None, None)
 }
 
+/// The raw 20 bytes of a peer ID, as sent to trackers
+pub fn peer_id_bytes(peer_id: &KsuidMs) -> [u8; 20] {
+    let mut bytes = [0u8; 20];
+    bytes.copy_from_slice(peer_id.bytes());
+    bytes
+}
+
+/// Name and version of the client that generated an Azureus-style peer ID
+///
+/// These look like `-qB4620-` followed by random bytes; the four characters
+/// after the client code are read as version digits. Other peer IDs, like
+/// our own KSUIDs, return `None`.
+pub fn identify_client(peer_id: &[u8; 20]) -> Option<String> {
+    if peer_id[0] != b'-' || peer_id[7] != b'-' {
+        return None;
+    }
+    let code = std::str::from_utf8(&peer_id[1..3]).ok()?;
+    let version = std::str::from_utf8(&peer_id[3..7]).ok()?;
+    if !code.chars().all(|c| c.is_ascii_alphabetic())
+        || !version.chars().all(|c| c.is_ascii_alphanumeric())
+    {
+        return None;
+    }
+
+    let version = version.trim_end_matches('0');
+    let version = if version.is_empty() { "0" } else { version };
+    let version: Vec<String> = version.chars().map(String::from).collect();
+    let name = CLIENT_CODES
+        .iter()
+        .find(|(known, _)| *known == code)
+        .map_or_else(
+            || format!("Unknown client {}", code),
+            |(_, name)| name.to_string(),
+        );
+    Some(format!("{} {}", name, version.join(".")))
+}
+
+/// Parse a peer ID from a 40-character hex string
+pub fn parse_peer_id_hex(peer_id_hex: &str) -> Result<KsuidMs, String> {
+    if peer_id_hex.len() != 40 {
+        return Err(format!(
+            "Invalid peer ID length: expected 40, got {}",
+            peer_id_hex.len()
+        ));
+    }
+
+    let bytes = hex::decode(peer_id_hex).map_err(|e| format!("Invalid hex in peer ID: {}", e))?;
+    let mut peer_id = [0u8; 20];
+    peer_id.copy_from_slice(&bytes);
+
+    Ok(KsuidMs::from_bytes(peer_id))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::peer::message::Message;
+    use std::io::Cursor;
+
+    /// Length of a handshake with the standard protocol string
+    const HANDSHAKE_LENGTH: usize = 68;
+
+    /// A stream that replays canned input and records what is written
+    struct MockStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl MockStream {
+        fn new(input: Vec<u8>) -> Self {
+            MockStream {
+                input: Cursor::new(input),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_exchange_expecting_peer_id_mismatch() {
+        let info_hash = [1u8; 20];
+        let remote = KsuidMs::from_bytes([2u8; 20]);
+        let mut stream = MockStream::new(Handshake::new(info_hash, remote).serialize());
+
+        let result = Handshake::exchange_expecting(
+            &mut stream,
+            info_hash,
+            KsuidMs::new(None, None),
+            Some([3u8; 20]),
+        );
+
+        assert_eq!(
+            result.unwrap_err(),
+            HandshakeError::PeerIdMismatch {
+                expected: [3u8; 20],
+                actual: [2u8; 20],
+            }
+        );
+        // Our handshake was still sent
+        assert_eq!(stream.output.len(), HANDSHAKE_LENGTH);
+    }
+
+    #[test]
+    fn test_exchange_expecting_peer_id_match() {
+        let info_hash = [1u8; 20];
+        let remote = KsuidMs::from_bytes([2u8; 20]);
+        let mut stream = MockStream::new(Handshake::new(info_hash, remote).serialize());
+
+        let response = Handshake::exchange_expecting(
+            &mut stream,
+            info_hash,
+            KsuidMs::new(None, None),
+            Some([2u8; 20]),
+        )
+        .unwrap();
+        assert_eq!(response.peer_id, remote);
+
+        // Without an expected peer ID any remote is accepted
+        let mut stream = MockStream::new(Handshake::new(info_hash, remote).serialize());
+        let result =
+            Handshake::exchange_expecting(&mut stream, info_hash, KsuidMs::new(None, None), None);
+        assert!(result.is_ok());
+    }
 
     #[test]
     fn test_handshake_new() {
@@ -173,8 +549,7 @@ mod tests {
         let peer_id = KsuidMs::new(None, None);
         let handshake = Handshake::new(info_hash, peer_id);
 
-        assert_eq!(handshake.pstrlen, 19);
-        assert_eq!(&handshake.pstr, PROTOCOL_STRING);
+        assert_eq!(handshake.protocol(), PROTOCOL_STRING);
         assert_eq!(handshake.info_hash, info_hash);
         assert_eq!(handshake.peer_id, peer_id);
     }
@@ -192,6 +567,91 @@ mod tests {
         assert_eq!(handshake, deserialized);
     }
 
+    #[test]
+    fn test_fast_extension_bit() {
+        let handshake = Handshake::new([1u8; 20], KsuidMs::new(None, None));
+        assert!(!handshake.supports_fast_extension());
+
+        let handshake = handshake.with_fast_extension();
+        assert_eq!(handshake.reserved, [0, 0, 0, 0, 0, 0, 0, 0x04]);
+
+        let deserialized = Handshake::deserialize(&handshake.serialize()).unwrap();
+        assert!(deserialized.supports_fast_extension());
+    }
+
+    #[test]
+    fn test_extension_protocol_bit() {
+        let handshake = Handshake::new([1u8; 20], KsuidMs::new(None, None));
+        assert!(!handshake.supports_extension_protocol());
+
+        let handshake = handshake.with_extension_protocol();
+        assert_eq!(handshake.reserved, [0, 0, 0, 0, 0, 0x10, 0, 0]);
+
+        let deserialized = Handshake::deserialize(&handshake.serialize()).unwrap();
+        assert!(deserialized.supports_extension_protocol());
+    }
+
+    #[test]
+    fn test_capabilities_intersection() {
+        let ours = Capabilities {
+            fast_extension: true,
+            extension_protocol: false,
+            x402: true,
+            dht: false,
+        };
+        let handshake =
+            Handshake::new([1u8; 20], KsuidMs::new(None, None)).with_reserved(ours.to_reserved());
+        assert_eq!(handshake.reserved, [0x08, 0, 0, 0, 0, 0, 0, 0x04]);
+        assert_eq!(handshake.capabilities(), ours);
+
+        // A peer with the Extension Protocol and x402 but no Fast Extension;
+        // bits we don't know about are ignored
+        let mut reserved = [0u8; 8];
+        reserved[0] = 0x80 | X402_BIT;
+        reserved[5] = EXTENSION_PROTOCOL_BIT;
+        let theirs = Capabilities::from_reserved(&reserved);
+        assert_eq!(
+            theirs,
+            Capabilities {
+                fast_extension: false,
+                extension_protocol: true,
+                x402: true,
+                dht: false,
+            }
+        );
+        assert_eq!(
+            ours.intersect(theirs),
+            Capabilities {
+                x402: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(theirs.intersect(ours), ours.intersect(theirs));
+        assert_eq!(
+            ours.intersect(Capabilities::default()),
+            Capabilities::default()
+        );
+    }
+
+    #[test]
+    fn test_x402_bit() {
+        let handshake = Handshake::new([1u8; 20], KsuidMs::new(None, None));
+        assert!(!handshake.supports_x402());
+
+        let handshake = handshake.enable_x402().with_fast_extension();
+        assert_eq!(handshake.reserved, [0x08, 0, 0, 0, 0, 0, 0, 0x04]);
+
+        let deserialized = Handshake::deserialize(&handshake.serialize()).unwrap();
+        assert!(deserialized.supports_x402());
+        assert!(deserialized.supports_fast_extension());
+
+        // The BitComet signature in byte 0 doesn't advertise x402
+        let mut bitcomet = Handshake::new([1u8; 20], KsuidMs::new(None, None));
+        bitcomet.reserved[0] = b'e';
+        bitcomet.reserved[1] = b'x';
+        assert!(!bitcomet.supports_x402());
+    }
+
     #[test]
     fn test_handshake_from_hex() {
         let hex = "d2474e86c95b19b8bcfdb92bc12c9d44667cfa36";
@@ -208,6 +668,34 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_parse_peer_id_hex() {
+        let hex = "0102030405060708090a0b0c0d0e0f1011121314";
+        let peer_id = parse_peer_id_hex(hex).unwrap();
+        assert_eq!(hex::encode(peer_id.bytes()), hex);
+
+        assert!(parse_peer_id_hex("0102").is_err());
+        assert!(parse_peer_id_hex(&"zz".repeat(20)).is_err());
+    }
+
+    #[test]
+    fn test_handshake_deserialize_from_with_trailing_data() {
+        let handshake = Handshake::new([1u8; 20], KsuidMs::new(None, None));
+        let mut data = handshake.serialize();
+        let message = Message::Have(7).serialize();
+        data.extend_from_slice(&message);
+
+        let (deserialized, consumed) = Handshake::deserialize_from(&data).unwrap();
+        assert_eq!(deserialized, handshake);
+        assert_eq!(consumed, HANDSHAKE_LENGTH);
+        assert_eq!(&data[consumed..], message.as_slice());
+        let mut rest = &data[consumed..];
+        assert_eq!(Message::receive(&mut rest).unwrap(), Message::Have(7));
+
+        // The plain form ignores the trailing bytes
+        assert_eq!(Handshake::deserialize(&data).unwrap(), handshake);
+    }
+
     #[test]
     fn test_handshake_deserialize_invalid_length() {
         let short_data = vec![0u8; 50];
@@ -223,4 +711,62 @@ mod tests {
         let result = Handshake::deserialize(&data);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_identify_client() {
+        let peer_id = |prefix: &[u8]| {
+            let mut peer_id = [b'x'; 20];
+            peer_id[..prefix.len()].copy_from_slice(prefix);
+            peer_id
+        };
+        assert_eq!(
+            identify_client(&peer_id(b"-qB4620-")),
+            Some("qBittorrent 4.6.2".to_string())
+        );
+        assert_eq!(
+            identify_client(&peer_id(b"-ZZ1000-")),
+            Some("Unknown client ZZ 1".to_string())
+        );
+        assert_eq!(identify_client(&peer_id(b"M7-2-0--")), None);
+        assert_eq!(identify_client(&peer_id_bytes(&generate_peer_id())), None);
+    }
+
+    #[test]
+    fn test_handshake_custom_protocol_round_trip() {
+        let handshake = Handshake::new([3u8; 20], KsuidMs::new(None, None))
+            .with_protocol(b"x402 protocol")
+            .unwrap()
+            .enable_x402();
+        let data = handshake.serialize();
+        assert_eq!(data[0], 13);
+        assert_eq!(data.len(), 1 + 13 + 48);
+
+        let (deserialized, consumed) =
+            Handshake::deserialize_from_protocol(&data, b"x402 protocol").unwrap();
+        assert_eq!(deserialized, handshake);
+        assert_eq!(consumed, data.len());
+        assert!(deserialized.supports_x402());
+
+        let mut stream = Cursor::new(data.clone());
+        let received = Handshake::receive_protocol(&mut stream, b"x402 protocol").unwrap();
+        assert_eq!(received, handshake);
+
+        // Peers expecting the standard string reject it
+        assert!(Handshake::deserialize(&data).is_err());
+        assert!(Handshake::receive(&mut Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn test_handshake_protocol_length_limits() {
+        let handshake = Handshake::new([3u8; 20], KsuidMs::new(None, None));
+        assert!(handshake.clone().with_protocol(b"").is_err());
+        assert!(handshake.clone().with_protocol(&[b'a'; 256]).is_err());
+
+        let long = handshake.with_protocol(&[b'a'; 255]).unwrap();
+        let data = long.serialize();
+        assert_eq!(data[0], 255);
+        let (deserialized, _) = Handshake::deserialize_from_protocol(&data, &[b'a'; 255]).unwrap();
+        assert_eq!(deserialized, long);
+        assert!(Handshake::deserialize_from_protocol(&data[..100], &[b'a'; 255]).is_err());
+    }
 }
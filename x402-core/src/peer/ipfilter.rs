@@ -0,0 +1,133 @@
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+
+/// Access levels below this block the range in `ipfilter.dat` files
+const BLOCK_LEVEL: u32 = 128;
+
+/// An inclusive range of IP addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    pub start: IpAddr,
+    pub end: IpAddr,
+}
+
+impl IpRange {
+    pub fn new(start: IpAddr, end: IpAddr) -> Self {
+        IpRange { start, end }
+    }
+
+    /// Whether `ip` lies in the range
+    ///
+    /// IPv4-mapped IPv6 addresses match IPv4 ranges, so filters also apply
+    /// to peers accepted on a dual-stack listener.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        match (self.start, self.end, ip) {
+            (IpAddr::V4(start), IpAddr::V4(end), IpAddr::V4(ip)) => start <= ip && ip <= end,
+            (IpAddr::V6(start), IpAddr::V6(end), IpAddr::V6(ip)) => start <= ip && ip <= end,
+            _ => false,
+        }
+    }
+}
+
+/// Parse the ranges blocked by an `ipfilter.dat` style list
+///
+/// Each line reads `start - end , level , description`, where IPv4
+/// addresses may be zero-padded (`001.002.003.000`). Ranges with a level of
+/// 128 or more are allowed rather than blocked and are skipped; the level and
+/// description are optional. Blank lines and lines starting with `#` or `//`
+/// are ignored.
+pub fn parse_ipfilter(contents: &str) -> Result<Vec<IpRange>, String> {
+    let mut ranges = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+            continue;
+        }
+        let error = |e: String| format!("Invalid IP filter line {}: {}", number + 1, e);
+
+        let mut fields = line.splitn(3, ',').map(str::trim);
+        let range = fields.next().unwrap_or_default();
+        if let Some(level) = fields.next() {
+            let level: u32 = level
+                .parse()
+                .map_err(|_| error(format!("bad access level '{}'", level)))?;
+            if level >= BLOCK_LEVEL {
+                continue;
+            }
+        }
+
+        let (start, end) = range
+            .split_once(" - ")
+            .or_else(|| range.split_once('-'))
+            .ok_or_else(|| error(format!("expected 'start - end', got '{}'", range)))?;
+        let start = parse_ip(start.trim()).map_err(error)?;
+        let end = parse_ip(end.trim()).map_err(error)?;
+        if start.is_ipv4() != end.is_ipv4() || start > end {
+            return Err(error(format!("bad range {} - {}", start, end)));
+        }
+        ranges.push(IpRange::new(start, end));
+    }
+    Ok(ranges)
+}
+
+/// Read and parse an `ipfilter.dat` style file
+pub fn load_ipfilter(path: &Path) -> Result<Vec<IpRange>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read IP filter {}: {}", path.display(), e))?;
+    parse_ipfilter(&contents)
+}
+
+/// Parse an address, accepting zero-padded IPv4 octets
+fn parse_ip(text: &str) -> Result<IpAddr, String> {
+    if let Ok(ip) = text.parse() {
+        return Ok(ip);
+    }
+    let octets: Vec<u8> = text
+        .split('.')
+        .map(|octet| octet.parse::<u8>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("bad address '{}'", text))?;
+    let octets: [u8; 4] = octets
+        .try_into()
+        .map_err(|_| format!("bad address '{}'", text))?;
+    Ok(IpAddr::V4(Ipv4Addr::from(octets)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ipfilter() {
+        let contents = "\
+# PeerGuardian style list
+001.002.004.000 - 001.002.004.255 , 000 , Bad Org
+010.000.000.000 - 010.255.255.255 , 200 , Allowed LAN
+192.168.1.10-192.168.1.20
+2001:db8:: - 2001:db8::ffff , 100 , v6 block
+";
+        let ranges = parse_ipfilter(contents).unwrap();
+        assert_eq!(ranges.len(), 3);
+        assert_eq!(
+            ranges[0],
+            IpRange::new("1.2.4.0".parse().unwrap(), "1.2.4.255".parse().unwrap())
+        );
+        assert!(ranges[2].contains("2001:db8::1".parse().unwrap()));
+
+        assert!(parse_ipfilter("1.2.3.4").is_err());
+        assert!(parse_ipfilter("1.2.3.4 - 1.2.3.0").is_err());
+        assert!(parse_ipfilter("1.2.3.256 - 1.2.3.300").is_err());
+    }
+
+    #[test]
+    fn test_range_contains() {
+        let range = IpRange::new("1.2.4.0".parse().unwrap(), "1.2.4.255".parse().unwrap());
+        assert!(range.contains("1.2.4.0".parse().unwrap()));
+        assert!(range.contains("1.2.4.77".parse().unwrap()));
+        assert!(range.contains("::ffff:1.2.4.77".parse().unwrap()));
+        assert!(!range.contains("1.2.5.0".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+    }
+}
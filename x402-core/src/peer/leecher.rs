@@ -0,0 +1,352 @@
+use std::collections::VecDeque;
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use svix_ksuid::KsuidMs;
+
+use crate::peer::bitfield::Bitfield;
+use crate::peer::extension::{EXTENDED_HANDSHAKE_ID, ExtendedHandshake};
+use crate::peer::handshake::{Capabilities, Handshake};
+use crate::peer::message::{BlockRequest, Message};
+use crate::peer::proxy::ProxyConfig;
+use crate::peer::state::PeerState;
+use crate::peer::transport::{PeerStream, TcpTransport, Transport};
+
+/// Extensions the leecher advertises unless told otherwise
+pub const DEFAULT_CAPABILITIES: Capabilities = Capabilities {
+    fast_extension: false,
+    extension_protocol: true,
+    x402: false,
+    dht: false,
+};
+
+/// Size of the blocks a piece is requested in
+pub const BLOCK_SIZE: u32 = 16 * 1024;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A connection to a single remote peer we download from, over TCP by default
+pub struct Leecher<S: PeerStream = TcpStream> {
+    /// The connection to the peer
+    stream: S,
+    /// Address of the peer
+    addr: SocketAddr,
+    /// Peer ID the remote sent in its handshake
+    remote_peer_id: KsuidMs,
+    /// Pieces the peer has announced
+    bitfield: Bitfield,
+    /// Announcements that changed `bitfield` since the last `take_announced`
+    announced: Vec<Message>,
+    /// Choke and interest state of the connection
+    state: PeerState,
+    /// Requests sent that the peer has not answered yet
+    in_flight: Vec<BlockRequest>,
+    /// Request queue depth the peer advertised in its extended handshake
+    peer_reqq: Option<usize>,
+    /// Extensions both sides advertised in the handshake
+    negotiated: Capabilities,
+}
+
+impl Leecher {
+    /// Connect to a peer and perform the handshake
+    pub fn connect(
+        addr: SocketAddr,
+        info_hash: [u8; 20],
+        peer_id: KsuidMs,
+        num_pieces: usize,
+    ) -> Result<Self, String> {
+        Self::connect_via(addr, info_hash, peer_id, num_pieces, None)
+    }
+
+    /// Connect to a peer like `connect`, through `proxy` if given
+    pub fn connect_via(
+        addr: SocketAddr,
+        info_hash: [u8; 20],
+        peer_id: KsuidMs,
+        num_pieces: usize,
+        proxy: Option<&ProxyConfig>,
+    ) -> Result<Self, String> {
+        Self::connect_with(
+            addr,
+            info_hash,
+            peer_id,
+            num_pieces,
+            proxy,
+            DEFAULT_CAPABILITIES,
+        )
+    }
+
+    /// Connect to a peer like `connect_via`, advertising `capabilities`
+    pub fn connect_with(
+        addr: SocketAddr,
+        info_hash: [u8; 20],
+        peer_id: KsuidMs,
+        num_pieces: usize,
+        proxy: Option<&ProxyConfig>,
+        capabilities: Capabilities,
+    ) -> Result<Self, String> {
+        let transport = TcpTransport {
+            proxy: proxy.cloned(),
+        };
+        Self::connect_over(
+            &transport,
+            addr,
+            info_hash,
+            peer_id,
+            num_pieces,
+            capabilities,
+        )
+    }
+}
+
+impl<S: PeerStream> Leecher<S> {
+    /// Connect to a peer over `transport`, advertising `capabilities`
+    pub fn connect_over<T: Transport<Stream = S>>(
+        transport: &T,
+        addr: SocketAddr,
+        info_hash: [u8; 20],
+        peer_id: KsuidMs,
+        num_pieces: usize,
+        capabilities: Capabilities,
+    ) -> Result<Self, String> {
+        let mut stream = transport.connect(addr, CONNECT_TIMEOUT)?;
+        stream
+            .set_read_timeout(Some(READ_TIMEOUT))
+            .map_err(|e| format!("Failed to set read timeout: {}", e))?;
+
+        let response = Handshake::new(info_hash, peer_id)
+            .with_reserved(capabilities.to_reserved())
+            .exchange_on(&mut stream)?;
+        let negotiated = capabilities.intersect(response.capabilities());
+
+        let mut leecher = Leecher {
+            stream,
+            addr,
+            remote_peer_id: response.peer_id,
+            bitfield: Bitfield::new(num_pieces),
+            announced: Vec::new(),
+            state: PeerState::new(),
+            in_flight: Vec::new(),
+            peer_reqq: None,
+            negotiated,
+        };
+        if negotiated.extension_protocol {
+            leecher.send(&ExtendedHandshake::default().to_message()?)?;
+        }
+        Ok(leecher)
+    }
+
+    /// Give up on a receive after `timeout` without any data from the peer
+    pub fn set_read_timeout(&mut self, timeout: Duration) -> Result<(), String> {
+        self.stream
+            .set_read_timeout(Some(timeout))
+            .map_err(|e| format!("Failed to set read timeout: {}", e))
+    }
+
+    /// Address of the peer
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Peer ID the remote sent in its handshake
+    pub fn remote_peer_id(&self) -> KsuidMs {
+        self.remote_peer_id
+    }
+
+    /// Pieces the peer has announced so far
+    pub fn bitfield(&self) -> &Bitfield {
+        &self.bitfield
+    }
+
+    /// Take the Bitfield, Have, HaveAll and HaveNone messages that changed
+    /// `bitfield` since the last call, to feed into an `Availability`
+    pub fn take_announced(&mut self) -> Vec<Message> {
+        std::mem::take(&mut self.announced)
+    }
+
+    /// Whether the peer is currently choking us
+    pub fn is_choked(&self) -> bool {
+        self.state.peer_choking
+    }
+
+    /// Choke and interest state of the connection
+    pub fn state(&self) -> &PeerState {
+        &self.state
+    }
+
+    /// Requests sent that the peer has not answered yet
+    pub fn in_flight(&self) -> &[BlockRequest] {
+        &self.in_flight
+    }
+
+    /// Extensions both sides advertised, and so the ones this connection may use
+    pub fn negotiated_capabilities(&self) -> Capabilities {
+        self.negotiated
+    }
+
+    /// Most requests the peer queues at once, if it advertised a `reqq`
+    pub fn max_requests(&self) -> Option<usize> {
+        self.peer_reqq
+    }
+
+    /// Send a Cancel for every outstanding request of a piece
+    pub fn cancel_piece(&mut self, index: u32) -> Result<(), String> {
+        let (cancelled, remaining) = self
+            .in_flight
+            .drain(..)
+            .partition(|request| request.index == index);
+        self.in_flight = remaining;
+        self.send_cancels(cancelled)
+    }
+
+    /// Send a Cancel for every outstanding request, e.g. before disconnecting
+    pub fn cancel_all(&mut self) -> Result<(), String> {
+        let cancelled = std::mem::take(&mut self.in_flight);
+        self.send_cancels(cancelled)
+    }
+
+    fn send_cancels(&mut self, requests: Vec<BlockRequest>) -> Result<(), String> {
+        for request in requests {
+            self.send(&request.to_cancel())?;
+        }
+        Ok(())
+    }
+
+    /// Send a message to the peer
+    pub fn send(&mut self, message: &Message) -> Result<(), String> {
+        message
+            .send(&mut self.stream)
+            .map_err(|e| format!("Failed to send message to {}: {}", self.addr, e))?;
+        self.state.on_sent(message);
+        Ok(())
+    }
+
+    /// Receive the next message, updating the connection state and the peer's pieces
+    pub fn receive(&mut self) -> Result<Message, String> {
+        let message = Message::receive(&mut self.stream)?;
+
+        self.state.on_received(&message);
+        match &message {
+            Message::Have(index) => {
+                let index = *index as usize;
+                if index < self.bitfield.num_pieces() && !self.bitfield.has(index) {
+                    self.bitfield.set(index);
+                    self.announced.push(message.clone());
+                }
+            }
+            Message::Bitfield(_) | Message::HaveAll | Message::HaveNone => {
+                self.bitfield = Bitfield::from_message(&message, self.bitfield.num_pieces())?;
+                // A full announcement replaces everything before it
+                self.announced.clear();
+                self.announced.push(message.clone());
+            }
+            Message::Extended {
+                id: EXTENDED_HANDSHAKE_ID,
+                payload,
+            } => {
+                // A malformed handshake only costs us its hints
+                if let Ok(handshake) = ExtendedHandshake::from_payload(payload)
+                    && let Some(reqq) = handshake.reqq
+                {
+                    self.peer_reqq = Some(reqq as usize);
+                }
+            }
+            _ => {}
+        }
+
+        Ok(message)
+    }
+
+    /// Declare interest and wait until the peer unchokes us
+    pub fn interested(&mut self) -> Result<(), String> {
+        self.send(&Message::Interested)?;
+        while self.state.peer_choking {
+            self.receive()?;
+        }
+        Ok(())
+    }
+
+    /// Download a whole piece, keeping up to `max_in_flight` block requests outstanding
+    ///
+    /// A peer that advertised a smaller `reqq` is sent at most that many, as
+    /// it would drop the rest.
+    /// If the peer chokes us mid-piece, its outstanding requests are considered
+    /// dropped and are requested again once it unchokes us. Blocks are only
+    /// requested while we are interested, so call `interested` first.
+    pub fn download_piece(
+        &mut self,
+        index: u32,
+        length: usize,
+        max_in_flight: usize,
+    ) -> Result<Vec<u8>, String> {
+        let piece = self.download_piece_until(index, length, max_in_flight, || false)?;
+        Ok(piece.expect("download is never stopped"))
+    }
+
+    /// Download a whole piece like `download_piece`, giving up once `stop` returns true
+    ///
+    /// `stop` is checked after every message from the peer. When it returns
+    /// true, the piece's outstanding requests are cancelled and `None` is
+    /// returned, e.g. because another peer already delivered the piece.
+    pub fn download_piece_until<F: Fn() -> bool>(
+        &mut self,
+        index: u32,
+        length: usize,
+        max_in_flight: usize,
+        stop: F,
+    ) -> Result<Option<Vec<u8>>, String> {
+        let max_in_flight = self
+            .peer_reqq
+            .map_or(max_in_flight, |reqq| max_in_flight.min(reqq))
+            .max(1);
+        let mut piece = vec![0u8; length];
+        let mut pending: VecDeque<BlockRequest> = (0..length as u32)
+            .step_by(BLOCK_SIZE as usize)
+            .map(|begin| BlockRequest {
+                index,
+                begin,
+                length: BLOCK_SIZE.min(length as u32 - begin),
+            })
+            .collect();
+
+        while !pending.is_empty() || self.in_flight.iter().any(|r| r.index == index) {
+            if stop() {
+                self.cancel_piece(index)?;
+                return Ok(None);
+            }
+            while self.state.can_request() && self.in_flight.len() < max_in_flight {
+                let Some(request) = pending.pop_front() else {
+                    break;
+                };
+                self.send(&request.to_request())?;
+                self.in_flight.push(request);
+            }
+
+            match self.receive()? {
+                Message::Piece {
+                    index: piece_index,
+                    begin,
+                    block,
+                } if piece_index == index => {
+                    let Some(position) = self.in_flight.iter().position(|r| {
+                        r.index == index && r.begin == begin && r.length as usize == block.len()
+                    }) else {
+                        continue;
+                    };
+                    self.in_flight.remove(position);
+                    piece[begin as usize..begin as usize + block.len()].copy_from_slice(&block);
+                }
+                Message::Choke => {
+                    // The peer discards our requests when it chokes us
+                    for request in self.in_flight.drain(..).rev() {
+                        pending.push_front(request);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Some(piece))
+    }
+}
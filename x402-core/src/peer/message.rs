@@ -0,0 +1,566 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// Largest message accepted by default, well above a 128 KiB block or the
+/// bitfield of any realistic torrent
+pub const DEFAULT_MAX_MESSAGE_LENGTH: u32 = 4 * 1024 * 1024;
+
+const CHOKE: u8 = 0;
+const UNCHOKE: u8 = 1;
+const INTERESTED: u8 = 2;
+const NOT_INTERESTED: u8 = 3;
+const HAVE: u8 = 4;
+const BITFIELD: u8 = 5;
+const REQUEST: u8 = 6;
+const PIECE: u8 = 7;
+const CANCEL: u8 = 8;
+// DHT (BEP 5)
+const PORT: u8 = 9;
+// Extension Protocol (BEP 10)
+const EXTENDED: u8 = 20;
+// Fast Extension (BEP 6)
+const SUGGEST_PIECE: u8 = 0x0D;
+const HAVE_ALL: u8 = 0x0E;
+const HAVE_NONE: u8 = 0x0F;
+const REJECT_REQUEST: u8 = 0x10;
+const ALLOWED_FAST: u8 = 0x11;
+// x402 extension, outside the range used by standard BitTorrent messages
+const PAYMENT: u8 = 0x40;
+const SUGGEST_TORRENTS: u8 = 0x41;
+
+/// A peer wire protocol message, as exchanged after the handshake
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    KeepAlive,
+    Choke,
+    Unchoke,
+    Interested,
+    NotInterested,
+    Have(u32),
+    Bitfield(Vec<u8>),
+    Request {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    Piece {
+        index: u32,
+        begin: u32,
+        block: Vec<u8>,
+    },
+    Cancel {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    /// Port of the sender's DHT node, sent when both sides support the DHT
+    Port(u16),
+    SuggestPiece(u32),
+    HaveAll,
+    HaveNone,
+    RejectRequest {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    AllowedFast(u32),
+    /// An Extension Protocol message: the extended message ID and its payload
+    Extended {
+        id: u8,
+        payload: Vec<u8>,
+    },
+    /// An x402 payment: the amount paid and an opaque proof for the settlement layer
+    Payment {
+        amount: u64,
+        proof: Vec<u8>,
+    },
+    /// x402 control message: info hashes the sender serves instead of the
+    /// one asked for in the handshake
+    SuggestTorrents(Vec<[u8; 20]>),
+}
+
+/// Errors from reading a message that callers may want to tell apart
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageError {
+    /// The length prefix exceeds the accepted maximum
+    MessageTooLarge { length: u32, max: u32 },
+    /// Any other failure: I/O or a malformed message
+    Other(String),
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageError::MessageTooLarge { length, max } => write!(
+                f,
+                "Message too large: {} bytes exceeds the limit of {}",
+                length, max
+            ),
+            MessageError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for MessageError {
+    fn from(message: String) -> Self {
+        MessageError::Other(message)
+    }
+}
+
+impl From<MessageError> for String {
+    fn from(error: MessageError) -> Self {
+        error.to_string()
+    }
+}
+
+/// A block of a piece, as named by Request and Cancel messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockRequest {
+    pub index: u32,
+    pub begin: u32,
+    pub length: u32,
+}
+
+impl BlockRequest {
+    /// Build the Request message for this block
+    pub fn to_request(self) -> Message {
+        Message::Request {
+            index: self.index,
+            begin: self.begin,
+            length: self.length,
+        }
+    }
+
+    /// Build the Cancel message for this block
+    pub fn to_cancel(self) -> Message {
+        Message::Cancel {
+            index: self.index,
+            begin: self.begin,
+            length: self.length,
+        }
+    }
+
+    /// Build the Reject Request message for this block
+    pub fn to_reject(self) -> Message {
+        Message::RejectRequest {
+            index: self.index,
+            begin: self.begin,
+            length: self.length,
+        }
+    }
+}
+
+impl Message {
+    /// Get the message ID, or `None` for a keep-alive
+    pub fn id(&self) -> Option<u8> {
+        match self {
+            Message::KeepAlive => None,
+            Message::Choke => Some(CHOKE),
+            Message::Unchoke => Some(UNCHOKE),
+            Message::Interested => Some(INTERESTED),
+            Message::NotInterested => Some(NOT_INTERESTED),
+            Message::Have(_) => Some(HAVE),
+            Message::Bitfield(_) => Some(BITFIELD),
+            Message::Request { .. } => Some(REQUEST),
+            Message::Piece { .. } => Some(PIECE),
+            Message::Cancel { .. } => Some(CANCEL),
+            Message::Port(_) => Some(PORT),
+            Message::SuggestPiece(_) => Some(SUGGEST_PIECE),
+            Message::HaveAll => Some(HAVE_ALL),
+            Message::HaveNone => Some(HAVE_NONE),
+            Message::RejectRequest { .. } => Some(REJECT_REQUEST),
+            Message::AllowedFast(_) => Some(ALLOWED_FAST),
+            Message::Extended { .. } => Some(EXTENDED),
+            Message::Payment { .. } => Some(PAYMENT),
+            Message::SuggestTorrents(_) => Some(SUGGEST_TORRENTS),
+        }
+    }
+
+    /// Serialize the message to bytes, including the 4-byte length prefix
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        if let Some(id) = self.id() {
+            payload.push(id);
+        }
+
+        match self {
+            Message::Have(index) | Message::SuggestPiece(index) | Message::AllowedFast(index) => {
+                payload.extend_from_slice(&index.to_be_bytes())
+            }
+            Message::Bitfield(bits) => payload.extend_from_slice(bits),
+            Message::Port(port) => payload.extend_from_slice(&port.to_be_bytes()),
+            Message::Request {
+                index,
+                begin,
+                length,
+            }
+            | Message::Cancel {
+                index,
+                begin,
+                length,
+            }
+            | Message::RejectRequest {
+                index,
+                begin,
+                length,
+            } => {
+                payload.extend_from_slice(&index.to_be_bytes());
+                payload.extend_from_slice(&begin.to_be_bytes());
+                payload.extend_from_slice(&length.to_be_bytes());
+            }
+            Message::Piece {
+                index,
+                begin,
+                block,
+            } => {
+                payload.extend_from_slice(&index.to_be_bytes());
+                payload.extend_from_slice(&begin.to_be_bytes());
+                payload.extend_from_slice(block);
+            }
+            Message::Extended { id, payload: body } => {
+                payload.push(*id);
+                payload.extend_from_slice(body);
+            }
+            Message::Payment { amount, proof } => {
+                payload.extend_from_slice(&amount.to_be_bytes());
+                payload.extend_from_slice(proof);
+            }
+            Message::SuggestTorrents(info_hashes) => {
+                payload.extend(info_hashes.iter().flatten());
+            }
+            _ => {}
+        }
+
+        let mut buf = Vec::with_capacity(4 + payload.len());
+        buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        buf.extend_from_slice(&payload);
+        buf
+    }
+
+    /// Deserialize a message from its payload (the bytes after the length prefix)
+    pub fn deserialize(payload: &[u8]) -> Result<Self, String> {
+        let Some((&id, body)) = payload.split_first() else {
+            return Ok(Message::KeepAlive);
+        };
+
+        match id {
+            CHOKE => expect_empty(body, Message::Choke),
+            UNCHOKE => expect_empty(body, Message::Unchoke),
+            INTERESTED => expect_empty(body, Message::Interested),
+            NOT_INTERESTED => expect_empty(body, Message::NotInterested),
+            HAVE => {
+                let [index] = read_u32s::<1>(body, "Have")?;
+                Ok(Message::Have(index))
+            }
+            BITFIELD => Ok(Message::Bitfield(body.to_vec())),
+            REQUEST => {
+                let [index, begin, length] = read_u32s::<3>(body, "Request")?;
+                Ok(Message::Request {
+                    index,
+                    begin,
+                    length,
+                })
+            }
+            PIECE => {
+                if body.len() < 8 {
+                    return Err(format!("Piece message too short: {} bytes", body.len()));
+                }
+                let [index, begin] = read_u32s::<2>(&body[..8], "Piece")?;
+                Ok(Message::Piece {
+                    index,
+                    begin,
+                    block: body[8..].to_vec(),
+                })
+            }
+            CANCEL => {
+                let [index, begin, length] = read_u32s::<3>(body, "Cancel")?;
+                Ok(Message::Cancel {
+                    index,
+                    begin,
+                    length,
+                })
+            }
+            PORT => {
+                let port: [u8; 2] = body.try_into().map_err(|_| {
+                    format!(
+                        "Invalid Port message length: expected 2, got {}",
+                        body.len()
+                    )
+                })?;
+                Ok(Message::Port(u16::from_be_bytes(port)))
+            }
+            SUGGEST_PIECE => {
+                let [index] = read_u32s::<1>(body, "Suggest Piece")?;
+                Ok(Message::SuggestPiece(index))
+            }
+            HAVE_ALL => expect_empty(body, Message::HaveAll),
+            HAVE_NONE => expect_empty(body, Message::HaveNone),
+            REJECT_REQUEST => {
+                let [index, begin, length] = read_u32s::<3>(body, "Reject Request")?;
+                Ok(Message::RejectRequest {
+                    index,
+                    begin,
+                    length,
+                })
+            }
+            ALLOWED_FAST => {
+                let [index] = read_u32s::<1>(body, "Allowed Fast")?;
+                Ok(Message::AllowedFast(index))
+            }
+            EXTENDED => {
+                let Some((&id, payload)) = body.split_first() else {
+                    return Err("Extended message too short: 0 bytes".to_string());
+                };
+                Ok(Message::Extended {
+                    id,
+                    payload: payload.to_vec(),
+                })
+            }
+            PAYMENT => {
+                let Some((amount, proof)) = body.split_first_chunk::<8>() else {
+                    return Err(format!("Payment message too short: {} bytes", body.len()));
+                };
+                Ok(Message::Payment {
+                    amount: u64::from_be_bytes(*amount),
+                    proof: proof.to_vec(),
+                })
+            }
+            SUGGEST_TORRENTS => {
+                let (info_hashes, rest) = body.as_chunks::<20>();
+                if !rest.is_empty() {
+                    return Err(format!(
+                        "Suggest Torrents length {} is not a multiple of 20",
+                        body.len()
+                    ));
+                }
+                Ok(Message::SuggestTorrents(info_hashes.to_vec()))
+            }
+            _ => Err(format!("Unknown message id: {}", id)),
+        }
+    }
+
+    /// Send the message over a stream
+    pub fn send<W: Write>(&self, stream: &mut W) -> io::Result<()> {
+        stream.write_all(&self.serialize())?;
+        stream.flush()
+    }
+
+    /// Receive a single message from a stream
+    pub fn receive<R: Read>(stream: &mut R) -> Result<Self, String> {
+        Self::try_receive(stream)?.ok_or_else(|| "Connection closed by peer".to_string())
+    }
+
+    /// Receive a single message, returning `None` if the peer closed the connection
+    /// cleanly between messages
+    pub fn try_receive<R: Read>(stream: &mut R) -> Result<Option<Self>, String> {
+        Ok(Self::try_receive_limited(
+            stream,
+            DEFAULT_MAX_MESSAGE_LENGTH,
+        )?)
+    }
+
+    /// Receive a single message, refusing messages longer than `max_length`
+    ///
+    /// The length prefix is checked before anything is allocated, so a peer
+    /// can't make us reserve gigabytes with a bogus prefix.
+    pub fn try_receive_limited<R: Read>(
+        stream: &mut R,
+        max_length: u32,
+    ) -> Result<Option<Self>, MessageError> {
+        let mut len_buf = [0u8; 4];
+        match stream.read_exact(&mut len_buf) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(format!("Failed to read message length: {}", e).into()),
+        }
+        let len = u32::from_be_bytes(len_buf);
+        if len > max_length {
+            return Err(MessageError::MessageTooLarge {
+                length: len,
+                max: max_length,
+            });
+        }
+
+        let mut payload = vec![0u8; len as usize];
+        stream
+            .read_exact(&mut payload)
+            .map_err(|e| format!("Failed to read message payload: {}", e))?;
+
+        Ok(Self::deserialize(&payload).map(Some)?)
+    }
+}
+
+/// Return `message` if the body is empty, as required for the state messages
+fn expect_empty(body: &[u8], message: Message) -> Result<Message, String> {
+    if !body.is_empty() {
+        return Err(format!(
+            "Unexpected payload of {} bytes for {:?}",
+            body.len(),
+            message
+        ));
+    }
+    Ok(message)
+}
+
+/// Read exactly `N` big-endian u32 values from a message body
+fn read_u32s<const N: usize>(body: &[u8], name: &str) -> Result<[u32; N], String> {
+    if body.len() != N * 4 {
+        return Err(format!(
+            "Invalid {} message length: expected {}, got {}",
+            name,
+            N * 4,
+            body.len()
+        ));
+    }
+
+    let mut values = [0u32; N];
+    for (value, chunk) in values.iter_mut().zip(body.chunks_exact(4)) {
+        *value = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(message: Message) {
+        let bytes = message.serialize();
+        let mut cursor = io::Cursor::new(bytes);
+        assert_eq!(Message::receive(&mut cursor).unwrap(), message);
+    }
+
+    #[test]
+    fn test_serialize_have() {
+        let bytes = Message::Have(7).serialize();
+        assert_eq!(bytes, vec![0, 0, 0, 5, 4, 0, 0, 0, 7]);
+    }
+
+    #[test]
+    fn test_serialize_keep_alive() {
+        assert_eq!(Message::KeepAlive.serialize(), vec![0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        roundtrip(Message::KeepAlive);
+        roundtrip(Message::Choke);
+        roundtrip(Message::Unchoke);
+        roundtrip(Message::Interested);
+        roundtrip(Message::NotInterested);
+        roundtrip(Message::Have(42));
+        roundtrip(Message::Bitfield(vec![0b1010_0000]));
+        roundtrip(Message::Request {
+            index: 1,
+            begin: 16384,
+            length: 16384,
+        });
+        roundtrip(Message::Piece {
+            index: 1,
+            begin: 0,
+            block: vec![1, 2, 3],
+        });
+        roundtrip(Message::Cancel {
+            index: 1,
+            begin: 16384,
+            length: 16384,
+        });
+        roundtrip(Message::SuggestPiece(3));
+        roundtrip(Message::HaveAll);
+        roundtrip(Message::HaveNone);
+        roundtrip(Message::AllowedFast(9));
+        roundtrip(Message::Extended {
+            id: 0,
+            payload: b"de".to_vec(),
+        });
+        roundtrip(Message::Payment {
+            amount: 1000,
+            proof: b"signature".to_vec(),
+        });
+        roundtrip(Message::SuggestTorrents(vec![[1u8; 20], [2u8; 20]]));
+    }
+
+    #[test]
+    fn test_serialize_reject_request() {
+        let request = BlockRequest {
+            index: 1,
+            begin: 16384,
+            length: 16384,
+        };
+        let bytes = request.to_reject().serialize();
+        assert_eq!(
+            bytes,
+            vec![0, 0, 0, 13, 0x10, 0, 0, 0, 1, 0, 0, 0x40, 0, 0, 0, 0x40, 0]
+        );
+        roundtrip(request.to_reject());
+    }
+
+    #[test]
+    fn test_port_wire_format() {
+        let bytes = Message::Port(6881).serialize();
+        assert_eq!(bytes, vec![0, 0, 0, 3, 9, 0x1A, 0xE1]);
+        roundtrip(Message::Port(6881));
+
+        assert!(Message::deserialize(&[9, 0x1A]).is_err());
+        assert!(Message::deserialize(&[9, 0x1A, 0xE1, 0]).is_err());
+    }
+
+    #[test]
+    fn test_try_receive_closed() {
+        let mut empty = io::Cursor::new(Vec::new());
+        assert_eq!(Message::try_receive(&mut empty).unwrap(), None);
+        assert!(Message::receive(&mut empty).is_err());
+    }
+
+    #[test]
+    fn test_oversized_length_prefix() {
+        // Only the prefix is available: reading a payload would fail with EOF instead
+        let mut stream = io::Cursor::new(vec![0xFF, 0xFF, 0xFF, 0xFF]);
+        let result = Message::try_receive_limited(&mut stream, DEFAULT_MAX_MESSAGE_LENGTH);
+
+        assert_eq!(
+            result.unwrap_err(),
+            MessageError::MessageTooLarge {
+                length: u32::MAX,
+                max: DEFAULT_MAX_MESSAGE_LENGTH,
+            }
+        );
+
+        let mut stream = io::Cursor::new(vec![0xFF, 0xFF, 0xFF, 0xFF]);
+        let result = Message::receive(&mut stream);
+        assert!(result.unwrap_err().starts_with("Message too large"));
+    }
+
+    #[test]
+    fn test_custom_message_limit() {
+        let bytes = Message::Bitfield(vec![0xFF; 16]).serialize();
+
+        let result = Message::try_receive_limited(&mut io::Cursor::new(bytes.clone()), 8);
+        assert!(matches!(
+            result,
+            Err(MessageError::MessageTooLarge { length: 17, max: 8 })
+        ));
+
+        let result = Message::try_receive_limited(&mut io::Cursor::new(bytes), 17);
+        assert_eq!(result.unwrap(), Some(Message::Bitfield(vec![0xFF; 16])));
+    }
+
+    #[test]
+    fn test_deserialize_invalid_have() {
+        let result = Message::deserialize(&[HAVE, 0, 0]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_deserialize_invalid_suggest_torrents() {
+        let mut payload = vec![SUGGEST_TORRENTS];
+        payload.extend_from_slice(&[7u8; 30]);
+        assert!(Message::deserialize(&payload).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_unknown_id() {
+        let result = Message::deserialize(&[99]);
+        assert!(result.is_err());
+    }
+}
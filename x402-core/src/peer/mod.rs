@@ -1,2 +1,20 @@
+pub mod address;
+pub mod availability;
+pub mod bitfield;
+pub mod cancel;
+pub mod download;
+pub mod extension;
+pub mod fast;
 pub mod handshake;
+pub mod ipfilter;
+pub mod leecher;
+pub mod message;
+#[cfg(feature = "mse")]
+pub mod mse;
+pub mod proxy;
+pub mod resume;
 pub mod serve;
+pub mod state;
+pub mod stats;
+pub mod throughput;
+pub mod transport;
@@ -0,0 +1,690 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use num_bigint::BigUint;
+use sha1::{Digest, Sha1};
+
+use crate::peer::transport::{PeerListener, PeerStream, Transport};
+
+/// Prime of the Diffie-Hellman group used by MSE
+const PRIME: &str = "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A63A36210000000000090563";
+const GENERATOR: u32 = 2;
+/// Length of the public keys and the shared secret
+const KEY_LENGTH: usize = 96;
+/// Length of the private keys
+const PRIVATE_KEY_LENGTH: usize = 20;
+/// Longest padding either side may send
+const MAX_PAD_LENGTH: usize = 512;
+/// Verification constant, sent encrypted so each side can find the other's
+/// encrypted stream after the padding
+const VC: [u8; 8] = [0; 8];
+/// RC4 output discarded before use, as its first bytes leak key material
+const RC4_DISCARD: usize = 1024;
+
+const CRYPTO_PLAINTEXT: u32 = 0x01;
+const CRYPTO_RC4: u32 = 0x02;
+
+/// Start of a plaintext BitTorrent handshake
+const PLAINTEXT_PREFIX: &[u8; 20] = b"\x13BitTorrent protocol";
+
+/// Whether connections have to be encrypted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EncryptionPolicy {
+    /// Only use RC4-encrypted connections
+    Required,
+    /// Encrypt when possible, falling back to plaintext with peers that don't
+    /// support MSE or only offer plaintext
+    #[default]
+    Preferred,
+}
+
+impl EncryptionPolicy {
+    fn crypto_provide(self) -> u32 {
+        match self {
+            EncryptionPolicy::Required => CRYPTO_RC4,
+            EncryptionPolicy::Preferred => CRYPTO_RC4 | CRYPTO_PLAINTEXT,
+        }
+    }
+}
+
+/// The RC4 stream cipher
+#[derive(Clone)]
+struct Rc4 {
+    state: [u8; 256],
+    i: u8,
+    j: u8,
+}
+
+impl Rc4 {
+    fn new(key: &[u8]) -> Self {
+        let mut state = [0u8; 256];
+        for (i, byte) in state.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut j = 0u8;
+        for i in 0..256 {
+            j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+            state.swap(i, j as usize);
+        }
+        Rc4 { state, i: 0, j: 0 }
+    }
+
+    /// A cipher keyed for MSE, with the first `RC4_DISCARD` bytes dropped
+    fn for_mse(key: &[u8]) -> Self {
+        let mut rc4 = Self::new(key);
+        rc4.apply(&mut [0u8; RC4_DISCARD]);
+        rc4
+    }
+
+    /// Encrypt or decrypt `data` in place
+    fn apply(&mut self, data: &mut [u8]) {
+        for byte in data {
+            self.i = self.i.wrapping_add(1);
+            self.j = self.j.wrapping_add(self.state[self.i as usize]);
+            self.state.swap(self.i as usize, self.j as usize);
+            let index = self.state[self.i as usize].wrapping_add(self.state[self.j as usize]);
+            *byte ^= self.state[index as usize];
+        }
+    }
+}
+
+/// Ciphers of an encrypted connection, one per direction
+struct Ciphers {
+    incoming: Rc4,
+    outgoing: Rc4,
+}
+
+/// A peer connection obfuscated with Message Stream Encryption
+///
+/// Once the MSE handshake is done, the regular BitTorrent handshake and
+/// messages run inside it as over any other stream. Depending on what was
+/// negotiated, the payload is RC4-encrypted or sent as plaintext.
+pub struct MseStream<S> {
+    inner: S,
+    /// Info hashes we accept, until the handshake of an incoming connection is done
+    pending: Option<(Arc<Vec<[u8; 20]>>, EncryptionPolicy)>,
+    /// Payload bytes received during the handshake, already decrypted
+    buffered: VecDeque<u8>,
+    /// `None` for a plaintext connection
+    ciphers: Option<Ciphers>,
+    /// Info hash the peer asked for, once known
+    info_hash: Option<[u8; 20]>,
+}
+
+impl<S: Read + Write> MseStream<S> {
+    /// Run the MSE handshake for the torrent `info_hash` as the connecting side
+    pub fn connect(
+        mut inner: S,
+        info_hash: [u8; 20],
+        policy: EncryptionPolicy,
+    ) -> Result<Self, String> {
+        let ciphers = initiate(&mut inner, info_hash, policy)
+            .map_err(|e| format!("MSE handshake failed: {}", e))?;
+        Ok(MseStream {
+            inner,
+            pending: None,
+            buffered: VecDeque::new(),
+            ciphers,
+            info_hash: Some(info_hash),
+        })
+    }
+
+    /// Run the MSE handshake as the accepting side, for a torrent in `info_hashes`
+    ///
+    /// With `Preferred`, a peer starting a plaintext BitTorrent handshake is
+    /// served without encryption.
+    pub fn accept(
+        inner: S,
+        info_hashes: &[[u8; 20]],
+        policy: EncryptionPolicy,
+    ) -> Result<Self, String> {
+        let mut stream = Self::accept_lazily(inner, Arc::new(info_hashes.to_vec()), policy);
+        stream.finish_accept()?;
+        Ok(stream)
+    }
+
+    /// An incoming connection whose handshake runs on its first read or write,
+    /// on the thread handling the connection rather than the accepting one
+    fn accept_lazily(inner: S, info_hashes: Arc<Vec<[u8; 20]>>, policy: EncryptionPolicy) -> Self {
+        MseStream {
+            inner,
+            pending: Some((info_hashes, policy)),
+            buffered: VecDeque::new(),
+            ciphers: None,
+            info_hash: None,
+        }
+    }
+
+    fn finish_accept(&mut self) -> Result<(), String> {
+        let Some((info_hashes, policy)) = self.pending.take() else {
+            return Ok(());
+        };
+        let mut first = [0u8; PLAINTEXT_PREFIX.len()];
+        read_exact(&mut self.inner, &mut first)?;
+        if &first == PLAINTEXT_PREFIX {
+            if policy == EncryptionPolicy::Required {
+                return Err("Peer does not support encryption".to_string());
+            }
+            self.buffered.extend(first);
+            return Ok(());
+        }
+
+        let (ciphers, info_hash, payload) = respond(&mut self.inner, &first, &info_hashes, policy)
+            .map_err(|e| format!("MSE handshake failed: {}", e))?;
+        self.ciphers = ciphers;
+        self.info_hash = Some(info_hash);
+        self.buffered.extend(payload);
+        Ok(())
+    }
+
+    /// Whether the payload is RC4-encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.ciphers.is_some()
+    }
+
+    /// Info hash the handshake was made for; `None` for plaintext incoming
+    /// connections, where the BitTorrent handshake names it instead
+    pub fn info_hash(&self) -> Option<[u8; 20]> {
+        self.info_hash
+    }
+}
+
+impl<S: Read + Write> Read for MseStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.finish_accept().map_err(io::Error::other)?;
+        if !self.buffered.is_empty() {
+            let n = buf.len().min(self.buffered.len());
+            for (slot, byte) in buf.iter_mut().zip(self.buffered.drain(..n)) {
+                *slot = byte;
+            }
+            return Ok(n);
+        }
+        let n = self.inner.read(buf)?;
+        if let Some(ciphers) = &mut self.ciphers {
+            ciphers.incoming.apply(&mut buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl<S: Read + Write> Write for MseStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.finish_accept().map_err(io::Error::other)?;
+        match &mut self.ciphers {
+            Some(ciphers) => {
+                // The cipher has advanced over all of `buf`, so all of it must go out
+                let mut encrypted = buf.to_vec();
+                ciphers.outgoing.apply(&mut encrypted);
+                self.inner.write_all(&encrypted)?;
+                Ok(buf.len())
+            }
+            None => self.inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: PeerStream> PeerStream for MseStream<S> {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_write_timeout(timeout)
+    }
+
+    fn data_pending(&self) -> io::Result<bool> {
+        if !self.buffered.is_empty() {
+            return Ok(true);
+        }
+        self.inner.data_pending()
+    }
+}
+
+/// Accepts MSE connections, and plaintext ones unless encryption is required
+///
+/// The MSE handshake of each connection runs on its first read, so a slow
+/// peer doesn't hold up accepting the others.
+pub struct MseListener<L> {
+    inner: L,
+    info_hashes: Arc<Vec<[u8; 20]>>,
+    policy: EncryptionPolicy,
+}
+
+impl<L: PeerListener> MseListener<L> {
+    /// Accept connections for the torrents in `info_hashes` from `inner`
+    pub fn new(inner: L, info_hashes: Vec<[u8; 20]>, policy: EncryptionPolicy) -> Self {
+        MseListener {
+            inner,
+            info_hashes: Arc::new(info_hashes),
+            policy,
+        }
+    }
+}
+
+impl<L: PeerListener> PeerListener for MseListener<L> {
+    type Stream = MseStream<L::Stream>;
+
+    fn accept(&self) -> io::Result<(Self::Stream, SocketAddr)> {
+        let (stream, addr) = self.inner.accept()?;
+        let stream = MseStream::accept_lazily(stream, Arc::clone(&self.info_hashes), self.policy);
+        Ok((stream, addr))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+/// Wraps another transport's connections for one torrent in MSE
+pub struct MseTransport<T> {
+    inner: T,
+    info_hash: [u8; 20],
+    policy: EncryptionPolicy,
+}
+
+impl<T: Transport> MseTransport<T> {
+    pub fn new(inner: T, info_hash: [u8; 20], policy: EncryptionPolicy) -> Self {
+        MseTransport {
+            inner,
+            info_hash,
+            policy,
+        }
+    }
+}
+
+impl<T: Transport> Transport for MseTransport<T>
+where
+    T::Stream: 'static,
+{
+    type Stream = MseStream<T::Stream>;
+    type Listener = MseListener<T::Listener>;
+
+    /// Connect with MSE; with `Preferred`, a peer that fails the MSE handshake
+    /// is connected to again without encryption
+    fn connect(&self, addr: SocketAddr, timeout: Duration) -> Result<Self::Stream, String> {
+        let stream = self.inner.connect(addr, timeout)?;
+        stream
+            .set_read_timeout(Some(timeout))
+            .map_err(|e| format!("Failed to set read timeout: {}", e))?;
+        match MseStream::connect(stream, self.info_hash, self.policy) {
+            Ok(stream) => Ok(stream),
+            Err(e) if self.policy == EncryptionPolicy::Required => Err(e),
+            Err(e) => {
+                log::warn!("{} with {}, retrying without encryption", e, addr);
+                let stream = self.inner.connect(addr, timeout)?;
+                Ok(MseStream {
+                    inner: stream,
+                    pending: None,
+                    buffered: VecDeque::new(),
+                    ciphers: None,
+                    info_hash: Some(self.info_hash),
+                })
+            }
+        }
+    }
+
+    fn bind(&self, addr: SocketAddr) -> io::Result<Self::Listener> {
+        let listener = self.inner.bind(addr)?;
+        Ok(MseListener::new(
+            listener,
+            vec![self.info_hash],
+            self.policy,
+        ))
+    }
+}
+
+/// Initiator side of the handshake, returning the ciphers if RC4 was selected
+fn initiate<S: Read + Write>(
+    stream: &mut S,
+    info_hash: [u8; 20],
+    policy: EncryptionPolicy,
+) -> Result<Option<Ciphers>, String> {
+    let private_key = random_bytes(PRIVATE_KEY_LENGTH)?;
+    let mut message = public_key(&private_key);
+    message.extend(random_pad()?);
+    write_all(stream, &message)?;
+
+    let mut their_key = [0u8; KEY_LENGTH];
+    read_exact(stream, &mut their_key)?;
+    let secret = shared_secret(&their_key, &private_key);
+
+    let mut outgoing = Rc4::for_mse(&hash(&[b"keyA", &secret, &info_hash]));
+    let mut incoming = Rc4::for_mse(&hash(&[b"keyB", &secret, &info_hash]));
+
+    let mut message = hash(&[b"req1", &secret]).to_vec();
+    message.extend(xor(hash(&[b"req2", &info_hash]), hash(&[b"req3", &secret])));
+    let mut encrypted = VC.to_vec();
+    encrypted.extend(policy.crypto_provide().to_be_bytes());
+    // No padding and no initial payload; the BitTorrent handshake follows
+    encrypted.extend(0u16.to_be_bytes());
+    encrypted.extend(0u16.to_be_bytes());
+    outgoing.apply(&mut encrypted);
+    message.extend(encrypted);
+    write_all(stream, &message)?;
+
+    // Their reply starts with the encrypted VC, after up to MAX_PAD_LENGTH bytes of padding
+    let mut marker = VC;
+    incoming.apply(&mut marker);
+    find_marker(stream, &marker, MAX_PAD_LENGTH)?;
+
+    let mut reply = [0u8; 6];
+    read_exact(stream, &mut reply)?;
+    incoming.apply(&mut reply);
+    let selected = u32::from_be_bytes([reply[0], reply[1], reply[2], reply[3]]);
+    let pad_length = u16::from_be_bytes([reply[4], reply[5]]) as usize;
+    if pad_length > MAX_PAD_LENGTH {
+        return Err(format!("padding too long: {} bytes", pad_length));
+    }
+    let mut pad = vec![0u8; pad_length];
+    read_exact(stream, &mut pad)?;
+    incoming.apply(&mut pad);
+
+    match selected {
+        CRYPTO_RC4 => Ok(Some(Ciphers { incoming, outgoing })),
+        CRYPTO_PLAINTEXT if policy == EncryptionPolicy::Preferred => Ok(None),
+        _ => Err(format!(
+            "peer selected unsupported crypto method {}",
+            selected
+        )),
+    }
+}
+
+/// Ciphers if RC4 was selected, the info hash the peer asked for and its
+/// decrypted initial payload
+type Accepted = (Option<Ciphers>, [u8; 20], Vec<u8>);
+
+/// Responder side of the handshake, after the first bytes of the peer's public key
+fn respond<S: Read + Write>(
+    stream: &mut S,
+    first: &[u8],
+    info_hashes: &[[u8; 20]],
+    policy: EncryptionPolicy,
+) -> Result<Accepted, String> {
+    let mut their_key = [0u8; KEY_LENGTH];
+    their_key[..first.len()].copy_from_slice(first);
+    read_exact(stream, &mut their_key[first.len()..])?;
+
+    let private_key = random_bytes(PRIVATE_KEY_LENGTH)?;
+    let mut message = public_key(&private_key);
+    message.extend(random_pad()?);
+    write_all(stream, &message)?;
+    let secret = shared_secret(&their_key, &private_key);
+
+    find_marker(stream, &hash(&[b"req1", &secret]), MAX_PAD_LENGTH)?;
+    let mut obfuscated = [0u8; 20];
+    read_exact(stream, &mut obfuscated)?;
+    let requested = xor(obfuscated, hash(&[b"req3", &secret]));
+    let info_hash = *info_hashes
+        .iter()
+        .find(|info_hash| hash(&[b"req2", info_hash.as_slice()]) == requested)
+        .ok_or("peer asked for a torrent we don't serve")?;
+
+    let mut incoming = Rc4::for_mse(&hash(&[b"keyA", &secret, &info_hash]));
+    let mut outgoing = Rc4::for_mse(&hash(&[b"keyB", &secret, &info_hash]));
+
+    let mut header = [0u8; 14];
+    read_exact(stream, &mut header)?;
+    incoming.apply(&mut header);
+    if header[..8] != VC {
+        return Err("invalid verification constant".to_string());
+    }
+    let provided = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+    let pad_length = u16::from_be_bytes([header[12], header[13]]) as usize;
+    if pad_length > MAX_PAD_LENGTH {
+        return Err(format!("padding too long: {} bytes", pad_length));
+    }
+    let mut pad = vec![0u8; pad_length + 2];
+    read_exact(stream, &mut pad)?;
+    incoming.apply(&mut pad);
+    let payload_length = u16::from_be_bytes([pad[pad_length], pad[pad_length + 1]]) as usize;
+    let mut payload = vec![0u8; payload_length];
+    read_exact(stream, &mut payload)?;
+    incoming.apply(&mut payload);
+
+    let selected = if provided & CRYPTO_RC4 != 0 {
+        CRYPTO_RC4
+    } else if provided & CRYPTO_PLAINTEXT != 0 && policy == EncryptionPolicy::Preferred {
+        CRYPTO_PLAINTEXT
+    } else {
+        return Err(format!("no acceptable crypto method in {}", provided));
+    };
+    let mut reply = VC.to_vec();
+    reply.extend(selected.to_be_bytes());
+    reply.extend(0u16.to_be_bytes());
+    outgoing.apply(&mut reply);
+    write_all(stream, &reply)?;
+
+    let ciphers = (selected == CRYPTO_RC4).then_some(Ciphers { incoming, outgoing });
+    Ok((ciphers, info_hash, payload))
+}
+
+/// Read until `marker` was received, allowing up to `max_skip` bytes before it
+fn find_marker<S: Read>(stream: &mut S, marker: &[u8], max_skip: usize) -> Result<(), String> {
+    let mut window = vec![0u8; marker.len()];
+    read_exact(stream, &mut window)?;
+    for _ in 0..max_skip {
+        if window == marker {
+            return Ok(());
+        }
+        let mut byte = [0u8; 1];
+        read_exact(stream, &mut byte)?;
+        window.remove(0);
+        window.push(byte[0]);
+    }
+    if window == marker {
+        return Ok(());
+    }
+    Err("peer's handshake not found after padding".to_string())
+}
+
+fn public_key(private_key: &[u8]) -> Vec<u8> {
+    let key = BigUint::from(GENERATOR).modpow(&BigUint::from_bytes_be(private_key), &prime());
+    to_key_bytes(&key)
+}
+
+fn shared_secret(their_key: &[u8], private_key: &[u8]) -> Vec<u8> {
+    let secret =
+        BigUint::from_bytes_be(their_key).modpow(&BigUint::from_bytes_be(private_key), &prime());
+    to_key_bytes(&secret)
+}
+
+fn prime() -> BigUint {
+    BigUint::parse_bytes(PRIME.as_bytes(), 16).expect("MSE prime is valid hex")
+}
+
+/// Big-endian bytes of a key, left-padded to `KEY_LENGTH`
+fn to_key_bytes(key: &BigUint) -> Vec<u8> {
+    let bytes = key.to_bytes_be();
+    let mut padded = vec![0u8; KEY_LENGTH - bytes.len()];
+    padded.extend(bytes);
+    padded
+}
+
+fn hash(parts: &[&[u8]]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn xor(a: [u8; 20], b: [u8; 20]) -> [u8; 20] {
+    std::array::from_fn(|i| a[i] ^ b[i])
+}
+
+fn random_bytes(length: usize) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![0u8; length];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| format!("Failed to generate random bytes: {}", e))?;
+    Ok(bytes)
+}
+
+/// Random padding of a random length up to `MAX_PAD_LENGTH`
+fn random_pad() -> Result<Vec<u8>, String> {
+    let length = u16::from_be_bytes(random_bytes(2)?.try_into().unwrap()) as usize;
+    random_bytes(length % (MAX_PAD_LENGTH + 1))
+}
+
+fn read_exact<S: Read>(stream: &mut S, buf: &mut [u8]) -> Result<(), String> {
+    stream.read_exact(buf).map_err(|e| e.to_string())
+}
+
+fn write_all<S: Write>(stream: &mut S, buf: &[u8]) -> Result<(), String> {
+    stream.write_all(buf).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::handshake::{Capabilities, Handshake, generate_peer_id};
+    use crate::peer::leecher::Leecher;
+    use crate::peer::serve::Seeder;
+    use crate::peer::transport::TcpTransport;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /// A loopback TCP connection, as (connecting, accepted) ends
+    fn loopback_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    #[test]
+    fn test_rc4_known_vector() {
+        let mut data = *b"Plaintext";
+        Rc4::new(b"Key").apply(&mut data);
+        assert_eq!(hex::encode(data), "bbf316e8d940af0ad3");
+    }
+
+    #[test]
+    fn test_bittorrent_handshake_inside_mse_tunnel() {
+        let info_hash = [7u8; 20];
+        let (client, server) = loopback_pair();
+
+        let seeder_id = generate_peer_id();
+        let accepted = thread::spawn(move || {
+            let known = [[1u8; 20], info_hash];
+            let mut stream = MseStream::accept(server, &known, EncryptionPolicy::Required).unwrap();
+            assert!(stream.is_encrypted());
+            assert_eq!(stream.info_hash(), Some(info_hash));
+            let handshake = Handshake::receive(&mut stream).unwrap();
+            Handshake::new(handshake.info_hash, seeder_id)
+                .send(&mut stream)
+                .unwrap();
+            handshake
+        });
+
+        let mut stream =
+            MseStream::connect(client, info_hash, EncryptionPolicy::Preferred).unwrap();
+        assert!(stream.is_encrypted());
+        let leecher_id = generate_peer_id();
+        let response = Handshake::exchange(&mut stream, info_hash, leecher_id).unwrap();
+
+        assert_eq!(response.peer_id, seeder_id);
+        let received = accepted.join().unwrap();
+        assert_eq!(received.info_hash, info_hash);
+        assert_eq!(received.peer_id, leecher_id);
+    }
+
+    #[test]
+    fn test_plaintext_peer_is_served_unless_encryption_is_required() {
+        let info_hash = [8u8; 20];
+        for policy in [EncryptionPolicy::Preferred, EncryptionPolicy::Required] {
+            let (mut client, server) = loopback_pair();
+            let accepted = thread::spawn(move || {
+                let mut stream = MseStream::accept(server, &[info_hash], policy)?;
+                assert!(!stream.is_encrypted());
+                let handshake = Handshake::receive(&mut stream)?;
+                Handshake::new(info_hash, generate_peer_id())
+                    .send(&mut stream)
+                    .map_err(|e| e.to_string())?;
+                Ok::<_, String>(handshake)
+            });
+
+            let exchanged = Handshake::exchange(&mut client, info_hash, generate_peer_id());
+            let accepted = accepted.join().unwrap();
+            match policy {
+                EncryptionPolicy::Preferred => {
+                    assert!(exchanged.is_ok());
+                    assert_eq!(accepted.unwrap().info_hash, info_hash);
+                }
+                EncryptionPolicy::Required => {
+                    assert!(exchanged.is_err());
+                    assert!(accepted.is_err());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_transport_falls_back_to_plaintext() {
+        let info_hash = [9u8; 20];
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // A peer without MSE drops the first connection, whose "handshake"
+        // is a public key, and answers a plaintext one on the second
+        let peer = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            assert!(Handshake::receive(&mut stream).is_err());
+            drop(stream);
+            let (mut stream, _) = listener.accept().unwrap();
+            let handshake = Handshake::receive(&mut stream).unwrap();
+            Handshake::new(info_hash, generate_peer_id())
+                .send(&mut stream)
+                .unwrap();
+            handshake.info_hash
+        });
+
+        let transport =
+            MseTransport::new(TcpTransport::new(), info_hash, EncryptionPolicy::Preferred);
+        let mut stream = transport.connect(addr, Duration::from_secs(5)).unwrap();
+        assert!(!stream.is_encrypted());
+        Handshake::exchange(&mut stream, info_hash, generate_peer_id()).unwrap();
+        assert_eq!(peer.join().unwrap(), info_hash);
+    }
+
+    #[test]
+    fn test_leecher_connects_to_seeder_over_mse() {
+        let info_hash = [10u8; 20];
+        let seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        seeder.add_torrent(info_hash);
+        let transport =
+            MseTransport::new(TcpTransport::new(), info_hash, EncryptionPolicy::Required);
+        let listener = transport.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::scope(|scope| {
+            let served = scope.spawn(|| {
+                let (stream, _) = listener.accept().unwrap();
+                seeder.handle_connection(stream)
+            });
+
+            let leecher = Leecher::connect_over(
+                &transport,
+                addr,
+                info_hash,
+                generate_peer_id(),
+                1,
+                Capabilities::default(),
+            )
+            .unwrap();
+            assert_eq!(leecher.remote_peer_id(), seeder.peer_id());
+            drop(leecher);
+            assert!(served.join().unwrap().is_ok());
+        });
+    }
+}
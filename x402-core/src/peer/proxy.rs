@@ -0,0 +1,300 @@
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+const SOCKS_VERSION: u8 = 5;
+/// Version of the username/password sub-negotiation (RFC 1929)
+const AUTH_VERSION: u8 = 1;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USER_PASS: u8 = 0x02;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xFF;
+const COMMAND_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// Credentials for a SOCKS5 proxy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyAuth {
+    pub username: String,
+    pub password: String,
+}
+
+/// A proxy that peer and tracker connections are dialed through
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxyConfig {
+    /// A SOCKS5 proxy (RFC 1928); host names are resolved by the proxy
+    Socks5 {
+        addr: SocketAddr,
+        auth: Option<ProxyAuth>,
+    },
+}
+
+impl ProxyConfig {
+    /// Open a connection to `host:port` through the proxy
+    ///
+    /// `host` is passed to the proxy as is, so names never hit the local
+    /// resolver. `timeout` bounds connecting to the proxy and each step of
+    /// the handshake.
+    pub fn connect(&self, host: &str, port: u16, timeout: Duration) -> Result<TcpStream, String> {
+        match self {
+            ProxyConfig::Socks5 { addr, auth } => {
+                let mut stream = TcpStream::connect_timeout(addr, timeout)
+                    .map_err(|e| format!("Failed to connect to proxy {}: {}", addr, e))?;
+                stream
+                    .set_read_timeout(Some(timeout))
+                    .and_then(|_| stream.set_write_timeout(Some(timeout)))
+                    .map_err(|e| format!("Failed to set proxy timeouts: {}", e))?;
+                socks5_handshake(&mut stream, auth.as_ref(), host, port)
+                    .map_err(|e| format!("SOCKS5 proxy {}: {}", addr, e))?;
+                Ok(stream)
+            }
+        }
+    }
+}
+
+/// Connect to `host:port`, through `proxy` if given
+///
+/// Without a proxy the host is resolved locally and its first address is used.
+pub(crate) fn dial(
+    proxy: Option<&ProxyConfig>,
+    host: &str,
+    port: u16,
+    timeout: Duration,
+) -> Result<TcpStream, String> {
+    if let Some(proxy) = proxy {
+        return proxy.connect(host, port, timeout);
+    }
+    let addr = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("Failed to resolve {}: {}", host, e))?
+        .next()
+        .ok_or_else(|| format!("No address found for {}", host))?;
+    TcpStream::connect_timeout(&addr, timeout)
+        .map_err(|e| format!("Failed to connect to {}: {}", host, e))
+}
+
+/// Negotiate authentication and ask the proxy to connect to `host:port`
+fn socks5_handshake(
+    stream: &mut TcpStream,
+    auth: Option<&ProxyAuth>,
+    host: &str,
+    port: u16,
+) -> Result<(), String> {
+    let io = |e: std::io::Error| e.to_string();
+
+    let greeting: &[u8] = match auth {
+        Some(_) => &[SOCKS_VERSION, 2, METHOD_NO_AUTH, METHOD_USER_PASS],
+        None => &[SOCKS_VERSION, 1, METHOD_NO_AUTH],
+    };
+    stream.write_all(greeting).map_err(io)?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).map_err(io)?;
+    if choice[0] != SOCKS_VERSION {
+        return Err(format!("unexpected version {}", choice[0]));
+    }
+    match (choice[1], auth) {
+        (METHOD_NO_AUTH, _) => {}
+        (METHOD_USER_PASS, Some(auth)) => authenticate(stream, auth)?,
+        (METHOD_NONE_ACCEPTABLE, _) => return Err("no acceptable auth method".to_string()),
+        (method, _) => return Err(format!("unexpected auth method {}", method)),
+    }
+
+    let mut request = vec![SOCKS_VERSION, COMMAND_CONNECT, 0];
+    match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let name =
+                u8::try_from(host.len()).map_err(|_| format!("host name too long: {}", host))?;
+            request.push(ATYP_DOMAIN);
+            request.push(name);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).map_err(io)?;
+
+    // Reply: version, status, reserved, then the bound address we don't need
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).map_err(io)?;
+    if reply[1] != 0 {
+        return Err(format!(
+            "connect to {}:{} failed with code {}",
+            host, port, reply[1]
+        ));
+    }
+    let address_length = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut length = [0u8; 1];
+            stream.read_exact(&mut length).map_err(io)?;
+            length[0] as usize
+        }
+        atyp => return Err(format!("unexpected address type {}", atyp)),
+    };
+    let mut bound = vec![0u8; address_length + 2];
+    stream.read_exact(&mut bound).map_err(io)?;
+    Ok(())
+}
+
+/// Username/password authentication (RFC 1929)
+fn authenticate(stream: &mut TcpStream, auth: &ProxyAuth) -> Result<(), String> {
+    let username = u8::try_from(auth.username.len()).map_err(|_| "username too long")?;
+    let password = u8::try_from(auth.password.len()).map_err(|_| "password too long")?;
+    let mut request = vec![AUTH_VERSION, username];
+    request.extend_from_slice(auth.username.as_bytes());
+    request.push(password);
+    request.extend_from_slice(auth.password.as_bytes());
+    stream.write_all(&request).map_err(|e| e.to_string())?;
+
+    let mut status = [0u8; 2];
+    stream.read_exact(&mut status).map_err(|e| e.to_string())?;
+    if status[0] != AUTH_VERSION {
+        return Err(format!("unexpected authentication version {}", status[0]));
+    }
+    if status[1] != 0 {
+        return Err(format!("authentication rejected with status {}", status[1]));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// A single-connection SOCKS5 proxy that returns the requested target and
+    /// sends `hello` as if it came from the target
+    fn mock_proxy(auth: Option<ProxyAuth>) -> (SocketAddr, thread::JoinHandle<(u8, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0u8; 2];
+            stream.read_exact(&mut header).unwrap();
+            let mut methods = vec![0u8; header[1] as usize];
+            stream.read_exact(&mut methods).unwrap();
+
+            match &auth {
+                Some(expected) => {
+                    assert!(methods.contains(&METHOD_USER_PASS));
+                    stream
+                        .write_all(&[SOCKS_VERSION, METHOD_USER_PASS])
+                        .unwrap();
+                    let mut version_and_length = [0u8; 2];
+                    stream.read_exact(&mut version_and_length).unwrap();
+                    let mut username = vec![0u8; version_and_length[1] as usize];
+                    stream.read_exact(&mut username).unwrap();
+                    let mut length = [0u8; 1];
+                    stream.read_exact(&mut length).unwrap();
+                    let mut password = vec![0u8; length[0] as usize];
+                    stream.read_exact(&mut password).unwrap();
+                    assert_eq!(username, expected.username.as_bytes());
+                    assert_eq!(password, expected.password.as_bytes());
+                    stream.write_all(&[AUTH_VERSION, 0]).unwrap();
+                }
+                None => stream.write_all(&[SOCKS_VERSION, METHOD_NO_AUTH]).unwrap(),
+            }
+
+            let mut request = [0u8; 4];
+            stream.read_exact(&mut request).unwrap();
+            let target = match request[3] {
+                ATYP_DOMAIN => {
+                    let mut length = [0u8; 1];
+                    stream.read_exact(&mut length).unwrap();
+                    let mut name = vec![0u8; length[0] as usize + 2];
+                    stream.read_exact(&mut name).unwrap();
+                    name
+                }
+                ATYP_IPV4 => {
+                    let mut ip = vec![0u8; 6];
+                    stream.read_exact(&mut ip).unwrap();
+                    ip
+                }
+                atyp => panic!("unexpected address type {}", atyp),
+            };
+            stream
+                .write_all(&[SOCKS_VERSION, 0, 0, ATYP_IPV4, 127, 0, 0, 1, 0, 80])
+                .unwrap();
+            stream.write_all(b"hello").unwrap();
+            (request[3], target)
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn test_connect_resolves_names_at_proxy() {
+        let auth = ProxyAuth {
+            username: "user".to_string(),
+            password: "secret".to_string(),
+        };
+        let (addr, proxy) = mock_proxy(Some(auth.clone()));
+        let config = ProxyConfig::Socks5 {
+            addr,
+            auth: Some(auth),
+        };
+
+        let mut stream = config
+            .connect("tracker.invalid", 6969, Duration::from_secs(5))
+            .unwrap();
+        let mut greeting = [0u8; 5];
+        stream.read_exact(&mut greeting).unwrap();
+        assert_eq!(&greeting, b"hello");
+
+        // The name went to the proxy unresolved, followed by the port
+        let (atyp, target) = proxy.join().unwrap();
+        assert_eq!(atyp, ATYP_DOMAIN);
+        assert_eq!(&target[..target.len() - 2], b"tracker.invalid");
+        assert_eq!(&target[target.len() - 2..], &6969u16.to_be_bytes());
+    }
+
+    #[test]
+    fn test_connect_to_ip_through_proxy() {
+        let (addr, proxy) = mock_proxy(None);
+        let config = ProxyConfig::Socks5 { addr, auth: None };
+
+        let stream = dial(Some(&config), "10.1.2.3", 6881, Duration::from_secs(5));
+        assert!(stream.is_ok());
+        let (atyp, target) = proxy.join().unwrap();
+        assert_eq!(atyp, ATYP_IPV4);
+        assert_eq!(target, vec![10, 1, 2, 3, 0x1a, 0xe1]);
+    }
+
+    #[test]
+    fn test_authenticate_checks_version_and_status() {
+        let auth = ProxyAuth {
+            username: "user".to_string(),
+            password: "secret".to_string(),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let replies = [[AUTH_VERSION, 0], [AUTH_VERSION, 1], [SOCKS_VERSION, 0]];
+        let server = thread::spawn(move || {
+            for reply in replies {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0u8; 13];
+                stream.read_exact(&mut request).unwrap();
+                stream.write_all(&reply).unwrap();
+            }
+        });
+
+        let results: Vec<Result<(), String>> = replies
+            .iter()
+            .map(|_| authenticate(&mut TcpStream::connect(addr).unwrap(), &auth))
+            .collect();
+        server.join().unwrap();
+
+        assert!(results[0].is_ok());
+        assert!(results[1].as_ref().unwrap_err().contains("rejected"));
+        assert!(results[2].as_ref().unwrap_err().contains("version"));
+    }
+}
@@ -0,0 +1,98 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::peer::bitfield::Bitfield;
+
+/// Pieces of a download already written and verified, saved so an
+/// interrupted download can pick up where it left off
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResumeState {
+    pub info_hash: [u8; 20],
+    pub completed: Bitfield,
+}
+
+impl ResumeState {
+    pub fn new(info_hash: [u8; 20], num_pieces: usize) -> Self {
+        ResumeState {
+            info_hash,
+            completed: Bitfield::new(num_pieces),
+        }
+    }
+
+    /// Write the state to a file: the info hash followed by the bitfield
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut data = self.info_hash.to_vec();
+        data.extend_from_slice(self.completed.as_bytes());
+
+        // Write to a temporary file first so a crash can't leave a truncated state
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, &data)
+            .and_then(|_| fs::rename(&temp_path, path))
+            .map_err(|e| format!("Failed to save resume state {}: {}", path.display(), e))
+    }
+
+    /// Load a saved state, returning `None` if there is none
+    ///
+    /// A state saved for a different torrent is an error rather than being
+    /// silently ignored.
+    pub fn load(
+        path: &Path,
+        info_hash: [u8; 20],
+        num_pieces: usize,
+    ) -> Result<Option<Self>, String> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(format!(
+                    "Failed to read resume state {}: {}",
+                    path.display(),
+                    e
+                ));
+            }
+        };
+
+        let Some((saved_hash, bits)) = data.split_first_chunk::<20>() else {
+            return Err(format!("Resume state {} is truncated", path.display()));
+        };
+        if *saved_hash != info_hash {
+            return Err(format!(
+                "Resume state {} belongs to another torrent",
+                path.display()
+            ));
+        }
+
+        Ok(Some(ResumeState {
+            info_hash,
+            completed: Bitfield::from_bytes(bits, num_pieces)?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resume_state_roundtrip() {
+        let path = std::env::temp_dir().join(format!("x402-resume-{}", std::process::id()));
+        let mut state = ResumeState::new([5u8; 20], 10);
+        state.completed.set(0);
+        state.completed.set(9);
+
+        state.save(&path).unwrap();
+        let loaded = ResumeState::load(&path, [5u8; 20], 10);
+        let other = ResumeState::load(&path, [6u8; 20], 10);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), Some(state));
+        assert!(other.is_err());
+    }
+
+    #[test]
+    fn test_resume_state_missing() {
+        let result = ResumeState::load(Path::new("/nonexistent/x402.resume"), [0u8; 20], 1);
+        assert_eq!(result, Ok(None));
+    }
+}
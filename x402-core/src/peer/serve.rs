@@ -1,148 +1,2873 @@
-use std::io;
-use std::net::{TcpListener, TcpStream};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use svix_ksuid::{KsuidLike, KsuidMs};
 
-use crate::peer::handshake::{generate_peer_id, Handshake};
+use crate::payment::exchange::{ExchangeMode, PaymentGate};
+use crate::peer::bitfield::Bitfield;
+use crate::peer::cancel::CancellationToken;
+use crate::peer::extension::ExtendedHandshake;
+use crate::peer::fast::{ALLOWED_FAST_COUNT, allowed_fast_set};
+use crate::peer::handshake::{Capabilities, Handshake, generate_peer_id, peer_id_bytes};
+use crate::peer::ipfilter::{IpRange, load_ipfilter};
+use crate::peer::message::{BlockRequest, Message};
+use crate::peer::stats::Stats;
+use crate::peer::transport::{PeerListener, PeerStream, TcpTransport, Transport};
+use crate::torrent::magnet::MagnetLink;
+use crate::torrent::store::PieceStore;
+use crate::torrent::types::Info;
+use crate::torrent::verify::verify_piece;
+use crate::tracker::announcer::Announcer;
 
-pub struct Seeder {
+/// Largest block a peer may request in a single Request message
+const MAX_BLOCK_LENGTH: u32 = 128 * 1024;
+/// Block requests queued per peer before further ones are refused; advertised
+/// as `reqq` in the extended handshake
+const MAX_QUEUED_REQUESTS: usize = 250;
+/// How long the status listener waits for a client to send its request
+const STATUS_READ_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a write to a peer may block before the peer is dropped
+const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a peer may go without sending anything before it is dropped;
+/// peers send keep-alives every two minutes
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(180);
+/// How often the statistics are saved to the stats file, if there is one
+const DEFAULT_STATS_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+/// How often `listen` checks whether it has been cancelled
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long the connections that wake cancelled accept loops up may take
+const WAKE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Extensions the seeder offers unless configured otherwise
+const DEFAULT_CAPABILITIES: Capabilities = Capabilities {
+    fast_extension: true,
+    extension_protocol: false,
+    x402: true,
+    dht: false,
+};
+/// Most DHT nodes learned from Port messages that are kept
+const MAX_DHT_NODES: usize = 256;
+
+/// Piece data for a torrent the seeder can upload
+struct ServedTorrent {
+    /// Where blocks are read from
+    store: Arc<dyn PieceStore>,
+    /// Pieces we have and advertise
+    bitfield: Bitfield,
+}
+
+impl ServedTorrent {
+    /// Read a block, refusing pieces we don't have
+    fn read_block(&self, index: u32, begin: u32, length: u32) -> Result<Vec<u8>, ReadError> {
+        if !self.bitfield.has(index as usize) {
+            return Err(ReadError::PieceNotAvailable(index));
+        }
+        Ok(self.store.read_block(index, begin, length)?)
+    }
+}
+
+/// The torrents a seeder serves
+#[derive(Default)]
+struct ServedSet {
+    /// Info hashes we're serving, in the order they were added
+    info_hashes: Vec<[u8; 20]>,
+    /// Piece data for the torrents we can upload, by info hash
+    torrents: HashMap<[u8; 20], Arc<ServedTorrent>>,
+}
+
+/// Errors reading a block to upload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadError {
+    /// The piece is not in the torrent's availability bitfield
+    PieceNotAvailable(u32),
+    /// Any other error
+    Other(String),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::PieceNotAvailable(index) => write!(f, "Piece {} is not available", index),
+            ReadError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for ReadError {
+    fn from(message: String) -> Self {
+        ReadError::Other(message)
+    }
+}
+
+/// How a seeder that charges for blocks treats peers without x402 support
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LegacyPeerPolicy {
+    /// Close the connection after the handshake, since the peer can't pay
+    #[default]
+    Reject,
+    /// Serve the peer for free
+    ServeFree,
+}
+
+impl From<ReadError> for String {
+    fn from(error: ReadError) -> Self {
+        error.to_string()
+    }
+}
+
+/// Errors that end a peer connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionError {
+    /// The peer stopped reading and a write blocked for longer than the write timeout
+    WriteTimeout,
+    /// The peer sent nothing, not even a keep-alive, for longer than the idle timeout
+    IdleTimeout,
+    /// Any other failure
+    Other(String),
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionError::WriteTimeout => write!(f, "Timed out writing to peer"),
+            ConnectionError::IdleTimeout => write!(f, "Peer was idle for too long"),
+            ConnectionError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for ConnectionError {
+    fn from(message: String) -> Self {
+        ConnectionError::Other(message)
+    }
+}
+
+impl From<ReadError> for ConnectionError {
+    fn from(error: ReadError) -> Self {
+        ConnectionError::Other(error.to_string())
+    }
+}
+
+impl From<ConnectionError> for String {
+    fn from(error: ConnectionError) -> Self {
+        error.to_string()
+    }
+}
+
+/// Why a listener could not be bound
+#[derive(Debug)]
+pub enum BindError {
+    /// The address is in use, e.g. by the previous run's sockets; this can clear up
+    AddrInUse(io::Error),
+    /// Binding the address is not allowed, e.g. a privileged port; retrying won't help
+    PermissionDenied(io::Error),
+    /// Any other failure
+    Other(io::Error),
+}
+
+impl BindError {
+    /// Whether binding may succeed if tried again later
+    pub fn is_transient(&self) -> bool {
+        matches!(self, BindError::AddrInUse(_))
+    }
+}
+
+impl fmt::Display for BindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindError::AddrInUse(e) => write!(f, "Address in use: {}", e),
+            BindError::PermissionDenied(e) => write!(f, "Permission denied: {}", e),
+            BindError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for BindError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::AddrInUse => BindError::AddrInUse(error),
+            io::ErrorKind::PermissionDenied => BindError::PermissionDenied(error),
+            _ => BindError::Other(error),
+        }
+    }
+}
+
+impl From<BindError> for io::Error {
+    fn from(error: BindError) -> Self {
+        match error {
+            BindError::AddrInUse(e) | BindError::PermissionDenied(e) | BindError::Other(e) => e,
+        }
+    }
+}
+
+/// Final accounting for a peer connection, reported when it closes
+///
+/// Lets the payment layer reconcile what the peer received against what it paid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settlement {
+    /// Address of the peer, if still known
+    pub peer: Option<SocketAddr>,
+    pub info_hash: [u8; 20],
+    /// Block payload bytes sent, not counting message framing
+    pub bytes_served: u64,
+    /// Blocks sent
+    pub blocks_served: u64,
+    /// Total price of the blocks served, 0 if they were free
+    pub charged: u64,
+    /// Total amount the peer paid
+    pub paid: u64,
+}
+
+/// Maps an info hash to info hashes of related torrents
+type RelatedTorrents = dyn Fn(&[u8; 20]) -> Vec<[u8; 20]> + Send + Sync;
+
+/// Checks the proof of a payment, given the info hash, amount and proof
+type PaymentVerifier = dyn Fn(&[u8; 20], u64, &[u8]) -> bool + Send + Sync;
+
+/// Serves pieces to peers, accepting them over TCP by default
+pub struct Seeder<T: Transport = TcpTransport> {
+    /// How peer connections are accepted
+    transport: T,
     /// The address to bind to
     address: String,
     /// The port to listen on
     port: u16,
     /// Our peer ID
     peer_id: KsuidMs,
-    /// Info hashes we're serving
-    info_hashes: Vec<[u8; 20]>,
+    /// Torrents we serve, changed through `&self` so it works while listening
+    served: Arc<RwLock<ServedSet>>,
+    /// Addresses to listen on instead of `address:port`, if set
+    listen_addrs: Vec<SocketAddr>,
+    /// Listeners created by `bind`, one per listen address
+    listeners: Vec<T::Listener>,
+    /// Tracker announce URLs to announce the served torrents to
+    trackers: Vec<String>,
+    /// Peer addresses whose connections are refused
+    ip_filter: Vec<IpRange>,
+    /// Price of a block, or 0 to serve for free
+    price: u64,
+    /// When peers have to pay relative to the blocks they receive
+    exchange_mode: ExchangeMode,
+    /// What to do with peers that don't advertise x402 when blocks have a price
+    legacy_peer_policy: LegacyPeerPolicy,
+    /// Extensions advertised in our handshake
+    capabilities: Capabilities,
+    /// How long a write to a peer may block before the peer is dropped
+    write_timeout: Duration,
+    /// How long a peer may stay silent before it is dropped
+    idle_timeout: Duration,
+    /// How often to retry a bind that fails because the address is in use
+    bind_retries: u32,
+    /// Delay before the first bind retry, doubled after each one
+    bind_backoff: Duration,
+    /// Called with the settlement of each peer connection that exchanged pieces
+    settlement: Option<Box<dyn Fn(Settlement) + Send + Sync>>,
+    /// Looks up torrents related to an info hash we don't serve
+    related: Option<Box<RelatedTorrents>>,
+    /// Checks payment proofs before they are credited, if set
+    payment_verifier: Option<Box<PaymentVerifier>>,
+    /// Upload statistics
+    stats: Stats,
+    /// File the statistics are loaded from and saved to
+    stats_file: Option<PathBuf>,
+    /// How often the statistics are saved to `stats_file` while listening
+    stats_flush_interval: Duration,
+    /// DHT nodes advertised by peers in Port messages
+    dht_nodes: Mutex<Vec<SocketAddr>>,
+    /// Port of the HTTP status listener, off if `None`
+    status_port: Option<u16>,
+    /// Status listener created by `bind`; always TCP, as it speaks HTTP
+    status_listener: Option<TcpListener>,
+    /// Stops `listen` and the peer connections when triggered
+    cancel: CancellationToken,
 }
 
 impl Seeder {
     pub fn new(address: String, port: u16) -> Self {
+        Self::over(TcpTransport::new(), address, port)
+    }
+
+    /// Accept a pending connection without blocking
+    ///
+    /// Returns `Ok(None)` when no connection is waiting, so callers can drive
+    /// their own event loop and pass accepted streams to `handle_connection`.
+    /// Requires a prior call to `bind`.
+    pub fn poll_accept(&self) -> io::Result<Option<(TcpStream, SocketAddr)>> {
+        if self.listeners.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Seeder is not bound",
+            ));
+        }
+
+        for listener in &self.listeners {
+            listener.set_nonblocking(true)?;
+            let result = listener.accept();
+            // `listen` expects blocking listeners
+            listener.set_nonblocking(false)?;
+            match result {
+                Ok((stream, addr)) => {
+                    // Accepted sockets may inherit non-blocking mode on some platforms
+                    stream.set_nonblocking(false)?;
+                    return Ok(Some((stream, addr)));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl<T: Transport> Seeder<T> {
+    /// Create a seeder that accepts peers over `transport`
+    pub fn over(transport: T, address: String, port: u16) -> Self {
         Seeder {
+            transport,
             address,
             port,
             peer_id: generate_peer_id(),
-            info_hashes: Vec::new(),
+            served: Arc::new(RwLock::new(ServedSet::default())),
+            listen_addrs: Vec::new(),
+            listeners: Vec::new(),
+            trackers: Vec::new(),
+            ip_filter: Vec::new(),
+            price: 0,
+            exchange_mode: ExchangeMode::default(),
+            legacy_peer_policy: LegacyPeerPolicy::default(),
+            capabilities: DEFAULT_CAPABILITIES,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            bind_retries: 0,
+            bind_backoff: Duration::ZERO,
+            settlement: None,
+            related: None,
+            payment_verifier: None,
+            stats: Stats::new(),
+            stats_file: None,
+            stats_flush_interval: DEFAULT_STATS_FLUSH_INTERVAL,
+            dht_nodes: Mutex::new(Vec::new()),
+            status_port: None,
+            status_listener: None,
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Stop listening when `cancel` is triggered
+    ///
+    /// `listen` then closes the peer connections and returns, without waiting
+    /// for silent peers.
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Use a fixed peer ID instead of a freshly generated one
+    pub fn with_peer_id(mut self, peer_id: KsuidMs) -> Self {
+        self.peer_id = peer_id;
+        self
+    }
+
+    /// Charge `price` for every block served
+    pub fn with_price(mut self, price: u64) -> Self {
+        self.price = price;
+        self
+    }
+
+    /// Set when peers have to pay relative to the blocks they receive
+    pub fn with_exchange_mode(mut self, exchange_mode: ExchangeMode) -> Self {
+        self.exchange_mode = exchange_mode;
+        self
+    }
+
+    /// Set how peers that don't advertise x402 are treated when blocks have a price
+    pub fn with_legacy_peer_policy(mut self, policy: LegacyPeerPolicy) -> Self {
+        self.legacy_peer_policy = policy;
+        self
+    }
+
+    /// Drop a peer once a write to it blocks for longer than `timeout`
+    ///
+    /// A peer that stops reading fills the socket buffers, after which every
+    /// write blocks; the timeout keeps it from holding a thread forever.
+    pub fn with_write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = timeout;
+        self
+    }
+
+    /// Drop a peer that sends nothing, not even a keep-alive, for `timeout`
+    ///
+    /// Defaults to three minutes, so peers sending the usual two-minute
+    /// keep-alives stay connected. `timeout` must not be zero.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Advertise these extensions instead of the Fast Extension and x402
+    ///
+    /// A connection only uses the extensions both sides advertise, so turning
+    /// x402 off treats every peer as one that can't pay.
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Retry binding up to `retries` times while the address is in use, waiting
+    /// `backoff` before the first retry and twice as long before each next one
+    ///
+    /// Helps on restart, when the old process's port is briefly unavailable.
+    /// Other bind errors, like a denied privileged port, fail right away.
+    pub fn with_bind_retry(mut self, retries: u32, backoff: Duration) -> Self {
+        self.bind_retries = retries;
+        self.bind_backoff = backoff;
+        self
+    }
+
+    /// Report the bytes served and the payments received to `settlement` when
+    /// a peer that exchanged pieces disconnects, however the connection ended
+    pub fn with_settlement_handler<F: Fn(Settlement) + Send + Sync + 'static>(
+        mut self,
+        settlement: F,
+    ) -> Self {
+        self.settlement = Some(Box::new(settlement));
+        self
+    }
+
+    /// Credit a peer's payment only once `verifier` accepts its proof
+    ///
+    /// `verifier` is called with the info hash, amount and proof of each
+    /// Payment message; a rejected proof drops the peer. Without a verifier,
+    /// payments are credited on the peer's word alone and counted as claimed.
+    pub fn with_payment_verifier<F: Fn(&[u8; 20], u64, &[u8]) -> bool + Send + Sync + 'static>(
+        mut self,
+        verifier: F,
+    ) -> Self {
+        self.payment_verifier = Some(Box::new(verifier));
+        self
+    }
+
+    /// Suggest alternatives to x402 peers asking for a torrent we don't serve
+    ///
+    /// `related` maps the unknown info hash to related ones; those we serve are
+    /// sent in a Suggest Torrents message before the connection is closed.
+    /// Other peers are disconnected right away as before.
+    pub fn with_related_torrents<F: Fn(&[u8; 20]) -> Vec<[u8; 20]> + Send + Sync + 'static>(
+        mut self,
+        related: F,
+    ) -> Self {
+        self.related = Some(Box::new(related));
+        self
+    }
+
+    /// Torrents we serve that are related to `info_hash`, per the lookup
+    fn suggestions(&self, info_hash: &[u8; 20]) -> Vec<[u8; 20]> {
+        let Some(related) = &self.related else {
+            return Vec::new();
+        };
+        related(info_hash)
+            .into_iter()
+            .filter(|related| self.is_serving(related))
+            .collect()
+    }
+
+    /// Serve live statistics as JSON over HTTP on `port` of the seeder's address
+    ///
+    /// Any request gets the same response, with the uptime, the served info
+    /// hashes, the active connections and the bytes uploaded.
+    pub fn with_status_port(mut self, port: u16) -> Self {
+        self.status_port = Some(port);
+        self
+    }
+
+    /// Upload statistics
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Keep the lifetime statistics in `path` across restarts
+    ///
+    /// The saved counters are loaded now, and saved back periodically while
+    /// listening, when `listen` returns and when the seeder is dropped.
+    pub fn load_stats_file(&mut self, path: &Path) -> Result<(), String> {
+        self.stats = Stats::load(path)?;
+        self.stats_file = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Save the statistics to the stats file this often while listening
+    pub fn with_stats_flush_interval(mut self, interval: Duration) -> Self {
+        self.stats_flush_interval = interval;
+        self
+    }
+
+    /// Save the statistics to the stats file now, if there is one
+    pub fn flush_stats(&self) -> Result<(), String> {
+        match &self.stats_file {
+            Some(path) => self.stats.save(path),
+            None => Ok(()),
+        }
+    }
+
+    /// DHT nodes peers advertised in Port messages, to bootstrap a DHT node from
+    ///
+    /// Only recorded when the DHT is enabled in our capabilities and the peer
+    /// advertised it too.
+    pub fn dht_nodes(&self) -> Vec<SocketAddr> {
+        self.dht_nodes.lock().unwrap().clone()
+    }
+
+    /// Remember a DHT node, up to `MAX_DHT_NODES` of them
+    fn add_dht_node(&self, node: SocketAddr) {
+        let mut nodes = self.dht_nodes.lock().unwrap();
+        if node.port() != 0 && !nodes.contains(&node) && nodes.len() < MAX_DHT_NODES {
+            nodes.push(node);
+        }
+    }
+
+    /// Local address of the status listener, if enabled and bound
+    pub fn status_addr(&self) -> Option<SocketAddr> {
+        self.status_listener
+            .as_ref()
+            .and_then(|l| l.local_addr().ok())
+    }
+
+    /// Current statistics as a JSON object
+    pub fn status_json(&self) -> String {
+        let torrents: Vec<String> = self
+            .info_hashes()
+            .iter()
+            .map(|info_hash| format!("\"{}\"", hex::encode(info_hash)))
+            .collect();
+        format!(
+            "{{\"uptime_secs\":{},\"torrents\":[{}],\"active_connections\":{},\"bytes_uploaded\":{}}}",
+            self.stats.uptime().as_secs(),
+            torrents.join(","),
+            self.stats.active_connections(),
+            self.stats.bytes_uploaded()
+        )
+    }
+
+    /// Current statistics in the Prometheus text exposition format
+    #[cfg(feature = "metrics")]
+    pub fn metrics_text(&self) -> String {
+        use std::fmt::Write as _;
+
+        let stats = &self.stats;
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, u64)]| {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(text, "{}{} {}", name, labels, value);
+            }
+        };
+        let value = |value: u64| [(String::new(), value)];
+
+        metric(
+            "x402_uptime_seconds",
+            "gauge",
+            "Seconds since the seeder started",
+            &value(stats.uptime().as_secs()),
+        );
+        metric(
+            "x402_active_connections",
+            "gauge",
+            "Peer connections currently being handled",
+            &value(stats.active_connections() as u64),
+        );
+        metric(
+            "x402_connections_total",
+            "counter",
+            "Peer connections accepted",
+            &value(stats.total_connections()),
+        );
+        metric(
+            "x402_rejected_connections_total",
+            "counter",
+            "Peer connections refused",
+            &value(stats.rejected_connections()),
+        );
+        let uploads: Vec<(String, u64)> = self
+            .info_hashes()
+            .iter()
+            .map(|info_hash| {
+                let labels = format!("{{info_hash=\"{}\"}}", hex::encode(info_hash));
+                (labels, stats.bytes_uploaded_for(info_hash))
+            })
+            .collect();
+        metric(
+            "x402_uploaded_bytes_total",
+            "counter",
+            "Block bytes sent to peers",
+            &uploads,
+        );
+        metric(
+            "x402_payments_received_total",
+            "counter",
+            "Payments whose proof the payment verifier accepted",
+            &value(stats.payments_received()),
+        );
+        metric(
+            "x402_paid_amount_total",
+            "counter",
+            "Sum of the verified payments",
+            &value(stats.amount_paid()),
+        );
+        metric(
+            "x402_payments_claimed_total",
+            "counter",
+            "Payments credited unverified, as there is no payment verifier",
+            &value(stats.payments_claimed()),
+        );
+        metric(
+            "x402_claimed_amount_total",
+            "counter",
+            "Sum of the unverified payments",
+            &value(stats.amount_claimed()),
+        );
+        text
+    }
+
+    /// Payment accounting for a new peer, or an error if the peer must be refused
+    ///
+    /// Only peers that advertised x402 in their handshake are charged; the
+    /// others are served for free or rejected, per the legacy peer policy.
+    fn payment_gate(&self, peer_supports_x402: bool) -> Result<Option<PaymentGate>, String> {
+        if self.price == 0 {
+            return Ok(None);
         }
+        if peer_supports_x402 {
+            return Ok(Some(PaymentGate::new(self.exchange_mode, self.price)));
+        }
+        match self.legacy_peer_policy {
+            LegacyPeerPolicy::Reject => {
+                Err("Peer does not support x402 payments and blocks are not free".to_string())
+            }
+            LegacyPeerPolicy::ServeFree => Ok(None),
+        }
+    }
+
+    /// Listen on several addresses, e.g. both an IPv4 and an IPv6 one,
+    /// instead of the address and port given to `new`
+    pub fn with_listen_addrs(mut self, addrs: Vec<SocketAddr>) -> Self {
+        self.listen_addrs = addrs;
+        self
+    }
+
+    /// Get our peer ID
+    pub fn peer_id(&self) -> KsuidMs {
+        self.peer_id
+    }
+
+    /// Set the tracker announce URLs to announce the served torrents to
+    pub fn set_trackers(&mut self, trackers: Vec<String>) {
+        self.trackers = trackers;
+    }
+
+    /// Tracker announce URLs the served torrents are announced to
+    pub fn trackers(&self) -> &[String] {
+        &self.trackers
+    }
+
+    /// Refuse connections from peers in any of `ranges`
+    pub fn set_ip_filter(&mut self, ranges: Vec<IpRange>) {
+        self.ip_filter = ranges;
+    }
+
+    /// Load the ranges to block from an `ipfilter.dat` style file
+    pub fn load_ip_filter_file(&mut self, path: &Path) -> Result<(), String> {
+        self.set_ip_filter(load_ipfilter(path)?);
+        Ok(())
+    }
+
+    /// Whether connections from `ip` are refused by the IP filter
+    pub fn is_blocked(&self, ip: IpAddr) -> bool {
+        self.ip_filter.iter().any(|range| range.contains(ip))
+    }
+
+    /// Create an announcer for the served torrents on the configured trackers
+    ///
+    /// Announces the bound port if the seeder has been bound, so binding to
+    /// port 0 advertises the port actually chosen.
+    pub fn announcer(&self) -> Announcer {
+        let port = self.listening_port().unwrap_or(self.port);
+        let peer_id = peer_id_bytes(&self.peer_id);
+        Announcer::new(self.trackers.clone(), self.info_hashes(), peer_id, port)
+    }
+
+    /// Load tracker announce URLs from a file, one per line
+    ///
+    /// Blank lines and lines starting with `#` are ignored.
+    pub fn load_trackers_file(&mut self, path: &Path) -> Result<(), String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read trackers file {}: {}", path.display(), e))?;
+        self.set_trackers(parse_trackers(&contents));
+        Ok(())
     }
 
     /// Add an info hash that this seeder can serve
-    pub fn add_torrent(&mut self, info_hash: [u8; 20]) {
-        self.info_hashes.push(info_hash);
+    pub fn add_torrent(&self, info_hash: [u8; 20]) {
+        let mut served = self.served.write().unwrap();
+        if !served.info_hashes.contains(&info_hash) {
+            served.info_hashes.push(info_hash);
+        }
     }
 
-    /// Add an info hash from hex string
-    pub fn add_torrent_hex(&mut self, info_hash_hex: &str) -> Result<(), String> {
-        if info_hash_hex.len() != 40 {
-            return Err(format!(
-                "Invalid info hash length: expected 40, got {}",
-                info_hash_hex.len()
-            ));
+    /// Add a torrent together with the data to upload its pieces from
+    ///
+    /// `bitfield` lists the pieces available in `store`; requests for other
+    /// pieces are refused.
+    pub fn add_torrent_data(
+        &self,
+        info_hash: [u8; 20],
+        store: Arc<dyn PieceStore>,
+        bitfield: Bitfield,
+    ) {
+        let mut served = self.served.write().unwrap();
+        if !served.info_hashes.contains(&info_hash) {
+            served.info_hashes.push(info_hash);
+        }
+        served
+            .torrents
+            .insert(info_hash, Arc::new(ServedTorrent { store, bitfield }));
+    }
+
+    /// Add a torrent with its data, working out which pieces `store` holds
+    ///
+    /// With `verify`, every piece is read and hashed against `info`, which can
+    /// take a while for large content. Without it, all pieces are assumed
+    /// present and correct: a truncated or corrupt file is then served as is,
+    /// so peers download pieces that fail their hash check, and the seeder
+    /// may well be banned for it. Only skip verification for data you trust.
+    ///
+    /// Returns the bitfield the torrent is served with.
+    pub fn add_torrent_with_data(
+        &self,
+        info_hash: [u8; 20],
+        info: &Info,
+        store: Arc<dyn PieceStore>,
+        verify: bool,
+    ) -> Result<Bitfield, String> {
+        let num_pieces = info.num_pieces();
+        let bitfield = if verify {
+            let mut bitfield = Bitfield::new(num_pieces);
+            for index in 0..num_pieces {
+                let piece = store.read_block(index as u32, 0, info.piece_size(index) as u32)?;
+                if verify_piece(info, index, &piece) {
+                    bitfield.set(index);
+                }
+            }
+            bitfield
+        } else {
+            Bitfield::full(num_pieces)
+        };
+        self.add_torrent_data(info_hash, store, bitfield.clone());
+        Ok(bitfield)
+    }
+
+    /// Stop serving a torrent, returning whether it was served
+    ///
+    /// New handshakes for it are refused; connections already exchanging its
+    /// pieces keep going until the peer disconnects.
+    pub fn remove_torrent(&self, info_hash: &[u8; 20]) -> bool {
+        let mut served = self.served.write().unwrap();
+        served.torrents.remove(info_hash);
+        let count = served.info_hashes.len();
+        served.info_hashes.retain(|served| served != info_hash);
+        served.info_hashes.len() != count
+    }
+
+    /// Whether handshakes for `info_hash` are accepted
+    pub fn is_serving(&self, info_hash: &[u8; 20]) -> bool {
+        self.served.read().unwrap().info_hashes.contains(info_hash)
+    }
+
+    /// Info hashes of the served torrents, in the order they were added
+    pub fn info_hashes(&self) -> Vec<[u8; 20]> {
+        self.served.read().unwrap().info_hashes.clone()
+    }
+
+    /// Log the info hashes being served, so operators can confirm what's loaded
+    fn log_served_torrents(&self) {
+        let info_hashes = self.info_hashes();
+        if info_hashes.is_empty() {
+            log::warn!("No torrents loaded; every connection will be rejected");
+            return;
         }
+        for info_hash in &info_hashes {
+            log::info!("Serving info hash {}", hex::encode(info_hash));
+        }
+        log::info!("Serving {} torrents", info_hashes.len());
+    }
+
+    /// Data of a served torrent, if we have it
+    fn torrent(&self, info_hash: &[u8; 20]) -> Option<Arc<ServedTorrent>> {
+        self.served.read().unwrap().torrents.get(info_hash).cloned()
+    }
+
+    /// Pieces we advertise for a torrent, if we have data for it
+    pub fn bitfield(&self, info_hash: &[u8; 20]) -> Option<Bitfield> {
+        self.torrent(info_hash)
+            .map(|torrent| torrent.bitfield.clone())
+    }
+
+    /// Pieces of a torrent we don't have, if we have data for it
+    pub fn missing_pieces(&self, info_hash: &[u8; 20]) -> Option<Vec<usize>> {
+        let bitfield = self.bitfield(info_hash)?;
+        Some(
+            (0..bitfield.num_pieces())
+                .filter(|&index| !bitfield.has(index))
+                .collect(),
+        )
+    }
 
-        let mut info_hash = [0u8; 20];
-        for i in 0..20 {
-            info_hash[i] = u8::from_str_radix(&info_hash_hex[i * 2..i * 2 + 2], 16)
-                .map_err(|e| format!("Invalid hex: {}", e))?;
+    /// Read a block of a served torrent
+    ///
+    /// Fails with `PieceNotAvailable` for pieces outside the torrent's bitfield.
+    pub fn read_block(
+        &self,
+        info_hash: &[u8; 20],
+        index: u32,
+        begin: u32,
+        length: u32,
+    ) -> Result<Vec<u8>, ReadError> {
+        let torrent = self
+            .torrent(info_hash)
+            .ok_or_else(|| format!("Unknown torrent {}", hex::encode(info_hash)))?;
+        torrent.read_block(index, begin, length)
+    }
+
+    /// Add an info hash from hex string
+    pub fn add_torrent_hex(&self, info_hash_hex: &str) -> Result<(), String> {
+        self.add_torrent(parse_info_hash_hex(info_hash_hex)?);
+        Ok(())
+    }
+
+    /// Serve the info hashes listed in an allowlist file, one in hex per line
+    ///
+    /// Blank lines and lines starting with `#` are ignored. Nothing is added
+    /// if any line isn't a valid info hash. Returns how many info hashes the
+    /// file lists.
+    pub fn load_allowlist(&self, path: &Path) -> Result<usize, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read allowlist {}: {}", path.display(), e))?;
+        let mut info_hashes = Vec::new();
+        for (number, line) in contents.lines().map(str::trim).enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let info_hash = parse_info_hash_hex(line)
+                .map_err(|e| format!("{} line {}: {}", path.display(), number + 1, e))?;
+            if !info_hashes.contains(&info_hash) {
+                info_hashes.push(info_hash);
+            }
         }
+        for &info_hash in &info_hashes {
+            self.add_torrent(info_hash);
+        }
+        Ok(info_hashes.len())
+    }
 
-        self.add_torrent(info_hash);
+    /// Add the v1 info hash of a magnet link, in hex or base32 form
+    ///
+    /// Only handshakes are accepted for it: block requests are refused until
+    /// the data is added with `add_torrent_data`.
+    pub fn add_torrent_from_magnet(&self, magnet: &MagnetLink) -> Result<(), String> {
+        if magnet.info_hash.is_empty() {
+            return Err("Magnet link has no v1 info hash".to_string());
+        }
+        self.add_torrent_hex(&magnet.info_hash_hex()?)?;
+        log::warn!(
+            "Serving {} from a magnet link; no data is available until its files are added",
+            magnet.output_name()
+        );
         Ok(())
     }
 
-    /// Start listening for incoming connections
+    /// Bind the listening socket and return the actual local address
+    ///
+    /// Binding to port 0 picks an ephemeral port, reported in the returned address.
+    /// With several listen addresses, this is the address of the first listener.
+    pub fn bind(&mut self) -> Result<SocketAddr, BindError> {
+        let addrs = self.bind_all()?;
+        Ok(addrs[0])
+    }
+
+    /// Bind a listener for every listen address and return their local addresses
+    pub fn bind_all(&mut self) -> Result<Vec<SocketAddr>, BindError> {
+        self.listeners = self.bind_listeners()?;
+        self.status_listener = self.bind_status_listener()?;
+        Ok(self.local_addrs()?)
+    }
+
+    fn bind_status_listener(&self) -> Result<Option<TcpListener>, BindError> {
+        let tcp = TcpTransport::new();
+        self.status_port
+            .map(|port| self.bind_listener((self.address.as_str(), port), |addr| tcp.bind(addr)))
+            .transpose()
+    }
+
+    fn bind_listeners(&self) -> Result<Vec<T::Listener>, BindError> {
+        let bind = |addr| self.transport.bind(addr);
+        if self.listen_addrs.is_empty() {
+            let listener = self.bind_listener((self.address.as_str(), self.port), bind)?;
+            return Ok(vec![listener]);
+        }
+        self.listen_addrs
+            .iter()
+            .map(|addr| self.bind_listener(addr, bind))
+            .collect()
+    }
+
+    /// Bind a listener with `bind`, retrying with backoff while the address is in use
+    fn bind_listener<A: ToSocketAddrs, L>(
+        &self,
+        addr: A,
+        bind: impl Fn(SocketAddr) -> io::Result<L>,
+    ) -> Result<L, BindError> {
+        let mut backoff = self.bind_backoff;
+        let mut retries = self.bind_retries;
+        loop {
+            match bind_first(&addr, &bind) {
+                Err(e) if e.is_transient() && retries > 0 => {
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    retries -= 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Get the local address of the first bound listener, if `bind` has been called
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listeners.first().and_then(|l| l.local_addr().ok())
+    }
+
+    /// Port peers connect to, as actually bound, or `None` until `bind` has been called
+    ///
+    /// With several listen addresses, this is the port of the first listener.
+    pub fn listening_port(&self) -> Option<u16> {
+        self.local_addr().map(|addr| addr.port())
+    }
+
+    /// Get the local addresses of every bound listener
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners
+            .iter()
+            .map(|listener| listener.local_addr())
+            .collect()
+    }
+
+    /// Start listening for incoming connections, blocking until cancelled
+    ///
+    /// Each listener accepts connections on its own thread; this returns only
+    /// once every listener has stopped.
     pub fn listen(&self) -> io::Result<()> {
-        let addr = format!("{}:{}", self.address, self.port);
-        let listener = TcpListener::bind(&addr)?;
-        println!("Seeder listening on {}", addr);
+        let bound;
+        let listeners = if self.listeners.is_empty() {
+            bound = self.bind_listeners()?;
+            &bound
+        } else {
+            &self.listeners
+        };
+        let bound_status;
+        let status_listener = match &self.status_listener {
+            Some(listener) => Some(listener),
+            None => {
+                bound_status = self.bind_status_listener()?;
+                bound_status.as_ref()
+            }
+        };
+        let peer_addrs = listeners
+            .iter()
+            .map(|listener| listener.local_addr())
+            .collect::<io::Result<Vec<_>>>()?;
+        let status_addr = status_listener
+            .map(|listener| listener.local_addr())
+            .transpose()?;
+        for addr in &peer_addrs {
+            println!("Seeder listening on {}", addr);
+        }
+        if let Some(addr) = status_addr {
+            println!("Status available on http://{}", addr);
+        }
         println!("Peer ID: {}", hex::encode(self.peer_id.bytes()));
+        if self.price > 0 && self.payment_verifier.is_none() {
+            log::warn!("No payment verifier set; peers' payments are taken on their word");
+        }
+        self.log_served_torrents();
 
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    println!("New connection from: {}", stream.peer_addr()?);
-                    if let Err(e) = self.handle_connection(stream) {
-                        eprintln!("Error handling connection: {}", e);
-                    }
+        thread::scope(|scope| {
+            if let Some(listener) = status_listener {
+                scope.spawn(move || self.status_loop(listener));
+            }
+            // Dropped once the listeners stop, which ends the flush loop
+            let (stop_flushing, flush_stop) = mpsc::channel::<()>();
+            if self.stats_file.is_some() {
+                scope.spawn(move || self.stats_flush_loop(flush_stop));
+            }
+            let acceptors: Vec<_> = listeners
+                .iter()
+                .map(|listener| scope.spawn(move || self.accept_loop(scope, listener)))
+                .collect();
+            scope.spawn(move || self.wake_on_cancel(&peer_addrs, status_addr));
+
+            let mut result = Ok(());
+            for acceptor in acceptors {
+                let outcome = acceptor
+                    .join()
+                    .unwrap_or_else(|_| Err(io::Error::other("Listener thread panicked")));
+                if result.is_ok() {
+                    result = outcome;
+                }
+            }
+            drop(stop_flushing);
+            result
+        })
+    }
+
+    /// Save the statistics every flush interval, and once more when stopped
+    fn stats_flush_loop(&self, stop: mpsc::Receiver<()>) {
+        loop {
+            let stopped = match stop.recv_timeout(self.stats_flush_interval) {
+                Err(RecvTimeoutError::Timeout) => false,
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+            };
+            if let Err(e) = self.flush_stats() {
+                log::warn!("Failed to save stats: {}", e);
+            }
+            if stopped {
+                return;
+            }
+        }
+    }
+
+    /// Connect to every listener once cancelled, waking up the loops accepting there
+    ///
+    /// Accepting blocks, so the loops only see the cancellation when the next
+    /// connection comes in.
+    fn wake_on_cancel(&self, peer_addrs: &[SocketAddr], status_addr: Option<SocketAddr>) {
+        while !self.cancel.is_cancelled() {
+            thread::sleep(CANCEL_POLL_INTERVAL);
+        }
+        for &addr in peer_addrs {
+            if let Err(e) = self.transport.connect(reachable(addr), WAKE_TIMEOUT) {
+                log::warn!("Failed to wake the listener on {}: {}", addr, e);
+            }
+        }
+        if let Some(addr) = status_addr
+            && let Err(e) = TcpStream::connect_timeout(&reachable(addr), WAKE_TIMEOUT)
+        {
+            log::warn!("Failed to wake the status listener on {}: {}", addr, e);
+        }
+    }
+
+    /// Serve peers connecting through a listener of any transport, blocking forever
+    ///
+    /// Unlike `listen`, nothing is bound and no status listener is started;
+    /// use this to accept peers over a transport other than TCP.
+    pub fn serve<L: PeerListener>(&self, listener: &L) -> io::Result<()> {
+        println!("Seeder listening on {}", listener.local_addr()?);
+        thread::scope(|scope| self.accept_loop(scope, listener))
+    }
+
+    /// Accept connections from one listener, handling each on its own thread
+    fn accept_loop<'scope, L: PeerListener>(
+        &'scope self,
+        scope: &'scope thread::Scope<'scope, '_>,
+        listener: &L,
+    ) -> io::Result<()> {
+        loop {
+            let accepted = listener.accept();
+            if self.cancel.is_cancelled() {
+                return Ok(());
+            }
+            match accepted {
+                Ok((stream, addr)) => {
+                    println!("New connection from: {}", addr);
+                    scope.spawn(move || {
+                        if let Err(e) = self.handle_connection(stream) {
+                            log::warn!("Error handling connection: {}", e);
+                        }
+                    });
                 }
                 Err(e) => {
-                    eprintln!("Connection failed: {}", e);
+                    log::warn!("Connection failed: {}", e);
                 }
             }
         }
+    }
 
-        Ok(())
+    /// Answer every status request with the current statistics
+    fn status_loop(&self, listener: &TcpListener) {
+        for stream in listener.incoming() {
+            if self.cancel.is_cancelled() {
+                return;
+            }
+            let result = stream.and_then(|stream| self.send_status(stream));
+            if let Err(e) = result {
+                log::warn!("Status request failed: {}", e);
+            }
+        }
+    }
+
+    fn send_status(&self, mut stream: TcpStream) -> io::Result<()> {
+        // The request is read so the client sees a clean close instead of a
+        // reset; only the metrics path is told apart
+        stream.set_read_timeout(Some(STATUS_READ_TIMEOUT))?;
+        let mut request = [0u8; 1024];
+        let _ = stream.read(&mut request);
+
+        #[cfg(feature = "metrics")]
+        if request.starts_with(b"GET /metrics ") {
+            return write_http_response(
+                &mut stream,
+                "text/plain; version=0.0.4",
+                &self.metrics_text(),
+            );
+        }
+        write_http_response(&mut stream, "application/json", &self.status_json())
     }
 
     /// Handle an incoming peer connection
-    fn handle_connection(&self, mut stream: TcpStream) -> Result<(), String> {
+    pub fn handle_connection<S: PeerStream>(&self, mut stream: S) -> Result<(), ConnectionError> {
+        let peer_addr = stream
+            .peer_addr()
+            .map_err(|e| format!("Failed to get peer address: {}", e))?;
+        if self.is_blocked(peer_addr.ip()) {
+            // Dropping the stream closes it before anything is read
+            self.stats.add_rejected();
+            return Err(format!("Refused blocked peer {}", peer_addr.ip()).into());
+        }
+        stream
+            .set_write_timeout(Some(self.write_timeout))
+            .map_err(|e| format!("Failed to set write timeout: {}", e))?;
+        // Wakes blocked reads up so idle peers can be dropped
+        stream
+            .set_read_timeout(Some(self.idle_timeout))
+            .map_err(|e| format!("Failed to set read timeout: {}", e))?;
+        let _connection = self.stats.connection();
         println!("Waiting for handshake...");
 
         // Receive the handshake from the leecher
-        let handshake = Handshake::receive(&mut stream)?;
+        let connected = Instant::now();
+        while !wait_for_message(&stream)? {
+            if self.cancel.is_cancelled() {
+                return Ok(());
+            }
+            if connected.elapsed() >= self.idle_timeout {
+                return Err(ConnectionError::IdleTimeout);
+            }
+        }
+        let handshake =
+            Handshake::receive(&mut stream).map_err(|e| self.read_error(e, connected))?;
 
         println!("Received handshake:");
         println!("  Info Hash: {}", handshake.info_hash_hex());
         println!("  Peer ID: {}", hex::encode(handshake.peer_id.bytes()));
 
         // Check if we have this torrent
-        if !self.info_hashes.contains(&handshake.info_hash) {
+        if !self.is_serving(&handshake.info_hash) {
+            self.stats.add_rejected();
+            let suggestions = self.suggestions(&handshake.info_hash);
+            if handshake.capabilities().x402 && !suggestions.is_empty() {
+                // The handshake only frames the suggestion; nothing is served
+                let response = Handshake::new(handshake.info_hash, self.peer_id)
+                    .with_reserved(self.capabilities.to_reserved());
+                write_result(response.send(&mut stream), "handshake")?;
+                write_result(
+                    Message::SuggestTorrents(suggestions).send(&mut stream),
+                    "torrent suggestions",
+                )?;
+            }
             return Err(format!(
                 "We don't have torrent with info hash: {}",
                 handshake.info_hash_hex()
-            ));
+            )
+            .into());
         }
 
         println!("Info hash matches! Sending handshake response...");
 
-        // Send our handshake response
-        let response = Handshake::new(handshake.info_hash, self.peer_id);
-        response
-            .send(&mut stream)
-            .map_err(|e| format!("Failed to send handshake: {}", e))?;
+        // Send our handshake response, offering our extensions
+        let response = Handshake::new(handshake.info_hash, self.peer_id)
+            .with_reserved(self.capabilities.to_reserved());
+        write_result(response.send(&mut stream), "handshake")?;
+        let negotiated = self.capabilities.intersect(handshake.capabilities());
 
         println!("Handshake successful!");
 
-        // TODO: Continue with piece exchange protocol
-
-        Ok(())
+        // The connection keeps the torrent's data even if it is removed meanwhile
+        match self.torrent(&handshake.info_hash) {
+            Some(torrent) => {
+                let gate = self.payment_gate(negotiated.x402).inspect_err(|_| {
+                    self.stats.add_rejected();
+                })?;
+                self.serve_pieces(
+                    &mut stream,
+                    &handshake.info_hash,
+                    &torrent,
+                    negotiated,
+                    gate,
+                )
+            }
+            None => Ok(()),
+        }
     }
-}
 
-#[cfg(test)]
+    /// Check a peer's payment before it is credited, and count it in the stats
+    fn check_payment(&self, info_hash: &[u8; 20], amount: u64, proof: &[u8]) -> Result<(), String> {
+        match &self.payment_verifier {
+            Some(verifier) if !verifier(info_hash, amount, proof) => {
+                Err(format!("Payment of {} has an invalid proof", amount))
+            }
+            Some(_) => {
+                self.stats.add_payment(amount);
+                Ok(())
+            }
+            None => {
+                self.stats.add_claimed_payment(amount);
+                Ok(())
+            }
+        }
+    }
+
+    /// Report a failed read as an idle timeout if nothing arrived since `last_message`
+    /// for the whole idle timeout
+    fn read_error(&self, error: String, last_message: Instant) -> ConnectionError {
+        if last_message.elapsed() >= self.idle_timeout {
+            ConnectionError::IdleTimeout
+        } else {
+            ConnectionError::Other(error)
+        }
+    }
+
+    /// Run the piece exchange with a peer until it disconnects
+    ///
+    /// Requested blocks are queued and sent one at a time, reading any message
+    /// the peer sent in between first, so a Cancel can still drop a queued block.
+    fn serve_pieces<S: PeerStream>(
+        &self,
+        stream: &mut S,
+        info_hash: &[u8; 20],
+        torrent: &ServedTorrent,
+        capabilities: Capabilities,
+        gate: Option<PaymentGate>,
+    ) -> Result<(), ConnectionError> {
+        let mut session = if capabilities.fast_extension {
+            // The allowed fast set is only defined for IPv4 peers
+            let allowed_fast = match stream.peer_addr() {
+                Ok(SocketAddr::V4(addr)) => allowed_fast_set(
+                    *addr.ip(),
+                    info_hash,
+                    torrent.bitfield.num_pieces(),
+                    ALLOWED_FAST_COUNT,
+                ),
+                _ => Vec::new(),
+            };
+            UploadSession::with_fast_extension(allowed_fast, &torrent.bitfield)
+        } else {
+            UploadSession::new()
+        };
+        session.gate = gate;
+        session.dht = capabilities.dht;
+        session.extended = capabilities.extension_protocol;
+
+        let result = self.exchange(stream, info_hash, torrent, &mut session);
+        if let Some(settlement) = &self.settlement {
+            settlement(Settlement {
+                peer: stream.peer_addr().ok(),
+                info_hash: *info_hash,
+                bytes_served: session.bytes_served,
+                blocks_served: session.blocks_served,
+                charged: session.gate.as_ref().map_or(0, PaymentGate::charged),
+                paid: session.gate.as_ref().map_or(0, PaymentGate::paid),
+            });
+        }
+        result
+    }
+
+    /// Send our pieces, then answer the peer's messages and requests until it disconnects
+    fn exchange<S: PeerStream>(
+        &self,
+        stream: &mut S,
+        info_hash: &[u8; 20],
+        torrent: &ServedTorrent,
+        session: &mut UploadSession,
+    ) -> Result<(), ConnectionError> {
+        for message in session.greeting(&torrent.bitfield) {
+            write_result(message.send(stream), "message")?;
+        }
+        if session.extended {
+            let handshake = ExtendedHandshake {
+                reqq: Some(MAX_QUEUED_REQUESTS as u32),
+                ..Default::default()
+            };
+            write_result(handshake.to_message()?.send(stream), "extended handshake")?;
+        }
+
+        let mut last_message = Instant::now();
+        loop {
+            if self.cancel.is_cancelled() {
+                break;
+            }
+            // A peer busy downloading still has to send keep-alives
+            if last_message.elapsed() >= self.idle_timeout {
+                return Err(ConnectionError::IdleTimeout);
+            }
+            if !session.can_send() || message_pending(&*stream)? {
+                if !wait_for_message(&*stream)? {
+                    continue;
+                }
+                let message =
+                    Message::try_receive(stream).map_err(|e| self.read_error(e, last_message))?;
+                let Some(message) = message else {
+                    break;
+                };
+                last_message = Instant::now();
+                if let Message::Payment { amount, proof } = &message {
+                    self.check_payment(info_hash, *amount, proof)?;
+                }
+                // Without the DHT negotiated the port means nothing to us
+                if let Message::Port(port) = message
+                    && session.dht
+                    && let Ok(peer) = stream.peer_addr()
+                {
+                    self.add_dht_node(SocketAddr::new(peer.ip(), port));
+                }
+                if let Some(reply) = session.on_message(message, torrent)? {
+                    write_result(reply.send(stream), "message")?;
+                }
+            } else if let Some(request) = session.next_block() {
+                let block = torrent.read_block(request.index, request.begin, request.length)?;
+                let length = block.len() as u64;
+                let piece = Message::Piece {
+                    index: request.index,
+                    begin: request.begin,
+                    block,
+                };
+                write_result(piece.send(stream), "piece")?;
+                session.bytes_served += length;
+                session.blocks_served += 1;
+                self.stats.add_uploaded(info_hash, length);
+            }
+        }
+
+        println!("Peer disconnected");
+        Ok(())
+    }
+}
+
+impl<T: Transport> Drop for Seeder<T> {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush_stats() {
+            log::warn!("Failed to save stats: {}", e);
+        }
+    }
+}
+
+/// Upload state for a single peer connection
+struct UploadSession {
+    /// Whether we are choking the peer
+    am_choking: bool,
+    /// Blocks requested by the peer that have not been sent yet
+    queue: VecDeque<BlockRequest>,
+    /// The requests in `queue`, to spot duplicates without scanning it
+    queued: HashSet<BlockRequest>,
+    /// Whether the Fast Extension was negotiated
+    fast: bool,
+    /// Whether both sides support the DHT
+    dht: bool,
+    /// Whether the Extension Protocol was negotiated
+    extended: bool,
+    /// Pieces the peer may request while choked
+    allowed_fast: Vec<u32>,
+    /// Payment accounting, if blocks are not free
+    gate: Option<PaymentGate>,
+    /// Block payload bytes sent to the peer
+    bytes_served: u64,
+    /// Blocks sent to the peer
+    blocks_served: u64,
+}
+
+impl UploadSession {
+    fn new() -> Self {
+        UploadSession {
+            am_choking: true,
+            queue: VecDeque::new(),
+            queued: HashSet::new(),
+            fast: false,
+            dht: false,
+            extended: false,
+            allowed_fast: Vec::new(),
+            gate: None,
+            bytes_served: 0,
+            blocks_served: 0,
+        }
+    }
+
+    /// Whether a queued block can be sent right away
+    fn can_send(&self) -> bool {
+        !self.queue.is_empty() && self.gate.as_ref().is_none_or(PaymentGate::may_serve)
+    }
+
+    /// Take the next block to send, charging the peer for it
+    fn next_block(&mut self) -> Option<BlockRequest> {
+        if !self.can_send() {
+            return None;
+        }
+        if let Some(gate) = &mut self.gate {
+            gate.on_block_served();
+        }
+        let request = self.queue.pop_front()?;
+        self.queued.remove(&request);
+        Some(request)
+    }
+
+    /// A session with the Fast Extension, offering the allowed fast pieces we have
+    fn with_fast_extension(allowed_fast: Vec<u32>, bitfield: &Bitfield) -> Self {
+        UploadSession {
+            fast: true,
+            allowed_fast: allowed_fast
+                .into_iter()
+                .filter(|&index| bitfield.has(index as usize))
+                .collect(),
+            ..Self::new()
+        }
+    }
+
+    /// Messages announcing our pieces, sent right after the handshake
+    fn greeting(&self, bitfield: &Bitfield) -> Vec<Message> {
+        let mut messages = vec![bitfield.to_message(self.fast)];
+        messages.extend(
+            self.allowed_fast
+                .iter()
+                .map(|&index| Message::AllowedFast(index)),
+        );
+        messages
+    }
+
+    /// Update the session from a peer message, returning an immediate reply if any
+    fn on_message(
+        &mut self,
+        message: Message,
+        torrent: &ServedTorrent,
+    ) -> Result<Option<Message>, String> {
+        match message {
+            Message::Interested if self.am_choking => {
+                self.am_choking = false;
+                Ok(Some(Message::Unchoke))
+            }
+            Message::Request {
+                index,
+                begin,
+                length,
+            } => {
+                if length > MAX_BLOCK_LENGTH {
+                    return Err(format!("Requested block too large: {} bytes", length));
+                }
+
+                let request = BlockRequest {
+                    index,
+                    begin,
+                    length,
+                };
+                let held = torrent.bitfield.has(index as usize);
+                let allowed = !self.am_choking || self.allowed_fast.contains(&index);
+
+                if held && allowed {
+                    if self.queued.contains(&request) {
+                        Ok(None)
+                    } else if self.queue.len() < MAX_QUEUED_REQUESTS {
+                        self.queued.insert(request);
+                        self.queue.push_back(request);
+                        Ok(None)
+                    } else if self.fast {
+                        Ok(Some(request.to_reject()))
+                    } else {
+                        Err(format!(
+                            "Peer queued more than {} requests",
+                            MAX_QUEUED_REQUESTS
+                        ))
+                    }
+                } else if self.fast {
+                    // With the Fast Extension every request gets an answer
+                    Ok(Some(request.to_reject()))
+                } else if !held && !self.am_choking {
+                    Err(ReadError::PieceNotAvailable(index).into())
+                } else {
+                    Ok(None)
+                }
+            }
+            Message::Payment { amount, .. } => {
+                // Checked by the seeder's payment verifier first, if it has one
+                if let Some(gate) = &mut self.gate {
+                    gate.on_payment(amount);
+                }
+                Ok(None)
+            }
+            Message::Cancel {
+                index,
+                begin,
+                length,
+            } => {
+                let request = BlockRequest {
+                    index,
+                    begin,
+                    length,
+                };
+                if !self.queued.remove(&request) {
+                    return Ok(None);
+                }
+                self.queue.retain(|queued| *queued != request);
+                Ok(self.fast.then(|| request.to_reject()))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Bind a listener with `bind`, trying each address `addr` resolves to
+fn bind_first<A: ToSocketAddrs, L>(
+    addr: &A,
+    bind: impl Fn(SocketAddr) -> io::Result<L>,
+) -> Result<L, BindError> {
+    let mut last_error = None;
+    for addr in addr.to_socket_addrs()? {
+        match bind(addr) {
+            Ok(listener) => return Ok(listener),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Address resolved to nothing")
+        })
+        .into())
+}
+
+/// Parse newline-separated tracker URLs, skipping blanks, comments and duplicates
+fn parse_trackers(contents: &str) -> Vec<String> {
+    let mut trackers: Vec<String> = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if !trackers.iter().any(|tracker| tracker == line) {
+            trackers.push(line.to_string());
+        }
+    }
+    trackers
+}
+
+/// Parse an info hash written as 40 hex characters
+fn parse_info_hash_hex(info_hash_hex: &str) -> Result<[u8; 20], String> {
+    if info_hash_hex.len() != 40 {
+        return Err(format!(
+            "Invalid info hash length: expected 40, got {}",
+            info_hash_hex.len()
+        ));
+    }
+
+    let bytes = hex::decode(info_hash_hex).map_err(|e| format!("Invalid hex: {}", e))?;
+    let mut info_hash = [0u8; 20];
+    info_hash.copy_from_slice(&bytes);
+    Ok(info_hash)
+}
+
+/// Tell a write that hit the write timeout apart from other write failures
+fn write_result(result: io::Result<()>, what: &str) -> Result<(), ConnectionError> {
+    result.map_err(|e| match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ConnectionError::WriteTimeout,
+        _ => ConnectionError::Other(format!("Failed to send {}: {}", what, e)),
+    })
+}
+
+/// Send `body` as a complete HTTP response and close the exchange
+fn write_http_response(stream: &mut TcpStream, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// `addr` with an unspecified IP replaced by loopback, so a listener bound to
+/// every interface can be connected to
+fn reachable(mut addr: SocketAddr) -> SocketAddr {
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        });
+    }
+    addr
+}
+
+/// Whether the peer has sent data (or closed the connection) that we haven't read yet
+fn message_pending<S: PeerStream>(stream: &S) -> Result<bool, String> {
+    stream
+        .data_pending()
+        .map_err(|e| format!("Failed to poll connection: {}", e))
+}
+
+/// Wait a moment for the peer to send something
+///
+/// Reads block until the idle timeout, so waiting in short slices lets the
+/// caller notice cancellation while the peer is silent.
+fn wait_for_message<S: PeerStream>(stream: &S) -> Result<bool, String> {
+    stream
+        .wait_for_data(CANCEL_POLL_INTERVAL)
+        .map_err(|e| format!("Failed to poll connection: {}", e))
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::peer::leecher::Leecher;
+    use crate::torrent::store::MemoryStore;
+    use crate::torrent::verify::hash_piece;
 
     #[test]
     fn test_seeder_new() {
         let seeder = Seeder::new("127.0.0.1".to_string(), 6881);
         assert_eq!(seeder.address, "127.0.0.1");
         assert_eq!(seeder.port, 6881);
-        assert_eq!(seeder.info_hashes.len(), 0);
+        assert_eq!(seeder.info_hashes().len(), 0);
+    }
+
+    /// Collects log records on the thread that emitted them
+    struct CaptureLogger;
+
+    thread_local! {
+        static CAPTURED: std::cell::RefCell<Vec<(log::Level, String)>> =
+            const { std::cell::RefCell::new(Vec::new()) };
+    }
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            CAPTURED.with(|captured| {
+                captured
+                    .borrow_mut()
+                    .push((record.level(), record.args().to_string()))
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture_logs(f: impl FnOnce()) -> Vec<(log::Level, String)> {
+        static LOGGER: CaptureLogger = CaptureLogger;
+        if log::set_logger(&LOGGER).is_ok() {
+            log::set_max_level(log::LevelFilter::Trace);
+        }
+        CAPTURED.with(|captured| captured.borrow_mut().clear());
+        f();
+        CAPTURED.with(|captured| captured.take())
+    }
+
+    #[test]
+    fn test_startup_logs_served_info_hashes() {
+        let seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        let logs = capture_logs(|| seeder.log_served_torrents());
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].0, log::Level::Warn);
+
+        seeder.add_torrent([0xab; 20]);
+        seeder.add_torrent([0x01; 20]);
+        let logs = capture_logs(|| seeder.log_served_torrents());
+        let messages: Vec<&str> = logs.iter().map(|(_, message)| message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                format!("Serving info hash {}", "ab".repeat(20)),
+                format!("Serving info hash {}", "01".repeat(20)),
+                "Serving 2 torrents".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stats_file_survives_restart() {
+        let path =
+            std::env::temp_dir().join(format!("x402-seeder-stats-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        seeder.load_stats_file(&path).unwrap();
+        seeder.stats.add_uploaded(&[1u8; 20], 16384);
+        seeder.stats.add_payment(10);
+        // Dropping the seeder saves the statistics
+        drop(seeder);
+
+        let mut restarted = Seeder::new("127.0.0.1".to_string(), 0);
+        restarted.load_stats_file(&path).unwrap();
+        restarted.stats.add_uploaded(&[1u8; 20], 16384);
+        restarted.flush_stats().unwrap();
+
+        let saved = Stats::load(&path).unwrap();
+        drop(restarted);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(saved.bytes_uploaded(), 32768);
+        assert_eq!(saved.payments_received(), 1);
+    }
+
+    #[test]
+    fn test_cancel_stops_listen_and_saves_stats() {
+        let path =
+            std::env::temp_dir().join(format!("x402-seeder-cancel-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let cancel = CancellationToken::new();
+        let mut seeder = Seeder::new("0.0.0.0".to_string(), 0)
+            .with_status_port(0)
+            .with_cancellation(cancel.clone());
+        seeder.load_stats_file(&path).unwrap();
+        seeder.stats.add_uploaded(&[1u8; 20], 16384);
+        seeder.bind().unwrap();
+        let listening = thread::spawn(move || (seeder.listen(), seeder));
+
+        thread::sleep(Duration::from_millis(200));
+        cancel.cancel();
+        let (result, seeder) = listening.join().unwrap();
+        result.unwrap();
+
+        // Saved when `listen` returned, before the seeder is dropped
+        let saved = Stats::load(&path).unwrap();
+        drop(seeder);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(saved.bytes_uploaded(), 16384);
+    }
+
+    #[test]
+    fn test_cancel_does_not_wait_for_idle_peers() {
+        let info_hash = [7u8; 20];
+        let cancel = CancellationToken::new();
+        let mut seeder = Seeder::new("127.0.0.1".to_string(), 0).with_cancellation(cancel.clone());
+        seeder.add_torrent_data(
+            info_hash,
+            Arc::new(MemoryStore::new(vec![0u8; 1024], 1024)),
+            Bitfield::full(1),
+        );
+        seeder.bind().unwrap();
+        let addr = seeder.local_addr().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || sender.send(seeder.listen().is_ok()));
+
+        // One peer stays silent before its handshake, the other after it
+        let _silent = TcpStream::connect(addr).unwrap();
+        let mut idle = TcpStream::connect(addr).unwrap();
+        Handshake::exchange(&mut idle, info_hash, generate_peer_id()).unwrap();
+        thread::sleep(Duration::from_millis(200));
+
+        let cancelled = Instant::now();
+        cancel.cancel();
+        assert!(receiver.recv_timeout(Duration::from_secs(2)).unwrap());
+        assert!(cancelled.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_with_peer_id_in_handshake_response() {
+        let peer_id =
+            crate::peer::handshake::parse_peer_id_hex("0102030405060708090a0b0c0d0e0f1011121314")
+                .unwrap();
+        let info_hash = [1u8; 20];
+        let seeder = Seeder::new("127.0.0.1".to_string(), 0).with_peer_id(peer_id);
+        seeder.add_torrent(info_hash);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            Handshake::exchange(&mut stream, info_hash, generate_peer_id()).unwrap()
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        seeder.handle_connection(stream).unwrap();
+
+        let response = client.join().unwrap();
+        assert_eq!(response.peer_id, peer_id);
+    }
+
+    #[test]
+    fn test_ip_filter_refuses_blocked_peer() {
+        let info_hash = [1u8; 20];
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ip = |text: &str| text.parse().unwrap();
+
+        // Out of range: the handshake goes through
+        let mut seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        seeder.add_torrent(info_hash);
+        seeder.set_ip_filter(vec![IpRange::new(ip("10.0.0.0"), ip("10.255.255.255"))]);
+        assert!(!seeder.is_blocked(ip("127.0.0.1")));
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            Handshake::exchange(&mut stream, info_hash, generate_peer_id())
+        });
+        let (stream, _) = listener.accept().unwrap();
+        seeder.handle_connection(stream).unwrap();
+        assert!(client.join().unwrap().is_ok());
+
+        // In range: the connection is closed without a handshake
+        seeder.set_ip_filter(vec![IpRange::new(ip("127.0.0.0"), ip("127.255.255.255"))]);
+        assert!(seeder.is_blocked(ip("127.0.0.1")));
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            Handshake::exchange(&mut stream, info_hash, generate_peer_id())
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let error = seeder.handle_connection(stream).unwrap_err();
+        assert!(error.to_string().contains("blocked"));
+        assert!(client.join().unwrap().is_err());
+        assert_eq!(seeder.stats().active_connections(), 0);
+    }
+
+    #[test]
+    fn test_listening_port() {
+        let mut seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        assert_eq!(seeder.listening_port(), None);
+
+        let addr = seeder.bind().unwrap();
+        assert_ne!(addr.port(), 0);
+        assert_eq!(seeder.listening_port(), Some(addr.port()));
+    }
+
+    #[test]
+    fn test_bind_port_in_use() {
+        let mut first = Seeder::new("127.0.0.1".to_string(), 0);
+        let port = first.bind().unwrap().port();
+
+        // The port stays taken through every retry
+        let mut second = Seeder::new("127.0.0.1".to_string(), port)
+            .with_bind_retry(2, Duration::from_millis(10));
+        let error = second.bind().unwrap_err();
+        assert!(matches!(error, BindError::AddrInUse(_)));
+        assert!(error.is_transient());
+
+        // Once the first seeder lets go, a retry picks the port up
+        let mut third = Seeder::new("127.0.0.1".to_string(), port)
+            .with_bind_retry(10, Duration::from_millis(20));
+        let release = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(first);
+        });
+        assert_eq!(third.bind().unwrap().port(), port);
+        release.join().unwrap();
+    }
+
+    #[test]
+    fn test_bind_error_classification() {
+        let denied = BindError::from(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(denied, BindError::PermissionDenied(_)));
+        assert!(!denied.is_transient());
+
+        let other = BindError::from(io::Error::from(io::ErrorKind::AddrNotAvailable));
+        assert!(matches!(other, BindError::Other(_)));
+        assert!(!other.is_transient());
+    }
+
+    #[test]
+    fn test_poll_accept_idle() {
+        let mut seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        let addr = seeder.bind().unwrap();
+        assert_ne!(addr.port(), 0);
+
+        assert!(seeder.poll_accept().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_poll_accept_pending() {
+        let mut seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        let addr = seeder.bind().unwrap();
+        let client = TcpStream::connect(addr).unwrap();
+
+        let mut accepted = None;
+        for _ in 0..100 {
+            accepted = seeder.poll_accept().unwrap();
+            if accepted.is_some() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let (_, peer_addr) = accepted.unwrap();
+        assert_eq!(peer_addr, client.local_addr().unwrap());
+    }
+
+    #[test]
+    fn test_poll_accept_unbound() {
+        let seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        assert!(seeder.poll_accept().is_err());
+    }
+
+    #[test]
+    fn test_listen_on_multiple_addresses() {
+        let info_hash = [4u8; 20];
+        let addrs = vec!["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()];
+        let mut seeder = Seeder::new("127.0.0.1".to_string(), 0).with_listen_addrs(addrs);
+        seeder.add_torrent(info_hash);
+        let peer_id = seeder.peer_id();
+
+        let bound = match seeder.bind_all() {
+            Ok(bound) => bound,
+            // Hosts without IPv6 can't run this test
+            Err(BindError::Other(e)) if e.kind() == io::ErrorKind::AddrNotAvailable => return,
+            Err(e) => panic!("Failed to bind: {}", e),
+        };
+        assert_eq!(bound.len(), 2);
+        assert!(bound[0].is_ipv4());
+        assert!(bound[1].is_ipv6());
+
+        thread::spawn(move || seeder.listen());
+
+        for addr in bound {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let response = Handshake::exchange(&mut stream, info_hash, generate_peer_id()).unwrap();
+            assert_eq!(response.peer_id, peer_id);
+        }
+    }
+
+    #[test]
+    fn test_interleaved_block_payment_exchange() {
+        let torrent = ServedTorrent {
+            store: Arc::new(MemoryStore::new(vec![0u8; 4 * 1024], 1024)),
+            bitfield: Bitfield::full(4),
+        };
+        let mut session = UploadSession::new();
+        session.gate = Some(PaymentGate::new(
+            ExchangeMode::Interleaved { block_credit: 1 },
+            10,
+        ));
+        session.on_message(Message::Interested, &torrent).unwrap();
+        for index in 0..3 {
+            let request = BlockRequest {
+                index,
+                begin: 0,
+                length: 1024,
+            };
+            session.on_message(request.to_request(), &torrent).unwrap();
+        }
+
+        // The first block goes out on credit
+        assert_eq!(session.next_block().map(|r| r.index), Some(0));
+        assert!(!session.can_send());
+        assert_eq!(session.next_block(), None);
+
+        // Each payment releases one more block
+        let payment = Message::Payment {
+            amount: 10,
+            proof: Vec::new(),
+        };
+        session.on_message(payment.clone(), &torrent).unwrap();
+        assert_eq!(session.next_block().map(|r| r.index), Some(1));
+        assert_eq!(session.next_block(), None);
+
+        session.on_message(payment, &torrent).unwrap();
+        assert_eq!(session.next_block().map(|r| r.index), Some(2));
+        assert_eq!(session.gate.as_ref().unwrap().outstanding(), 10);
+    }
+
+    #[test]
+    fn test_payment_verifier_checks_proofs() {
+        let seeder = Seeder::new("127.0.0.1".to_string(), 0)
+            .with_payment_verifier(|_, _, proof| proof == b"ok");
+        seeder.check_payment(&[1u8; 20], 10, b"ok").unwrap();
+        assert!(
+            seeder
+                .check_payment(&[1u8; 20], u64::MAX, b"forged")
+                .is_err()
+        );
+        assert_eq!(seeder.stats().payments_received(), 1);
+        assert_eq!(seeder.stats().amount_paid(), 10);
+        assert_eq!(seeder.stats().payments_claimed(), 0);
+
+        // Without a verifier the payment is only counted as claimed
+        let unverified = Seeder::new("127.0.0.1".to_string(), 0);
+        unverified.check_payment(&[1u8; 20], 5, b"").unwrap();
+        assert_eq!(unverified.stats().payments_received(), 0);
+        assert_eq!(unverified.stats().payments_claimed(), 1);
+        assert_eq!(unverified.stats().amount_claimed(), 5);
+    }
+
+    #[test]
+    fn test_forged_payment_drops_peer() {
+        let info_hash = [8u8; 20];
+        let seeder = Seeder::new("127.0.0.1".to_string(), 0)
+            .with_price(10)
+            .with_payment_verifier(|_, _, proof| proof == b"ok");
+        seeder.add_torrent_data(
+            info_hash,
+            Arc::new(MemoryStore::new(vec![0u8; 1024], 1024)),
+            Bitfield::full(1),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let x402 = Capabilities {
+                x402: true,
+                ..Default::default()
+            };
+            let mut leecher =
+                Leecher::connect_with(addr, info_hash, generate_peer_id(), 1, None, x402).unwrap();
+            leecher.send(&Message::Interested).unwrap();
+            let request = BlockRequest {
+                index: 0,
+                begin: 0,
+                length: 1024,
+            };
+            leecher.send(&request.to_request()).unwrap();
+            let forged = Message::Payment {
+                amount: u64::MAX,
+                proof: b"forged".to_vec(),
+            };
+            leecher.send(&forged).unwrap();
+            // Read until the seeder hangs up, noting whether a block came first
+            let mut served = false;
+            while let Ok(message) = leecher.receive() {
+                served |= matches!(message, Message::Piece { .. });
+            }
+            served
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let result = seeder.handle_connection(stream);
+        assert!(result.unwrap_err().to_string().contains("invalid proof"));
+        assert!(!client.join().unwrap());
+        assert_eq!(seeder.stats().payments_received(), 0);
+    }
+
+    #[test]
+    fn test_write_timeout_drops_peer_that_never_reads() {
+        // More than the socket buffers on both ends can hold
+        const PIECES: usize = 200;
+        let piece_length = MAX_BLOCK_LENGTH as usize;
+        let info_hash = [6u8; 20];
+        let data = vec![0u8; PIECES * piece_length];
+        let seeder =
+            Seeder::new("127.0.0.1".to_string(), 0).with_write_timeout(Duration::from_millis(200));
+        seeder.add_torrent_data(
+            info_hash,
+            Arc::new(MemoryStore::new(data, piece_length)),
+            Bitfield::full(PIECES),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            Handshake::exchange(&mut stream, info_hash, generate_peer_id()).unwrap();
+            Message::Interested.send(&mut stream).unwrap();
+            for index in 0..PIECES as u32 {
+                let request = BlockRequest {
+                    index,
+                    begin: 0,
+                    length: MAX_BLOCK_LENGTH,
+                };
+                request.to_request().send(&mut stream).unwrap();
+            }
+            // Hold the connection open without reading anything more
+            std::thread::sleep(Duration::from_secs(10));
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || sender.send(seeder.handle_connection(stream)));
+        let result = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(result, Err(ConnectionError::WriteTimeout));
+    }
+
+    #[test]
+    fn test_idle_timeout_reaps_silent_peer() {
+        let info_hash = [7u8; 20];
+        let seeder =
+            Seeder::new("127.0.0.1".to_string(), 0).with_idle_timeout(Duration::from_millis(200));
+        seeder.add_torrent_data(
+            info_hash,
+            Arc::new(MemoryStore::new(vec![0u8; 1024], 1024)),
+            Bitfield::full(1),
+        );
+        let seeder = Arc::new(seeder);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            Handshake::exchange(&mut stream, info_hash, generate_peer_id()).unwrap();
+            // Go silent without closing the connection
+            std::thread::sleep(Duration::from_secs(10));
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handler = Arc::clone(&seeder);
+        std::thread::spawn(move || sender.send(handler.handle_connection(stream)));
+        let result = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(result, Err(ConnectionError::IdleTimeout));
+        assert_eq!(seeder.stats().active_connections(), 0);
+    }
+
+    #[test]
+    fn test_add_torrent_with_data_verify() {
+        let piece_length = 1024;
+        let data: Vec<u8> = (0..3 * piece_length).map(|i| i as u8).collect();
+        let pieces: Vec<u8> = data.chunks(piece_length).flat_map(hash_piece).collect();
+        let info = Info {
+            name: "test.bin".to_string(),
+            plength: piece_length,
+            pieces: serde_bytes::ByteBuf::from(pieces),
+            length: Some(data.len()),
+            ..Default::default()
+        };
+        // The middle piece is corrupt on disk
+        let mut stored = data;
+        stored[piece_length + 10] ^= 0xff;
+        let store: Arc<dyn PieceStore> = Arc::new(MemoryStore::new(stored, piece_length));
+        let seeder = Seeder::new("127.0.0.1".to_string(), 0);
+
+        let trusted = seeder
+            .add_torrent_with_data([1u8; 20], &info, Arc::clone(&store), false)
+            .unwrap();
+        assert_eq!(trusted, Bitfield::full(3));
+
+        let verified = seeder
+            .add_torrent_with_data([2u8; 20], &info, store, true)
+            .unwrap();
+        assert!(verified.has(0) && !verified.has(1) && verified.has(2));
+        assert_eq!(seeder.bitfield(&[2u8; 20]), Some(verified));
+    }
+
+    #[test]
+    fn test_settlement_counts_served_bytes() {
+        let info_hash = [8u8; 20];
+        let piece_length = 32 * 1024;
+        let data: Vec<u8> = (0..50_000).map(|i| i as u8).collect();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let seeder = Seeder::new("127.0.0.1".to_string(), 0)
+            .with_settlement_handler(move |settlement| sender.send(settlement).unwrap());
+        seeder.add_torrent_data(
+            info_hash,
+            Arc::new(MemoryStore::new(data.clone(), piece_length)),
+            Bitfield::full(2),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let mut leecher = Leecher::connect(addr, info_hash, generate_peer_id(), 2).unwrap();
+            leecher.interested().unwrap();
+            let mut received = leecher.download_piece(0, piece_length, 4).unwrap();
+            received.extend(leecher.download_piece(1, 50_000 - piece_length, 4).unwrap());
+            received
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let handler = std::thread::spawn(move || seeder.handle_connection(stream));
+        assert_eq!(client.join().unwrap(), data);
+        handler.join().unwrap().unwrap();
+
+        // Blocks of 16384, 16384, 16384 and 848 bytes
+        let settlement = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(settlement.info_hash, info_hash);
+        assert_eq!(settlement.bytes_served, data.len() as u64);
+        assert_eq!(settlement.blocks_served, 4);
+        assert_eq!((settlement.charged, settlement.paid), (0, 0));
+    }
+
+    #[test]
+    fn test_payment_gate_negotiation() {
+        let free = Seeder::new("127.0.0.1".to_string(), 0);
+        assert!(free.payment_gate(true).unwrap().is_none());
+        assert!(free.payment_gate(false).unwrap().is_none());
+
+        // Only x402 peers are charged; others are rejected by default
+        let paid = Seeder::new("127.0.0.1".to_string(), 0).with_price(10);
+        let gate = paid.payment_gate(true).unwrap().unwrap();
+        assert!(!gate.may_serve());
+        assert!(paid.payment_gate(false).is_err());
+
+        let lenient = Seeder::new("127.0.0.1".to_string(), 0)
+            .with_price(10)
+            .with_legacy_peer_policy(LegacyPeerPolicy::ServeFree);
+        assert!(lenient.payment_gate(true).unwrap().is_some());
+        assert!(lenient.payment_gate(false).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_handshake_response_advertises_x402() {
+        let info_hash = [5u8; 20];
+        let seeder = Seeder::new("127.0.0.1".to_string(), 0).with_price(10);
+        seeder.add_torrent(info_hash);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            Handshake::exchange(&mut stream, info_hash, generate_peer_id()).unwrap()
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        seeder.handle_connection(stream).unwrap();
+
+        assert!(client.join().unwrap().supports_x402());
+    }
+
+    #[test]
+    fn test_suggests_related_torrents_to_x402_peers() {
+        let (served, unknown, elsewhere) = ([1u8; 20], [2u8; 20], [3u8; 20]);
+        let seeder = Arc::new({
+            let seeder = Seeder::new("127.0.0.1".to_string(), 0)
+                .with_related_torrents(move |_| vec![elsewhere, served]);
+            seeder.add_torrent(served);
+            seeder
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // An x402 peer gets a handshake, then the related torrents we serve
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            Handshake::new(unknown, generate_peer_id())
+                .enable_x402()
+                .exchange_on(&mut stream)
+                .unwrap();
+            Message::receive(&mut stream).unwrap()
+        });
+        let (stream, _) = listener.accept().unwrap();
+        assert!(seeder.handle_connection(stream).is_err());
+        assert_eq!(
+            client.join().unwrap(),
+            Message::SuggestTorrents(vec![served])
+        );
+
+        // A standard peer is disconnected without a reply
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            Handshake::exchange(&mut stream, unknown, generate_peer_id())
+        });
+        let (stream, _) = listener.accept().unwrap();
+        assert!(seeder.handle_connection(stream).is_err());
+        assert!(client.join().unwrap().is_err());
+        assert_eq!(seeder.stats.rejected_connections(), 2);
+    }
+
+    #[test]
+    fn test_leecher_negotiates_common_capabilities() {
+        let info_hash = [5u8; 20];
+        let seeder = Seeder::new("127.0.0.1".to_string(), 0).with_capabilities(Capabilities {
+            fast_extension: true,
+            ..Default::default()
+        });
+        seeder.add_torrent(info_hash);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let ours = Capabilities {
+                fast_extension: true,
+                extension_protocol: true,
+                x402: true,
+                dht: true,
+            };
+            Leecher::connect_with(addr, info_hash, generate_peer_id(), 1, None, ours)
+                .unwrap()
+                .negotiated_capabilities()
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        seeder.handle_connection(stream).unwrap();
+
+        let negotiated = client.join().unwrap();
+        assert_eq!(
+            negotiated,
+            Capabilities {
+                fast_extension: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_port_message_records_dht_node() {
+        for dht in [true, false] {
+            let info_hash = [6u8; 20];
+            let seeder = Seeder::new("127.0.0.1".to_string(), 0).with_capabilities(Capabilities {
+                dht,
+                ..Default::default()
+            });
+            seeder.add_torrent_data(
+                info_hash,
+                Arc::new(MemoryStore::new(vec![0u8; 1024], 1024)),
+                Bitfield::full(1),
+            );
+
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let client = std::thread::spawn(move || {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                let ours = Capabilities {
+                    dht: true,
+                    ..Default::default()
+                };
+                Handshake::new(info_hash, generate_peer_id())
+                    .with_reserved(ours.to_reserved())
+                    .send(&mut stream)
+                    .unwrap();
+                Handshake::receive(&mut stream).unwrap();
+                Message::Port(6882).send(&mut stream).unwrap();
+                // Once the Interested is answered, the Port before it was handled
+                Message::Interested.send(&mut stream).unwrap();
+                while Message::receive(&mut stream).unwrap() != Message::Unchoke {}
+            });
+
+            let (stream, _) = listener.accept().unwrap();
+            seeder.handle_connection(stream).unwrap();
+            client.join().unwrap();
+
+            let expected = if dht {
+                vec!["127.0.0.1:6882".parse().unwrap()]
+            } else {
+                Vec::new()
+            };
+            assert_eq!(seeder.dht_nodes(), expected);
+        }
+    }
+
+    #[test]
+    fn test_status_port() {
+        let info_hash = [6u8; 20];
+        let mut seeder = Seeder::new("127.0.0.1".to_string(), 0).with_status_port(0);
+        seeder.add_torrent(info_hash);
+        seeder.bind().unwrap();
+        let status_addr = seeder.status_addr().unwrap();
+        thread::spawn(move || seeder.listen());
+
+        let mut stream = TcpStream::connect(status_addr).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains("Content-Type: application/json"));
+        let status: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert!(status["uptime_secs"].is_u64());
+        assert_eq!(
+            status["torrents"],
+            serde_json::json!([hex::encode(info_hash)])
+        );
+        assert_eq!(status["active_connections"], 0);
+        assert_eq!(status["bytes_uploaded"], 0);
+    }
+
+    #[test]
+    fn test_status_port_off_by_default() {
+        let mut seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        seeder.bind().unwrap();
+        assert!(seeder.status_addr().is_none());
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_metrics_text() {
+        let (first, second) = ([1u8; 20], [2u8; 20]);
+        let seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        seeder.add_torrent(first);
+        seeder.add_torrent(second);
+        seeder.stats.add_uploaded(&first, 16384);
+        seeder.stats.add_rejected();
+        seeder.stats.add_payment(10);
+        seeder.stats.add_payment(5);
+
+        let text = seeder.metrics_text();
+        let mut typed = Vec::new();
+        for line in text.lines() {
+            if let Some(declaration) = line.strip_prefix("# TYPE ") {
+                let (name, kind) = declaration.split_once(' ').unwrap();
+                assert!(kind == "counter" || kind == "gauge", "{}", line);
+                typed.push(name.to_string());
+            } else if !line.starts_with("# HELP ") {
+                // Every sample belongs to the last declared metric
+                let name = line.split(['{', ' ']).next().unwrap();
+                assert_eq!(Some(&name.to_string()), typed.last(), "{}", line);
+            }
+        }
+        for name in [
+            "x402_active_connections",
+            "x402_connections_total",
+            "x402_rejected_connections_total",
+            "x402_uploaded_bytes_total",
+            "x402_payments_received_total",
+            "x402_paid_amount_total",
+        ] {
+            assert!(typed.iter().any(|typed| typed == name), "{}", name);
+        }
+
+        assert!(text.contains(&format!(
+            "x402_uploaded_bytes_total{{info_hash=\"{}\"}} 16384\n",
+            hex::encode(first)
+        )));
+        assert!(text.contains(&format!(
+            "x402_uploaded_bytes_total{{info_hash=\"{}\"}} 0\n",
+            hex::encode(second)
+        )));
+        assert!(text.contains("x402_rejected_connections_total 1\n"));
+        assert!(text.contains("x402_payments_received_total 2\n"));
+        assert!(text.contains("x402_paid_amount_total 15\n"));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_status_port_serves_metrics() {
+        let mut seeder = Seeder::new("127.0.0.1".to_string(), 0).with_status_port(0);
+        seeder.add_torrent([7u8; 20]);
+        seeder.bind().unwrap();
+        let status_addr = seeder.status_addr().unwrap();
+        thread::spawn(move || seeder.listen());
+
+        let mut stream = TcpStream::connect(status_addr).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Content-Type: text/plain; version=0.0.4"));
+        assert!(body.contains("# TYPE x402_connections_total counter\n"));
+    }
+
+    #[test]
+    fn test_cancel_removes_queued_block() {
+        let torrent = ServedTorrent {
+            store: Arc::new(MemoryStore::new(vec![0u8; 64 * 1024], 32 * 1024)),
+            bitfield: Bitfield::full(2),
+        };
+        let mut session = UploadSession::new();
+
+        let reply = session.on_message(Message::Interested, &torrent).unwrap();
+        assert_eq!(reply, Some(Message::Unchoke));
+
+        let first = BlockRequest {
+            index: 0,
+            begin: 0,
+            length: 16384,
+        };
+        let second = BlockRequest {
+            index: 1,
+            begin: 16384,
+            length: 16384,
+        };
+        session.on_message(first.to_request(), &torrent).unwrap();
+        session.on_message(second.to_request(), &torrent).unwrap();
+        assert_eq!(session.queue.len(), 2);
+
+        session.on_message(first.to_cancel(), &torrent).unwrap();
+
+        assert_eq!(session.queue, VecDeque::from([second]));
+    }
+
+    #[test]
+    fn test_request_while_choked_is_ignored() {
+        let torrent = ServedTorrent {
+            store: Arc::new(MemoryStore::new(vec![0u8; 1024], 1024)),
+            bitfield: Bitfield::full(1),
+        };
+        let mut session = UploadSession::new();
+        let request = BlockRequest {
+            index: 0,
+            begin: 0,
+            length: 1024,
+        };
+
+        session.on_message(request.to_request(), &torrent).unwrap();
+
+        assert!(session.queue.is_empty());
+    }
+
+    #[test]
+    fn test_request_queue_is_capped() {
+        let torrent = ServedTorrent {
+            store: Arc::new(MemoryStore::new(vec![0u8; 1024], 1024)),
+            bitfield: Bitfield::full(1),
+        };
+        let request = |begin| BlockRequest {
+            index: 0,
+            begin,
+            length: 1,
+        };
+        for fast in [true, false] {
+            let mut session = if fast {
+                UploadSession::with_fast_extension(Vec::new(), &torrent.bitfield)
+            } else {
+                UploadSession::new()
+            };
+            session.on_message(Message::Interested, &torrent).unwrap();
+            for begin in 0..MAX_QUEUED_REQUESTS as u32 {
+                session
+                    .on_message(request(begin).to_request(), &torrent)
+                    .unwrap();
+            }
+            // Duplicates are still ignored once the queue is full
+            let duplicate = session.on_message(request(0).to_request(), &torrent);
+            assert_eq!(duplicate, Ok(None));
+            assert_eq!(session.queue.len(), MAX_QUEUED_REQUESTS);
+
+            let over = request(MAX_QUEUED_REQUESTS as u32);
+            let result = session.on_message(over.to_request(), &torrent);
+            if fast {
+                assert_eq!(result, Ok(Some(over.to_reject())));
+            } else {
+                assert!(result.is_err());
+            }
+            assert_eq!(session.queue.len(), MAX_QUEUED_REQUESTS);
+
+            // A cancelled request frees its slot
+            session
+                .on_message(request(0).to_cancel(), &torrent)
+                .unwrap();
+            session.on_message(over.to_request(), &torrent).unwrap();
+            assert_eq!(session.queue.back(), Some(&over));
+            assert_eq!(session.queued.len(), MAX_QUEUED_REQUESTS);
+        }
+    }
+
+    #[test]
+    fn test_extended_handshake_advertises_reqq() {
+        let info_hash = [9u8; 20];
+        let seeder = Seeder::new("127.0.0.1".to_string(), 0).with_capabilities(Capabilities {
+            extension_protocol: true,
+            ..Default::default()
+        });
+        seeder.add_torrent_data(
+            info_hash,
+            Arc::new(MemoryStore::new(vec![0u8; 1024], 1024)),
+            Bitfield::full(1),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let mut leecher =
+                Leecher::connect_via(addr, info_hash, generate_peer_id(), 1, None).unwrap();
+            while leecher.max_requests().is_none() {
+                leecher.receive().unwrap();
+            }
+            leecher.max_requests()
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        std::thread::spawn(move || seeder.handle_connection(stream));
+        assert_eq!(client.join().unwrap(), Some(MAX_QUEUED_REQUESTS));
+    }
+
+    #[test]
+    fn test_load_trackers_file() {
+        let path = std::env::temp_dir().join(format!("x402-trackers-{}.txt", std::process::id()));
+        fs::write(
+            &path,
+            "# public trackers\nhttp://tracker.example.com/announce\n\n  udp://tracker.example.org:6969  \nhttp://tracker.example.com/announce\n",
+        )
+        .unwrap();
+
+        let mut seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        let result = seeder.load_trackers_file(&path);
+        fs::remove_file(&path).unwrap();
+
+        result.unwrap();
+        assert_eq!(
+            seeder.trackers(),
+            [
+                "http://tracker.example.com/announce".to_string(),
+                "udp://tracker.example.org:6969".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_allowlist() {
+        let path = std::env::temp_dir().join(format!("x402-allowlist-{}.txt", std::process::id()));
+        fs::write(
+            &path,
+            "# curated\nd69f91e6b2ae4c542468d1073a71d4ea13879a7f\n\n  0123456789ABCDEF0123456789abcdef01234567  \n",
+        )
+        .unwrap();
+        let invalid = path.with_extension("bad");
+        fs::write(
+            &invalid,
+            "d69f91e6b2ae4c542468d1073a71d4ea13879a7f\nnot-a-hash\n",
+        )
+        .unwrap();
+
+        let seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        let loaded = seeder.load_allowlist(&path);
+        let rejected = Seeder::new("127.0.0.1".to_string(), 0);
+        let error = rejected.load_allowlist(&invalid);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&invalid).unwrap();
+
+        assert_eq!(loaded.unwrap(), 2);
+        assert_eq!(
+            seeder.info_hashes(),
+            vec![
+                parse_info_hash_hex("d69f91e6b2ae4c542468d1073a71d4ea13879a7f").unwrap(),
+                parse_info_hash_hex("0123456789abcdef0123456789abcdef01234567").unwrap(),
+            ]
+        );
+        assert!(error.unwrap_err().contains("line 2"));
+        assert!(rejected.info_hashes().is_empty());
+
+        // 40 bytes, but not 40 characters: an error rather than a panic
+        let non_ascii = format!("a\u{e9}{}\n", "a".repeat(37));
+        assert_eq!(non_ascii.trim().len(), 40);
+        let path = path.with_extension("utf8");
+        fs::write(&path, non_ascii).unwrap();
+        let result = Seeder::new("127.0.0.1".to_string(), 0).load_allowlist(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(result.unwrap_err().contains("line 1"));
+    }
+
+    #[test]
+    fn test_load_missing_trackers_file() {
+        let mut seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        let result = seeder.load_trackers_file(Path::new("/nonexistent/trackers.txt"));
+        assert!(result.unwrap_err().contains("Failed to read trackers file"));
+    }
+
+    #[test]
+    fn test_fast_extension_allowed_fast_while_choked() {
+        let torrent = ServedTorrent {
+            store: Arc::new(MemoryStore::new(vec![0u8; 4 * 1024], 1024)),
+            bitfield: Bitfield::full(4),
+        };
+        let mut session = UploadSession::with_fast_extension(vec![2], &torrent.bitfield);
+        assert_eq!(
+            session.greeting(&torrent.bitfield),
+            vec![Message::HaveAll, Message::AllowedFast(2)]
+        );
+
+        let allowed = BlockRequest {
+            index: 2,
+            begin: 0,
+            length: 1024,
+        };
+        let reply = session.on_message(allowed.to_request(), &torrent).unwrap();
+        assert_eq!(reply, None);
+        assert_eq!(session.queue, VecDeque::from([allowed]));
+
+        // Other pieces are rejected until the peer is unchoked
+        let choked = BlockRequest {
+            index: 1,
+            ..allowed
+        };
+        let reply = session.on_message(choked.to_request(), &torrent).unwrap();
+        assert_eq!(reply, Some(choked.to_reject()));
+
+        // Cancelling a queued block is answered with a reject
+        let reply = session.on_message(allowed.to_cancel(), &torrent).unwrap();
+        assert_eq!(reply, Some(allowed.to_reject()));
+        assert!(session.queue.is_empty());
+    }
+
+    #[test]
+    fn test_full_seed_sends_have_all_with_fast_extension() {
+        let info_hash = [9u8; 20];
+        let seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        seeder.add_torrent_data(
+            info_hash,
+            Arc::new(MemoryStore::new(vec![0u8; 64 * 1024], 1024)),
+            Bitfield::full(64),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            Handshake::new(info_hash, generate_peer_id())
+                .with_fast_extension()
+                .send(&mut stream)
+                .unwrap();
+            let response = Handshake::receive(&mut stream).unwrap();
+            assert!(response.supports_fast_extension());
+            Message::receive(&mut stream).unwrap()
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        std::thread::spawn(move || seeder.handle_connection(stream));
+        assert_eq!(client.join().unwrap(), Message::HaveAll);
+    }
+
+    #[test]
+    fn test_fast_extension_greeting_partial() {
+        let mut bitfield = Bitfield::new(4);
+        bitfield.set(1);
+        // Allowed fast pieces we don't have are not offered
+        let session = UploadSession::with_fast_extension(vec![0, 1], &bitfield);
+        assert_eq!(
+            session.greeting(&bitfield),
+            vec![
+                Message::Bitfield(bitfield.as_bytes().to_vec()),
+                Message::AllowedFast(1),
+            ]
+        );
+
+        let empty = Bitfield::new(4);
+        let session = UploadSession::with_fast_extension(vec![0], &empty);
+        assert_eq!(session.greeting(&empty), vec![Message::HaveNone]);
+    }
+
+    #[test]
+    fn test_partial_seed() {
+        let info_hash = [7u8; 20];
+        let mut bitfield = Bitfield::new(3);
+        bitfield.set(0);
+        bitfield.set(2);
+        let seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        seeder.add_torrent_data(
+            info_hash,
+            Arc::new(MemoryStore::new(vec![1u8; 3 * 1024], 1024)),
+            bitfield,
+        );
+
+        assert_eq!(seeder.missing_pieces(&info_hash), Some(vec![1]));
+        assert_eq!(
+            seeder.read_block(&info_hash, 0, 0, 16).unwrap(),
+            vec![1u8; 16]
+        );
+        assert_eq!(
+            seeder.read_block(&info_hash, 2, 0, 16).unwrap(),
+            vec![1u8; 16]
+        );
+        assert_eq!(
+            seeder.read_block(&info_hash, 1, 0, 16),
+            Err(ReadError::PieceNotAvailable(1))
+        );
+
+        // The advertised bitfield leaves out piece 1, and requests for it are refused
+        let served = seeder.torrent(&info_hash).unwrap();
+        let torrent = &*served;
+        let mut session = UploadSession::new();
+        assert_eq!(
+            session.greeting(&torrent.bitfield),
+            vec![Message::Bitfield(vec![0b1010_0000])]
+        );
+        session.on_message(Message::Interested, torrent).unwrap();
+        let request = BlockRequest {
+            index: 1,
+            begin: 0,
+            length: 16,
+        };
+        assert!(session.on_message(request.to_request(), torrent).is_err());
+
+        let mut session = UploadSession::with_fast_extension(Vec::new(), &torrent.bitfield);
+        session.on_message(Message::Interested, torrent).unwrap();
+        assert_eq!(
+            session.on_message(request.to_request(), torrent),
+            Ok(Some(request.to_reject()))
+        );
     }
 
     #[test]
     fn test_add_torrent() {
-        let mut seeder = Seeder::new("127.0.0.1".to_string(), 6881);
+        let seeder = Seeder::new("127.0.0.1".to_string(), 6881);
         let info_hash = [1u8; 20];
         seeder.add_torrent(info_hash);
-        assert_eq!(seeder.info_hashes.len(), 1);
-        assert_eq!(seeder.info_hashes[0], info_hash);
+        assert_eq!(seeder.info_hashes().len(), 1);
+        assert_eq!(seeder.info_hashes()[0], info_hash);
+    }
+
+    #[test]
+    fn test_remove_torrent_while_listening() {
+        let info_hash = [11u8; 20];
+        let piece_length = 32 * 1024;
+        let data: Vec<u8> = (0..2 * piece_length).map(|i| i as u8).collect();
+        let mut seeder = Seeder::new("127.0.0.1".to_string(), 0);
+        seeder.add_torrent_data(
+            info_hash,
+            Arc::new(MemoryStore::new(data.clone(), piece_length)),
+            Bitfield::full(2),
+        );
+        let addr = seeder.bind().unwrap();
+        let seeder = Arc::new(seeder);
+        {
+            let seeder = Arc::clone(&seeder);
+            thread::spawn(move || seeder.listen());
+        }
+        assert!(seeder.is_serving(&info_hash));
+
+        let mut leecher = Leecher::connect(addr, info_hash, generate_peer_id(), 2).unwrap();
+        leecher.interested().unwrap();
+        let first = leecher.download_piece(0, piece_length, 4).unwrap();
+        assert_eq!(first, data[..piece_length]);
+
+        assert!(seeder.remove_torrent(&info_hash));
+        assert!(!seeder.is_serving(&info_hash));
+        assert!(seeder.info_hashes().is_empty());
+        assert!(!seeder.remove_torrent(&info_hash));
+
+        // The open connection still finishes, but new peers are refused
+        let second = leecher.download_piece(1, piece_length, 4).unwrap();
+        assert_eq!(second, data[piece_length..]);
+        assert!(Leecher::connect(addr, info_hash, generate_peer_id(), 2).is_err());
+
+        seeder.add_torrent(info_hash);
+        assert!(seeder.is_serving(&info_hash));
     }
 
     #[test]
     fn test_add_torrent_hex() {
-        let mut seeder = Seeder::new("127.0.0.1".to_string(), 6881);
+        let seeder = Seeder::new("127.0.0.1".to_string(), 6881);
         let hex = "d2474e86c95b19b8bcfdb92bc12c9d44667cfa36";
 
         let result = seeder.add_torrent_hex(hex);
         assert!(result.is_ok());
-        assert_eq!(seeder.info_hashes.len(), 1);
+        assert_eq!(seeder.info_hashes().len(), 1);
+    }
+
+    #[test]
+    fn test_add_torrent_from_magnet() {
+        let seeder = Seeder::new("127.0.0.1".to_string(), 6881);
+        let magnet =
+            MagnetLink::parse("magnet:?xt=urn:btih:2JDU5BWJLMM3RPH5XEV4CLE5IRTHZ6RW").unwrap();
+
+        seeder.add_torrent_from_magnet(&magnet).unwrap();
+        assert_eq!(seeder.info_hashes().len(), 1);
+        assert_eq!(
+            hex::encode(seeder.info_hashes()[0]),
+            "d2474e86c95b19b8bcfdb92bc12c9d44667cfa36"
+        );
+        assert_eq!(seeder.bitfield(&seeder.info_hashes()[0]), None);
+
+        let v2_only = MagnetLink::parse(
+            "magnet:?xt=urn:btmh:1220caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e",
+        )
+        .unwrap();
+        assert!(seeder.add_torrent_from_magnet(&v2_only).is_err());
     }
 
     #[test]
     fn test_add_torrent_hex_invalid() {
-        let mut seeder = Seeder::new("127.0.0.1".to_string(), 6881);
+        let seeder = Seeder::new("127.0.0.1".to_string(), 6881);
         let result = seeder.add_torrent_hex("invalid");
         assert!(result.is_err());
     }
@@ -0,0 +1,133 @@
+use crate::peer::message::Message;
+
+/// Choke and interest state of a peer connection, from our side
+///
+/// Both sides start out choking and not interested. The flags are driven by
+/// the messages we send and receive on the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerState {
+    /// Whether we are choking the peer
+    pub am_choking: bool,
+    /// Whether we are interested in the peer's pieces
+    pub am_interested: bool,
+    /// Whether the peer is choking us
+    pub peer_choking: bool,
+    /// Whether the peer is interested in our pieces
+    pub peer_interested: bool,
+}
+
+impl Default for PeerState {
+    fn default() -> Self {
+        PeerState {
+            am_choking: true,
+            am_interested: false,
+            peer_choking: true,
+            peer_interested: false,
+        }
+    }
+}
+
+impl PeerState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Update the state for a message we sent
+    pub fn on_sent(&mut self, message: &Message) {
+        match message {
+            Message::Choke => self.am_choking = true,
+            Message::Unchoke => self.am_choking = false,
+            Message::Interested => self.am_interested = true,
+            Message::NotInterested => self.am_interested = false,
+            _ => {}
+        }
+    }
+
+    /// Update the state for a message the peer sent
+    pub fn on_received(&mut self, message: &Message) {
+        match message {
+            Message::Choke => self.peer_choking = true,
+            Message::Unchoke => self.peer_choking = false,
+            Message::Interested => self.peer_interested = true,
+            Message::NotInterested => self.peer_interested = false,
+            _ => {}
+        }
+    }
+
+    /// Whether we may request blocks: the peer isn't choking us and we're interested
+    pub fn can_request(&self) -> bool {
+        !self.peer_choking && self.am_interested
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_initial_state() {
+        let state = PeerState::new();
+        assert!(state.am_choking);
+        assert!(!state.am_interested);
+        assert!(state.peer_choking);
+        assert!(!state.peer_interested);
+        assert!(!state.can_request());
+    }
+
+    #[test]
+    fn test_received_transitions() {
+        let mut state = PeerState::new();
+
+        state.on_received(&Message::Unchoke);
+        assert!(!state.peer_choking);
+        state.on_received(&Message::Interested);
+        assert!(state.peer_interested);
+        state.on_received(&Message::NotInterested);
+        assert!(!state.peer_interested);
+        state.on_received(&Message::Choke);
+        assert!(state.peer_choking);
+
+        // Received messages never change our own flags
+        assert!(state.am_choking);
+        assert!(!state.am_interested);
+    }
+
+    #[test]
+    fn test_sent_transitions() {
+        let mut state = PeerState::new();
+
+        state.on_sent(&Message::Unchoke);
+        assert!(!state.am_choking);
+        state.on_sent(&Message::Interested);
+        assert!(state.am_interested);
+        state.on_sent(&Message::NotInterested);
+        assert!(!state.am_interested);
+        state.on_sent(&Message::Choke);
+        assert!(state.am_choking);
+
+        // Sent messages never change the peer's flags
+        assert!(state.peer_choking);
+        assert!(!state.peer_interested);
+    }
+
+    #[test]
+    fn test_can_request() {
+        let mut state = PeerState::new();
+
+        state.on_sent(&Message::Interested);
+        assert!(!state.can_request());
+        state.on_received(&Message::Unchoke);
+        assert!(state.can_request());
+
+        // Other messages leave the state alone
+        state.on_received(&Message::Have(3));
+        state.on_sent(&Message::KeepAlive);
+        assert!(state.can_request());
+
+        state.on_received(&Message::Choke);
+        assert!(!state.can_request());
+        state.on_received(&Message::Unchoke);
+        state.on_sent(&Message::NotInterested);
+        assert!(!state.can_request());
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Live upload statistics of a seeder, shared between connection threads
+#[derive(Debug)]
+pub struct Stats {
+    started: Instant,
+    active_connections: AtomicUsize,
+    total_connections: AtomicU64,
+    rejected_connections: AtomicU64,
+    bytes_uploaded: AtomicU64,
+    /// Block bytes sent per info hash
+    torrent_uploads: Mutex<HashMap<[u8; 20], u64>>,
+    /// Payments whose proof the payment verifier accepted
+    payments_received: AtomicU64,
+    amount_paid: AtomicU64,
+    /// Payments credited without a payment verifier, on the peer's word
+    payments_claimed: AtomicU64,
+    amount_claimed: AtomicU64,
+}
+
+/// Lifetime counters kept across restarts, as saved by `Stats::save`
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct SavedStats {
+    bytes_uploaded: u64,
+    /// Block bytes sent per info hash, keyed by the hex info hash
+    torrent_uploads: HashMap<String, u64>,
+    payments_received: u64,
+    amount_paid: u64,
+    #[serde(default)]
+    payments_claimed: u64,
+    #[serde(default)]
+    amount_claimed: u64,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            started: Instant::now(),
+            active_connections: AtomicUsize::new(0),
+            total_connections: AtomicU64::new(0),
+            rejected_connections: AtomicU64::new(0),
+            bytes_uploaded: AtomicU64::new(0),
+            torrent_uploads: Mutex::new(HashMap::new()),
+            payments_received: AtomicU64::new(0),
+            amount_paid: AtomicU64::new(0),
+            payments_claimed: AtomicU64::new(0),
+            amount_claimed: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the lifetime counters saved by `save`, or start from zero if
+    /// there is no file yet
+    ///
+    /// Uploaded bytes and payments carry on from the saved values; connection
+    /// counts and the uptime start over.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::new()),
+            Err(e) => return Err(format!("Failed to read stats {}: {}", path.display(), e)),
+        };
+        let saved: SavedStats = serde_json::from_slice(&data)
+            .map_err(|e| format!("Failed to parse stats {}: {}", path.display(), e))?;
+
+        let mut torrent_uploads = HashMap::new();
+        for (info_hash, bytes) in saved.torrent_uploads {
+            let info_hash = hex::decode(&info_hash)
+                .ok()
+                .and_then(|bytes| <[u8; 20]>::try_from(bytes).ok())
+                .ok_or_else(|| format!("Invalid info hash in stats: {}", info_hash))?;
+            torrent_uploads.insert(info_hash, bytes);
+        }
+        Ok(Stats {
+            bytes_uploaded: AtomicU64::new(saved.bytes_uploaded),
+            torrent_uploads: Mutex::new(torrent_uploads),
+            payments_received: AtomicU64::new(saved.payments_received),
+            amount_paid: AtomicU64::new(saved.amount_paid),
+            payments_claimed: AtomicU64::new(saved.payments_claimed),
+            amount_claimed: AtomicU64::new(saved.amount_claimed),
+            ..Self::default()
+        })
+    }
+
+    /// Save the lifetime counters as JSON, for `load` after a restart
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let saved = SavedStats {
+            bytes_uploaded: self.bytes_uploaded(),
+            torrent_uploads: self
+                .torrent_uploads
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(info_hash, bytes)| (hex::encode(info_hash), *bytes))
+                .collect(),
+            payments_received: self.payments_received(),
+            amount_paid: self.amount_paid(),
+            payments_claimed: self.payments_claimed(),
+            amount_claimed: self.amount_claimed(),
+        };
+        let data = serde_json::to_vec_pretty(&saved)
+            .map_err(|e| format!("Failed to encode stats: {}", e))?;
+
+        // Write to a temporary file first so a crash can't leave truncated stats
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, &data)
+            .and_then(|_| fs::rename(&temp_path, path))
+            .map_err(|e| format!("Failed to save stats {}: {}", path.display(), e))
+    }
+
+    /// Time since the statistics were created
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Number of peer connections currently being handled
+    pub fn active_connections(&self) -> usize {
+        self.active_connections.load(Ordering::SeqCst)
+    }
+
+    /// Number of peer connections accepted so far
+    pub fn total_connections(&self) -> u64 {
+        self.total_connections.load(Ordering::SeqCst)
+    }
+
+    /// Number of peer connections refused, e.g. for an unknown torrent
+    pub fn rejected_connections(&self) -> u64 {
+        self.rejected_connections.load(Ordering::SeqCst)
+    }
+
+    /// Total block bytes sent to peers
+    pub fn bytes_uploaded(&self) -> u64 {
+        self.bytes_uploaded.load(Ordering::SeqCst)
+    }
+
+    /// Block bytes sent to peers for the torrent with `info_hash`
+    pub fn bytes_uploaded_for(&self, info_hash: &[u8; 20]) -> u64 {
+        let uploads = self.torrent_uploads.lock().unwrap();
+        uploads.get(info_hash).copied().unwrap_or(0)
+    }
+
+    /// Number of payments whose proof the payment verifier accepted
+    pub fn payments_received(&self) -> u64 {
+        self.payments_received.load(Ordering::SeqCst)
+    }
+
+    /// Sum of the amounts of all verified payments
+    pub fn amount_paid(&self) -> u64 {
+        self.amount_paid.load(Ordering::SeqCst)
+    }
+
+    /// Number of payments credited without a payment verifier
+    ///
+    /// Nothing backs these but the peer's claim.
+    pub fn payments_claimed(&self) -> u64 {
+        self.payments_claimed.load(Ordering::SeqCst)
+    }
+
+    /// Sum of the amounts of all unverified payments
+    pub fn amount_claimed(&self) -> u64 {
+        self.amount_claimed.load(Ordering::SeqCst)
+    }
+
+    /// Count a connection as active until the returned guard is dropped
+    pub(crate) fn connection(&self) -> ConnectionGuard<'_> {
+        self.active_connections.fetch_add(1, Ordering::SeqCst);
+        self.total_connections.fetch_add(1, Ordering::SeqCst);
+        ConnectionGuard { stats: self }
+    }
+
+    /// Record a refused connection
+    pub(crate) fn add_rejected(&self) {
+        self.rejected_connections.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Record block bytes sent to a peer for the torrent with `info_hash`
+    pub(crate) fn add_uploaded(&self, info_hash: &[u8; 20], bytes: u64) {
+        self.bytes_uploaded.fetch_add(bytes, Ordering::SeqCst);
+        *self
+            .torrent_uploads
+            .lock()
+            .unwrap()
+            .entry(*info_hash)
+            .or_insert(0) += bytes;
+    }
+
+    /// Record a verified payment of `amount` from a peer
+    pub(crate) fn add_payment(&self, amount: u64) {
+        self.payments_received.fetch_add(1, Ordering::SeqCst);
+        self.amount_paid.fetch_add(amount, Ordering::SeqCst);
+    }
+
+    /// Record a payment of `amount` a peer claimed, credited without checking
+    pub(crate) fn add_claimed_payment(&self, amount: u64) {
+        self.payments_claimed.fetch_add(1, Ordering::SeqCst);
+        self.amount_claimed.fetch_add(amount, Ordering::SeqCst);
+    }
+}
+
+/// Keeps a connection counted in `Stats::active_connections` while alive
+pub(crate) struct ConnectionGuard<'a> {
+    stats: &'a Stats,
+}
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        self.stats.active_connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_guard() {
+        let stats = Stats::new();
+        let first = stats.connection();
+        let second = stats.connection();
+        assert_eq!(stats.active_connections(), 2);
+
+        drop(first);
+        assert_eq!(stats.active_connections(), 1);
+        drop(second);
+        assert_eq!(stats.active_connections(), 0);
+        assert_eq!(stats.total_connections(), 2);
+    }
+
+    #[test]
+    fn test_uploads_per_torrent() {
+        let stats = Stats::new();
+        stats.add_uploaded(&[1u8; 20], 100);
+        stats.add_uploaded(&[2u8; 20], 50);
+        stats.add_uploaded(&[1u8; 20], 25);
+
+        assert_eq!(stats.bytes_uploaded(), 175);
+        assert_eq!(stats.bytes_uploaded_for(&[1u8; 20]), 125);
+        assert_eq!(stats.bytes_uploaded_for(&[3u8; 20]), 0);
+    }
+
+    #[test]
+    fn test_saved_stats_accumulate_across_restarts() {
+        let path = std::env::temp_dir().join(format!("x402-stats-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(Stats::load(&path).unwrap().bytes_uploaded(), 0);
+
+        let stats = Stats::load(&path).unwrap();
+        stats.add_uploaded(&[1u8; 20], 100);
+        stats.add_payment(10);
+        stats.connection();
+        stats.save(&path).unwrap();
+
+        // After a restart the counters carry on instead of resetting
+        let restarted = Stats::load(&path).unwrap();
+        restarted.add_uploaded(&[1u8; 20], 50);
+        restarted.add_uploaded(&[2u8; 20], 25);
+        restarted.add_payment(5);
+        restarted.add_claimed_payment(7);
+        restarted.save(&path).unwrap();
+
+        let reloaded = Stats::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(reloaded.bytes_uploaded(), 175);
+        assert_eq!(reloaded.bytes_uploaded_for(&[1u8; 20]), 150);
+        assert_eq!(reloaded.bytes_uploaded_for(&[2u8; 20]), 25);
+        assert_eq!(reloaded.payments_received(), 2);
+        assert_eq!(reloaded.amount_paid(), 15);
+        assert_eq!(reloaded.payments_claimed(), 1);
+        assert_eq!(reloaded.amount_claimed(), 7);
+        assert_eq!(reloaded.total_connections(), 0);
+    }
+}
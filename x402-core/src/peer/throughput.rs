@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+/// Weight of the newest sample used by `ThroughputEstimator::new`
+pub const DEFAULT_SMOOTHING: f64 = 0.3;
+
+/// Estimates transfer speed as an exponential moving average of recent ticks
+///
+/// Each tick reports the bytes transferred since the previous one, so a short
+/// burst or stall moves the estimate without dominating it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThroughputEstimator {
+    /// Weight of the newest sample, between 0 and 1
+    smoothing: f64,
+    /// Smoothed rate in bytes per second, `None` until the first tick
+    rate: Option<f64>,
+}
+
+impl Default for ThroughputEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ThroughputEstimator {
+    pub fn new() -> Self {
+        Self::with_smoothing(DEFAULT_SMOOTHING)
+    }
+
+    /// An estimator giving the newest sample weight `smoothing`, clamped to 0..=1
+    ///
+    /// Higher values follow speed changes faster but jitter more.
+    pub fn with_smoothing(smoothing: f64) -> Self {
+        ThroughputEstimator {
+            smoothing: smoothing.clamp(0.0, 1.0),
+            rate: None,
+        }
+    }
+
+    /// Record `bytes` transferred over a tick lasting `elapsed`
+    ///
+    /// Zero-length ticks carry no rate and are ignored.
+    pub fn record(&mut self, bytes: u64, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        if seconds <= 0.0 {
+            return;
+        }
+        let sample = bytes as f64 / seconds;
+        self.rate = Some(match self.rate {
+            Some(rate) => rate + self.smoothing * (sample - rate),
+            None => sample,
+        });
+    }
+
+    /// Current estimate in bytes per second, 0 before any tick
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.rate.unwrap_or(0.0)
+    }
+
+    /// Time left to transfer `remaining_bytes` at the current rate
+    ///
+    /// `Duration::MAX` while nothing has been transferred yet.
+    pub fn eta(&self, remaining_bytes: u64) -> Duration {
+        let rate = self.bytes_per_sec();
+        if remaining_bytes == 0 {
+            return Duration::ZERO;
+        }
+        if rate <= 0.0 {
+            return Duration::MAX;
+        }
+        Duration::try_from_secs_f64(remaining_bytes as f64 / rate).unwrap_or(Duration::MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steady_rate_converges() {
+        let mut estimator = ThroughputEstimator::new();
+        assert_eq!(estimator.eta(1000), Duration::MAX);
+
+        // A slow start, then a steady 1 MB/s
+        estimator.record(100_000, Duration::from_secs(1));
+        for _ in 0..30 {
+            estimator.record(250_000, Duration::from_millis(250));
+        }
+
+        assert!((estimator.bytes_per_sec() - 1_000_000.0).abs() < 1_000.0);
+        let eta = estimator.eta(10_000_000);
+        assert!(eta > Duration::from_millis(9_990) && eta < Duration::from_millis(10_010));
+        assert_eq!(estimator.eta(0), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_smoothing_damps_spikes() {
+        let mut estimator = ThroughputEstimator::with_smoothing(0.5);
+        estimator.record(1000, Duration::from_secs(1));
+        estimator.record(3000, Duration::from_secs(1));
+        assert_eq!(estimator.bytes_per_sec(), 2000.0);
+
+        // Ticks without elapsed time are ignored
+        estimator.record(5000, Duration::ZERO);
+        assert_eq!(estimator.bytes_per_sec(), 2000.0);
+    }
+}
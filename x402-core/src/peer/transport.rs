@@ -0,0 +1,386 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::peer::proxy::ProxyConfig;
+
+/// Connections the kernel queues before `accept` picks them up
+const LISTEN_BACKLOG: i32 = 128;
+/// How often the default `PeerStream::wait_for_data` checks for data
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// A connection to a peer, whatever carries it
+pub trait PeerStream: Read + Write + Send {
+    /// Address of the remote end
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Make reads fail after `timeout` without data, or block forever with `None`
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Make writes fail after blocking for `timeout`, or never with `None`
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Whether the peer sent data, or closed the connection, that hasn't been
+    /// read yet; must not block
+    fn data_pending(&self) -> io::Result<bool>;
+
+    /// Wait up to `timeout` for what `data_pending` reports, without reading it
+    ///
+    /// The default polls `data_pending`; streams that can block until data
+    /// arrives should do that instead.
+    fn wait_for_data(&self, timeout: Duration) -> io::Result<bool> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.data_pending()? {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            thread::sleep(WAIT_POLL_INTERVAL);
+        }
+    }
+}
+
+/// Accepts the incoming connections of a transport
+pub trait PeerListener: Send + Sync {
+    type Stream: PeerStream + 'static;
+
+    /// Wait for the next connection
+    fn accept(&self) -> io::Result<(Self::Stream, SocketAddr)>;
+
+    /// Address the listener is bound to
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+/// How connections to peers are made and accepted, e.g. TCP, µTP or TLS
+///
+/// A seeder shares its transport with the threads serving its listeners.
+pub trait Transport: Send + Sync {
+    type Stream: PeerStream;
+    type Listener: PeerListener<Stream = Self::Stream>;
+
+    /// Open a connection to `addr`, giving up after `timeout`
+    fn connect(&self, addr: SocketAddr, timeout: Duration) -> Result<Self::Stream, String>;
+
+    /// Listen for connections on `addr`
+    fn bind(&self, addr: SocketAddr) -> io::Result<Self::Listener>;
+}
+
+/// Plain TCP, dialing out through a proxy if one is set
+#[derive(Debug, Clone, Default)]
+pub struct TcpTransport {
+    pub(crate) proxy: Option<ProxyConfig>,
+}
+
+impl TcpTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make outgoing connections through `proxy`
+    pub fn with_proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+}
+
+impl Transport for TcpTransport {
+    type Stream = TcpStream;
+    type Listener = TcpListener;
+
+    fn connect(&self, addr: SocketAddr, timeout: Duration) -> Result<TcpStream, String> {
+        let stream = match &self.proxy {
+            Some(proxy) => proxy.connect(&addr.ip().to_string(), addr.port(), timeout)?,
+            None => TcpStream::connect_timeout(&addr, timeout)
+                .map_err(|e| format!("Failed to connect to {}: {}", addr, e))?,
+        };
+        // Small messages like Cancels must go out right away; with Nagle they
+        // can be held back and dropped when the connection closes right after
+        stream
+            .set_nodelay(true)
+            .map_err(|e| format!("Failed to set TCP_NODELAY: {}", e))?;
+        Ok(stream)
+    }
+
+    /// Bind with `SO_REUSEADDR`, so a restarted seeder can take its port back
+    /// while connections of the previous run linger in `TIME_WAIT`
+    fn bind(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        // On Windows the option lets other sockets steal the port instead
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(LISTEN_BACKLOG)?;
+        Ok(TcpListener::from(socket))
+    }
+}
+
+impl PeerStream for TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn data_pending(&self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let result = self.peek(&mut [0u8; 1]);
+        self.set_nonblocking(false)?;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn wait_for_data(&self, timeout: Duration) -> io::Result<bool> {
+        let previous = self.read_timeout()?;
+        // A zero timeout is rejected rather than meaning "don't wait"
+        self.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        let result = self.peek(&mut [0u8; 1]);
+        self.set_read_timeout(previous)?;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl PeerListener for TcpListener {
+    type Stream = TcpStream;
+
+    fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        TcpListener::accept(self)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpListener::local_addr(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::handshake::{Capabilities, generate_peer_id};
+    use crate::peer::leecher::Leecher;
+    use crate::peer::serve::Seeder;
+    use std::collections::VecDeque;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+
+    /// One direction of an in-memory connection
+    #[derive(Default)]
+    struct Pipe {
+        /// Unread bytes, and whether the writing end is gone
+        state: Mutex<(VecDeque<u8>, bool)>,
+        ready: Condvar,
+    }
+
+    impl Pipe {
+        fn close(&self) {
+            self.state.lock().unwrap().1 = true;
+            self.ready.notify_all();
+        }
+    }
+
+    struct MemoryStream {
+        incoming: Arc<Pipe>,
+        outgoing: Arc<Pipe>,
+        peer: SocketAddr,
+        read_timeout: Mutex<Option<Duration>>,
+    }
+
+    impl MemoryStream {
+        fn pair(a: SocketAddr, b: SocketAddr) -> (Self, Self) {
+            let (forward, backward) = (Arc::new(Pipe::default()), Arc::new(Pipe::default()));
+            let first = MemoryStream {
+                incoming: backward.clone(),
+                outgoing: forward.clone(),
+                peer: b,
+                read_timeout: Mutex::new(None),
+            };
+            let second = MemoryStream {
+                incoming: forward,
+                outgoing: backward,
+                peer: a,
+                read_timeout: Mutex::new(None),
+            };
+            (first, second)
+        }
+    }
+
+    impl Read for MemoryStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let timeout = *self.read_timeout.lock().unwrap();
+            let mut state = self.incoming.state.lock().unwrap();
+            while state.0.is_empty() && !state.1 {
+                state = match timeout {
+                    Some(timeout) => {
+                        let (state, result) =
+                            self.incoming.ready.wait_timeout(state, timeout).unwrap();
+                        if result.timed_out() {
+                            return Err(io::ErrorKind::WouldBlock.into());
+                        }
+                        state
+                    }
+                    None => self.incoming.ready.wait(state).unwrap(),
+                };
+            }
+            let n = buf.len().min(state.0.len());
+            for (byte, slot) in state.0.drain(..n).zip(buf.iter_mut()) {
+                *slot = byte;
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for MemoryStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut state = self.outgoing.state.lock().unwrap();
+            if state.1 {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            state.0.extend(buf);
+            self.outgoing.ready.notify_all();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Drop for MemoryStream {
+        fn drop(&mut self) {
+            self.outgoing.close();
+            self.incoming.close();
+        }
+    }
+
+    impl PeerStream for MemoryStream {
+        fn peer_addr(&self) -> io::Result<SocketAddr> {
+            Ok(self.peer)
+        }
+
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            *self.read_timeout.lock().unwrap() = timeout;
+            Ok(())
+        }
+
+        fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn data_pending(&self) -> io::Result<bool> {
+            let state = self.incoming.state.lock().unwrap();
+            Ok(!state.0.is_empty() || state.1)
+        }
+    }
+
+    struct MemoryListener {
+        addr: SocketAddr,
+        connections: Mutex<Receiver<MemoryStream>>,
+    }
+
+    impl PeerListener for MemoryListener {
+        type Stream = MemoryStream;
+
+        fn accept(&self) -> io::Result<(MemoryStream, SocketAddr)> {
+            let stream = self
+                .connections
+                .lock()
+                .unwrap()
+                .recv()
+                .map_err(|_| io::Error::from(io::ErrorKind::ConnectionAborted))?;
+            let peer = stream.peer;
+            Ok((stream, peer))
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Ok(self.addr)
+        }
+    }
+
+    /// Connects to the single listener it, or a clone of it, bound, without any sockets
+    #[derive(Clone)]
+    struct MemoryTransport {
+        local: SocketAddr,
+        listener: Arc<Mutex<Option<Sender<MemoryStream>>>>,
+    }
+
+    impl Transport for MemoryTransport {
+        type Stream = MemoryStream;
+        type Listener = MemoryListener;
+
+        fn connect(&self, addr: SocketAddr, _timeout: Duration) -> Result<MemoryStream, String> {
+            let (ours, theirs) = MemoryStream::pair(self.local, addr);
+            self.listener
+                .lock()
+                .unwrap()
+                .as_ref()
+                .and_then(|listener| listener.send(theirs).ok())
+                .ok_or_else(|| format!("Failed to connect to {}: nothing listening", addr))?;
+            Ok(ours)
+        }
+
+        fn bind(&self, addr: SocketAddr) -> io::Result<MemoryListener> {
+            let (sender, receiver) = mpsc::channel();
+            *self.listener.lock().unwrap() = Some(sender);
+            Ok(MemoryListener {
+                addr,
+                connections: Mutex::new(receiver),
+            })
+        }
+    }
+
+    #[test]
+    fn test_handshake_over_memory_transport() {
+        let info_hash = [9u8; 20];
+        let transport = MemoryTransport {
+            local: "10.0.0.2:51413".parse().unwrap(),
+            listener: Arc::new(Mutex::new(None)),
+        };
+        let mut seeder = Seeder::over(transport.clone(), "10.0.0.1".to_string(), 6881);
+        seeder.add_torrent(info_hash);
+        let addr = seeder.bind().unwrap();
+        assert_eq!(addr, "10.0.0.1:6881".parse().unwrap());
+
+        let seeder = Arc::new(seeder);
+        let listening = seeder.clone();
+        // Blocks waiting for more connections once the test is done
+        thread::spawn(move || listening.listen());
+
+        let leecher = Leecher::connect_over(
+            &transport,
+            addr,
+            info_hash,
+            generate_peer_id(),
+            1,
+            Capabilities::default(),
+        )
+        .unwrap();
+        assert_eq!(leecher.remote_peer_id(), seeder.peer_id());
+        assert_eq!(leecher.addr(), addr);
+    }
+}
@@ -0,0 +1,126 @@
+use std::ops::Range;
+
+/// A dictionary key and where its bencoded value sits in the input
+pub(crate) type DictEntry<'a> = (&'a [u8], Range<usize>);
+
+/// Offset just past the bencoded value starting at `start`
+///
+/// Only the structure is checked, so the exact bytes of a value can be
+/// sliced out of the input, whatever order its dictionary keys are in.
+pub(crate) fn value_end(data: &[u8], start: usize) -> Result<usize, String> {
+    let mut pos = start;
+    // Open lists and dictionaries, walked without recursion so deeply nested
+    // input can't exhaust the stack
+    let mut depth = 0usize;
+    loop {
+        match data.get(pos) {
+            None => return Err("Unexpected end of bencode".to_string()),
+            Some(b'i') => pos = integer_end(data, pos)?,
+            Some(b'l') | Some(b'd') => {
+                depth += 1;
+                pos += 1;
+                continue;
+            }
+            Some(b'e') if depth > 0 => {
+                depth -= 1;
+                pos += 1;
+            }
+            Some(b'0'..=b'9') => pos = string_at(data, pos)?.1,
+            Some(byte) => {
+                return Err(format!(
+                    "Invalid bencode byte 0x{:02x} at offset {}",
+                    byte, pos
+                ));
+            }
+        }
+        if depth == 0 {
+            return Ok(pos);
+        }
+    }
+}
+
+/// The keys of the dictionary `data` and the ranges of their values, in input order
+///
+/// `data` must hold exactly one dictionary, with nothing after it.
+pub(crate) fn dict_entries(data: &[u8]) -> Result<Vec<DictEntry<'_>>, String> {
+    if data.first() != Some(&b'd') {
+        return Err("Bencode is not a dictionary".to_string());
+    }
+    let mut entries = Vec::new();
+    let mut pos = 1;
+    while data.get(pos) != Some(&b'e') {
+        if !data.get(pos).is_some_and(u8::is_ascii_digit) {
+            return Err(format!("Dictionary key at offset {} is not a string", pos));
+        }
+        let (key, value_start) = string_at(data, pos)?;
+        pos = value_end(data, value_start)?;
+        entries.push((key, value_start..pos));
+    }
+    if pos + 1 != data.len() {
+        return Err(format!("Trailing data after bencode at offset {}", pos + 1));
+    }
+    Ok(entries)
+}
+
+/// The contents of the string starting at `start`, and the offset past it
+fn string_at(data: &[u8], start: usize) -> Result<(&[u8], usize), String> {
+    let colon = data[start..]
+        .iter()
+        .position(|&byte| byte == b':')
+        .map(|offset| start + offset)
+        .ok_or_else(|| format!("Unterminated string length at offset {}", start))?;
+    let len: usize = std::str::from_utf8(&data[start..colon])
+        .ok()
+        .filter(|digits| digits.bytes().all(|byte| byte.is_ascii_digit()))
+        .and_then(|digits| digits.parse().ok())
+        .ok_or_else(|| format!("Invalid string length at offset {}", start))?;
+    let end = (colon + 1)
+        .checked_add(len)
+        .filter(|&end| end <= data.len())
+        .ok_or_else(|| format!("String at offset {} runs past the end", start))?;
+    Ok((&data[colon + 1..end], end))
+}
+
+/// Offset past the integer starting at `start`
+fn integer_end(data: &[u8], start: usize) -> Result<usize, String> {
+    let end = data[start..]
+        .iter()
+        .position(|&byte| byte == b'e')
+        .map(|offset| start + offset)
+        .ok_or_else(|| format!("Unterminated integer at offset {}", start))?;
+    let digits = data[start + 1..end]
+        .strip_prefix(b"-")
+        .unwrap_or(&data[start + 1..end]);
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return Err(format!("Invalid integer at offset {}", start));
+    }
+    Ok(end + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dict_entries_keep_input_order() {
+        let data = b"d4:infod4:name1:a6:lengthi1ee3:fooli-2e3:bare1:a0:e";
+        let entries = dict_entries(data).unwrap();
+        let keys: Vec<&[u8]> = entries.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, vec![&b"info"[..], b"foo", b"a"]);
+        let values: Vec<&[u8]> = entries
+            .iter()
+            .map(|(_, value)| &data[value.clone()])
+            .collect();
+        assert_eq!(
+            values,
+            vec![&b"d4:name1:a6:lengthi1ee"[..], b"li-2e3:bare", b"0:"]
+        );
+
+        assert!(dict_entries(b"d4:infoi1ee ").is_err());
+        assert!(dict_entries(b"d4:infoi1e").is_err());
+        assert!(dict_entries(b"di1ei2ee").is_err());
+        assert!(dict_entries(b"d4:info5:abce").is_err());
+        assert!(dict_entries(b"d4:infoixee").is_err());
+        assert!(dict_entries(b"le").is_err());
+    }
+}
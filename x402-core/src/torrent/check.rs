@@ -0,0 +1,396 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::torrent::magnet::MagnetLink;
+use crate::torrent::types::{Info, Torrent};
+use crate::torrent::verify::verify_piece;
+
+/// Outcome of checking a local file against a magnet link
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckResult {
+    /// Everything that could be checked matched
+    Pass {
+        /// Number of pieces verified, if torrent metadata was available
+        verified_pieces: Option<usize>,
+    },
+    /// The magnet carries no size and no metadata was given, so nothing was checked
+    Inconclusive,
+    /// The file size differs from the expected length
+    SizeMismatch { expected: u64, actual: u64 },
+    /// The torrent metadata does not belong to the magnet link
+    InfoHashMismatch { expected: String, actual: String },
+    /// Some pieces of the file don't match their hashes
+    PieceMismatch { failed: Vec<usize> },
+}
+
+impl CheckResult {
+    pub fn is_pass(&self) -> bool {
+        matches!(self, CheckResult::Pass { .. })
+    }
+}
+
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckResult::Pass {
+                verified_pieces: Some(count),
+            } => write!(f, "PASS: size and all {} pieces match", count),
+            CheckResult::Pass {
+                verified_pieces: None,
+            } => write!(f, "PASS: size matches"),
+            CheckResult::Inconclusive => {
+                write!(
+                    f,
+                    "UNKNOWN: the magnet has no size and no metadata was given"
+                )
+            }
+            CheckResult::SizeMismatch { expected, actual } => write!(
+                f,
+                "FAIL: size mismatch, expected {} bytes, got {}",
+                expected, actual
+            ),
+            CheckResult::InfoHashMismatch { expected, actual } => write!(
+                f,
+                "FAIL: metadata info hash {} does not match magnet {}",
+                actual, expected
+            ),
+            CheckResult::PieceMismatch { failed } => {
+                write!(f, "FAIL: {} pieces don't match: {:?}", failed.len(), failed)
+            }
+        }
+    }
+}
+
+/// Check a local file against a magnet link
+///
+/// Magnets carry no piece hashes, so without `torrent` only the size (`xl`)
+/// can be compared. When the torrent metadata is available it must match the
+/// magnet's info hash, and every piece of the file is verified as well.
+pub fn check_file(
+    magnet: &MagnetLink,
+    path: &Path,
+    torrent: Option<&Torrent>,
+) -> Result<CheckResult, String> {
+    let actual = fs::metadata(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        .len();
+
+    if let Some(expected) = magnet.exact_length
+        && expected != actual
+    {
+        return Ok(CheckResult::SizeMismatch { expected, actual });
+    }
+
+    let Some(torrent) = torrent else {
+        return Ok(match magnet.exact_length {
+            Some(_) => CheckResult::Pass {
+                verified_pieces: None,
+            },
+            None => CheckResult::Inconclusive,
+        });
+    };
+
+    // A v2-only magnet has no v1 info hash to compare the metadata against
+    if !magnet.info_hash.is_empty() {
+        let expected = magnet.info_hash_hex()?.to_lowercase();
+        let info_hash = torrent.info_hash_hex()?;
+        if info_hash != expected {
+            return Ok(CheckResult::InfoHashMismatch {
+                expected,
+                actual: info_hash,
+            });
+        }
+    }
+    if torrent.info.files.is_some() {
+        return Err("Checking multi-file torrents is not supported".to_string());
+    }
+
+    let expected = torrent.info.total_length() as u64;
+    if expected != actual {
+        return Ok(CheckResult::SizeMismatch { expected, actual });
+    }
+
+    let data = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let failed: Vec<usize> = data
+        .chunks(torrent.info.plength)
+        .enumerate()
+        .filter(|(index, piece)| !verify_piece(&torrent.info, *index, piece))
+        .map(|(index, _)| index)
+        .collect();
+
+    if !failed.is_empty() {
+        return Ok(CheckResult::PieceMismatch { failed });
+    }
+
+    Ok(CheckResult::Pass {
+        verified_pieces: Some(torrent.info.num_pieces()),
+    })
+}
+
+/// Outcome of verifying every piece of a torrent's files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of pieces in the torrent
+    pub total_pieces: usize,
+    /// Pieces that don't match their hash or couldn't be read, in order
+    pub failed: Vec<usize>,
+}
+
+impl VerifyReport {
+    /// Whether every piece matched
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// Number of pieces that matched
+    pub fn verified_pieces(&self) -> usize {
+        self.total_pieces - self.failed.len()
+    }
+}
+
+/// Verify every piece of a torrent's files, hashing on `threads` threads
+///
+/// `base_path` is the file itself for a single-file torrent, or the directory
+/// holding the files of a multi-file one. The threads take pieces one at a
+/// time and read them straight from the files, so only a piece per thread is
+/// held in memory. Pieces in missing or short files count as failed. The
+/// report is the same whatever the number of threads.
+pub fn verify_files_parallel(
+    info: &Info,
+    base_path: &Path,
+    threads: usize,
+) -> Result<VerifyReport, String> {
+    let paths: Vec<PathBuf> = match &info.files {
+        Some(files) => files
+            .iter()
+            .map(|file| file.safe_path(base_path))
+            .collect::<Result<_, String>>()?,
+        None => vec![base_path.to_path_buf()],
+    };
+
+    let num_pieces = info.num_pieces();
+    let next = AtomicUsize::new(0);
+    let mut failed: Vec<usize> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.clamp(1, num_pieces.max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    // Every thread reads through handles of its own
+                    let mut files: Vec<Option<File>> =
+                        paths.iter().map(|path| File::open(path).ok()).collect();
+                    let mut failed = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        if index >= num_pieces {
+                            break failed;
+                        }
+                        let piece = read_piece(info, &mut files, index);
+                        if !piece.is_some_and(|piece| verify_piece(info, index, &piece)) {
+                            failed.push(index);
+                        }
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+    failed.sort_unstable();
+
+    Ok(VerifyReport {
+        total_pieces: num_pieces,
+        failed,
+    })
+}
+
+/// Read piece `index` from the torrent's files, or `None` if part of it is missing
+fn read_piece(info: &Info, files: &mut [Option<File>], index: usize) -> Option<Vec<u8>> {
+    let mut piece = vec![0u8; info.piece_size(index)];
+    let mut filled = 0;
+    for (file, offset, len) in info.files_for_range(index * info.plength, piece.len()) {
+        let file = files[file].as_mut()?;
+        file.seek(SeekFrom::Start(offset as u64)).ok()?;
+        file.read_exact(&mut piece[filled..filled + len]).ok()?;
+        filled += len;
+    }
+    Some(piece)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::parser::parse_torrent;
+
+    const SAMPLE_INFO_HASH: &str = "d69f91e6b2ae4c542468d1073a71d4ea13879a7f";
+
+    fn magnet(info_hash: &str, exact_length: Option<u64>) -> MagnetLink {
+        MagnetLink {
+            info_hash: info_hash.to_string(),
+            exact_length,
+            ..Default::default()
+        }
+    }
+
+    fn temp_file(name: &str, data: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("x402-check-{}-{}", std::process::id(), name));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_check_size_mismatch() {
+        let path = temp_file("size", &[0u8; 100]);
+        let result = check_file(&magnet(SAMPLE_INFO_HASH, Some(92063)), &path, None);
+        fs::remove_file(&path).unwrap();
+
+        let result = result.unwrap();
+        assert_eq!(
+            result,
+            CheckResult::SizeMismatch {
+                expected: 92063,
+                actual: 100,
+            }
+        );
+        assert!(!result.is_pass());
+    }
+
+    #[test]
+    fn test_check_size_only() {
+        let path = temp_file("size-only", &[0u8; 100]);
+        let sized = check_file(&magnet(SAMPLE_INFO_HASH, Some(100)), &path, None);
+        let without_size = check_file(&magnet(SAMPLE_INFO_HASH, None), &path, None);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            sized.unwrap(),
+            CheckResult::Pass {
+                verified_pieces: None
+            }
+        );
+        assert_eq!(without_size.unwrap(), CheckResult::Inconclusive);
+    }
+
+    #[test]
+    fn test_check_pieces_against_metadata() {
+        let torrent = parse_torrent(include_bytes!("../../../sample.torrent")).unwrap();
+        // Same size as the sample content, but not its data
+        let path = temp_file("pieces", &vec![0u8; 92063]);
+        let result = check_file(&magnet(SAMPLE_INFO_HASH, None), &path, Some(&torrent));
+        let wrong_hash = check_file(&magnet(&"0".repeat(40), None), &path, Some(&torrent));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            result.unwrap(),
+            CheckResult::PieceMismatch {
+                failed: vec![0, 1, 2]
+            }
+        );
+        assert!(matches!(
+            wrong_hash.unwrap(),
+            CheckResult::InfoHashMismatch { .. }
+        ));
+    }
+
+    #[test]
+    fn test_check_accepts_base32_and_uppercase_info_hashes() {
+        let torrent = parse_torrent(include_bytes!("../../../sample.torrent")).unwrap();
+        let path = temp_file("base32", &vec![0u8; 92063]);
+        let base32 = crate::torrent::infohash::hex_to_base32(SAMPLE_INFO_HASH).unwrap();
+        let results: Vec<CheckResult> = [base32, SAMPLE_INFO_HASH.to_uppercase()]
+            .iter()
+            .map(|info_hash| check_file(&magnet(info_hash, None), &path, Some(&torrent)).unwrap())
+            .collect();
+        fs::remove_file(&path).unwrap();
+
+        // The info hashes match, so the pieces are what gets checked
+        for result in results {
+            assert_eq!(
+                result,
+                CheckResult::PieceMismatch {
+                    failed: vec![0, 1, 2]
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_verify_files_parallel_matches_serial() {
+        use crate::torrent::types::FileEntry;
+        use crate::torrent::verify::hash_piece;
+
+        let piece_length = 64;
+        let data: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let lengths = [300, 0, 450, 250];
+        let info = Info {
+            name: "test".to_string(),
+            plength: piece_length,
+            pieces: serde_bytes::ByteBuf::from(
+                data.chunks(piece_length)
+                    .flat_map(hash_piece)
+                    .collect::<Vec<u8>>(),
+            ),
+            files: Some(
+                lengths
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &length)| FileEntry {
+                        length,
+                        path: vec![format!("file{}.bin", i)],
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+
+        // Corrupt a piece inside a file and one spanning the first two files
+        let mut stored = data.clone();
+        stored[5 * piece_length + 1] ^= 0xff;
+        stored[300] ^= 0xff;
+        let dir = std::env::temp_dir().join(format!("x402-verify-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut offset = 0;
+        for (i, length) in lengths.iter().enumerate() {
+            fs::write(
+                dir.join(format!("file{}.bin", i)),
+                &stored[offset..offset + length],
+            )
+            .unwrap();
+            offset += length;
+        }
+
+        let serial: Vec<usize> = stored
+            .chunks(piece_length)
+            .enumerate()
+            .filter(|(index, piece)| !verify_piece(&info, *index, piece))
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(serial, vec![4, 5]);
+
+        let reports: Vec<VerifyReport> = [1, 2, 3, 8, 64]
+            .iter()
+            .map(|&threads| verify_files_parallel(&info, &dir, threads).unwrap())
+            .collect();
+        // Without the last file, the pieces it holds fail too
+        fs::remove_file(dir.join("file3.bin")).unwrap();
+        let missing = verify_files_parallel(&info, &dir, 4).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        for report in reports {
+            assert_eq!(
+                report,
+                VerifyReport {
+                    total_pieces: 16,
+                    failed: serial.clone(),
+                }
+            );
+        }
+        assert_eq!(missing.failed, vec![4, 5, 11, 12, 13, 14, 15]);
+        assert_eq!(missing.verified_pieces(), 9);
+        assert!(!missing.is_complete());
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::torrent::types::{Info, Torrent};
+use crate::torrent::verify::hash_piece;
+
+/// Hash `reader` one piece at a time, returning the hashes and the total length
+///
+/// Only a single piece is held in memory, however long the input is. The
+/// last piece is shorter when the length isn't a multiple of `piece_length`.
+pub fn hash_pieces<R: Read>(
+    mut reader: R,
+    piece_length: usize,
+) -> Result<(Vec<[u8; 20]>, usize), String> {
+    if piece_length == 0 {
+        return Err("Piece length is 0".to_string());
+    }
+
+    let mut buf = vec![0u8; piece_length];
+    let mut hashes = Vec::new();
+    let mut total = 0;
+    loop {
+        let filled = fill(&mut reader, &mut buf)
+            .map_err(|e| format!("Failed to read piece {}: {}", hashes.len(), e))?;
+        if filled == 0 {
+            break;
+        }
+        hashes.push(hash_piece(&buf[..filled]));
+        total += filled;
+        if filled < piece_length {
+            break;
+        }
+    }
+    Ok((hashes, total))
+}
+
+/// Create a single-file torrent for `path`, hashing it as it's read
+pub fn make_torrent(path: &Path, announce: &str, piece_length: usize) -> Result<Torrent, String> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("Invalid file name: {}", path.display()))?
+        .to_string();
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let (hashes, length) = hash_pieces(file, piece_length)?;
+
+    let mut info = Info {
+        name,
+        plength: piece_length,
+        length: Some(length),
+        ..Default::default()
+    };
+    info.set_piece_hashes(&hashes)?;
+    Ok(Torrent {
+        announce: announce.to_string(),
+        info,
+        ..Default::default()
+    })
+}
+
+/// Read until `buf` is full or the input ends, returning the bytes read
+fn fill<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::verify::verify_piece;
+    use std::fs;
+
+    #[test]
+    fn test_make_torrent_hashes_each_piece() {
+        let path = std::env::temp_dir().join(format!("x402-create-{}.bin", std::process::id()));
+        let data: Vec<u8> = (0..100u32).map(|i| (i * 7) as u8).collect();
+        fs::write(&path, &data).unwrap();
+
+        let torrent = make_torrent(&path, "http://tracker.example/announce", 32).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let info = &torrent.info;
+        assert_eq!(info.length, Some(100));
+        assert_eq!(info.num_pieces(), 4);
+        // The last piece holds the 4 bytes left over
+        assert_eq!(info.piece_size(3), 4);
+        for (index, piece) in data.chunks(32).enumerate() {
+            assert_eq!(info.piece_hash(index).unwrap(), hash_piece(piece));
+            assert!(verify_piece(info, index, piece));
+        }
+    }
+}
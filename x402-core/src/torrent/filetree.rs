@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+
+/// A BitTorrent v2 file tree (BEP 52), mapping path components to nodes
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct FileTree(pub BTreeMap<String, FileTreeNode>);
+
+/// A node in a v2 file tree: either a file or a nested directory
+///
+/// Files are encoded as a dictionary with a single empty-string key holding
+/// the file attributes, so they must be tried before directories.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum FileTreeNode {
+    File {
+        #[serde(rename = "")]
+        attributes: FileAttributes,
+    },
+    Directory(FileTree),
+}
+
+/// Attributes of a file in a v2 file tree
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FileAttributes {
+    pub length: u64,
+
+    /// Root of the file's merkle tree, absent for empty files
+    #[serde(
+        default,
+        rename = "pieces root",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub pieces_root: Option<ByteBuf>,
+}
+
+impl FileTree {
+    /// List every file in the tree with its full path, in path order
+    pub fn files(&self) -> Vec<(Vec<String>, &FileAttributes)> {
+        let mut files = Vec::new();
+        self.collect_files(&mut Vec::new(), &mut files);
+        files
+    }
+
+    /// Sum of the lengths of every file in the tree
+    pub fn total_length(&self) -> u64 {
+        self.files()
+            .iter()
+            .map(|(_, attributes)| attributes.length)
+            .sum()
+    }
+
+    fn collect_files<'a>(
+        &'a self,
+        prefix: &mut Vec<String>,
+        files: &mut Vec<(Vec<String>, &'a FileAttributes)>,
+    ) {
+        for (name, node) in &self.0 {
+            prefix.push(name.clone());
+            match node {
+                FileTreeNode::File { attributes } => files.push((prefix.clone(), attributes)),
+                FileTreeNode::Directory(tree) => tree.collect_files(prefix, files),
+            }
+            prefix.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_file_tree() {
+        let data = b"d5:b.txtd0:d6:lengthi0eee3:dird5:a.txtd0:d6:lengthi5e11:pieces root3:abceeee";
+        let tree: FileTree = serde_bencode::from_bytes(data).unwrap();
+
+        let files = tree.files();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].0, vec!["b.txt".to_string()]);
+        assert_eq!(files[0].1.length, 0);
+        assert_eq!(files[0].1.pieces_root, None);
+        assert_eq!(files[1].0, vec!["dir".to_string(), "a.txt".to_string()]);
+        assert_eq!(files[1].1.pieces_root, Some(ByteBuf::from(b"abc".to_vec())));
+        assert_eq!(tree.total_length(), 5);
+
+        // Re-encoding must reproduce the original bytes
+        assert_eq!(serde_bencode::to_bytes(&tree).unwrap(), data.to_vec());
+    }
+}
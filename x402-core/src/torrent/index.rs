@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::torrent::types::Torrent;
+
+/// A torrent whose info hash is already in the index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Duplicate {
+    pub info_hash: [u8; 20],
+    /// Name of the torrent indexed first
+    pub existing_name: String,
+    /// Name of the torrent that was rejected; can differ when only the
+    /// fields outside the info dictionary changed
+    pub name: String,
+}
+
+impl fmt::Display for Duplicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Duplicate info hash {}: '{}' is already indexed as '{}'",
+            hex::encode(self.info_hash),
+            self.name,
+            self.existing_name
+        )
+    }
+}
+
+/// Errors adding a torrent to a `TorrentIndex`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndexError {
+    /// A torrent with the same info hash is already indexed
+    Duplicate(Duplicate),
+    /// The info hash couldn't be derived
+    InfoHash(String),
+}
+
+impl fmt::Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexError::Duplicate(duplicate) => write!(f, "{}", duplicate),
+            IndexError::InfoHash(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<IndexError> for String {
+    fn from(error: IndexError) -> Self {
+        error.to_string()
+    }
+}
+
+/// Parsed torrents keyed by info hash, rejecting duplicates
+#[derive(Debug, Default)]
+pub struct TorrentIndex {
+    torrents: HashMap<[u8; 20], Torrent>,
+}
+
+impl TorrentIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a torrent, keeping the first one indexed when its info hash is taken
+    pub fn add(&mut self, torrent: Torrent) -> Result<(), IndexError> {
+        let info_hash = torrent.info_hash().map_err(IndexError::InfoHash)?;
+        if let Some(existing) = self.torrents.get(&info_hash) {
+            return Err(IndexError::Duplicate(Duplicate {
+                info_hash,
+                existing_name: existing.info.name.clone(),
+                name: torrent.info.name,
+            }));
+        }
+        self.torrents.insert(info_hash, torrent);
+        Ok(())
+    }
+
+    /// The indexed torrent with this info hash
+    pub fn get(&self, info_hash: &[u8; 20]) -> Option<&Torrent> {
+        self.torrents.get(info_hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.torrents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.torrents.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::types::Info;
+    use serde_bytes::ByteBuf;
+
+    fn torrent(announce: &str) -> Torrent {
+        Torrent {
+            announce: announce.to_string(),
+            info: Info {
+                name: "file.bin".to_string(),
+                plength: 16,
+                pieces: ByteBuf::from(vec![7u8; 20]),
+                length: Some(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_add_reports_duplicate_info_hash() {
+        let mut index = TorrentIndex::new();
+        let first = torrent("http://one.example/announce");
+        let info_hash = first.info_hash().unwrap();
+        index.add(first).unwrap();
+
+        // Same info dict, different tracker: still the same content
+        let error = index
+            .add(torrent("http://two.example/announce"))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            IndexError::Duplicate(Duplicate {
+                info_hash,
+                existing_name: "file.bin".to_string(),
+                name: "file.bin".to_string(),
+            })
+        );
+        assert_eq!(index.len(), 1);
+        assert_eq!(
+            index.get(&info_hash).unwrap().announce,
+            "http://one.example/announce"
+        );
+    }
+}
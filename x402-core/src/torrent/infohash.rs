@@ -1,11 +1,211 @@
+/// A SHA-1 implementation used to derive info hashes
+///
+/// Lets callers plug in an accelerated backend instead of the software `sha1` crate.
+pub trait Hasher {
+    fn sha1(&self, data: &[u8]) -> [u8; 20];
+}
+
+/// The default hasher, backed by the `sha1` crate
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha1Hasher;
+
+impl Hasher for Sha1Hasher {
+    fn sha1(&self, data: &[u8]) -> [u8; 20] {
+        use sha1::{Digest, Sha1};
+
+        let mut hasher = Sha1::new();
+
+        hasher.update(data);
+        let result = hasher.finalize();
+        let mut hash = [0u8; 20];
+        hash.copy_from_slice(&result);
+        hash
+    }
+}
+
 pub fn derive_infohash(info_bytes: &[u8]) -> [u8; 20] {
-    use sha1::{Digest, Sha1};
+    derive_infohash_with(&Sha1Hasher, info_bytes)
+}
+
+/// Derive the v1 info hash using a custom SHA-1 implementation
+pub fn derive_infohash_with<H: Hasher>(hasher: &H, info_bytes: &[u8]) -> [u8; 20] {
+    hasher.sha1(info_bytes)
+}
 
-    let mut hasher = Sha1::new();
+/// Derive the v2 info hash (SHA-256 of the bencoded info dictionary, BEP 52)
+pub fn derive_infohash_v2(info_bytes: &[u8]) -> [u8; 32] {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
 
     hasher.update(info_bytes);
     let result = hasher.finalize();
-    let mut infohash = [0u8; 20];
+    let mut infohash = [0u8; 32];
     infohash.copy_from_slice(&result);
     infohash
 }
+
+/// Truncate a v2 info hash to the 20 bytes used on the v1 wire protocol and DHT
+pub fn truncate_v2(sha256: [u8; 32]) -> [u8; 20] {
+    let mut truncated = [0u8; 20];
+    truncated.copy_from_slice(&sha256[..20]);
+    truncated
+}
+
+/// RFC 4648 base32 alphabet, as used for info hashes in magnet links
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Convert a 40-character hex info hash to its 32-character base32 form
+///
+/// The result is uppercase and unpadded, as BitTorrent clients write it.
+pub fn hex_to_base32(hex: &str) -> Result<String, String> {
+    if hex.len() != 40 {
+        return Err(format!(
+            "Invalid hex info hash length: expected 40, got {}",
+            hex.len()
+        ));
+    }
+    let bytes = hex::decode(hex).map_err(|e| format!("Invalid hex in info hash: {}", e))?;
+
+    let mut encoded = String::with_capacity(32);
+    // 20 bytes are exactly 32 groups of 5 bits, so no padding is needed
+    for chunk in bytes.chunks(5) {
+        let bits = chunk.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+        for shift in (0..8).rev() {
+            encoded.push(BASE32_ALPHABET[((bits >> (shift * 5)) & 0x1F) as usize] as char);
+        }
+    }
+    Ok(encoded)
+}
+
+/// Convert a 32-character base32 info hash to its 40-character lowercase hex form
+///
+/// Lowercase base32 is accepted as well.
+pub fn base32_to_hex(b32: &str) -> Result<String, String> {
+    if b32.len() != 32 {
+        return Err(format!(
+            "Invalid base32 info hash length: expected 32, got {}",
+            b32.len()
+        ));
+    }
+
+    let mut bytes = Vec::with_capacity(20);
+    for chunk in b32.as_bytes().chunks(8) {
+        let mut bits = 0u64;
+        for &c in chunk {
+            let value = BASE32_ALPHABET
+                .iter()
+                .position(|&a| a == c.to_ascii_uppercase())
+                .ok_or_else(|| format!("Invalid base32 character '{}' in info hash", c as char))?;
+            bits = (bits << 5) | value as u64;
+        }
+        bytes.extend_from_slice(&bits.to_be_bytes()[3..]);
+    }
+    Ok(hex::encode(bytes))
+}
+
+/// Both info hashes of a hybrid torrent
+///
+/// Peers may identify a hybrid torrent by either the v1 hash or the
+/// truncated v2 hash in the handshake, so both must be accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HybridInfoHash {
+    /// SHA-1 of the info dictionary
+    pub v1: [u8; 20],
+    /// SHA-256 of the info dictionary
+    pub v2: [u8; 32],
+}
+
+impl HybridInfoHash {
+    /// Derive both info hashes from the bencoded info dictionary
+    pub fn from_info_bytes(info_bytes: &[u8]) -> Self {
+        HybridInfoHash {
+            v1: derive_infohash(info_bytes),
+            v2: derive_infohash_v2(info_bytes),
+        }
+    }
+
+    /// The v2 info hash truncated to 20 bytes
+    pub fn v2_truncated(&self) -> [u8; 20] {
+        truncate_v2(self.v2)
+    }
+
+    /// Whether a 20-byte info hash from a handshake or the DHT names this torrent
+    pub fn matches(&self, info_hash: &[u8; 20]) -> bool {
+        *info_hash == self.v1 || *info_hash == self.v2_truncated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records its input and returns a fixed hash
+    struct StubHasher {
+        seen: std::cell::RefCell<Vec<u8>>,
+    }
+
+    impl Hasher for StubHasher {
+        fn sha1(&self, data: &[u8]) -> [u8; 20] {
+            self.seen.borrow_mut().extend_from_slice(data);
+            [0x42; 20]
+        }
+    }
+
+    #[test]
+    fn test_derive_infohash_with_custom_hasher() {
+        let hasher = StubHasher {
+            seen: Default::default(),
+        };
+
+        assert_eq!(derive_infohash_with(&hasher, b"d4:name1:ae"), [0x42; 20]);
+        assert_eq!(hasher.seen.into_inner(), b"d4:name1:ae".to_vec());
+    }
+
+    #[test]
+    fn test_truncate_v2() {
+        let sha256: [u8; 32] = std::array::from_fn(|i| i as u8);
+        let truncated = truncate_v2(sha256);
+        assert_eq!(truncated.as_slice(), &sha256[..20]);
+    }
+
+    #[test]
+    fn test_hybrid_info_hash_matches() {
+        let hash = HybridInfoHash::from_info_bytes(b"d4:name1:ae");
+
+        assert!(hash.matches(&hash.v1));
+        assert!(hash.matches(&hash.v2_truncated()));
+        assert!(!hash.matches(&[0u8; 20]));
+    }
+
+    const HEX: &str = "d2474e86c95b19b8bcfdb92bc12c9d44667cfa36";
+    const BASE32: &str = "2JDU5BWJLMM3RPH5XEV4CLE5IRTHZ6RW";
+
+    #[test]
+    fn test_hex_base32_round_trip() {
+        assert_eq!(hex_to_base32(HEX).unwrap(), BASE32);
+        assert_eq!(base32_to_hex(BASE32).unwrap(), HEX);
+        assert_eq!(base32_to_hex(&BASE32.to_lowercase()).unwrap(), HEX);
+        assert_eq!(
+            hex_to_base32(&HEX.to_uppercase()).unwrap(),
+            BASE32.to_string()
+        );
+    }
+
+    #[test]
+    fn test_hex_base32_invalid() {
+        assert!(hex_to_base32("d2474e86").is_err());
+        assert!(hex_to_base32(&"zz".repeat(20)).is_err());
+        assert!(base32_to_hex("2JDU5BWJ").is_err());
+        // 0, 1 and 8 are not in the base32 alphabet
+        assert!(base32_to_hex(&"0".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_derive_infohash_default() {
+        assert_eq!(
+            hex::encode(derive_infohash(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+    }
+}
@@ -1,33 +1,136 @@
 use std::collections::HashMap;
+use std::fmt;
+
+use crate::payment::price::PriceUnit;
+use crate::peer::address::PeerAddress;
+use crate::torrent::infohash::{base32_to_hex, hex_to_base32};
+use crate::torrent::naming::sanitize_filename;
+
+/// Magnet parameter carrying the x402 price, as `<amount>` or `<amount>:<unit>`
+const PRICE_PARAM: &str = "x.402";
+/// Accepted alternative spelling of the price parameter
+const PRICE_PARAM_ALT: &str = "x402";
+/// Multihash prefix of a v2 info hash: SHA-256 (0x12), 32 bytes long (0x20)
+const SHA256_MULTIHASH_PREFIX: &str = "1220";
+
+/// Encoding of the info hash in a magnet URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashEncoding {
+    /// 40 hex characters
+    #[default]
+    Hex,
+    /// 32 base32 characters
+    Base32,
+}
+
+/// How `MagnetLink::to_url_with` writes the info hash
+///
+/// Some clients and trackers match info hashes as exact strings, so the
+/// encoding and case have to be what they expect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UrlOptions {
+    pub encoding: HashEncoding,
+    /// Write the hash in uppercase instead of lowercase
+    pub uppercase: bool,
+}
+
+/// Errors parsing a magnet link
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MagnetError {
+    /// Neither an `xt` info hash nor `kt` keywords, so nothing to look for
+    EmptyMagnet,
+    /// Any other malformed input
+    Invalid(String),
+}
+
+impl fmt::Display for MagnetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MagnetError::EmptyMagnet => {
+                write!(f, "Magnet link has neither an 'xt' nor a 'kt' parameter")
+            }
+            MagnetError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
 
-#[derive(Debug, Clone, PartialEq)]
+impl From<String> for MagnetError {
+    fn from(message: String) -> Self {
+        MagnetError::Invalid(message)
+    }
+}
+
+impl From<MagnetError> for String {
+    fn from(error: MagnetError) -> Self {
+        error.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct MagnetLink {
+    /// v1 info hash from `urn:btih`, empty for a v2-only magnet
     pub info_hash: String,
+    /// v2 info hash from `urn:btmh`, as the 64 hex characters of the SHA-256
+    pub info_hash_v2: Option<String>,
     pub display_name: Option<String>,
+    /// Search keywords from `kt`, for magnets that find content by keyword
+    pub keywords: Vec<String>,
     pub trackers: Vec<String>,
+    /// Peers to connect to directly, from `x.pe`
+    pub peers: Vec<PeerAddress>,
     pub exact_length: Option<u64>,
+    /// x402 price of the content
+    pub price: Option<u64>,
+    /// Unit of `price`, if the magnet names one
+    pub price_unit: Option<PriceUnit>,
 }
 
 impl MagnetLink {
     /// Parse a magnet URL string into a MagnetLink struct
-    pub fn parse(url: &str) -> Result<Self, String> {
+    ///
+    /// A magnet needs an `xt` info hash or `kt` keywords; one with neither
+    /// fails with `MagnetError::EmptyMagnet`.
+    pub fn parse(url: &str) -> Result<Self, MagnetError> {
         if !url.starts_with("magnet:?") {
-            return Err("Invalid magnet link: must start with 'magnet:?'".to_string());
+            return Err("Invalid magnet link: must start with 'magnet:?'"
+                .to_string()
+                .into());
         }
 
         let params_str = &url[8..]; // Skip "magnet:?"
         let params = parse_query_params(params_str);
 
-        // Extract info hash from xt parameter
-        let info_hash = params
-            .get("xt")
-            .and_then(|v| v.first())
-            .ok_or("Missing required 'xt' parameter")?;
+        // Extract the search keywords, separated by `+` or spaces
+        let keywords: Vec<String> = params
+            .get("kt")
+            .into_iter()
+            .flatten()
+            .flat_map(|v| {
+                url_decode(v)
+                    .split_whitespace()
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        // Extract the info hashes from the xt parameters; a hybrid magnet has both
+        let xts = params.get("xt").map(Vec::as_slice).unwrap_or_default();
+        if xts.is_empty() && keywords.is_empty() {
+            return Err(MagnetError::EmptyMagnet);
+        }
 
-        println!("Extracted xt parameter: {}", info_hash);
+        let mut info_hash = String::new();
+        let mut info_hash_v2 = None;
+        for xt in xts {
+            println!("Extracted xt parameter: {}", xt);
 
-        // Validate and extract the hash
-        let info_hash = extract_info_hash(info_hash)?;
+            // Validate and extract the hash
+            if xt.starts_with("urn:btmh:") {
+                info_hash_v2 = Some(extract_info_hash_v2(xt)?);
+            } else {
+                info_hash = extract_info_hash(xt)?;
+            }
+        }
 
         // Extract display name
         let display_name = params
@@ -36,42 +139,189 @@ impl MagnetLink {
             .map(|s| url_decode(s));
 
         // Extract all tracker URLs
-        let trackers = params
+        let mut trackers: Vec<String> = params
             .get("tr")
             .map(|v| v.iter().map(|s| url_decode(s)).collect())
             .unwrap_or_default();
 
+        // Some generators number their trackers instead: `tr.1`, `tr.2`, ...
+        let mut indexed: Vec<(u32, String)> = params
+            .iter()
+            .filter_map(|(key, values)| {
+                let index = key.strip_prefix("tr.")?.parse::<u32>().ok()?;
+                Some(values.iter().map(move |value| (index, url_decode(value))))
+            })
+            .flatten()
+            .collect();
+        indexed.sort_by_key(|(index, _)| *index);
+        for (_, tracker) in indexed {
+            if !trackers.contains(&tracker) {
+                trackers.push(tracker);
+            }
+        }
+
+        // Extract the direct peers; `x.pe` may also name a host, which is skipped
+        let peers: Vec<PeerAddress> = params
+            .get("x.pe")
+            .into_iter()
+            .flatten()
+            .filter_map(|value| url_decode(value).parse().ok())
+            .collect();
+
         // Extract exact length
         let exact_length = params
             .get("xl")
             .and_then(|v| v.first())
             .and_then(|s| s.parse::<u64>().ok());
 
+        // Extract the x402 price
+        let (price, price_unit) = match params
+            .get(PRICE_PARAM)
+            .or_else(|| params.get(PRICE_PARAM_ALT))
+            .and_then(|v| v.first())
+        {
+            Some(value) => {
+                let (price, unit) = parse_price(&url_decode(value))?;
+                (Some(price), unit)
+            }
+            None => (None, None),
+        };
+
         Ok(MagnetLink {
             info_hash,
+            info_hash_v2,
             display_name,
+            keywords,
             trackers,
+            peers,
             exact_length,
+            price,
+            price_unit,
         })
     }
 
-    /// Convert the MagnetLink back to a magnet URL string
+    /// Parse a magnet link, also accepting a bare query string without `magnet:?`
+    ///
+    /// Only input that starts with a plain parameter name (like `xt=`) is
+    /// treated as a bare query, so other URLs are still rejected.
+    pub fn parse_lenient(input: &str) -> Result<Self, MagnetError> {
+        let input = input.trim();
+        if input.starts_with("magnet:?") {
+            return Self::parse(input);
+        }
+
+        let query = input.strip_prefix('?').unwrap_or(input);
+        let is_bare_query = query.split_once('=').is_some_and(|(key, _)| {
+            !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_')
+        });
+        if !is_bare_query {
+            return Err(
+                "Invalid magnet link: expected 'magnet:?' or a bare magnet query"
+                    .to_string()
+                    .into(),
+            );
+        }
+
+        Self::parse(&format!("magnet:?{}", query))
+    }
+
+    /// Provisional output file name until the metadata is known
+    ///
+    /// Uses the sanitized `dn`, falling back to the info hash.
+    pub fn output_name(&self) -> String {
+        let info_hash = match &self.info_hash_v2 {
+            Some(info_hash_v2) if self.info_hash.is_empty() => info_hash_v2,
+            _ => &self.info_hash,
+        };
+        self.display_name
+            .as_deref()
+            .and_then(sanitize_filename)
+            .unwrap_or_else(|| info_hash.clone())
+    }
+
+    /// The info hash as 40 lowercase hex characters, whichever form the link used
+    pub fn info_hash_hex(&self) -> Result<String, String> {
+        match self.info_hash.len() {
+            0 => Err("Magnet link has no v1 info hash".to_string()),
+            32 => base32_to_hex(&self.info_hash),
+            _ => Ok(self.info_hash.clone()),
+        }
+    }
+
+    /// The info hash as 32 uppercase base32 characters, whichever form the link used
+    pub fn info_hash_base32(&self) -> Result<String, String> {
+        match self.info_hash.len() {
+            32 => base32_to_hex(&self.info_hash).map(|_| self.info_hash.to_uppercase()),
+            _ => hex_to_base32(&self.info_hash),
+        }
+    }
+
+    /// Convert the MagnetLink back to a magnet URL string, with a lowercase hex info hash
     pub fn to_url(&self) -> String {
-        let mut url = format!("magnet:?xt=urn:btih:{}", self.info_hash);
+        self.to_url_with(&UrlOptions::default())
+    }
+
+    /// Convert the MagnetLink back to a magnet URL string, choosing how the info hash is written
+    ///
+    /// A hash that can't be converted to the requested encoding is written as
+    /// stored. A v2 info hash is always written in hex, as a SHA-256 multihash.
+    pub fn to_url_with(&self, opts: &UrlOptions) -> String {
+        let case = |hash: String| {
+            if opts.uppercase {
+                hash.to_uppercase()
+            } else {
+                hash.to_lowercase()
+            }
+        };
+        let mut params = Vec::new();
+        if !self.info_hash.is_empty() {
+            let info_hash = match opts.encoding {
+                HashEncoding::Hex => self.info_hash_hex(),
+                HashEncoding::Base32 => self.info_hash_base32(),
+            }
+            .unwrap_or_else(|_| self.info_hash.clone());
+            params.push(format!("xt=urn:btih:{}", case(info_hash)));
+        }
+        if let Some(info_hash_v2) = &self.info_hash_v2 {
+            params.push(format!(
+                "xt=urn:btmh:{}{}",
+                SHA256_MULTIHASH_PREFIX,
+                case(info_hash_v2.clone())
+            ));
+        }
 
         if let Some(ref name) = self.display_name {
-            url.push_str(&format!("&dn={}", url_encode(name)));
+            params.push(format!("dn={}", url_encode(name)));
+        }
+
+        if !self.keywords.is_empty() {
+            params.push(format!("kt={}", url_encode(&self.keywords.join(" "))));
         }
 
         for tracker in &self.trackers {
-            url.push_str(&format!("&tr={}", url_encode(tracker)));
+            params.push(format!("tr={}", url_encode(tracker)));
+        }
+
+        for peer in &self.peers {
+            params.push(format!("x.pe={}", url_encode(&peer.to_string())));
         }
 
         if let Some(length) = self.exact_length {
-            url.push_str(&format!("&xl={}", length));
+            params.push(format!("xl={}", length));
+        }
+
+        if let Some(price) = self.price {
+            let mut param = format!("{}={}", PRICE_PARAM, price);
+            if let Some(unit) = self.price_unit {
+                param.push_str(&format!(":{}", unit));
+            }
+            params.push(param);
         }
 
-        url
+        format!("magnet:?{}", params.join("&"))
     }
 }
 
@@ -79,11 +329,12 @@ impl MagnetLink {
 fn parse_query_params(query: &str) -> HashMap<String, Vec<String>> {
     let mut params: HashMap<String, Vec<String>> = HashMap::new();
 
-    for pair in query.split('&') {
+    // The original magnet spec also allows `;` between parameters
+    for pair in query.split(['&', ';']) {
         if let Some((key, value)) = pair.split_once('=') {
             params
                 .entry(key.to_string())
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(value.to_string());
         }
     }
@@ -91,6 +342,18 @@ fn parse_query_params(query: &str) -> HashMap<String, Vec<String>> {
     params
 }
 
+/// Parse an x402 price value: an amount, optionally followed by `:<unit>`
+fn parse_price(value: &str) -> Result<(u64, Option<PriceUnit>), String> {
+    let (amount, unit) = match value.split_once(':') {
+        Some((amount, unit)) => (amount, Some(unit.parse::<PriceUnit>()?)),
+        None => (value, None),
+    };
+    let amount = amount
+        .parse::<u64>()
+        .map_err(|_| format!("Invalid x402 price: '{}'", value))?;
+    Ok((amount, unit))
+}
+
 /// Extract the info hash from an xt parameter value
 fn extract_info_hash(xt: &str) -> Result<String, String> {
     // Expected format: urn:btih:<hash>
@@ -116,6 +379,28 @@ fn extract_info_hash(xt: &str) -> Result<String, String> {
     Ok(hash.to_lowercase())
 }
 
+/// Extract the v2 info hash from an xt parameter value
+///
+/// Only SHA-256 multihashes are defined for v2 torrents (BEP 52).
+fn extract_info_hash_v2(xt: &str) -> Result<String, String> {
+    // Expected format: urn:btmh:1220<64 hex characters>
+    let multihash = xt
+        .strip_prefix("urn:btmh:")
+        .ok_or("Invalid xt parameter: must be 'urn:btmh:<multihash>'")?;
+    let hash = multihash
+        .strip_prefix(SHA256_MULTIHASH_PREFIX)
+        .ok_or("Invalid v2 info hash: not a SHA-256 multihash")?;
+
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "Invalid v2 info hash: expected 64 hex characters, got '{}'",
+            hash
+        ));
+    }
+
+    Ok(hash.to_lowercase())
+}
+
 /// Simple URL decode (percent-encoding)
 fn url_decode(s: &str) -> String {
     let mut result = String::new();
@@ -179,6 +464,46 @@ mod tests {
         assert_eq!(magnet.exact_length, Some(2147483648));
     }
 
+    #[test]
+    fn test_parse_indexed_trackers() {
+        let url = "magnet:?xt=urn:btih:d2474e86c95b19b8bcfdb92bc12c9d44667cfa36&tr.2=udp%3A%2F%2Fsecond.example.com%3A80&tr=http://plain.example.com&tr.1=http://first.example.com&tr.10=http://tenth.example.com";
+        let magnet = MagnetLink::parse(url).unwrap();
+
+        assert_eq!(
+            magnet.trackers,
+            vec![
+                "http://plain.example.com".to_string(),
+                "http://first.example.com".to_string(),
+                "udp://second.example.com:80".to_string(),
+                "http://tenth.example.com".to_string(),
+            ]
+        );
+
+        // Written back in the standard repeated form
+        let url = magnet.to_url();
+        assert!(!url.contains("tr."));
+        assert_eq!(url.matches("&tr=").count(), 4);
+    }
+
+    #[test]
+    fn test_parse_semicolon_separated_magnet() {
+        let url = "magnet:?xt=urn:btih:d2474e86c95b19b8bcfdb92bc12c9d44667cfa36;dn=Ubuntu+20.04;tr=http://tracker.example.com&xl=1024";
+        let magnet = MagnetLink::parse(url).unwrap();
+
+        assert_eq!(magnet.info_hash, "d2474e86c95b19b8bcfdb92bc12c9d44667cfa36");
+        assert_eq!(magnet.display_name, Some("Ubuntu 20.04".to_string()));
+        assert_eq!(
+            magnet.trackers,
+            vec!["http://tracker.example.com".to_string()]
+        );
+        assert_eq!(magnet.exact_length, Some(1024));
+
+        // Written back with `&` only
+        let url = magnet.to_url();
+        assert!(!url.contains(';'));
+        assert!(url.contains("&dn=Ubuntu"));
+    }
+
     #[test]
     fn test_to_url() {
         let magnet = MagnetLink {
@@ -186,6 +511,7 @@ mod tests {
             display_name: Some("Test File".to_string()),
             trackers: vec!["udp://tracker.example.com:80".to_string()],
             exact_length: Some(1024),
+            ..Default::default()
         };
 
         let url = magnet.to_url();
@@ -196,15 +522,78 @@ mod tests {
         assert!(url.contains("xl=1024"));
     }
 
+    #[test]
+    fn test_to_url_with_hash_options() {
+        let magnet = MagnetLink {
+            info_hash: "d2474e86c95b19b8bcfdb92bc12c9d44667cfa36".to_string(),
+            ..Default::default()
+        };
+
+        let upper_hex = magnet.to_url_with(&UrlOptions {
+            encoding: HashEncoding::Hex,
+            uppercase: true,
+        });
+        assert_eq!(
+            upper_hex,
+            "magnet:?xt=urn:btih:D2474E86C95B19B8BCFDB92BC12C9D44667CFA36"
+        );
+
+        let upper_base32 = magnet.to_url_with(&UrlOptions {
+            encoding: HashEncoding::Base32,
+            uppercase: true,
+        });
+        assert_eq!(
+            upper_base32,
+            "magnet:?xt=urn:btih:2JDU5BWJLMM3RPH5XEV4CLE5IRTHZ6RW"
+        );
+
+        let lower_base32 = magnet.to_url_with(&UrlOptions {
+            encoding: HashEncoding::Base32,
+            uppercase: false,
+        });
+        assert_eq!(
+            lower_base32,
+            "magnet:?xt=urn:btih:2jdu5bwjlmm3rph5xev4cle5irthz6rw"
+        );
+
+        // A base32 link is written as lowercase hex by default
+        let base32 = MagnetLink::parse(&upper_base32).unwrap();
+        assert_eq!(
+            base32.to_url(),
+            "magnet:?xt=urn:btih:d2474e86c95b19b8bcfdb92bc12c9d44667cfa36"
+        );
+    }
+
     #[test]
     fn test_parse_invalid_magnet() {
         let result = MagnetLink::parse("http://example.com");
         assert!(result.is_err());
 
-        let result = MagnetLink::parse("magnet:?dn=test");
+        let result = MagnetLink::parse("magnet:?xt=urn:sha1:abc");
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_parse_empty_magnet() {
+        assert_eq!(
+            MagnetLink::parse("magnet:?dn=foo"),
+            Err(MagnetError::EmptyMagnet)
+        );
+        assert_eq!(MagnetLink::parse("magnet:?"), Err(MagnetError::EmptyMagnet));
+    }
+
+    #[test]
+    fn test_parse_keyword_magnet() {
+        let magnet = MagnetLink::parse("magnet:?kt=foo").unwrap();
+        assert_eq!(magnet.keywords, vec!["foo".to_string()]);
+        assert!(magnet.info_hash.is_empty());
+        assert!(magnet.info_hash_hex().is_err());
+
+        let magnet = MagnetLink::parse("magnet:?kt=martin+luther&dn=Speech").unwrap();
+        assert_eq!(magnet.keywords, vec!["martin", "luther"]);
+        assert_eq!(magnet.to_url(), "magnet:?dn=Speech&kt=martin+luther");
+    }
+
     #[test]
     fn test_roundtrip() {
         let original = MagnetLink {
@@ -212,6 +601,7 @@ mod tests {
             display_name: Some("Test".to_string()),
             trackers: vec!["udp://tracker.test.com:80".to_string()],
             exact_length: Some(999),
+            ..Default::default()
         };
 
         let url = original.to_url();
@@ -219,4 +609,164 @@ mod tests {
 
         assert_eq!(original, parsed);
     }
+
+    #[test]
+    fn test_parse_direct_peers() {
+        let magnet = MagnetLink::parse(
+            "magnet:?xt=urn:btih:d2474e86c95b19b8bcfdb92bc12c9d44667cfa36\
+             &x.pe=10.0.0.1:6881&x.pe=%5B2001%3Adb8%3A%3A1%5D%3A51413&x.pe=peer.example:6881",
+        )
+        .unwrap();
+        assert_eq!(
+            magnet.peers,
+            vec![
+                "10.0.0.1:6881".parse().unwrap(),
+                "[2001:db8::1]:51413".parse().unwrap(),
+            ]
+        );
+        assert_eq!(MagnetLink::parse(&magnet.to_url()).unwrap(), magnet);
+    }
+
+    #[test]
+    fn test_v2_only_roundtrip() {
+        let original = MagnetLink {
+            info_hash_v2: Some(
+                "8f14e45fceea167a5a36dedd4bea2543ed9a8b5c6f30a1c1c7b9c9f26a3a57e0".to_string(),
+            ),
+            display_name: Some("v2".to_string()),
+            ..Default::default()
+        };
+
+        let url = original.to_url();
+        assert!(url.starts_with(
+            "magnet:?xt=urn:btmh:12208f14e45fceea167a5a36dedd4bea2543ed9a8b5c6f30a1c1c7b9c9f26a3a57e0&dn="
+        ));
+        assert!(!url.contains("btih"));
+        assert_eq!(MagnetLink::parse(&url).unwrap(), original);
+        assert!(original.info_hash_hex().is_err());
+    }
+
+    #[test]
+    fn test_hybrid_magnet() {
+        let url = "magnet:?xt=urn:btih:d2474e86c95b19b8bcfdb92bc12c9d44667cfa36\
+            &xt=urn:btmh:1220CAF1E1C30E81CB361B9EE167C4AA64228A7FA4FA9F6105232B28AD099F3A302E";
+        let magnet = MagnetLink::parse(url).unwrap();
+        assert_eq!(magnet.info_hash, "d2474e86c95b19b8bcfdb92bc12c9d44667cfa36");
+        assert_eq!(
+            magnet.info_hash_v2.as_deref(),
+            Some("caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e")
+        );
+        assert_eq!(MagnetLink::parse(&magnet.to_url()).unwrap(), magnet);
+
+        // Only SHA-256 multihashes of the right length are accepted
+        assert!(MagnetLink::parse("magnet:?xt=urn:btmh:1114abcd").is_err());
+        assert!(MagnetLink::parse("magnet:?xt=urn:btmh:1220abcd").is_err());
+    }
+
+    #[test]
+    fn test_priced_magnet_roundtrip() {
+        let original = MagnetLink {
+            info_hash: "d2474e86c95b19b8bcfdb92bc12c9d44667cfa36".to_string(),
+            price: Some(5000),
+            price_unit: Some(PriceUnit::Lamports),
+            ..Default::default()
+        };
+
+        let url = original.to_url();
+        assert!(url.ends_with("&x.402=5000:lamports"));
+        assert_eq!(MagnetLink::parse(&url).unwrap(), original);
+    }
+
+    #[test]
+    fn test_parse_price_without_unit() {
+        let url = "magnet:?xt=urn:btih:d2474e86c95b19b8bcfdb92bc12c9d44667cfa36&x402=250";
+        let magnet = MagnetLink::parse(url).unwrap();
+
+        assert_eq!(magnet.price, Some(250));
+        assert_eq!(magnet.price_unit, None);
+        assert!(magnet.to_url().ends_with("&x.402=250"));
+    }
+
+    #[test]
+    fn test_parse_unknown_price_unit() {
+        let url = "magnet:?xt=urn:btih:d2474e86c95b19b8bcfdb92bc12c9d44667cfa36&x.402=10:doge";
+        let result = MagnetLink::parse(url);
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Unknown price unit 'doge': expected 'lamports' or 'usdc'"
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_price() {
+        let url = "magnet:?xt=urn:btih:d2474e86c95b19b8bcfdb92bc12c9d44667cfa36&x.402=cheap";
+        assert!(MagnetLink::parse(url).is_err());
+    }
+
+    #[test]
+    fn test_parse_lenient_bare_query() {
+        let query = "xt=urn:btih:d2474e86c95b19b8bcfdb92bc12c9d44667cfa36&dn=test";
+        let magnet = MagnetLink::parse_lenient(query).unwrap();
+
+        assert_eq!(magnet.info_hash, "d2474e86c95b19b8bcfdb92bc12c9d44667cfa36");
+        assert_eq!(magnet.display_name, Some("test".to_string()));
+        assert_eq!(
+            magnet,
+            MagnetLink::parse_lenient(&format!("?{}", query)).unwrap()
+        );
+        // The strict parser still requires the scheme
+        assert!(MagnetLink::parse(query).is_err());
+    }
+
+    #[test]
+    fn test_parse_lenient_rejects_other_urls() {
+        let result = MagnetLink::parse_lenient(
+            "http://example.com/?xt=urn:btih:d2474e86c95b19b8bcfdb92bc12c9d44667cfa36",
+        );
+        assert!(result.is_err());
+
+        assert!(MagnetLink::parse_lenient("not a magnet").is_err());
+        assert!(MagnetLink::parse_lenient("").is_err());
+    }
+
+    #[test]
+    fn test_output_name_sanitizes_display_name() {
+        let url =
+            "magnet:?xt=urn:btih:d2474e86c95b19b8bcfdb92bc12c9d44667cfa36&dn=../../etc/passwd";
+        let magnet = MagnetLink::parse(url).unwrap();
+
+        assert_eq!(magnet.display_name, Some("../../etc/passwd".to_string()));
+        assert_eq!(magnet.output_name(), "etc_passwd");
+    }
+
+    #[test]
+    fn test_output_name_falls_back_to_info_hash() {
+        let url = "magnet:?xt=urn:btih:d2474e86c95b19b8bcfdb92bc12c9d44667cfa36&dn=..";
+        let magnet = MagnetLink::parse(url).unwrap();
+
+        assert_eq!(
+            magnet.output_name(),
+            "d2474e86c95b19b8bcfdb92bc12c9d44667cfa36"
+        );
+    }
+
+    #[test]
+    fn test_info_hash_hex_and_base32() {
+        let hex = MagnetLink::parse("magnet:?xt=urn:btih:d2474e86c95b19b8bcfdb92bc12c9d44667cfa36")
+            .unwrap();
+        let base32 =
+            MagnetLink::parse("magnet:?xt=urn:btih:2JDU5BWJLMM3RPH5XEV4CLE5IRTHZ6RW").unwrap();
+
+        for magnet in [&hex, &base32] {
+            assert_eq!(
+                magnet.info_hash_hex().unwrap(),
+                "d2474e86c95b19b8bcfdb92bc12c9d44667cfa36"
+            );
+            assert_eq!(
+                magnet.info_hash_base32().unwrap(),
+                "2JDU5BWJLMM3RPH5XEV4CLE5IRTHZ6RW"
+            );
+        }
+    }
 }
@@ -0,0 +1,199 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use memmap2::Mmap;
+
+use crate::torrent::store::{PieceStore, block_range};
+use crate::torrent::types::Info;
+
+/// A file of the torrent mapped into memory
+struct MappedFile {
+    /// The mapping, or `None` for an empty file, which can't be mapped
+    map: Option<Mmap>,
+}
+
+/// A read-only piece store backed by memory-mapped files
+///
+/// Blocks are copied straight out of the page cache instead of going through
+/// a seek and read per request, which suits seeding large files.
+pub struct MmapStore {
+    files: Vec<MappedFile>,
+    /// Info dictionary describing how the content is split into files
+    info: Info,
+    total_length: usize,
+}
+
+impl MmapStore {
+    /// Map a torrent's content from disk
+    ///
+    /// For a single-file torrent `path` is the file itself; for a multi-file
+    /// torrent it is the directory holding the files listed in the info dictionary,
+    /// and any file path that would escape it is rejected.
+    pub fn open(info: &Info, path: &Path) -> Result<Self, String> {
+        let expected: Vec<(PathBuf, usize)> = match &info.files {
+            Some(files) => files
+                .iter()
+                .map(|file| Ok((file.safe_path(path)?, file.length)))
+                .collect::<Result<_, String>>()?,
+            None => vec![(path.to_path_buf(), info.total_length())],
+        };
+
+        let mut files = Vec::with_capacity(expected.len());
+        let mut offset = 0;
+        for (file_path, length) in expected {
+            let file = File::open(&file_path)
+                .map_err(|e| format!("Failed to open {}: {}", file_path.display(), e))?;
+            let actual = file
+                .metadata()
+                .map_err(|e| format!("Failed to read {}: {}", file_path.display(), e))?
+                .len() as usize;
+            if actual != length {
+                return Err(format!(
+                    "Length mismatch for {}: expected {} bytes, found {}",
+                    file_path.display(),
+                    length,
+                    actual
+                ));
+            }
+
+            let map = if length == 0 {
+                None
+            } else {
+                // SAFETY: the mapping is read-only; as with any mmap, the file
+                // must not be truncated by another process while it is mapped.
+                let map = unsafe { Mmap::map(&file) }
+                    .map_err(|e| format!("Failed to map {}: {}", file_path.display(), e))?;
+                Some(map)
+            };
+
+            files.push(MappedFile { map });
+            offset += length;
+        }
+
+        if offset != info.total_length() {
+            return Err(format!(
+                "Mapped length {} does not match torrent length {}",
+                offset,
+                info.total_length()
+            ));
+        }
+
+        Ok(MmapStore {
+            files,
+            info: info.clone(),
+            total_length: offset,
+        })
+    }
+}
+
+impl PieceStore for MmapStore {
+    fn read_block(&self, index: u32, begin: u32, length: u32) -> Result<Vec<u8>, String> {
+        let (start, end) = block_range(self.info.plength, self.total_length, index, begin, length)?;
+
+        // A block may span several files
+        let mut block = Vec::with_capacity(end - start);
+        for (file, offset, len) in self.info.files_for_range(start, end - start) {
+            if let Some(map) = &self.files[file].map {
+                block.extend_from_slice(&map[offset..offset + len]);
+            }
+        }
+
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::types::FileEntry;
+    use std::fs;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("x402-mmap-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn multi_file_info(lengths: &[usize]) -> Info {
+        let files = lengths
+            .iter()
+            .enumerate()
+            .map(|(i, &length)| FileEntry {
+                length,
+                path: vec![format!("file{}.bin", i)],
+            })
+            .collect();
+        Info {
+            name: "test".to_string(),
+            plength: 64,
+            files: Some(files),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_mmap_store_cross_file_block() {
+        let dir = temp_dir("cross");
+        let data: Vec<u8> = (0..160).map(|i| i as u8).collect();
+        fs::write(dir.join("file0.bin"), &data[..100]).unwrap();
+        fs::write(dir.join("file1.bin"), &data[100..]).unwrap();
+        let info = multi_file_info(&[100, 60]);
+
+        let store = MmapStore::open(&info, &dir);
+        let blocks = store.map(|store| {
+            (
+                store.read_block(1, 0, 64),
+                store.read_block(2, 0, 32),
+                store.read_block(2, 0, 33),
+            )
+        });
+        fs::remove_dir_all(&dir).unwrap();
+
+        let (spanning, last, too_long) = blocks.unwrap();
+        // Piece 1 covers bytes 64..128, crossing into the second file at 100
+        assert_eq!(spanning.unwrap(), data[64..128].to_vec());
+        assert_eq!(last.unwrap(), data[128..].to_vec());
+        assert!(too_long.is_err());
+    }
+
+    #[test]
+    fn test_mmap_store_single_file() {
+        let dir = temp_dir("single");
+        let path = dir.join("data.bin");
+        let data: Vec<u8> = (0..100).collect();
+        fs::write(&path, &data).unwrap();
+        let info = Info {
+            name: "data.bin".to_string(),
+            plength: 40,
+            length: Some(100),
+            ..Default::default()
+        };
+
+        let block = MmapStore::open(&info, &path).and_then(|store| store.read_block(2, 10, 5));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(block.unwrap(), data[90..95].to_vec());
+    }
+
+    #[test]
+    fn test_mmap_store_length_mismatch() {
+        let dir = temp_dir("mismatch");
+        fs::write(dir.join("file0.bin"), [0u8; 10]).unwrap();
+        fs::write(dir.join("file1.bin"), []).unwrap();
+        let info = multi_file_info(&[10, 5]);
+
+        let result = MmapStore::open(&info, &dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.err().unwrap().contains("Length mismatch"));
+    }
+
+    #[test]
+    fn test_mmap_store_rejects_traversal() {
+        let mut info = multi_file_info(&[10]);
+        info.files.as_mut().unwrap()[0].path = vec!["..".to_string(), "secret".to_string()];
+
+        let result = MmapStore::open(&info, Path::new("downloads"));
+        assert!(result.err().unwrap().contains("Unsafe path"));
+    }
+}
@@ -1,4 +1,17 @@
+pub(crate) mod bencode;
+pub mod check;
+pub mod create;
+pub mod filetree;
+pub mod index;
 pub mod infohash;
 pub mod magnet;
+pub mod mmap;
+pub mod naming;
 pub mod parser;
+pub mod raw;
+pub mod source;
+pub mod store;
+pub mod summary;
 pub mod types;
+pub mod verify;
+pub mod writer;
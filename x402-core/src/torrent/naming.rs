@@ -0,0 +1,59 @@
+/// Make a torrent-supplied name safe to use as a single file name
+///
+/// Path separators split the name into components; empty, `.` and `..`
+/// components are dropped and the rest are joined with `_`. Control
+/// characters are removed. Returns `None` if nothing usable is left.
+pub fn sanitize_filename(name: &str) -> Option<String> {
+    let components: Vec<String> = name
+        .split(['/', '\\'])
+        .map(|component| {
+            component
+                .chars()
+                .filter(|c| !c.is_control())
+                .collect::<String>()
+        })
+        .map(|component| component.trim().to_string())
+        .filter(|component| !component.is_empty() && component != "." && component != "..")
+        .collect();
+
+    if components.is_empty() {
+        None
+    } else {
+        Some(components.join("_"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_plain_name() {
+        assert_eq!(
+            sanitize_filename("Ubuntu 20.04.iso"),
+            Some("Ubuntu 20.04.iso".to_string())
+        );
+    }
+
+    #[test]
+    fn test_sanitize_path_traversal() {
+        assert_eq!(
+            sanitize_filename("../../etc/passwd"),
+            Some("etc_passwd".to_string())
+        );
+        assert_eq!(
+            sanitize_filename("..\\..\\boot.ini"),
+            Some("boot.ini".to_string())
+        );
+        assert_eq!(sanitize_filename("/"), None);
+        assert_eq!(sanitize_filename(".."), None);
+    }
+
+    #[test]
+    fn test_sanitize_control_characters() {
+        assert_eq!(
+            sanitize_filename("evil\u{0}name\n.txt"),
+            Some("evilname.txt".to_string())
+        );
+    }
+}
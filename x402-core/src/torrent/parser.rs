@@ -1,43 +1,213 @@
+use std::fmt;
+
 use hex::encode;
 use serde_bencode;
+use serde_bencode::value::Value;
+use serde_bytes::ByteBuf;
+
+use crate::torrent::bencode::dict_entries;
+use crate::torrent::infohash::derive_infohash_v2;
+use crate::torrent::summary::TorrentSummary;
+use crate::torrent::types::Torrent;
+
+/// Leading bytes of a binary string shown by `dump_bencode`
+const RAW_PREVIEW_BYTES: usize = 20;
+/// Most pieces `parse_torrent` accepts; real torrents stay far below this
+pub const DEFAULT_MAX_PIECES: usize = 10_000_000;
+
+/// Errors parsing a torrent file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The data isn't a bencoded torrent
+    Decode(String),
+    /// The `pieces` buffer lists more pieces than allowed, which would make
+    /// per-piece structures like bitfields exhaust memory
+    TooManyPieces { count: usize, max: usize },
+    /// The info dict has both a single-file `length` and a multi-file `files`
+    /// list, so it's unclear which describes the content
+    AmbiguousFileModel,
+}
 
-use crate::torrent::infohash::derive_infohash;
-use crate::torrent::types::{Info, Torrent};
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Decode(message) => write!(f, "Failed to decode torrent: {}", message),
+            ParseError::TooManyPieces { count, max } => {
+                write!(
+                    f,
+                    "Torrent has {} pieces, more than the limit of {}",
+                    count, max
+                )
+            }
+            ParseError::AmbiguousFileModel => write!(
+                f,
+                "Torrent info has both 'length' and 'files'; it must be single-file or multi-file"
+            ),
+        }
+    }
+}
+
+impl From<ParseError> for String {
+    fn from(error: ParseError) -> Self {
+        error.to_string()
+    }
+}
 
 /// Parse a torrent file and return the Torrent struct
-fn parse_torrent(data: &[u8]) -> Result<Torrent, String> {
-    serde_bencode::from_bytes(data).map_err(|e| format!("Failed to decode torrent: {}", e))
+///
+/// Torrents with more than `DEFAULT_MAX_PIECES` pieces, or with both
+/// `length` and `files`, are rejected.
+pub fn parse_torrent(data: &[u8]) -> Result<Torrent, String> {
+    Ok(parse_torrent_with_max_pieces(data, DEFAULT_MAX_PIECES)?)
 }
 
-/// Calculate the info hash for a torrent
-fn calculate_info_hash(torrent: &Torrent) -> Result<String, String> {
-    let info_bytes = serde_bencode::to_bytes(&torrent.info)
-        .map_err(|e| format!("Failed to encode info dict: {}", e))?;
-    let info_hash = derive_infohash(&info_bytes);
-    Ok(encode(info_hash))
+/// Parse a torrent file, rejecting it if it has more than `max_pieces` pieces
+pub fn parse_torrent_with_max_pieces(
+    data: &[u8],
+    max_pieces: usize,
+) -> Result<Torrent, ParseError> {
+    let mut torrent: Torrent =
+        serde_bencode::from_bytes(data).map_err(|e| ParseError::Decode(e.to_string()))?;
+    // Keep the info dictionary's own bytes, as re-encoding `Info` would drop
+    // unknown keys and change the info hash
+    torrent.raw_info = dict_entries(data)
+        .ok()
+        .and_then(|entries| entries.into_iter().find(|(key, _)| *key == b"info"))
+        .map(|(_, info)| ByteBuf::from(&data[info]));
+    if torrent.info.length.is_some() && torrent.info.files.is_some() {
+        return Err(ParseError::AmbiguousFileModel);
+    }
+    let count = torrent.info.num_pieces();
+    if count > max_pieces {
+        return Err(ParseError::TooManyPieces {
+            count,
+            max: max_pieces,
+        });
+    }
+    Ok(torrent)
+}
+
+/// Parse a torrent file, rejecting piece lengths `Info::validate_piece_length` refuses
+///
+/// `parse_torrent` accepts any piece length, so existing torrents with odd
+/// lengths can still be inspected.
+pub fn parse_torrent_strict(data: &[u8]) -> Result<Torrent, String> {
+    let torrent = parse_torrent(data)?;
+    torrent.info.validate_piece_length()?;
+    Ok(torrent)
+}
+
+/// Calculate the v2 (SHA-256) info hash for a torrent
+///
+/// Only meaningful for v2 and hybrid torrents.
+pub fn calculate_info_hash_v2(torrent: &Torrent) -> Result<String, String> {
+    Ok(encode(derive_infohash_v2(&torrent.info_bytes()?)))
+}
+
+/// Parse a torrent file and build its summary
+pub fn summarize_torrent(data: &[u8]) -> Result<TorrentSummary, String> {
+    let decoded = parse_torrent(data)?;
+    TorrentSummary::from_torrent(&decoded)
+}
+
+/// Render the structure of any bencoded data, for debugging
+///
+/// Unlike `parse_torrent`, this accepts any valid bencode, so malformed or
+/// unusual torrents can still be looked at. Dictionary keys are sorted, and
+/// binary strings are shown as their length and the hex of their first bytes.
+pub fn dump_bencode(data: &[u8]) -> Result<String, String> {
+    let value: Value =
+        serde_bencode::from_bytes(data).map_err(|e| format!("Failed to decode bencode: {}", e))?;
+    let mut dump = String::new();
+    dump_value(&mut dump, &value, 0);
+    Ok(dump)
+}
+
+fn dump_value(dump: &mut String, value: &Value, depth: usize) {
+    let indent = "  ".repeat(depth + 1);
+    match value {
+        Value::Int(n) => dump.push_str(&n.to_string()),
+        Value::Bytes(bytes) => dump.push_str(&dump_bytes(bytes)),
+        Value::List(items) if items.is_empty() => dump.push_str("[]"),
+        Value::List(items) => {
+            dump.push_str("[\n");
+            for item in items {
+                dump.push_str(&indent);
+                dump_value(dump, item, depth + 1);
+                dump.push('\n');
+            }
+            dump.push_str(&indent[2..]);
+            dump.push(']');
+        }
+        Value::Dict(entries) if entries.is_empty() => dump.push_str("{}"),
+        Value::Dict(entries) => {
+            let mut entries: Vec<_> = entries.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            dump.push_str("{\n");
+            for (key, value) in entries {
+                dump.push_str(&indent);
+                dump.push_str(&String::from_utf8_lossy(key));
+                dump.push_str(": ");
+                dump_value(dump, value, depth + 1);
+                dump.push('\n');
+            }
+            dump.push_str(&indent[2..]);
+            dump.push('}');
+        }
+    }
+}
+
+/// Quote printable text, show anything else as its length and leading bytes in hex
+fn dump_bytes(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) if !text.chars().any(char::is_control) => format!("{:?}", text),
+        _ => {
+            let preview = encode(&bytes[..bytes.len().min(RAW_PREVIEW_BYTES)]);
+            let more = if bytes.len() > RAW_PREVIEW_BYTES {
+                "..."
+            } else {
+                ""
+            };
+            format!("<{} bytes> {}{}", bytes.len(), preview, more)
+        }
+    }
 }
 
 /// Decode and print torrent information
-pub fn decode_torrent(data: &[u8]) -> Result<(), String> {
+pub fn decode_torrent(data: &[u8]) -> Result<TorrentSummary, String> {
     let decoded = parse_torrent(data)?;
-    let info_hash = calculate_info_hash(&decoded)?;
+    let summary = TorrentSummary::from_torrent(&decoded)?;
 
-    println!("Tracker URL: {}", decoded.announce);
+    println!("Tracker URL: {}", summary.trackers[0]);
+    if summary.trackers.len() > 1 {
+        println!("Additional Trackers:");
+        for tracker in &summary.trackers[1..] {
+            println!("  - {}", tracker);
+        }
+    }
     println!("Info:");
-    println!("  Name: {}", decoded.info.name);
-    println!("  Piece Length: {}", decoded.info.plength);
-    println!("  Number of Pieces: {}", decoded.info.pieces.len() / 20);
-    if let Some(length) = decoded.info.length {
-        println!("  File Length: {} bytes", length);
+    println!("  Name: {}", summary.name);
+    println!("  Piece Length: {}", summary.piece_length);
+    println!("  Number of Pieces: {}", summary.num_pieces);
+    println!("  Total Length: {} bytes", summary.total_length);
+    if summary.is_private {
+        println!("  Private: yes");
+    }
+    if let Err(problems) = decoded.validate() {
+        for problem in problems {
+            println!("  Warning: {}", problem);
+        }
     }
-    println!("Info Hash: {}", info_hash);
+    println!("Format: {}", decoded.format_version());
+    println!("Info Hash: {}", summary.info_hash_hex);
 
-    Ok(())
+    Ok(summary)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::torrent::types::Info;
 
     // Helper function to create a minimal valid torrent file in bencode format
     fn create_test_torrent() -> Vec<u8> {
@@ -50,7 +220,9 @@ mod tests {
                     1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20,
                 ]),
                 length: Some(1024),
+                ..Default::default()
             },
+            ..Default::default()
         };
         serde_bencode::to_bytes(&torrent).unwrap()
     }
@@ -77,11 +249,92 @@ mod tests {
         assert!(result.unwrap_err().contains("Failed to decode torrent"));
     }
 
+    #[test]
+    fn test_parse_rejects_too_many_pieces() {
+        let mut torrent = parse_torrent(&create_test_torrent()).unwrap();
+        torrent.info.pieces = serde_bytes::ByteBuf::from(vec![0u8; 101 * 20]);
+        let data = serde_bencode::to_bytes(&torrent).unwrap();
+
+        assert_eq!(
+            parse_torrent_with_max_pieces(&data, 100).unwrap_err(),
+            ParseError::TooManyPieces {
+                count: 101,
+                max: 100
+            }
+        );
+        assert!(parse_torrent_with_max_pieces(&data, 101).is_ok());
+        assert!(parse_torrent(&data).is_ok());
+    }
+
+    #[test]
+    fn test_parse_rejects_length_and_files() {
+        let data = b"d8:announce0:4:infod5:filesld6:lengthi10e4:pathl5:a.txteee\
+            6:lengthi10e4:name4:test12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
+
+        assert_eq!(
+            parse_torrent_with_max_pieces(data, DEFAULT_MAX_PIECES).unwrap_err(),
+            ParseError::AmbiguousFileModel
+        );
+        assert!(
+            parse_torrent(data)
+                .unwrap_err()
+                .contains("both 'length' and 'files'")
+        );
+    }
+
+    #[test]
+    fn test_dump_bencode_shows_unexpected_keys() {
+        let mut data = b"d8:announce18:http://tracker.com4:infod6:lengthi40000e".to_vec();
+        data.extend_from_slice(b"4:name8:test.txt12:piece lengthi16384e6:pieces60:");
+        data.extend((0..60).map(|i| i as u8));
+        data.extend_from_slice(b"e9:x-mystery3:\x00\xff\x016:x-tagsl1:a1:bdeee");
+
+        let dump = dump_bencode(&data).unwrap();
+        assert_eq!(
+            dump,
+            r#"{
+  announce: "http://tracker.com"
+  info: {
+    length: 40000
+    name: "test.txt"
+    piece length: 16384
+    pieces: <60 bytes> 000102030405060708090a0b0c0d0e0f10111213...
+  }
+  x-mystery: <3 bytes> 00ff01
+  x-tags: [
+    "a"
+    "b"
+    {}
+  ]
+}"#
+        );
+
+        assert!(dump_bencode(b"d4:info").is_err());
+    }
+
+    #[test]
+    fn test_parse_torrent_strict_piece_length() {
+        let data = create_test_torrent();
+        assert!(parse_torrent_strict(&data).is_ok());
+
+        let mut torrent = parse_torrent(&data).unwrap();
+        torrent.info.plength = 12345;
+        let data = serde_bencode::to_bytes(&torrent).unwrap();
+
+        // The lenient parser still accepts it
+        assert!(parse_torrent(&data).is_ok());
+        assert!(
+            parse_torrent_strict(&data)
+                .unwrap_err()
+                .contains("not a power of two")
+        );
+    }
+
     #[test]
     fn test_calculate_info_hash() {
         let data = create_test_torrent();
         let torrent = parse_torrent(&data).unwrap();
-        let info_hash = calculate_info_hash(&torrent);
+        let info_hash = torrent.info_hash_hex();
 
         assert!(info_hash.is_ok());
         let hash = info_hash.unwrap();
@@ -96,8 +349,8 @@ mod tests {
         let data = create_test_torrent();
         let torrent = parse_torrent(&data).unwrap();
 
-        let hash1 = calculate_info_hash(&torrent).unwrap();
-        let hash2 = calculate_info_hash(&torrent).unwrap();
+        let hash1 = torrent.info_hash_hex().unwrap();
+        let hash2 = torrent.info_hash_hex().unwrap();
 
         assert_eq!(hash1, hash2);
     }
@@ -112,7 +365,9 @@ mod tests {
                 // 3 pieces (60 bytes total)
                 pieces: serde_bytes::ByteBuf::from(vec![0u8; 60]),
                 length: Some(100000),
+                ..Default::default()
             },
+            ..Default::default()
         };
 
         let data = serde_bencode::to_bytes(&torrent).unwrap();
@@ -123,6 +378,41 @@ mod tests {
         assert_eq!(parsed.info.pieces.len() / 20, 3); // 3 pieces
     }
 
+    #[test]
+    fn test_parse_v2_torrent() {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"d8:announce22:http://tracker.example4:infod9:file treed");
+        data.extend_from_slice(b"5:b.txtd0:d6:lengthi10e11:pieces root32:");
+        data.extend_from_slice(&[0xCD; 32]);
+        data.extend_from_slice(b"ee3:dird5:a.txtd0:d6:lengthi40000e11:pieces root32:");
+        data.extend_from_slice(&[0xAB; 32]);
+        data.extend_from_slice(b"eeee12:meta versioni2e4:name4:test12:piece lengthi16384ee");
+        data.extend_from_slice(b"12:piece layersd32:");
+        data.extend_from_slice(&[0xAB; 32]);
+        data.extend_from_slice(b"96:");
+        data.extend_from_slice(&[0x11; 96]);
+        data.extend_from_slice(b"ee");
+
+        let torrent = parse_torrent(&data).unwrap();
+
+        assert_eq!(torrent.info.meta_version, Some(2));
+        assert!(torrent.info.pieces.is_empty());
+        let tree = torrent.info.file_tree.as_ref().unwrap();
+        let files = tree.files();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].0, vec!["b.txt".to_string()]);
+        assert_eq!(files[1].0, vec!["dir".to_string(), "a.txt".to_string()]);
+        assert_eq!(files[1].1.length, 40000);
+        assert_eq!(torrent.info.total_length(), 40010);
+
+        let layers = torrent.piece_layers.as_ref().unwrap();
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers.values().next().unwrap().len(), 96);
+
+        let hash_v2 = calculate_info_hash_v2(&torrent).unwrap();
+        assert_eq!(hash_v2.len(), 64);
+    }
+
     #[test]
     fn test_decode_torrent_output() {
         let data = create_test_torrent();
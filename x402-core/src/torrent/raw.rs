@@ -0,0 +1,135 @@
+use std::ops::Range;
+
+use crate::torrent::bencode::dict_entries;
+use crate::torrent::infohash::derive_infohash;
+use crate::torrent::parser::parse_torrent;
+use crate::torrent::types::Torrent;
+
+/// A torrent kept as the bytes it was parsed from, unknown keys included
+///
+/// `Torrent` only keeps the fields it models, so re-encoding it drops keys
+/// like `created by` or private extensions inside `info`, which changes the
+/// info hash. A `RawTorrent` re-emits exactly the bytes it was parsed from,
+/// whatever order their keys are in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawTorrent {
+    /// The torrent file as parsed
+    data: Vec<u8>,
+    /// Top-level keys in file order, with the range of their value in `data`
+    entries: Vec<(Vec<u8>, Range<usize>)>,
+}
+
+impl RawTorrent {
+    /// Parse a torrent file, keeping every key
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let entries: Vec<(Vec<u8>, Range<usize>)> = dict_entries(data)
+            .map_err(|e| format!("Failed to decode torrent: {}", e))?
+            .into_iter()
+            .map(|(key, value)| (key.to_vec(), value))
+            .collect();
+        let raw = RawTorrent {
+            data: data.to_vec(),
+            entries,
+        };
+        if !raw.get("info").is_some_and(|info| info.starts_with(b"d")) {
+            return Err("Torrent has no info dictionary".to_string());
+        }
+        Ok(raw)
+    }
+
+    /// Encode the torrent, byte-identical to the data it was parsed from
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    /// The bencoded info dictionary, exactly as the info hash covers it
+    pub fn info_bytes(&self) -> &[u8] {
+        self.get("info")
+            .expect("parse checked the info dictionary is there")
+    }
+
+    /// SHA-1 info hash of the original info dictionary, unknown keys included
+    pub fn info_hash(&self) -> [u8; 20] {
+        derive_infohash(self.info_bytes())
+    }
+
+    /// The bencoded value of a top-level key
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|(entry, _)| entry.as_slice() == key.as_bytes())
+            .map(|(_, value)| &self.data[value.clone()])
+    }
+
+    /// The top-level keys, in the order the file has them
+    pub fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.entries.iter().map(|(key, _)| key.as_slice())
+    }
+
+    /// The fields `Torrent` models, for everything but re-emitting
+    pub fn to_torrent(&self) -> Result<Torrent, String> {
+        parse_torrent(&self.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A torrent like real clients write, with keys `Torrent` doesn't model
+    fn real_world_torrent() -> Vec<u8> {
+        let mut data = b"d8:announce35:http://tracker.example.com/announce\
+            13:announce-listll35:http://tracker.example.com/announceel23:udp://backup.example:80ee\
+            7:comment12:Test release10:created by13:mktorrent 1.1\
+            13:creation datei1700000000e\
+            4:infod6:lengthi40000e4:name8:test.iso12:piece lengthi16384e6:pieces60:"
+            .to_vec();
+        data.extend_from_slice(&[0xab; 60]);
+        data.extend_from_slice(b"7:privatei1e6:source3:ABC9:x-creator5:tool1e8:url-listl0:ee");
+        data
+    }
+
+    #[test]
+    fn test_round_trip_is_byte_identical() {
+        let data = real_world_torrent();
+        let raw = RawTorrent::parse(&data).unwrap();
+        assert_eq!(raw.to_bytes(), data);
+
+        let start = data.windows(7).position(|w| w == b"4:infod").unwrap() + 6;
+        let end = data.len() - b"8:url-listl0:ee".len();
+        assert_eq!(raw.info_bytes(), &data[start..end]);
+        assert_eq!(raw.info_hash(), derive_infohash(&data[start..end]));
+        assert_eq!(raw.get("comment"), Some(&b"12:Test release"[..]));
+
+        // The typed model drops `x-creator`, but hashes the info dict as parsed
+        let torrent = raw.to_torrent().unwrap();
+        assert_eq!(torrent.info.name, "test.iso");
+        assert_eq!(torrent.info_hash().unwrap(), raw.info_hash());
+    }
+
+    #[test]
+    fn test_unsorted_keys_are_kept_in_order() {
+        let data = b"d4:infod4:name5:a.txt12:piece lengthi16e6:lengthi10e6:pieces0:e\
+            8:announce22:http://t.example/anncee";
+        let raw = RawTorrent::parse(data).unwrap();
+        assert_eq!(raw.to_bytes(), data);
+        let keys: Vec<&[u8]> = raw.keys().collect();
+        assert_eq!(keys, vec![&b"info"[..], b"announce"]);
+
+        let info = b"d4:name5:a.txt12:piece lengthi16e6:lengthi10e6:pieces0:e";
+        assert_eq!(raw.info_bytes(), info);
+        assert_eq!(raw.info_hash(), derive_infohash(info));
+        assert_eq!(
+            raw.to_torrent().unwrap().info_hash().unwrap(),
+            raw.info_hash()
+        );
+    }
+
+    #[test]
+    fn test_parse_requires_an_info_dictionary() {
+        assert!(RawTorrent::parse(b"le").is_err());
+        assert!(RawTorrent::parse(b"d8:announce1:xe").is_err());
+        assert!(RawTorrent::parse(b"d4:infoi1ee").is_err());
+        assert!(RawTorrent::parse(b"d4:infodee trailing").is_err());
+    }
+}
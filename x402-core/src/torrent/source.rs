@@ -0,0 +1,209 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use crate::torrent::magnet::MagnetLink;
+use crate::torrent::parser::parse_torrent;
+use crate::torrent::types::Torrent;
+
+/// How every magnet URL starts
+const MAGNET_PREFIX: &str = "magnet:?";
+
+/// What kind of source an input names, as told by `SourceKind::detect`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    Magnet,
+    TorrentPath,
+    /// Neither a magnet URL nor something that looks like a torrent file
+    Unknown,
+}
+
+impl SourceKind {
+    /// Tell a magnet URL from a torrent file path
+    ///
+    /// A file that can be read is a magnet if its contents start like a magnet
+    /// URL, as in a saved `.magnet` file, and a torrent if they start like a
+    /// bencoded dictionary, whatever its name; one that can't, e.g. because it
+    /// doesn't exist, is taken by its `.torrent` extension.
+    pub fn detect(input: &str) -> Self {
+        if input.starts_with(MAGNET_PREFIX) {
+            return SourceKind::Magnet;
+        }
+        let mut start = Vec::new();
+        let read = File::open(input).and_then(|file| {
+            file.take(MAGNET_PREFIX.len() as u64)
+                .read_to_end(&mut start)
+        });
+        match read {
+            Ok(_) if start.starts_with(MAGNET_PREFIX.as_bytes()) => SourceKind::Magnet,
+            Ok(_) if start.first() == Some(&b'd') => SourceKind::TorrentPath,
+            Ok(read) if read > 0 => SourceKind::Unknown,
+            _ => {
+                let extension = Path::new(input).extension();
+                if extension.is_some_and(|extension| extension.eq_ignore_ascii_case("torrent")) {
+                    SourceKind::TorrentPath
+                } else {
+                    SourceKind::Unknown
+                }
+            }
+        }
+    }
+}
+
+/// Where a download's metadata comes from: a magnet link or a torrent file
+#[derive(Debug, Clone)]
+pub enum TorrentSource {
+    Magnet(MagnetLink),
+    File(Torrent),
+}
+
+impl TorrentSource {
+    /// Parse a magnet URL, or read and parse the torrent file at `source`
+    pub fn load(source: &str) -> Result<Self, String> {
+        if SourceKind::detect(source) == SourceKind::Magnet {
+            return Ok(TorrentSource::Magnet(load_magnet(source)?));
+        }
+        let data = fs::read(source).map_err(|e| format!("Failed to read {}: {}", source, e))?;
+        Ok(TorrentSource::File(parse_torrent(&data)?))
+    }
+
+    /// Total size of the content in bytes, if known
+    ///
+    /// A magnet only knows its size from the optional `xl` parameter; a torrent
+    /// file sums the lengths of its files.
+    pub fn total_size(&self) -> Option<u64> {
+        match self {
+            TorrentSource::Magnet(magnet) => magnet.exact_length,
+            TorrentSource::File(torrent) => {
+                let info = &torrent.info;
+                if info.length.is_none() && info.files.is_none() && info.file_tree.is_none() {
+                    return None;
+                }
+                Some(info.total_length() as u64)
+            }
+        }
+    }
+}
+
+/// Parse a magnet URL, or the magnet link saved in the file at `input`
+pub fn load_magnet(input: &str) -> Result<MagnetLink, String> {
+    if input.starts_with(MAGNET_PREFIX) {
+        return Ok(MagnetLink::parse(input)?);
+    }
+    let contents =
+        fs::read_to_string(input).map_err(|e| format!("Failed to read {}: {}", input, e))?;
+    Ok(MagnetLink::parse(contents.trim())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::types::{FileEntry, Info};
+
+    const MAGNET: &str = "magnet:?xt=urn:btih:d2474e86c95b19b8bcfdb92bc12c9d44667cfa36";
+
+    #[test]
+    fn test_detect_magnet() {
+        assert_eq!(SourceKind::detect(MAGNET), SourceKind::Magnet);
+    }
+
+    #[test]
+    fn test_detect_torrent_path() {
+        // A missing file goes by its extension, an existing one by its contents
+        assert_eq!(
+            SourceKind::detect("/nonexistent/ubuntu.torrent"),
+            SourceKind::TorrentPath
+        );
+        let sample = concat!(env!("CARGO_MANIFEST_DIR"), "/../sample.torrent");
+        assert_eq!(SourceKind::detect(sample), SourceKind::TorrentPath);
+
+        let path = std::env::temp_dir().join(format!("x402-source-{}.dat", std::process::id()));
+        fs::write(&path, b"d4:infodee").unwrap();
+        assert_eq!(
+            SourceKind::detect(path.to_str().unwrap()),
+            SourceKind::TorrentPath
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_detect_ambiguous_input() {
+        assert_eq!(
+            SourceKind::detect("/nonexistent/notes"),
+            SourceKind::Unknown
+        );
+        assert_eq!(
+            SourceKind::detect("magnet:xt=urn:btih:abc"),
+            SourceKind::Unknown
+        );
+
+        // A .torrent name doesn't make a non-bencoded file a torrent
+        let path = std::env::temp_dir().join(format!("x402-source-{}.torrent", std::process::id()));
+        fs::write(&path, b"<html>Not found</html>").unwrap();
+        assert_eq!(
+            SourceKind::detect(path.to_str().unwrap()),
+            SourceKind::Unknown
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_detect_magnet_file() {
+        let path = std::env::temp_dir().join(format!("x402-source-{}.magnet", std::process::id()));
+        fs::write(&path, format!("{}&dn=ubuntu.iso\n", MAGNET)).unwrap();
+        let input = path.to_str().unwrap();
+        assert_eq!(SourceKind::detect(input), SourceKind::Magnet);
+
+        let magnet = load_magnet(input).unwrap();
+        assert_eq!(magnet.info_hash, "d2474e86c95b19b8bcfdb92bc12c9d44667cfa36");
+        assert_eq!(magnet.display_name.as_deref(), Some("ubuntu.iso"));
+        assert!(matches!(
+            TorrentSource::load(input),
+            Ok(TorrentSource::Magnet(_))
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_magnet_total_size() {
+        let source = TorrentSource::load(&format!("{}&xl=2147483648", MAGNET)).unwrap();
+        assert_eq!(source.total_size(), Some(2147483648));
+
+        let source = TorrentSource::load(MAGNET).unwrap();
+        assert_eq!(source.total_size(), None);
+    }
+
+    #[test]
+    fn test_file_total_size() {
+        let files = vec![
+            FileEntry {
+                length: 100,
+                path: vec!["a.txt".to_string()],
+            },
+            FileEntry {
+                length: 250,
+                path: vec!["b.txt".to_string()],
+            },
+        ];
+        let source = TorrentSource::File(Torrent {
+            info: Info {
+                files: Some(files),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert_eq!(source.total_size(), Some(350));
+
+        let source = TorrentSource::File(Torrent {
+            info: Info {
+                length: Some(42),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert_eq!(source.total_size(), Some(42));
+
+        let source = TorrentSource::File(Torrent::default());
+        assert_eq!(source.total_size(), None);
+    }
+}
@@ -0,0 +1,78 @@
+/// Source of piece data for a served torrent
+pub trait PieceStore: Send + Sync {
+    /// Read `length` bytes starting at offset `begin` within piece `index`
+    fn read_block(&self, index: u32, begin: u32, length: u32) -> Result<Vec<u8>, String>;
+}
+
+/// A piece store backed by the whole content held in memory
+pub struct MemoryStore {
+    /// The torrent content, all files concatenated
+    data: Vec<u8>,
+    /// Nominal piece length
+    piece_length: usize,
+}
+
+impl MemoryStore {
+    pub fn new(data: Vec<u8>, piece_length: usize) -> Self {
+        MemoryStore { data, piece_length }
+    }
+}
+
+impl PieceStore for MemoryStore {
+    fn read_block(&self, index: u32, begin: u32, length: u32) -> Result<Vec<u8>, String> {
+        let (start, end) = block_range(self.piece_length, self.data.len(), index, begin, length)?;
+        Ok(self.data[start..end].to_vec())
+    }
+}
+
+/// Translate a block within a piece to an absolute byte range, checking bounds
+pub(crate) fn block_range(
+    piece_length: usize,
+    total_length: usize,
+    index: u32,
+    begin: u32,
+    length: u32,
+) -> Result<(usize, usize), String> {
+    let piece_start = index as usize * piece_length;
+    if piece_start >= total_length {
+        return Err(format!("Piece index {} out of range", index));
+    }
+    let piece_end = (piece_start + piece_length).min(total_length);
+
+    let start = piece_start + begin as usize;
+    let end = start + length as usize;
+    if end > piece_end {
+        return Err(format!(
+            "Block out of range: piece {} offset {} length {}",
+            index, begin, length
+        ));
+    }
+
+    Ok((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_store_read_block() {
+        let data: Vec<u8> = (0..100).collect();
+        let store = MemoryStore::new(data, 40);
+
+        assert_eq!(store.read_block(0, 0, 4).unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(store.read_block(1, 10, 2).unwrap(), vec![50, 51]);
+        // The last piece is short
+        assert_eq!(store.read_block(2, 0, 20).unwrap().len(), 20);
+    }
+
+    #[test]
+    fn test_memory_store_out_of_range() {
+        let store = MemoryStore::new(vec![0u8; 100], 40);
+
+        assert!(store.read_block(3, 0, 1).is_err());
+        assert!(store.read_block(2, 0, 21).is_err());
+        // A block must not spill into the next piece
+        assert!(store.read_block(0, 30, 20).is_err());
+    }
+}
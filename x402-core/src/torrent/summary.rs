@@ -0,0 +1,101 @@
+use serde::Serialize;
+
+use crate::torrent::types::Torrent;
+
+/// Structured summary of a torrent, as shown by inspection
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TorrentSummary {
+    pub name: String,
+    pub info_hash_hex: String,
+    pub piece_length: usize,
+    pub num_pieces: usize,
+    pub total_length: usize,
+    pub trackers: Vec<String>,
+    pub is_private: bool,
+}
+
+impl TorrentSummary {
+    /// Build a summary from a parsed torrent
+    pub fn from_torrent(torrent: &Torrent) -> Result<Self, String> {
+        Ok(TorrentSummary {
+            name: torrent.info.name.clone(),
+            info_hash_hex: torrent.info_hash_hex()?,
+            piece_length: torrent.info.plength,
+            num_pieces: torrent.info.num_pieces(),
+            total_length: torrent.info.total_length(),
+            trackers: torrent.trackers(),
+            is_private: torrent.info.is_private(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::parser::parse_torrent;
+    use crate::torrent::types::{FileEntry, Info};
+
+    #[test]
+    fn test_summary_from_sample_torrent() {
+        let data = include_bytes!("../../../sample.torrent");
+        let torrent = parse_torrent(data).unwrap();
+        let summary = TorrentSummary::from_torrent(&torrent).unwrap();
+
+        assert_eq!(summary.name, "sample.txt");
+        assert_eq!(
+            summary.info_hash_hex,
+            "d69f91e6b2ae4c542468d1073a71d4ea13879a7f"
+        );
+        assert_eq!(summary.piece_length, 32768);
+        assert_eq!(summary.num_pieces, 3);
+        assert_eq!(summary.total_length, 92063);
+        assert_eq!(
+            summary.trackers,
+            vec!["http://bittorrent-test-tracker.codecrafters.io/announce".to_string()]
+        );
+        assert!(!summary.is_private);
+    }
+
+    #[test]
+    fn test_summary_multi_file_private() {
+        let torrent = Torrent {
+            announce: "http://tracker.example.com".to_string(),
+            announce_list: Some(vec![
+                vec!["http://tracker.example.com".to_string()],
+                vec!["udp://backup.example.com:80".to_string()],
+            ]),
+            info: Info {
+                name: "album".to_string(),
+                plength: 16384,
+                pieces: serde_bytes::ByteBuf::from(vec![0u8; 40]),
+                length: None,
+                files: Some(vec![
+                    FileEntry {
+                        length: 20000,
+                        path: vec!["a.flac".to_string()],
+                    },
+                    FileEntry {
+                        length: 5000,
+                        path: vec!["covers".to_string(), "front.jpg".to_string()],
+                    },
+                ]),
+                private: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let summary = TorrentSummary::from_torrent(&torrent).unwrap();
+
+        assert_eq!(summary.num_pieces, 2);
+        assert_eq!(summary.total_length, 25000);
+        assert_eq!(
+            summary.trackers,
+            vec![
+                "http://tracker.example.com".to_string(),
+                "udp://backup.example.com:80".to_string()
+            ]
+        );
+        assert!(summary.is_private);
+    }
+}
@@ -1,20 +1,1178 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
 use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub(crate) struct Torrent {
-    pub(crate) announce: String,
-    pub(crate) info: Info,
+use crate::torrent::filetree::FileTree;
+use crate::torrent::infohash::{derive_infohash, derive_infohash_v2};
+use crate::torrent::magnet::MagnetLink;
+use crate::torrent::naming::sanitize_filename;
+use crate::torrent::verify::{MERKLE_BLOCK_SIZE, merkle_root};
+
+/// v2 piece hashes of each file larger than a piece, keyed by its pieces root
+pub type PieceLayers = BTreeMap<[u8; 32], Vec<[u8; 32]>>;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Torrent {
+    pub announce: String,
+
+    #[serde(
+        default,
+        rename = "announce-list",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub announce_list: Option<Vec<Vec<String>>>,
+
+    pub info: Info,
+
+    /// v2 merkle layers keyed by each file's pieces root (BEP 52)
+    #[serde(
+        default,
+        rename = "piece layers",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub piece_layers: Option<BTreeMap<ByteBuf, ByteBuf>>,
+
+    /// The info dictionary exactly as it was parsed, unknown keys included;
+    /// `None` for torrents built in code
+    #[serde(skip)]
+    pub raw_info: Option<ByteBuf>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub(crate) struct Info {
-    pub(crate) name: String,
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Info {
+    pub name: String,
 
     #[serde(rename = "piece length")]
-    pub(crate) plength: usize,
+    pub plength: usize,
 
-    pub(crate) pieces: serde_bytes::ByteBuf,
+    /// Concatenated v1 SHA-1 piece hashes, absent from v2-only torrents
+    #[serde(
+        default,
+        deserialize_with = "deserialize_pieces",
+        skip_serializing_if = "is_empty"
+    )]
+    pub pieces: ByteBuf,
 
     #[serde(default)]
-    pub(crate) length: Option<usize>,
+    pub length: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<FileEntry>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private: Option<u8>,
+
+    /// Tag private trackers add to make the info hash unique to them
+    #[serde(default, rename = "source", skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+
+    #[serde(
+        default,
+        rename = "meta version",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub meta_version: Option<u64>,
+
+    #[serde(default, rename = "file tree", skip_serializing_if = "Option::is_none")]
+    pub file_tree: Option<FileTree>,
+}
+
+/// A file in a multi-file torrent
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FileEntry {
+    pub length: usize,
+    pub path: Vec<String>,
+}
+
+/// Smallest piece length accepted by `Info::validate_piece_length`
+pub const MIN_PIECE_LENGTH: usize = 16 * 1024;
+/// Largest piece length accepted by `Info::validate_piece_length`
+pub const MAX_PIECE_LENGTH: usize = 64 * 1024 * 1024;
+
+/// Names that Windows reserves for devices, with or without an extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+impl FileEntry {
+    /// Path of the file under the download directory `base`
+    ///
+    /// Every component must be a plain file name: `.`, `..`, empty
+    /// components, separators, absolute or drive prefixes and reserved device
+    /// names are rejected, so the result can never escape `base`.
+    pub fn safe_path(&self, base: &Path) -> Result<PathBuf, String> {
+        if self.path.is_empty() {
+            return Err("File has an empty path".to_string());
+        }
+
+        let mut path = base.to_path_buf();
+        for component in &self.path {
+            check_component(component)
+                .map_err(|e| format!("Unsafe path {:?}: {}", self.path, e))?;
+            path.push(component);
+        }
+        Ok(path)
+    }
+}
+
+fn check_component(component: &str) -> Result<(), String> {
+    match component {
+        "" => return Err("empty component".to_string()),
+        "." | ".." => return Err(format!("'{}' component", component)),
+        _ => {}
+    }
+    if component.contains(['/', '\\', ':']) {
+        return Err(format!("separator in component '{}'", component));
+    }
+    if component.chars().any(|c| c.is_control()) {
+        return Err(format!("control character in component {:?}", component));
+    }
+
+    let stem = component.split('.').next().unwrap_or(component).trim_end();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        return Err(format!("reserved name '{}'", component));
+    }
+    Ok(())
+}
+
+/// Fields outside the info dictionary that differ between two torrents
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TorrentDiff {
+    /// Bencoded names of the differing fields, e.g. `announce-list`
+    pub fields: Vec<&'static str>,
+}
+
+impl TorrentDiff {
+    /// Whether every field outside the info dictionary matches
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+/// Which BitTorrent metadata versions a torrent carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TorrentFormat {
+    /// Only v1 piece hashes (BEP 3)
+    V1,
+    /// Only a v2 file tree (BEP 52)
+    V2,
+    /// Both v1 piece hashes and a v2 file tree
+    Hybrid,
+}
+
+impl std::fmt::Display for TorrentFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TorrentFormat::V1 => write!(f, "v1"),
+            TorrentFormat::V2 => write!(f, "v2"),
+            TorrentFormat::Hybrid => write!(f, "hybrid (v1+v2)"),
+        }
+    }
+}
+
+impl Torrent {
+    /// Detect whether the torrent is v1-only, v2-only or hybrid
+    pub fn format_version(&self) -> TorrentFormat {
+        let has_v2 = self.info.meta_version == Some(2) || self.info.file_tree.is_some();
+        let has_v1 = !self.info.pieces.is_empty();
+
+        match (has_v1, has_v2) {
+            (true, true) => TorrentFormat::Hybrid,
+            (false, true) => TorrentFormat::V2,
+            _ => TorrentFormat::V1,
+        }
+    }
+
+    /// The bencoded info dictionary the info hashes are taken over
+    ///
+    /// A parsed torrent keeps the dictionary as it appeared in the file, with
+    /// the keys `Info` doesn't model, and those bytes are used as long as
+    /// `info` still matches them. Torrents built in code, or whose `info` was
+    /// changed, have `info` re-encoded with its keys sorted instead.
+    pub fn info_bytes(&self) -> Result<Vec<u8>, String> {
+        let encoded = serde_bencode::to_bytes(&self.info)
+            .map_err(|e| format!("Failed to encode info dict: {}", e))?;
+        if let Some(raw) = &self.raw_info {
+            let unchanged = serde_bencode::from_bytes::<Info>(raw)
+                .and_then(|info| serde_bencode::to_bytes(&info))
+                .is_ok_and(|parsed| parsed == encoded);
+            if unchanged {
+                return Ok(raw.to_vec());
+            }
+        }
+        Ok(encoded)
+    }
+
+    /// SHA-1 info hash of the bencoded info dictionary
+    ///
+    /// The hash follows any change to `info`, while the fields outside it,
+    /// like the trackers, don't affect it.
+    pub fn info_hash(&self) -> Result<[u8; 20], String> {
+        Ok(derive_infohash(&self.info_bytes()?))
+    }
+
+    /// The info hash as 40 lowercase hex characters
+    pub fn info_hash_hex(&self) -> Result<String, String> {
+        Ok(hex::encode(self.info_hash()?))
+    }
+
+    /// Whether both torrents describe the same content, i.e. have the same info hash
+    ///
+    /// Trackers and other fields outside the info dictionary are ignored.
+    pub fn same_content(&self, other: &Torrent) -> bool {
+        match (self.info_hash(), other.info_hash()) {
+            (Ok(hash), Ok(other_hash)) => hash == other_hash,
+            _ => false,
+        }
+    }
+
+    /// List the fields outside the info dictionary that differ from `other`
+    pub fn diff(&self, other: &Torrent) -> TorrentDiff {
+        let mut fields = Vec::new();
+        if self.announce != other.announce {
+            fields.push("announce");
+        }
+        if self.announce_list != other.announce_list {
+            fields.push("announce-list");
+        }
+        if self.piece_layers != other.piece_layers {
+            fields.push("piece layers");
+        }
+        TorrentDiff { fields }
+    }
+
+    /// Check the torrent's internal consistency, returning every problem found
+    ///
+    /// Covers the name, piece length, file list and paths, the size of the v1
+    /// piece hashes against the content length and the size of v2 piece layers.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let info = &self.info;
+        let mut problems = Vec::new();
+
+        if info.name.is_empty() {
+            problems.push("Name is missing".to_string());
+        }
+        if let Err(e) = info.validate_piece_length() {
+            problems.push(e);
+        }
+
+        match (info.length, &info.files) {
+            (Some(_), Some(_)) => problems.push("Both length and files are set".to_string()),
+            (None, None) if info.file_tree.is_none() => {
+                problems.push("Neither length, files nor file tree is set".to_string())
+            }
+            _ => {}
+        }
+        if let Some(files) = &info.files {
+            if files.is_empty() {
+                problems.push("File list is empty".to_string());
+            }
+            for (index, file) in files.iter().enumerate() {
+                if file.path.is_empty() || file.path.iter().any(String::is_empty) {
+                    problems.push(format!("File {} has an empty path {:?}", index, file.path));
+                }
+            }
+        }
+
+        if self.format_version() != TorrentFormat::V2 {
+            if !info.pieces.len().is_multiple_of(20) {
+                problems.push(format!(
+                    "Pieces length {} is not a multiple of 20",
+                    info.pieces.len()
+                ));
+            } else if info.plength > 0 {
+                let expected = info.total_length().div_ceil(info.plength);
+                if info.num_pieces() != expected {
+                    problems.push(format!(
+                        "Expected {} piece hashes for {} bytes in pieces of {}, found {}",
+                        expected,
+                        info.total_length(),
+                        info.plength,
+                        info.num_pieces()
+                    ));
+                }
+            }
+        }
+        for (root, layer) in self.piece_layers.iter().flatten() {
+            if !layer.len().is_multiple_of(32) {
+                problems.push(format!(
+                    "Piece layer of {} has length {}, not a multiple of 32",
+                    hex::encode(root),
+                    layer.len()
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Split the v2 piece layers into per-piece hashes, keyed by pieces root
+    ///
+    /// Each layer must hash up to the root it is stored under, so pieces
+    /// checked against it with `verify_piece_v2` are as trustworthy as the
+    /// info dictionary itself.
+    pub fn parse_piece_layers(&self) -> Result<PieceLayers, String> {
+        // Layers are padded up to a power of two with the hash of a piece of zeros
+        let width = self.info.plength / MERKLE_BLOCK_SIZE;
+        let padding = merkle_root(Vec::new(), width, [0u8; 32]);
+
+        let mut layers = PieceLayers::new();
+        for (root, layer) in self.piece_layers.iter().flatten() {
+            let root: [u8; 32] = root
+                .as_slice()
+                .try_into()
+                .map_err(|_| format!("Pieces root of {} bytes, expected 32", root.len()))?;
+            if !layer.len().is_multiple_of(32) {
+                return Err(format!(
+                    "Piece layer of {} has length {}, not a multiple of 32",
+                    hex::encode(root),
+                    layer.len()
+                ));
+            }
+            let hashes: Vec<[u8; 32]> = layer
+                .chunks(32)
+                .map(|hash| hash.try_into().unwrap())
+                .collect();
+            if merkle_root(hashes.clone(), 1, padding) != root {
+                return Err(format!(
+                    "Piece layer of {} does not match its root",
+                    hex::encode(root)
+                ));
+            }
+            layers.insert(root, hashes);
+        }
+        Ok(layers)
+    }
+
+    /// Serialize the torrent as JSON, with the piece hashes and piece layers in base64
+    pub fn to_json(&self) -> Result<String, String> {
+        // JSON object keys must be strings, so the layers are encoded by hand
+        let mut torrent = self.clone();
+        let piece_layers = torrent.piece_layers.take();
+        let mut json = serde_json::to_value(&torrent)
+            .map_err(|e| format!("Failed to encode torrent: {}", e))?;
+        if !self.info.pieces.is_empty() {
+            json["info"]["pieces"] = BASE64.encode(&self.info.pieces).into();
+        }
+        if let Some(layers) = piece_layers {
+            json["piece layers"] = layers
+                .iter()
+                .map(|(root, layer)| (BASE64.encode(root), BASE64.encode(layer).into()))
+                .collect::<serde_json::Map<_, _>>()
+                .into();
+        }
+        serde_json::to_string_pretty(&json).map_err(|e| format!("Failed to encode torrent: {}", e))
+    }
+
+    /// Parse a torrent from JSON, as written by `to_json`
+    ///
+    /// The piece hashes may be given in base64, in hex after a `hex:` prefix,
+    /// or as an array of bytes. Piece layer roots and hashes are base64, or
+    /// hex after a `hex:` prefix.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let mut json: serde_json::Value = serde_json::from_str(json)
+            .map_err(|e| format!("Failed to parse torrent JSON: {}", e))?;
+        let piece_layers = json
+            .as_object_mut()
+            .and_then(|json| json.remove("piece layers"))
+            .map(decode_piece_layers)
+            .transpose()?;
+        let mut torrent: Torrent = serde_json::from_value(json)
+            .map_err(|e| format!("Failed to parse torrent JSON: {}", e))?;
+        torrent.piece_layers = piece_layers;
+        Ok(torrent)
+    }
+
+    /// Build a magnet link for the torrent
+    ///
+    /// Carries the info hashes of the torrent's format, its name, length and
+    /// every tracker. The trackers are never trimmed, as a private torrent
+    /// (BEP 27) can only find peers through them, and no parameters pointing
+    /// at other peer sources are added.
+    pub fn to_magnet(&self) -> Result<MagnetLink, String> {
+        let format = self.format_version();
+        let info_hash = match format {
+            TorrentFormat::V2 => String::new(),
+            _ => self.info_hash_hex()?,
+        };
+        let info_hash_v2 = match format {
+            TorrentFormat::V1 => None,
+            _ => Some(hex::encode(derive_infohash_v2(&self.info_bytes()?))),
+        };
+
+        Ok(MagnetLink {
+            info_hash,
+            info_hash_v2,
+            display_name: Some(self.info.name.clone()).filter(|name| !name.is_empty()),
+            keywords: Vec::new(),
+            trackers: self
+                .trackers()
+                .into_iter()
+                .filter(|tracker| !tracker.is_empty())
+                .collect(),
+            peers: Vec::new(),
+            exact_length: Some(self.info.total_length() as u64),
+            price: None,
+            price_unit: None,
+        })
+    }
+
+    /// Get all tracker URLs, the primary announce URL first, without duplicates
+    pub fn trackers(&self) -> Vec<String> {
+        let mut trackers = vec![self.announce.clone()];
+        for tier in self.announce_list.iter().flatten() {
+            for tracker in tier {
+                if !trackers.contains(tracker) {
+                    trackers.push(tracker.clone());
+                }
+            }
+        }
+        trackers
+    }
+}
+
+impl Info {
+    /// Total length of the content: the single file length or the sum of all files
+    pub fn total_length(&self) -> usize {
+        match (&self.files, self.length, &self.file_tree) {
+            (Some(files), _, _) => files.iter().map(|f| f.length).sum(),
+            (None, Some(length), _) => length,
+            (None, None, Some(tree)) => tree.total_length() as usize,
+            (None, None, None) => 0,
+        }
+    }
+
+    /// Split a byte range of the content across the files it touches
+    ///
+    /// Returns `(file_index, file_offset, len)` for each file the range
+    /// overlaps, in order; empty files are skipped and anything past the end of
+    /// the content is left out. A single-file torrent is file 0.
+    pub fn files_for_range(&self, offset: usize, length: usize) -> Vec<(usize, usize, usize)> {
+        let file_lengths = match &self.files {
+            Some(files) => files.iter().map(|file| file.length).collect(),
+            None => vec![self.total_length()],
+        };
+
+        let end = offset + length;
+        let mut spans = Vec::new();
+        let mut file_start = 0;
+        for (index, file_length) in file_lengths.into_iter().enumerate() {
+            if file_start >= end {
+                break;
+            }
+            let file_end = file_start + file_length;
+            if file_length > 0 && file_end > offset {
+                let from = offset.max(file_start);
+                spans.push((index, from - file_start, end.min(file_end) - from));
+            }
+            file_start = file_end;
+        }
+        spans
+    }
+
+    /// Number of pieces described by the `pieces` hash buffer
+    pub fn num_pieces(&self) -> usize {
+        self.pieces.len() / 20
+    }
+
+    /// Get the SHA-1 hash of a piece
+    pub fn piece_hash(&self, index: usize) -> Option<[u8; 20]> {
+        let hash = self.pieces.get(index * 20..(index + 1) * 20)?;
+        let mut result = [0u8; 20];
+        result.copy_from_slice(hash);
+        Some(result)
+    }
+
+    /// Split the concatenated `pieces` buffer into one SHA-1 hash per piece
+    pub fn split_piece_hashes(&self) -> Vec<[u8; 20]> {
+        self.pieces
+            .chunks_exact(20)
+            .map(|hash| hash.try_into().unwrap())
+            .collect()
+    }
+
+    /// Replace the piece hashes, rebuilding the `pieces` buffer
+    ///
+    /// Fails without changing anything unless there is exactly one hash per
+    /// piece of the content, so update the files before the hashes.
+    pub fn set_piece_hashes(&mut self, hashes: &[[u8; 20]]) -> Result<(), String> {
+        if self.plength == 0 {
+            return Err("Piece length is 0".to_string());
+        }
+        let expected = self.total_length().div_ceil(self.plength);
+        if hashes.len() != expected {
+            return Err(format!(
+                "Expected {} piece hashes for {} bytes, got {}",
+                expected,
+                self.total_length(),
+                hashes.len()
+            ));
+        }
+        self.pieces = ByteBuf::from(hashes.concat());
+        Ok(())
+    }
+
+    /// Length of a piece, accounting for the shorter last piece
+    pub fn piece_size(&self, index: usize) -> usize {
+        let start = index * self.plength;
+        self.total_length().saturating_sub(start).min(self.plength)
+    }
+
+    /// Check that the piece length is a power of two within sane bounds
+    pub fn validate_piece_length(&self) -> Result<(), String> {
+        if !self.plength.is_power_of_two() {
+            return Err(format!(
+                "Piece length {} is not a power of two",
+                self.plength
+            ));
+        }
+        if !(MIN_PIECE_LENGTH..=MAX_PIECE_LENGTH).contains(&self.plength) {
+            return Err(format!(
+                "Piece length {} is outside {}..={} bytes",
+                self.plength, MIN_PIECE_LENGTH, MAX_PIECE_LENGTH
+            ));
+        }
+        Ok(())
+    }
+
+    /// Output file or directory name, from the sanitized `name`
+    pub fn output_name(&self) -> Option<String> {
+        sanitize_filename(&self.name)
+    }
+
+    /// Whether the torrent is marked private (BEP 27)
+    pub fn is_private(&self) -> bool {
+        self.private == Some(1)
+    }
+}
+
+fn is_empty(bytes: &ByteBuf) -> bool {
+    bytes.is_empty()
+}
+
+/// Read the piece hashes as raw bytes from bencode, or as a base64 string,
+/// a `hex:` tagged hex string or an array of bytes from JSON
+fn deserialize_pieces<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ByteBuf, D::Error> {
+    struct PiecesVisitor;
+
+    impl<'de> Visitor<'de> for PiecesVisitor {
+        type Value = ByteBuf;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("piece hashes as bytes, or a base64 or hex: tagged string")
+        }
+
+        fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<ByteBuf, E> {
+            Ok(ByteBuf::from(bytes))
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<ByteBuf, E> {
+            Ok(ByteBuf::from(bytes))
+        }
+
+        fn visit_str<E: de::Error>(self, text: &str) -> Result<ByteBuf, E> {
+            decode_pieces(text).map(ByteBuf::from).map_err(E::custom)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ByteBuf, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            Ok(ByteBuf::from(bytes))
+        }
+    }
+
+    // JSON would hand a string to `deserialize_bytes` as its raw bytes
+    deserializer.deserialize_any(PiecesVisitor)
+}
+
+/// Prefix marking piece hashes given in hex rather than base64
+const HEX_PIECES_PREFIX: &str = "hex:";
+
+/// Decode piece hashes from text
+///
+/// The hashes are base64, as `Torrent::to_json` writes them, unless the text
+/// starts with `hex:`. The prefix can't occur in base64, so the two never mix up.
+fn decode_pieces(text: &str) -> Result<Vec<u8>, String> {
+    match text.strip_prefix(HEX_PIECES_PREFIX) {
+        Some(hex) => hex::decode(hex).map_err(|e| format!("Invalid hex pieces: {}", e)),
+        None => BASE64
+            .decode(text)
+            .map_err(|e| format!("Invalid base64 pieces: {}", e)),
+    }
+}
+
+/// Decode the `piece layers` object of a JSON torrent, encoded like the pieces
+fn decode_piece_layers(json: serde_json::Value) -> Result<BTreeMap<ByteBuf, ByteBuf>, String> {
+    let serde_json::Value::Object(layers) = json else {
+        return Err("Piece layers are not a JSON object".to_string());
+    };
+    layers
+        .iter()
+        .map(|(root, layer)| {
+            let layer = layer
+                .as_str()
+                .ok_or_else(|| format!("Piece layer of {} is not a string", root))?;
+            Ok((
+                ByteBuf::from(decode_pieces(root)?),
+                ByteBuf::from(decode_pieces(layer)?),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::filetree::{FileAttributes, FileTreeNode};
+
+    fn file_tree() -> FileTree {
+        let mut tree = FileTree::default();
+        tree.0.insert(
+            "a.txt".to_string(),
+            FileTreeNode::File {
+                attributes: FileAttributes {
+                    length: 10,
+                    pieces_root: None,
+                },
+            },
+        );
+        tree
+    }
+
+    #[test]
+    fn test_format_v1() {
+        let torrent = Torrent {
+            info: Info {
+                pieces: ByteBuf::from(vec![0u8; 20]),
+                length: Some(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(torrent.format_version(), TorrentFormat::V1);
+    }
+
+    fn multi_file_info() -> Info {
+        let file = |length, name: &str| FileEntry {
+            length,
+            path: vec![name.to_string()],
+        };
+        Info {
+            files: Some(vec![
+                file(100, "a"),
+                file(0, "empty"),
+                file(50, "b"),
+                file(200, "c"),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_files_for_range_within_one_file() {
+        let info = multi_file_info();
+        assert_eq!(info.files_for_range(10, 20), vec![(0, 10, 20)]);
+        assert_eq!(info.files_for_range(160, 40), vec![(3, 10, 40)]);
+
+        let single = Info {
+            length: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(single.files_for_range(90, 20), vec![(0, 90, 10)]);
+    }
+
+    #[test]
+    fn test_files_for_range_spanning_files() {
+        let info = multi_file_info();
+        // Bytes 90..170 end a, cover all of b and start c; the empty file is skipped
+        assert_eq!(
+            info.files_for_range(90, 80),
+            vec![(0, 90, 10), (2, 0, 50), (3, 0, 20)]
+        );
+        assert!(info.files_for_range(350, 10).is_empty());
+    }
+
+    #[test]
+    fn test_format_v2() {
+        let torrent = Torrent {
+            info: Info {
+                meta_version: Some(2),
+                file_tree: Some(file_tree()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(torrent.format_version(), TorrentFormat::V2);
+    }
+
+    #[test]
+    fn test_format_hybrid() {
+        let torrent = Torrent {
+            info: Info {
+                pieces: ByteBuf::from(vec![0u8; 20]),
+                length: Some(10),
+                meta_version: Some(2),
+                file_tree: Some(file_tree()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(torrent.format_version(), TorrentFormat::Hybrid);
+    }
+
+    #[test]
+    fn test_split_and_set_piece_hashes() {
+        let mut info = Info {
+            plength: 16,
+            length: Some(40),
+            pieces: ByteBuf::from((0..60).collect::<Vec<u8>>()),
+            ..Default::default()
+        };
+        let mut hashes = info.split_piece_hashes();
+        assert_eq!(hashes.len(), 3);
+        assert_eq!(hashes[1], info.piece_hash(1).unwrap());
+
+        hashes.swap(0, 2);
+        info.set_piece_hashes(&hashes).unwrap();
+        assert_eq!(info.split_piece_hashes(), hashes);
+        assert_eq!(info.piece_hash(0).unwrap()[0], 40);
+
+        // A hash per piece is required, and a failed set leaves the buffer alone
+        assert!(info.set_piece_hashes(&hashes[..2]).is_err());
+        assert_eq!(info.split_piece_hashes(), hashes);
+    }
+
+    #[test]
+    fn test_validate_piece_length() {
+        for plength in [16 * 1024, 256 * 1024, 4 * 1024 * 1024, 64 * 1024 * 1024] {
+            let info = Info {
+                plength,
+                ..Default::default()
+            };
+            assert!(info.validate_piece_length().is_ok(), "{}", plength);
+        }
+
+        let info = Info {
+            plength: 12345,
+            ..Default::default()
+        };
+        assert!(
+            info.validate_piece_length()
+                .unwrap_err()
+                .contains("not a power of two")
+        );
+
+        for plength in [0, 8 * 1024, 128 * 1024 * 1024] {
+            let info = Info {
+                plength,
+                ..Default::default()
+            };
+            assert!(info.validate_piece_length().is_err(), "{}", plength);
+        }
+    }
+
+    #[test]
+    fn test_source_changes_info_hash() {
+        let torrent = |source: &str| Torrent {
+            info: Info {
+                name: "a.txt".to_string(),
+                plength: 16 * 1024,
+                pieces: ByteBuf::from(vec![0u8; 20]),
+                length: Some(10),
+                private: Some(1),
+                source: Some(source.to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let first = torrent("TRACKER-A");
+        let second = torrent("TRACKER-B");
+        assert_ne!(first.info_hash().unwrap(), second.info_hash().unwrap());
+
+        // The key survives a decode and re-encode, keeping the hash intact
+        let data = serde_bencode::to_bytes(&first).unwrap();
+        let key = b"6:source9:TRACKER-A";
+        assert!(data.windows(key.len()).any(|window| window == key));
+        let decoded: Torrent = serde_bencode::from_bytes(&data).unwrap();
+        assert_eq!(decoded.info.source.as_deref(), Some("TRACKER-A"));
+        assert_eq!(decoded.info_hash().unwrap(), first.info_hash().unwrap());
+    }
+
+    #[test]
+    fn test_info_hash_after_mutation() {
+        let mut torrent = Torrent {
+            announce: "http://tracker.example.com/announce".to_string(),
+            info: Info {
+                name: "a.txt".to_string(),
+                plength: 16 * 1024,
+                pieces: ByteBuf::from(vec![7u8; 20]),
+                length: Some(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let original = torrent.info_hash_hex().unwrap();
+        assert_eq!(original.len(), 40);
+        assert_eq!(original, hex::encode(torrent.info_hash().unwrap()));
+
+        // Trackers live outside the info dictionary
+        torrent.announce_list = Some(vec![vec!["udp://backup.example.com:80".to_string()]]);
+        assert_eq!(torrent.info_hash_hex().unwrap(), original);
+
+        torrent.info.name = "b.txt".to_string();
+        assert_ne!(torrent.info_hash_hex().unwrap(), original);
+    }
+
+    #[test]
+    fn test_info_hash_of_parsed_torrent_covers_unknown_keys() {
+        let mut data = b"d8:announce22:http://t.example/annce4:infod6:lengthi10e4:name5:a.txt\
+            12:piece lengthi16384e6:pieces20:"
+            .to_vec();
+        data.extend_from_slice(&[7u8; 20]);
+        data.extend_from_slice(b"9:x-creator4:toolee");
+        let info_bytes =
+            &data[data.windows(6).position(|w| w == b"4:info").unwrap() + 6..data.len() - 1];
+
+        let torrent = crate::torrent::parser::parse_torrent(&data).unwrap();
+        assert_eq!(torrent.info_bytes().unwrap(), info_bytes);
+        assert_eq!(torrent.info_hash().unwrap(), derive_infohash(info_bytes));
+        let magnet = torrent.to_magnet().unwrap();
+        assert_eq!(magnet.info_hash, hex::encode(derive_infohash(info_bytes)));
+
+        // Built in code without the unknown key, the same fields hash differently
+        let rebuilt = Torrent {
+            raw_info: None,
+            ..torrent.clone()
+        };
+        assert_ne!(rebuilt.info_hash().unwrap(), torrent.info_hash().unwrap());
+        assert!(!torrent.same_content(&rebuilt));
+
+        let mut retracked = torrent.clone();
+        retracked.announce = "http://other.example/announce".to_string();
+        assert!(torrent.same_content(&retracked));
+
+        // Once `info` changes the parsed bytes no longer apply
+        let mut renamed = torrent.clone();
+        renamed.info.name = "b.txt".to_string();
+        let mut renamed_rebuilt = rebuilt.clone();
+        renamed_rebuilt.info.name = "b.txt".to_string();
+        assert_eq!(
+            renamed.info_hash().unwrap(),
+            renamed_rebuilt.info_hash().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_json_base64_pieces() {
+        let pieces: Vec<u8> = (0..60).collect();
+        let json = format!(
+            r#"{{"announce":"http://t/announce","info":{{"name":"a.txt","piece length":16384,"length":40000,"pieces":"{}"}}}}"#,
+            BASE64.encode(&pieces)
+        );
+        let torrent = Torrent::from_json(&json).unwrap();
+        assert_eq!(torrent.info.pieces.len(), 60);
+        assert_eq!(torrent.info.num_pieces(), 3);
+        assert_eq!(torrent.info.pieces.as_slice(), pieces.as_slice());
+
+        let hex_json = json.replace(
+            &BASE64.encode(&pieces),
+            &format!("hex:{}", hex::encode(&pieces)),
+        );
+        let from_hex = Torrent::from_json(&hex_json).unwrap();
+        assert_eq!(from_hex.info.pieces, torrent.info.pieces);
+
+        // Untagged hex digits are base64 like any other string
+        let untagged = json.replace(&BASE64.encode(&pieces), &hex::encode(&pieces[..30]));
+        let from_untagged = Torrent::from_json(&untagged).unwrap();
+        assert_eq!(
+            from_untagged.info.pieces.as_slice(),
+            BASE64.decode(hex::encode(&pieces[..30])).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_json_roundtrip_keeps_info_hash() {
+        let data = include_bytes!("../../../sample.torrent");
+        let torrent = crate::torrent::parser::parse_torrent(data).unwrap();
+
+        let json = torrent.to_json().unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert!(value["info"]["pieces"].is_string());
+
+        let decoded = Torrent::from_json(&json).unwrap();
+        assert_eq!(decoded.info_hash().unwrap(), torrent.info_hash().unwrap());
+        assert_eq!(decoded.info.pieces, torrent.info.pieces);
+    }
+
+    #[test]
+    fn test_json_roundtrip_keeps_piece_layers() {
+        let root = vec![0xab; 32];
+        let mut tree = FileTree::default();
+        tree.0.insert(
+            "a.bin".to_string(),
+            FileTreeNode::File {
+                attributes: FileAttributes {
+                    length: 40000,
+                    pieces_root: Some(ByteBuf::from(root.clone())),
+                },
+            },
+        );
+        let layer: Vec<u8> = (0..96).collect();
+        let torrent = Torrent {
+            announce: "http://t/announce".to_string(),
+            info: Info {
+                name: "a.bin".to_string(),
+                plength: 16384,
+                pieces: ByteBuf::from(vec![7u8; 60]),
+                length: Some(40000),
+                meta_version: Some(2),
+                file_tree: Some(tree),
+                ..Default::default()
+            },
+            piece_layers: Some(BTreeMap::from([(
+                ByteBuf::from(root.clone()),
+                ByteBuf::from(layer.clone()),
+            )])),
+            ..Default::default()
+        };
+        assert_eq!(torrent.format_version(), TorrentFormat::Hybrid);
+
+        let json = torrent.to_json().unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value["piece layers"][BASE64.encode(&root)],
+            BASE64.encode(&layer)
+        );
+
+        let decoded = Torrent::from_json(&json).unwrap();
+        assert_eq!(decoded.piece_layers, torrent.piece_layers);
+        assert_eq!(decoded.info_hash().unwrap(), torrent.info_hash().unwrap());
+        assert_eq!(decoded.info_bytes().unwrap(), torrent.info_bytes().unwrap());
+
+        // Hex works for the layers as it does for the pieces
+        let hex_json = json
+            .replace(
+                &BASE64.encode(&root),
+                &format!("hex:{}", hex::encode(&root)),
+            )
+            .replace(
+                &BASE64.encode(&layer),
+                &format!("hex:{}", hex::encode(&layer)),
+            );
+        let from_hex = Torrent::from_json(&hex_json).unwrap();
+        assert_eq!(from_hex.piece_layers, torrent.piece_layers);
+    }
+
+    #[test]
+    fn test_same_content_ignores_trackers() {
+        let torrent = Torrent {
+            announce: "http://tracker.example.com/announce".to_string(),
+            info: Info {
+                name: "a.txt".to_string(),
+                plength: 16 * 1024,
+                pieces: ByteBuf::from(vec![7u8; 20]),
+                length: Some(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let retracked = Torrent {
+            announce: "http://other.example.com/announce".to_string(),
+            announce_list: Some(vec![vec!["udp://backup.example.com:80".to_string()]]),
+            ..torrent.clone()
+        };
+
+        assert!(torrent.same_content(&retracked));
+        assert_eq!(
+            torrent.diff(&retracked).fields,
+            vec!["announce", "announce-list"]
+        );
+        assert!(torrent.diff(&torrent.clone()).is_empty());
+
+        let mut renamed = torrent.clone();
+        renamed.info.name = "b.txt".to_string();
+        assert!(!torrent.same_content(&renamed));
+        assert!(torrent.diff(&renamed).is_empty());
+    }
+
+    #[test]
+    fn test_validate_consistent_torrents() {
+        let data = include_bytes!("../../../sample.torrent");
+        let torrent = crate::torrent::parser::parse_torrent(data).unwrap();
+        assert_eq!(torrent.validate(), Ok(()));
+
+        // 350 bytes in pieces of 16 KiB is a single piece
+        let multi_file = Torrent {
+            info: Info {
+                name: "dir".to_string(),
+                plength: 16 * 1024,
+                pieces: ByteBuf::from(vec![0u8; 20]),
+                ..multi_file_info()
+            },
+            ..Default::default()
+        };
+        assert_eq!(multi_file.validate(), Ok(()));
+
+        let v2 = Torrent {
+            info: Info {
+                name: "a.txt".to_string(),
+                plength: 16 * 1024,
+                meta_version: Some(2),
+                file_tree: Some(file_tree()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(v2.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_reports_every_problem() {
+        let torrent = Torrent {
+            info: Info {
+                plength: 1000,
+                pieces: ByteBuf::from(vec![0u8; 30]),
+                files: Some(vec![entry(&["a.txt"]), entry(&[]), entry(&["dir", ""])]),
+                ..Default::default()
+            },
+            piece_layers: Some(BTreeMap::from([(
+                ByteBuf::from(vec![0xab; 32]),
+                ByteBuf::from(vec![0u8; 40]),
+            )])),
+            ..Default::default()
+        };
+        let problems = torrent.validate().unwrap_err();
+        assert_eq!(problems.len(), 6, "{:?}", problems);
+        assert_eq!(problems[0], "Name is missing");
+        assert!(problems[1].contains("not a power of two"));
+        assert!(problems[2].starts_with("File 1 has an empty path"));
+        assert!(problems[3].starts_with("File 2 has an empty path"));
+        assert_eq!(problems[4], "Pieces length 30 is not a multiple of 20");
+        assert!(problems[5].contains("not a multiple of 32"));
+
+        // Piece hashes that don't cover the content
+        let short = Torrent {
+            info: Info {
+                name: "a.txt".to_string(),
+                plength: 16 * 1024,
+                pieces: ByteBuf::from(vec![0u8; 20]),
+                length: Some(40 * 1024),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            short.validate(),
+            Err(vec![
+                "Expected 3 piece hashes for 40960 bytes in pieces of 16384, found 1".to_string()
+            ])
+        );
+
+        let both = Torrent {
+            info: Info {
+                files: Some(vec![entry(&["a.txt"])]),
+                ..short.info.clone()
+            },
+            ..Default::default()
+        };
+        assert!(
+            both.validate()
+                .unwrap_err()
+                .contains(&"Both length and files are set".to_string())
+        );
+    }
+
+    #[test]
+    fn test_private_torrent_magnet_keeps_trackers() {
+        let torrent = Torrent {
+            announce: "https://tracker.example.com/abc123/announce".to_string(),
+            announce_list: Some(vec![
+                vec!["https://tracker.example.com/abc123/announce".to_string()],
+                vec!["https://backup.example.com/abc123/announce".to_string()],
+            ]),
+            info: Info {
+                name: "private.bin".to_string(),
+                plength: 16 * 1024,
+                pieces: ByteBuf::from(vec![3u8; 20]),
+                length: Some(1000),
+                private: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let magnet = torrent.to_magnet().unwrap();
+        assert_eq!(magnet.info_hash, torrent.info_hash_hex().unwrap());
+        assert_eq!(magnet.info_hash_v2, None);
+        assert_eq!(
+            magnet.trackers,
+            vec![
+                "https://tracker.example.com/abc123/announce".to_string(),
+                "https://backup.example.com/abc123/announce".to_string(),
+            ]
+        );
+
+        let url = magnet.to_url();
+        assert!(url.contains("&tr=https%3A%2F%2Ftracker.example.com%2Fabc123%2Fannounce"));
+        for param in ["x.pe=", "so=", "ws=", "as="] {
+            assert!(!url.contains(param), "{}", url);
+        }
+    }
+
+    fn entry(path: &[&str]) -> FileEntry {
+        FileEntry {
+            length: 1,
+            path: path.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_safe_path_nested() {
+        let base = Path::new("downloads");
+        assert_eq!(
+            entry(&["dir", "sub", "a.txt"]).safe_path(base).unwrap(),
+            base.join("dir").join("sub").join("a.txt")
+        );
+    }
+
+    #[test]
+    fn test_safe_path_rejects_traversal() {
+        let base = Path::new("downloads");
+        assert!(
+            entry(&["..", "..", "etc", "passwd"])
+                .safe_path(base)
+                .is_err()
+        );
+        assert!(entry(&["dir", "..", "..", "x"]).safe_path(base).is_err());
+        assert!(entry(&["/etc", "passwd"]).safe_path(base).is_err());
+        assert!(entry(&["C:", "x"]).safe_path(base).is_err());
+        assert!(entry(&["a\\..\\..\\b"]).safe_path(base).is_err());
+        assert!(entry(&["."]).safe_path(base).is_err());
+        assert!(entry(&[""]).safe_path(base).is_err());
+        assert!(entry(&[]).safe_path(base).is_err());
+    }
+
+    #[test]
+    fn test_safe_path_rejects_reserved_names() {
+        let base = Path::new("downloads");
+        assert!(entry(&["dir", "CON"]).safe_path(base).is_err());
+        assert!(entry(&["nul.txt"]).safe_path(base).is_err());
+        assert!(entry(&["com1"]).safe_path(base).is_err());
+        assert!(entry(&["console.txt"]).safe_path(base).is_ok());
+    }
 }
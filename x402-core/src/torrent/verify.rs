@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::torrent::types::{Info, PieceLayers};
+
+/// Size of the blocks hashed into the leaves of a v2 merkle tree (BEP 52)
+pub const MERKLE_BLOCK_SIZE: usize = 16 * 1024;
+
+/// Compute the SHA-1 hash of a piece
+pub fn hash_piece(data: &[u8]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    hasher.update(data);
+    let mut hash = [0u8; 20];
+    hash.copy_from_slice(&hasher.finalize());
+    hash
+}
+
+/// Check a downloaded piece against its hash in the info dictionary
+pub fn verify_piece(info: &Info, index: usize, data: &[u8]) -> bool {
+    data.len() == info.piece_size(index) && info.piece_hash(index) == Some(hash_piece(data))
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// Root of a merkle tree over `leaves`
+///
+/// The leaves are padded with `padding` up to `width`, or to the next power
+/// of two if there are more leaves than that.
+pub(crate) fn merkle_root(mut layer: Vec<[u8; 32]>, width: usize, padding: [u8; 32]) -> [u8; 32] {
+    let width = layer.len().max(width).next_power_of_two();
+    layer.resize(width, padding);
+    while layer.len() > 1 {
+        layer = layer
+            .chunks(2)
+            .map(|pair| sha256(&[pair[0], pair[1]].concat()))
+            .collect();
+    }
+    layer[0]
+}
+
+/// Compute the v2 hash of a piece: the merkle root over its 16 KiB blocks
+///
+/// A piece at the end of a file is padded with zero hashes, as if it were a
+/// full `piece_length` long.
+pub fn hash_piece_v2(data: &[u8], piece_length: usize) -> [u8; 32] {
+    let leaves = data.chunks(MERKLE_BLOCK_SIZE).map(sha256).collect();
+    merkle_root(leaves, piece_length / MERKLE_BLOCK_SIZE, [0u8; 32])
+}
+
+/// Check piece `index` of the file with `pieces_root` against its v2 hashes
+///
+/// Files no larger than a piece have no layer, so their only piece is checked
+/// against the root directly.
+pub fn verify_piece_v2(
+    layers: &PieceLayers,
+    pieces_root: &[u8; 32],
+    piece_length: usize,
+    index: usize,
+    data: &[u8],
+) -> bool {
+    if data.is_empty() || data.len() > piece_length {
+        return false;
+    }
+    match layers.get(pieces_root) {
+        Some(layer) => layer.get(index) == Some(&hash_piece_v2(data, piece_length)),
+        None => {
+            let leaves = data.chunks(MERKLE_BLOCK_SIZE).map(sha256).collect();
+            index == 0 && merkle_root(leaves, 1, [0u8; 32]) == *pieces_root
+        }
+    }
+}
+
+/// Hashes a piece's blocks as they arrive, so the piece can be verified
+/// without reading it back
+///
+/// Blocks that arrive ahead of the hashed prefix are buffered until the gap
+/// before them is filled.
+pub struct StreamingVerifier {
+    index: usize,
+    expected: [u8; 20],
+    length: usize,
+    hasher: Sha1,
+    /// Length of the prefix fed to the hasher so far
+    hashed: usize,
+    /// Blocks past the hashed prefix, keyed by offset
+    buffered: BTreeMap<usize, Vec<u8>>,
+}
+
+impl StreamingVerifier {
+    /// Start verifying piece `index` of a torrent
+    pub fn new(info: &Info, index: usize) -> Result<Self, String> {
+        let expected = info
+            .piece_hash(index)
+            .ok_or_else(|| format!("Piece index {} out of range", index))?;
+        Ok(StreamingVerifier {
+            index,
+            expected,
+            length: info.piece_size(index),
+            hasher: Sha1::new(),
+            hashed: 0,
+            buffered: BTreeMap::new(),
+        })
+    }
+
+    /// Feed a block starting at offset `begin` within the piece
+    ///
+    /// Fails for blocks past the end of the piece or overlapping data already given.
+    pub fn add_block(&mut self, begin: usize, block: &[u8]) -> Result<(), String> {
+        let end = begin + block.len();
+        if end > self.length {
+            return Err(format!(
+                "Block out of range: piece {} offset {} length {}",
+                self.index,
+                begin,
+                block.len()
+            ));
+        }
+        let overlaps_before = self
+            .buffered
+            .range(..=begin)
+            .next_back()
+            .is_some_and(|(offset, data)| offset + data.len() > begin);
+        let overlaps_after = self
+            .buffered
+            .range(begin..)
+            .next()
+            .is_some_and(|(&offset, _)| offset < end);
+        if begin < self.hashed || overlaps_before || overlaps_after {
+            return Err(format!(
+                "Overlapping block: piece {} offset {} length {}",
+                self.index,
+                begin,
+                block.len()
+            ));
+        }
+
+        if begin != self.hashed {
+            self.buffered.insert(begin, block.to_vec());
+            return Ok(());
+        }
+        self.hasher.update(block);
+        self.hashed = end;
+        while let Some(block) = self.buffered.remove(&self.hashed) {
+            self.hasher.update(&block);
+            self.hashed += block.len();
+        }
+        Ok(())
+    }
+
+    /// Whether every byte of the piece has been hashed
+    pub fn is_complete(&self) -> bool {
+        self.hashed == self.length
+    }
+
+    /// Finish the hash and check it against the info dictionary
+    ///
+    /// Fails if part of the piece is still missing.
+    pub fn finish(self) -> Result<bool, String> {
+        if !self.is_complete() {
+            return Err(format!(
+                "Piece {} incomplete: {} of {} bytes in order",
+                self.index, self.hashed, self.length
+            ));
+        }
+        Ok(self.hasher.finalize().as_slice() == self.expected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_info(data: &[u8], piece_length: usize) -> Info {
+        let pieces: Vec<u8> = data.chunks(piece_length).flat_map(hash_piece).collect();
+        Info {
+            name: "test.bin".to_string(),
+            plength: piece_length,
+            pieces: serde_bytes::ByteBuf::from(pieces),
+            length: Some(data.len()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_verify_piece() {
+        let data: Vec<u8> = (0..100).collect();
+        let info = test_info(&data, 40);
+
+        assert!(verify_piece(&info, 0, &data[..40]));
+        assert!(verify_piece(&info, 2, &data[80..]));
+        assert!(!verify_piece(&info, 1, &data[..40]));
+        assert!(!verify_piece(&info, 3, &[]));
+    }
+
+    #[test]
+    fn test_streaming_verifier_in_order() {
+        let data: Vec<u8> = (0..100).collect();
+        let info = test_info(&data, 40);
+
+        let mut verifier = StreamingVerifier::new(&info, 1).unwrap();
+        for begin in (0..40).step_by(16) {
+            verifier
+                .add_block(begin, &data[40 + begin..40 + (begin + 16).min(40)])
+                .unwrap();
+        }
+        assert!(verifier.is_complete());
+        assert!(verifier.finish().unwrap());
+
+        // The short last piece, with a corrupted byte
+        let mut last = data[80..].to_vec();
+        last[3] ^= 0xff;
+        let mut verifier = StreamingVerifier::new(&info, 2).unwrap();
+        verifier.add_block(0, &last).unwrap();
+        assert!(!verifier.finish().unwrap());
+    }
+
+    #[test]
+    fn test_streaming_verifier_reordered_block() {
+        let data: Vec<u8> = (0..100).collect();
+        let info = test_info(&data, 40);
+
+        let mut verifier = StreamingVerifier::new(&info, 0).unwrap();
+        verifier.add_block(0, &data[..10]).unwrap();
+        verifier.add_block(20, &data[20..40]).unwrap();
+        assert!(!verifier.is_complete());
+        assert!(verifier.add_block(25, &data[25..30]).is_err());
+
+        verifier.add_block(10, &data[10..20]).unwrap();
+        assert!(verifier.is_complete());
+        assert!(verifier.finish().unwrap());
+
+        let mut verifier = StreamingVerifier::new(&info, 0).unwrap();
+        verifier.add_block(20, &data[20..40]).unwrap();
+        assert!(verifier.finish().is_err());
+        assert!(StreamingVerifier::new(&info, 3).is_err());
+    }
+
+    #[test]
+    fn test_verify_piece_v2() {
+        use crate::torrent::types::Torrent;
+        use serde_bytes::ByteBuf;
+        use std::collections::BTreeMap;
+
+        fn pair(left: [u8; 32], right: [u8; 32]) -> [u8; 32] {
+            sha256(&[left, right].concat())
+        }
+
+        // Two full pieces of two blocks, then a short one of a single block
+        let piece_length = 2 * MERKLE_BLOCK_SIZE;
+        let data: Vec<u8> = (0..2 * piece_length + 5000)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let blocks: Vec<[u8; 32]> = data.chunks(MERKLE_BLOCK_SIZE).map(sha256).collect();
+        let layer = [
+            pair(blocks[0], blocks[1]),
+            pair(blocks[2], blocks[3]),
+            pair(blocks[4], [0u8; 32]),
+        ];
+        let padding = pair([0u8; 32], [0u8; 32]);
+        let root = pair(pair(layer[0], layer[1]), pair(layer[2], padding));
+
+        let mut torrent = Torrent {
+            info: Info {
+                plength: piece_length,
+                ..Default::default()
+            },
+            piece_layers: Some(BTreeMap::from([(
+                ByteBuf::from(root.to_vec()),
+                ByteBuf::from(layer.concat()),
+            )])),
+            ..Default::default()
+        };
+        let layers = torrent.parse_piece_layers().unwrap();
+        assert_eq!(layers[&root], layer.to_vec());
+
+        let pieces: Vec<&[u8]> = data.chunks(piece_length).collect();
+        for (index, piece) in pieces.iter().enumerate() {
+            assert!(verify_piece_v2(&layers, &root, piece_length, index, piece));
+        }
+        let mut corrupt = pieces[1].to_vec();
+        corrupt[100] ^= 0xff;
+        assert!(!verify_piece_v2(&layers, &root, piece_length, 1, &corrupt));
+        assert!(!verify_piece_v2(&layers, &root, piece_length, 0, pieces[1]));
+        assert!(!verify_piece_v2(&layers, &root, piece_length, 3, pieces[2]));
+
+        // A file that fits in one piece is checked against its root
+        let small = &data[..MERKLE_BLOCK_SIZE + 10];
+        let small_root = pair(blocks[0], sha256(&small[MERKLE_BLOCK_SIZE..]));
+        assert!(verify_piece_v2(
+            &layers,
+            &small_root,
+            piece_length,
+            0,
+            small
+        ));
+        assert!(!verify_piece_v2(
+            &layers,
+            &small_root,
+            piece_length,
+            0,
+            &data[..10]
+        ));
+
+        // A layer that doesn't hash up to its root is refused
+        torrent.piece_layers = Some(BTreeMap::from([(
+            ByteBuf::from(root.to_vec()),
+            ByteBuf::from(layer[..2].concat()),
+        )]));
+        assert!(torrent.parse_piece_layers().is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::torrent::types::Info;
+
+/// Writes verified pieces into a torrent's output files, in any order
+///
+/// The files are created at their full size up front, so a piece can be
+/// written at its offset before the pieces ahead of it have arrived. On
+/// filesystems that support it the files stay sparse until written.
+pub struct PieceWriter {
+    files: Vec<File>,
+    /// Info dictionary describing how the content is split into files
+    info: Info,
+}
+
+impl PieceWriter {
+    /// Create or open a torrent's output files at their full size
+    ///
+    /// For a single-file torrent `path` is the file itself; for a multi-file
+    /// torrent it is the directory the files are created in, and any file path
+    /// that would escape it is rejected. Existing files keep their contents, so
+    /// a resumed download doesn't lose the pieces already written.
+    pub fn create(info: &Info, path: &Path) -> Result<Self, String> {
+        let expected: Vec<(PathBuf, usize)> = match &info.files {
+            Some(files) => files
+                .iter()
+                .map(|file| Ok((file.safe_path(path)?, file.length)))
+                .collect::<Result<_, String>>()?,
+            None => vec![(path.to_path_buf(), info.total_length())],
+        };
+
+        let mut files = Vec::with_capacity(expected.len());
+        for (file_path, length) in expected {
+            if let Some(parent) = file_path.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(false)
+                .open(&file_path)
+                .map_err(|e| format!("Failed to open {}: {}", file_path.display(), e))?;
+            // Extending with set_len leaves a hole instead of writing zeros
+            file.set_len(length as u64)
+                .map_err(|e| format!("Failed to allocate {}: {}", file_path.display(), e))?;
+            files.push(file);
+        }
+
+        Ok(PieceWriter {
+            files,
+            info: info.clone(),
+        })
+    }
+
+    /// Write a verified piece at its offset, split across the files it spans
+    pub fn write_piece(&mut self, index: usize, data: &[u8]) -> Result<(), String> {
+        if index >= self.info.num_pieces() {
+            return Err(format!("Piece {} out of range", index));
+        }
+        let expected = self.info.piece_size(index);
+        if data.len() != expected {
+            return Err(format!(
+                "Piece {} is {} bytes, expected {}",
+                index,
+                data.len(),
+                expected
+            ));
+        }
+
+        let mut written = 0;
+        for (file, offset, len) in self
+            .info
+            .files_for_range(index * self.info.plength, expected)
+        {
+            let output = &mut self.files[file];
+            output
+                .seek(SeekFrom::Start(offset as u64))
+                .and_then(|_| output.write_all(&data[written..written + len]))
+                .map_err(|e| format!("Failed to write piece {}: {}", index, e))?;
+            written += len;
+        }
+        Ok(())
+    }
+
+    /// Flush everything written to disk
+    pub fn sync(&self) -> Result<(), String> {
+        for file in &self.files {
+            file.sync_all()
+                .map_err(|e| format!("Failed to sync output: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::types::FileEntry;
+    use crate::torrent::verify::hash_piece;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("x402-writer-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_write_pieces_out_of_order() {
+        let dir = temp_dir("order");
+        let data: Vec<u8> = (0..160).map(|i| i as u8).collect();
+        let files = [(100, "a.bin"), (60, "sub/b.bin")]
+            .iter()
+            .map(|&(length, name)| FileEntry {
+                length,
+                path: name.split('/').map(str::to_string).collect(),
+            })
+            .collect();
+        let info = Info {
+            name: "test".to_string(),
+            plength: 64,
+            pieces: serde_bytes::ByteBuf::from(
+                data.chunks(64).flat_map(hash_piece).collect::<Vec<u8>>(),
+            ),
+            files: Some(files),
+            ..Default::default()
+        };
+
+        let mut writer = PieceWriter::create(&info, &dir).unwrap();
+        // Full size before anything is written
+        assert_eq!(fs::metadata(dir.join("a.bin")).unwrap().len(), 100);
+        assert_eq!(fs::metadata(dir.join("sub/b.bin")).unwrap().len(), 60);
+
+        // Piece 1 covers bytes 64..128, crossing into the second file at 100
+        for index in [2, 0, 1] {
+            let start = index * 64;
+            let end = (start + 64).min(data.len());
+            writer.write_piece(index, &data[start..end]).unwrap();
+        }
+        assert!(writer.write_piece(2, &data[..64]).is_err());
+        writer.sync().unwrap();
+
+        let first = fs::read(dir.join("a.bin")).unwrap();
+        let second = fs::read(dir.join("sub/b.bin")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(first, data[..100].to_vec());
+        assert_eq!(second, data[100..].to_vec());
+    }
+}
@@ -0,0 +1,487 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, SocketAddr};
+
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
+
+use crate::peer::address::{COMPACT_PEER_LENGTH, COMPACT_PEER6_LENGTH, PeerAddress};
+use crate::peer::proxy::ProxyConfig;
+use crate::tracker::http;
+
+/// Number of peers asked for when the request doesn't say
+pub const DEFAULT_NUMWANT: u32 = 50;
+
+/// Lifecycle event sent with an announce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnounceEvent {
+    Started,
+    Stopped,
+    Completed,
+}
+
+impl fmt::Display for AnnounceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnnounceEvent::Started => write!(f, "started"),
+            AnnounceEvent::Stopped => write!(f, "stopped"),
+            AnnounceEvent::Completed => write!(f, "completed"),
+        }
+    }
+}
+
+/// Parameters of an announce to an HTTP tracker
+#[derive(Debug, Clone)]
+pub struct AnnounceRequest {
+    pub info_hash: [u8; 20],
+    pub peer_id: [u8; 20],
+    /// Port we accept peer connections on
+    pub port: u16,
+    pub uploaded: u64,
+    pub downloaded: u64,
+    /// Bytes still needed to complete the torrent
+    pub left: u64,
+    /// Event to report, or `None` for a regular re-announce
+    pub event: Option<AnnounceEvent>,
+    /// Ask for the compact peer list; the response is parsed either way
+    pub compact: bool,
+    /// Number of peers to ask for, `DEFAULT_NUMWANT` if `None`
+    pub numwant: Option<u32>,
+    /// Our session key, letting the tracker recognize us if our IP changes
+    pub key: Option<u32>,
+    /// Tracker ID returned by a previous announce to this tracker
+    pub tracker_id: Option<Vec<u8>>,
+}
+
+impl AnnounceRequest {
+    /// Build the full announce URL for a tracker
+    pub fn to_url(&self, tracker: &str) -> String {
+        let separator = if tracker.contains('?') { '&' } else { '?' };
+        let mut url = format!(
+            "{}{}info_hash={}&peer_id={}&port={}&uploaded={}&downloaded={}&left={}&compact={}&numwant={}",
+            tracker,
+            separator,
+            percent_encode(&self.info_hash),
+            percent_encode(&self.peer_id),
+            self.port,
+            self.uploaded,
+            self.downloaded,
+            self.left,
+            u8::from(self.compact),
+            self.numwant.unwrap_or(DEFAULT_NUMWANT)
+        );
+        if let Some(event) = self.event {
+            url.push_str(&format!("&event={}", event));
+        }
+        if let Some(key) = self.key {
+            url.push_str(&format!("&key={:08x}", key));
+        }
+        if let Some(tracker_id) = &self.tracker_id {
+            url.push_str(&format!("&trackerid={}", percent_encode(tracker_id)));
+        }
+        url
+    }
+}
+
+/// State kept across the announces to one tracker
+///
+/// Trackers use the `key` to correlate our announces when our address
+/// changes, e.g. after a NAT rebinding, and may hand out a tracker ID they
+/// expect back in every later announce.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnounceSession {
+    /// Random key chosen when the session starts
+    pub key: u32,
+    /// Tracker ID from the last response that carried one
+    pub tracker_id: Option<Vec<u8>>,
+}
+
+impl Default for AnnounceSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnnounceSession {
+    /// A session with a fresh random key
+    pub fn new() -> Self {
+        // Every RandomState gets fresh random hashing keys
+        AnnounceSession {
+            key: RandomState::new().build_hasher().finish() as u32,
+            tracker_id: None,
+        }
+    }
+
+    /// Announce with the session's key and tracker ID, through `proxy` if given
+    ///
+    /// A tracker ID in the response replaces the stored one; a response
+    /// without one keeps it, as trackers only send it when it changes.
+    pub fn announce(
+        &mut self,
+        tracker: &str,
+        request: &AnnounceRequest,
+        proxy: Option<&ProxyConfig>,
+    ) -> Result<AnnounceResponse, String> {
+        let request = AnnounceRequest {
+            key: Some(self.key),
+            tracker_id: self.tracker_id.clone(),
+            ..request.clone()
+        };
+        let response = announce_via(tracker, &request, proxy)?;
+        if let Some(tracker_id) = &response.tracker_id {
+            self.tracker_id = Some(tracker_id.clone());
+        }
+        Ok(response)
+    }
+}
+
+/// A peer returned by a tracker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerEntry {
+    pub addr: SocketAddr,
+    /// The peer's ID, only sent by trackers using the dictionary model
+    pub peer_id: Option<[u8; 20]>,
+}
+
+/// A successful announce response
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnnounceResponse {
+    /// Seconds the tracker wants us to wait before re-announcing
+    pub interval: u64,
+    /// Seconds we must wait at least before re-announcing, if given
+    pub min_interval: Option<u64>,
+    /// Number of seeders, if reported
+    pub complete: Option<u64>,
+    /// Number of leechers, if reported
+    pub incomplete: Option<u64>,
+    pub peers: Vec<PeerEntry>,
+    /// ID the tracker wants back in our next announces, if given
+    pub tracker_id: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawAnnounceResponse {
+    #[serde(default, rename = "failure reason")]
+    failure_reason: Option<String>,
+    #[serde(default)]
+    interval: Option<u64>,
+    #[serde(default, rename = "min interval")]
+    min_interval: Option<u64>,
+    #[serde(default)]
+    complete: Option<u64>,
+    #[serde(default)]
+    incomplete: Option<u64>,
+    #[serde(default)]
+    peers: Option<RawPeers>,
+    /// Compact IPv6 peers (BEP 7)
+    #[serde(default)]
+    peers6: Option<ByteBuf>,
+    #[serde(default, rename = "tracker id")]
+    tracker_id: Option<ByteBuf>,
+}
+
+/// Peers in either the compact or the dictionary model
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawPeers {
+    Compact(ByteBuf),
+    Dictionary(Vec<RawPeer>),
+}
+
+#[derive(Debug, Deserialize)]
+struct RawPeer {
+    ip: String,
+    port: u16,
+    #[serde(default, rename = "peer id")]
+    peer_id: Option<ByteBuf>,
+}
+
+impl RawPeer {
+    fn to_entry(&self) -> Result<PeerEntry, String> {
+        let ip: IpAddr = self
+            .ip
+            .parse()
+            .map_err(|_| format!("Invalid peer IP address: {}", self.ip))?;
+        // Some trackers send malformed IDs; the address is still usable
+        let peer_id = self
+            .peer_id
+            .as_ref()
+            .and_then(|id| <[u8; 20]>::try_from(id.as_slice()).ok());
+
+        Ok(PeerEntry {
+            addr: SocketAddr::new(ip, self.port),
+            peer_id,
+        })
+    }
+}
+
+impl AnnounceResponse {
+    /// Parse a bencoded announce response body
+    pub fn parse(body: &[u8]) -> Result<Self, String> {
+        let raw: RawAnnounceResponse = serde_bencode::from_bytes(body)
+            .map_err(|e| format!("Failed to parse announce response: {}", e))?;
+
+        if let Some(reason) = raw.failure_reason {
+            return Err(format!("Tracker error: {}", reason));
+        }
+        let interval = raw
+            .interval
+            .ok_or("Announce response is missing the interval")?;
+        let compact_entries = |data: &[u8], record_length| -> Result<Vec<PeerEntry>, String> {
+            Ok(PeerAddress::from_compact_list(data, record_length)?
+                .into_iter()
+                .map(|addr| PeerEntry {
+                    addr: addr.into(),
+                    peer_id: None,
+                })
+                .collect())
+        };
+        let mut peers = match raw.peers {
+            Some(RawPeers::Compact(peers)) => compact_entries(&peers, COMPACT_PEER_LENGTH)?,
+            Some(RawPeers::Dictionary(peers)) => peers
+                .iter()
+                .map(RawPeer::to_entry)
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        // IPv6 peers come in a separate key and are merged after the IPv4 ones
+        if let Some(peers6) = raw.peers6 {
+            peers.extend(compact_entries(&peers6, COMPACT_PEER6_LENGTH)?);
+        }
+
+        Ok(AnnounceResponse {
+            interval,
+            min_interval: raw.min_interval,
+            complete: raw.complete,
+            incomplete: raw.incomplete,
+            peers,
+            tracker_id: raw.tracker_id.map(ByteBuf::into_vec),
+        })
+    }
+}
+
+/// Announce to an HTTP tracker
+pub fn announce(tracker: &str, request: &AnnounceRequest) -> Result<AnnounceResponse, String> {
+    announce_via(tracker, request, None)
+}
+
+/// Announce to an HTTP tracker, through `proxy` if given
+pub fn announce_via(
+    tracker: &str,
+    request: &AnnounceRequest,
+    proxy: Option<&ProxyConfig>,
+) -> Result<AnnounceResponse, String> {
+    let body = http::get(&build_announce_url(tracker, request), proxy)?;
+    AnnounceResponse::parse(&body)
+}
+
+/// Build the URL `announce` would fetch, without contacting the tracker
+///
+/// Useful for debugging tracker connectivity: the info hash and peer ID are
+/// percent-encoded exactly as they would be sent.
+pub fn build_announce_url(tracker: &str, request: &AnnounceRequest) -> String {
+    request.to_url(tracker)
+}
+
+/// Percent-encode raw bytes for use in a query string
+pub(crate) fn percent_encode(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    fn test_request() -> AnnounceRequest {
+        AnnounceRequest {
+            info_hash: [0xAB; 20],
+            peer_id: *b"-X4020-abcdefghijklm",
+            port: 6881,
+            uploaded: 0,
+            downloaded: 0,
+            left: 100,
+            event: Some(AnnounceEvent::Started),
+            compact: true,
+            numwant: None,
+            key: None,
+            tracker_id: None,
+        }
+    }
+
+    #[test]
+    fn test_announce_url() {
+        let url = test_request().to_url("http://tracker.example.com/announce");
+
+        assert!(url.starts_with("http://tracker.example.com/announce?info_hash=%AB%AB"));
+        assert!(url.contains("&peer_id=-X4020-abcdefghijklm&"));
+        assert!(url.contains("&port=6881&uploaded=0&downloaded=0&left=100&compact=1"));
+        assert!(url.ends_with("&numwant=50&event=started"));
+
+        let request = AnnounceRequest {
+            compact: false,
+            ..test_request()
+        };
+        assert!(request.to_url("http://t/announce").contains("&compact=0"));
+
+        // Trackers with a passkey in the query keep it
+        let url = test_request().to_url("http://tracker.example.com/announce?passkey=x");
+        assert!(url.starts_with("http://tracker.example.com/announce?passkey=x&info_hash="));
+    }
+
+    #[test]
+    fn test_build_announce_url_encodes_info_hash() {
+        let mut info_hash = [0x7Au8; 20];
+        info_hash[..8].copy_from_slice(&[0x00, b'A', b'-', b'~', b' ', b'%', b'&', 0xFF]);
+        let request = AnnounceRequest {
+            info_hash,
+            ..test_request()
+        };
+
+        let url = build_announce_url("http://tracker.example.com/announce", &request);
+        assert!(url.starts_with(
+            "http://tracker.example.com/announce?info_hash=%00A-~%20%25%26%FFzzzzzzzzzzzz&peer_id="
+        ));
+    }
+
+    #[test]
+    fn test_announce_url_event_and_numwant() {
+        let request = AnnounceRequest {
+            event: None,
+            ..test_request()
+        };
+        let url = request.to_url("http://t/announce");
+        assert!(url.contains("&numwant=50"));
+        assert!(!url.contains("event="));
+
+        let request = AnnounceRequest {
+            event: Some(AnnounceEvent::Stopped),
+            numwant: Some(0),
+            ..test_request()
+        };
+        let url = request.to_url("http://t/announce");
+        assert!(url.contains("&numwant=0&"));
+        assert!(url.ends_with("&event=stopped"));
+
+        let request = AnnounceRequest {
+            event: Some(AnnounceEvent::Completed),
+            numwant: Some(200),
+            ..test_request()
+        };
+        let url = request.to_url("http://t/announce");
+        assert!(url.contains("&numwant=200&"));
+        assert!(url.ends_with("&event=completed"));
+    }
+
+    #[test]
+    fn test_announce_url_session() {
+        let request = AnnounceRequest {
+            key: Some(0x1a2b),
+            tracker_id: Some(b"id 7".to_vec()),
+            ..test_request()
+        };
+        let url = request.to_url("http://t/announce");
+        assert!(url.ends_with("&event=started&key=00001a2b&trackerid=id%207"));
+    }
+
+    #[test]
+    fn test_parse_announce_response() {
+        let body =
+            b"d8:completei3e10:incompletei1e8:intervali1800e12:min intervali60e5:peers6:\x7f\x00\x00\x01\x1a\xe1e";
+        let response = AnnounceResponse::parse(body).unwrap();
+
+        assert_eq!(response.interval, 1800);
+        assert_eq!(response.min_interval, Some(60));
+        assert_eq!(response.complete, Some(3));
+        assert_eq!(response.incomplete, Some(1));
+        assert_eq!(response.tracker_id, None);
+        assert_eq!(
+            response.peers,
+            vec![PeerEntry {
+                addr: "127.0.0.1:6881".parse().unwrap(),
+                peer_id: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_mixed_compact_peers() {
+        let mut body = b"d8:intervali1800e5:peers6:\x7f\x00\x00\x01\x1a\xe16:peers618:".to_vec();
+        body.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        body.extend_from_slice(&[0xc8, 0xd5, b'e']);
+        let response = AnnounceResponse::parse(&body).unwrap();
+
+        let addrs: Vec<SocketAddr> = response.peers.iter().map(|peer| peer.addr).collect();
+        assert_eq!(
+            addrs,
+            vec![
+                "127.0.0.1:6881".parse().unwrap(),
+                "[2001:db8::1]:51413".parse().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_only_ipv6_compact_peers() {
+        let mut body = b"d8:intervali1800e6:peers618:".to_vec();
+        body.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        body.extend_from_slice(&[0x1a, 0xe1, b'e']);
+        let response = AnnounceResponse::parse(&body).unwrap();
+        assert_eq!(response.peers.len(), 1);
+        assert_eq!(response.peers[0].addr, "[::1]:6881".parse().unwrap());
+
+        // IPv6 records are 18 bytes
+        let body = b"d8:intervali1800e6:peers66:\x7f\x00\x00\x01\x1a\xe1e";
+        assert!(AnnounceResponse::parse(body).is_err());
+    }
+
+    #[test]
+    fn test_parse_dictionary_peers() {
+        let body = b"d8:intervali900e5:peersld2:ip8:10.0.0.17:peer id20:-X4020-abcdefghijklm4:porti6881eed2:ip3:::14:porti51413eeee";
+        let response = AnnounceResponse::parse(body).unwrap();
+
+        assert_eq!(
+            response.peers,
+            vec![
+                PeerEntry {
+                    addr: "10.0.0.1:6881".parse().unwrap(),
+                    peer_id: Some(*b"-X4020-abcdefghijklm"),
+                },
+                PeerEntry {
+                    addr: "[::1]:51413".parse().unwrap(),
+                    peer_id: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_dictionary_peers_invalid_ip() {
+        let body = b"d8:intervali900e5:peersld2:ip7:bad-ip!4:porti1eeee";
+        let result = AnnounceResponse::parse(body);
+        assert!(result.unwrap_err().contains("Invalid peer IP address"));
+    }
+
+    #[test]
+    fn test_parse_tracker_id() {
+        let body = b"d8:intervali1800e5:peers0:10:tracker id6:abc123e";
+        let response = AnnounceResponse::parse(body).unwrap();
+        assert_eq!(response.tracker_id, Some(b"abc123".to_vec()));
+    }
+
+    #[test]
+    fn test_parse_failure_response() {
+        let body = b"d14:failure reason17:torrent not founde";
+        let result = AnnounceResponse::parse(body);
+        assert_eq!(result.unwrap_err(), "Tracker error: torrent not found");
+    }
+}
@@ -0,0 +1,368 @@
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::peer::proxy::ProxyConfig;
+use crate::tracker::announce::{AnnounceEvent, AnnounceRequest, AnnounceSession};
+
+/// Upper bound on the wait between two announces to the same tracker
+pub const DEFAULT_MAX_INTERVAL: Duration = Duration::from_secs(30 * 60);
+/// How long to wait before retrying a failed announce
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Announce state of one torrent on one tracker
+#[derive(Debug, Clone)]
+pub struct AnnounceStatus {
+    pub tracker: String,
+    pub info_hash: [u8; 20],
+    /// Whether the tracker has accepted our `started` announce
+    pub started: bool,
+    /// When we last announced successfully
+    pub last_announce: Option<Instant>,
+    /// When the next announce is due
+    pub next_announce: Instant,
+    /// Seeders reported by the tracker in its last response
+    pub seeders: Option<u64>,
+    /// Leechers reported by the tracker in its last response
+    pub leechers: Option<u64>,
+    /// Error from the last announce attempt, if it failed
+    pub last_error: Option<String>,
+    /// Key and tracker ID sent with every announce to this tracker
+    pub session: AnnounceSession,
+}
+
+/// Periodically announces served torrents to their trackers
+pub struct Announcer {
+    trackers: Vec<String>,
+    info_hashes: Vec<[u8; 20]>,
+    peer_id: [u8; 20],
+    /// Port we accept peer connections on
+    port: u16,
+    max_interval: Duration,
+    proxy: Option<ProxyConfig>,
+}
+
+impl Announcer {
+    pub fn new(
+        trackers: Vec<String>,
+        info_hashes: Vec<[u8; 20]>,
+        peer_id: [u8; 20],
+        port: u16,
+    ) -> Self {
+        Announcer {
+            trackers,
+            info_hashes,
+            peer_id,
+            port,
+            max_interval: DEFAULT_MAX_INTERVAL,
+            proxy: None,
+        }
+    }
+
+    /// Re-announce at least this often, even if the tracker asks for a longer interval
+    pub fn with_max_interval(mut self, max_interval: Duration) -> Self {
+        self.max_interval = max_interval;
+        self
+    }
+
+    /// Announce through a proxy instead of connecting to trackers directly
+    pub fn with_proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Start announcing in a background thread
+    ///
+    /// Every torrent is announced to every tracker right away, then again
+    /// whenever its interval expires. The returned handle sends the `stopped`
+    /// announces when it is stopped or dropped.
+    pub fn start(self) -> AnnounceHandle {
+        let now = Instant::now();
+        let statuses = self
+            .trackers
+            .iter()
+            .flat_map(|tracker| {
+                self.info_hashes
+                    .iter()
+                    .map(move |&info_hash| AnnounceStatus {
+                        tracker: tracker.clone(),
+                        info_hash,
+                        started: false,
+                        last_announce: None,
+                        next_announce: now,
+                        seeders: None,
+                        leechers: None,
+                        last_error: None,
+                        session: AnnounceSession::new(),
+                    })
+            })
+            .collect();
+
+        let state = Arc::new(Mutex::new(statuses));
+        let (stop, stop_receiver) = mpsc::channel();
+        let thread = {
+            let state = Arc::clone(&state);
+            thread::spawn(move || self.run(&state, stop_receiver))
+        };
+
+        AnnounceHandle {
+            state,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    fn run(&self, state: &Mutex<Vec<AnnounceStatus>>, stop: mpsc::Receiver<()>) {
+        loop {
+            self.announce_due(state);
+
+            let next = state
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|status| status.next_announce)
+                .min();
+            let Some(next) = next else {
+                // Nothing to announce, just wait for shutdown
+                let _ = stop.recv();
+                return;
+            };
+
+            match stop.recv_timeout(next.saturating_duration_since(Instant::now())) {
+                Err(RecvTimeoutError::Timeout) => continue,
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        self.announce_stopped(state);
+    }
+
+    /// Announce every torrent whose interval has expired
+    fn announce_due(&self, state: &Mutex<Vec<AnnounceStatus>>) {
+        let due: Vec<(usize, AnnounceStatus)> = state
+            .lock()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .filter(|(_, status)| status.next_announce <= Instant::now())
+            .map(|(i, status)| (i, status.clone()))
+            .collect();
+
+        // Announce without holding the lock so status can be read meanwhile
+        for (i, mut due) in due {
+            let event = if due.started {
+                None
+            } else {
+                Some(AnnounceEvent::Started)
+            };
+            let result = due.session.announce(
+                &due.tracker,
+                &self.request(due.info_hash, event),
+                self.proxy.as_ref(),
+            );
+
+            let now = Instant::now();
+            let mut statuses = state.lock().unwrap();
+            let status = &mut statuses[i];
+            status.session = due.session;
+            match result {
+                Ok(response) => {
+                    let interval = Duration::from_secs(response.interval);
+                    status.started = true;
+                    status.last_announce = Some(now);
+                    status.next_announce = now + interval.min(self.max_interval);
+                    status.seeders = response.complete;
+                    status.leechers = response.incomplete;
+                    status.last_error = None;
+                }
+                Err(e) => {
+                    log::warn!("Announce to {} failed: {}", due.tracker, e);
+                    status.next_announce = now + RETRY_INTERVAL.min(self.max_interval);
+                    status.last_error = Some(e);
+                }
+            }
+        }
+    }
+
+    /// Tell the trackers we accepted a `started` announce from that we're leaving
+    fn announce_stopped(&self, state: &Mutex<Vec<AnnounceStatus>>) {
+        let started: Vec<AnnounceStatus> = state
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|status| status.started)
+            .cloned()
+            .collect();
+
+        for mut status in started {
+            // We're leaving, so no peers are needed
+            let request = AnnounceRequest {
+                numwant: Some(0),
+                ..self.request(status.info_hash, Some(AnnounceEvent::Stopped))
+            };
+            let result = status
+                .session
+                .announce(&status.tracker, &request, self.proxy.as_ref());
+            if let Err(e) = result {
+                log::warn!("Stopped announce to {} failed: {}", status.tracker, e);
+            }
+        }
+    }
+
+    fn request(&self, info_hash: [u8; 20], event: Option<AnnounceEvent>) -> AnnounceRequest {
+        // A seeder has the whole torrent, so nothing is left to download
+        AnnounceRequest {
+            info_hash,
+            peer_id: self.peer_id,
+            port: self.port,
+            uploaded: 0,
+            downloaded: 0,
+            left: 0,
+            event,
+            compact: true,
+            numwant: None,
+            key: None,
+            tracker_id: None,
+        }
+    }
+}
+
+/// Handle to a running `Announcer`
+pub struct AnnounceHandle {
+    state: Arc<Mutex<Vec<AnnounceStatus>>>,
+    stop: mpsc::Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AnnounceHandle {
+    /// Current announce state for every torrent on every tracker
+    pub fn status(&self) -> Vec<AnnounceStatus> {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// Send the `stopped` announces and wait for the announce thread to exit
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = self.stop.send(());
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for AnnounceHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    /// Start a tracker that answers every announce, reporting each request line
+    fn start_mock_tracker() -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream: TcpStream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buffer).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..n]);
+                }
+
+                let request = String::from_utf8_lossy(&request);
+                let request_line = request.lines().next().unwrap_or_default().to_string();
+                stream
+                    .write_all(b"HTTP/1.0 200 OK\r\n\r\nd8:completei1e8:intervali1800e5:peers0:10:tracker id5:t-42ae")
+                    .unwrap();
+                if sender.send(request_line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        (url, receiver)
+    }
+
+    #[test]
+    fn test_announces_started_then_stopped() {
+        let (tracker, requests) = start_mock_tracker();
+        let handle = Announcer::new(vec![tracker], vec![[1u8; 20]], [2u8; 20], 6881).start();
+
+        let first = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(first.contains("event=started"));
+        assert!(first.contains("port=6881"));
+        assert!(first.contains("numwant=50"));
+
+        handle.stop();
+
+        let last = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(last.contains("event=stopped"));
+        assert!(last.contains("numwant=0"));
+    }
+
+    #[test]
+    fn test_status_after_announce() {
+        let (tracker, requests) = start_mock_tracker();
+        let handle = Announcer::new(vec![tracker.clone()], vec![[1u8; 20]], [2u8; 20], 6881)
+            .with_max_interval(Duration::from_secs(600))
+            .start();
+
+        requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        // The status is updated right after the response is read
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let status = loop {
+            let status = handle.status().remove(0);
+            if status.started || Instant::now() > deadline {
+                break status;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        assert_eq!(status.tracker, tracker);
+        assert!(status.started);
+        assert_eq!(status.seeders, Some(1));
+        assert!(status.last_error.is_none());
+        // The tracker's 30 minute interval is capped at 10 minutes
+        let wait = status.next_announce - status.last_announce.unwrap();
+        assert_eq!(wait, Duration::from_secs(600));
+    }
+
+    #[test]
+    fn test_reannounce_echoes_tracker_id() {
+        let (tracker, requests) = start_mock_tracker();
+        let handle = Announcer::new(vec![tracker], vec![[1u8; 20]], [2u8; 20], 6881)
+            .with_max_interval(Duration::from_millis(50))
+            .start();
+
+        let first = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        let second = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        handle.stop();
+
+        // Only announces after the first response can echo its tracker ID
+        assert!(!first.contains("trackerid="));
+        assert!(second.contains("&trackerid=t-42a"));
+
+        // The key stays the same for the whole session
+        let key = |request: &str| {
+            let start = request.find("&key=").unwrap() + "&key=".len();
+            request[start..start + 8].to_string()
+        };
+        assert_eq!(key(&first), key(&second));
+    }
+}
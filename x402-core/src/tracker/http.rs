@@ -0,0 +1,128 @@
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use crate::peer::proxy::{ProxyConfig, dial};
+
+/// How long to wait when connecting to, writing to or reading from a tracker
+const TRACKER_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The parts of an `http://` URL needed to send a request
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct HttpUrl {
+    pub host: String,
+    pub port: u16,
+    /// Path including any query string, always starting with `/`
+    pub path: String,
+}
+
+impl HttpUrl {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("Unsupported tracker URL: {}", url))?;
+
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(position) if rest[position..].starts_with('?') => {
+                (&rest[..position], format!("/{}", &rest[position..]))
+            }
+            Some(position) => (&rest[..position], rest[position..].to_string()),
+            None => (rest, "/".to_string()),
+        };
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse::<u16>()
+                    .map_err(|_| format!("Invalid port in tracker URL: {}", url))?;
+                (host, port)
+            }
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("Missing host in tracker URL: {}", url));
+        }
+
+        Ok(HttpUrl {
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+}
+
+/// Send a GET request, through `proxy` if given, and return the response body
+///
+/// Only plain HTTP is supported, which is what most trackers speak. Through a
+/// proxy, the tracker's host name is resolved by the proxy.
+pub(crate) fn get(url: &str, proxy: Option<&ProxyConfig>) -> Result<Vec<u8>, String> {
+    let url = HttpUrl::parse(url)?;
+
+    let mut stream = dial(proxy, &url.host, url.port, TRACKER_TIMEOUT)
+        .map_err(|e| format!("Failed to connect to tracker {}: {}", url.host, e))?;
+    stream
+        .set_read_timeout(Some(TRACKER_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(TRACKER_TIMEOUT)))
+        .map_err(|e| format!("Failed to set tracker timeouts: {}", e))?;
+
+    // HTTP/1.0 so the tracker closes the connection after the response
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: x402\r\n\r\n",
+        url.path, url.host
+    );
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| format!("Failed to send tracker request: {}", e))?;
+
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .map_err(|e| format!("Failed to read tracker response: {}", e))?;
+
+    parse_response(&response)
+}
+
+/// Split an HTTP response, returning the body of a successful response
+fn parse_response(response: &[u8]) -> Result<Vec<u8>, String> {
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or("Malformed tracker response")?;
+
+    let head = String::from_utf8_lossy(&response[..header_end]);
+    let status_line = head.lines().next().unwrap_or_default();
+    let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+    if status != "200" {
+        return Err(format!("Tracker returned {}", status_line));
+    }
+
+    Ok(response[header_end + 4..].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        let url = HttpUrl::parse("http://tracker.example.com:8080/announce?key=1").unwrap();
+        assert_eq!(url.host, "tracker.example.com");
+        assert_eq!(url.port, 8080);
+        assert_eq!(url.path, "/announce?key=1");
+
+        let url = HttpUrl::parse("http://tracker.example.com").unwrap();
+        assert_eq!(url.port, 80);
+        assert_eq!(url.path, "/");
+
+        assert!(HttpUrl::parse("udp://tracker.example.com:6969").is_err());
+        assert!(HttpUrl::parse("http://tracker.example.com:port/").is_err());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let body = parse_response(b"HTTP/1.0 200 OK\r\nContent-Length: 2\r\n\r\nde").unwrap();
+        assert_eq!(body, b"de");
+
+        let result = parse_response(b"HTTP/1.1 404 Not Found\r\n\r\n");
+        assert!(result.unwrap_err().contains("404"));
+    }
+}
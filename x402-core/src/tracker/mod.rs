@@ -0,0 +1,4 @@
+pub mod announce;
+pub mod announcer;
+mod http;
+pub mod scrape;
@@ -0,0 +1,153 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
+
+use crate::peer::proxy::ProxyConfig;
+use crate::tracker::announce::percent_encode;
+use crate::tracker::http;
+
+/// Swarm statistics a tracker reports for one torrent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct ScrapeStats {
+    /// Number of seeders
+    #[serde(default)]
+    pub complete: u64,
+    /// Number of leechers
+    #[serde(default)]
+    pub incomplete: u64,
+    /// Number of completed downloads
+    #[serde(default)]
+    pub downloaded: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawScrapeResponse {
+    #[serde(default, rename = "failure reason")]
+    failure_reason: Option<String>,
+    #[serde(default)]
+    files: BTreeMap<ByteBuf, ScrapeStats>,
+}
+
+/// Ask a tracker for the swarm statistics of several torrents
+pub fn scrape(
+    tracker_url: &str,
+    info_hashes: &[[u8; 20]],
+) -> Result<HashMap<[u8; 20], ScrapeStats>, String> {
+    scrape_via(tracker_url, info_hashes, None)
+}
+
+/// Ask a tracker for swarm statistics, through `proxy` if given
+pub fn scrape_via(
+    tracker_url: &str,
+    info_hashes: &[[u8; 20]],
+    proxy: Option<&ProxyConfig>,
+) -> Result<HashMap<[u8; 20], ScrapeStats>, String> {
+    let url = scrape_url(tracker_url, info_hashes)?;
+    let body = http::get(&url, proxy)?;
+    parse_scrape_response(&body)
+}
+
+/// Derive the scrape URL from an announce URL
+///
+/// By convention the last path segment must start with `announce`, which is
+/// replaced by `scrape`; other trackers don't support scraping.
+pub fn scrape_url(tracker_url: &str, info_hashes: &[[u8; 20]]) -> Result<String, String> {
+    let (base, query) = match tracker_url.split_once('?') {
+        Some((base, query)) => (base, Some(query)),
+        None => (tracker_url, None),
+    };
+
+    let segment_start = base.rfind('/').map_or(0, |i| i + 1);
+    let segment = &base[segment_start..];
+    let Some(rest) = segment.strip_prefix("announce") else {
+        return Err(format!("Tracker does not support scrape: {}", tracker_url));
+    };
+
+    let mut url = format!("{}scrape{}", &base[..segment_start], rest);
+    let mut separator = '?';
+    if let Some(query) = query {
+        url.push('?');
+        url.push_str(query);
+        separator = '&';
+    }
+    for info_hash in info_hashes {
+        url.push(separator);
+        url.push_str("info_hash=");
+        url.push_str(&percent_encode(info_hash));
+        separator = '&';
+    }
+
+    Ok(url)
+}
+
+/// Parse a bencoded scrape response body
+pub fn parse_scrape_response(body: &[u8]) -> Result<HashMap<[u8; 20], ScrapeStats>, String> {
+    let raw: RawScrapeResponse = serde_bencode::from_bytes(body)
+        .map_err(|e| format!("Failed to parse scrape response: {}", e))?;
+
+    if let Some(reason) = raw.failure_reason {
+        return Err(format!("Tracker error: {}", reason));
+    }
+
+    raw.files
+        .into_iter()
+        .map(|(info_hash, stats)| {
+            let info_hash: [u8; 20] = info_hash.as_slice().try_into().map_err(|_| {
+                format!(
+                    "Invalid info hash length in scrape response: {}",
+                    info_hash.len()
+                )
+            })?;
+            Ok((info_hash, stats))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrape_url() {
+        let url = scrape_url("http://tracker.example.com/announce", &[[0xAB; 20]]).unwrap();
+        assert_eq!(
+            url,
+            format!(
+                "http://tracker.example.com/scrape?info_hash={}",
+                "%AB".repeat(20)
+            )
+        );
+
+        let url = scrape_url("http://tracker.example.com/x/announce.php?passkey=k", &[]).unwrap();
+        assert_eq!(url, "http://tracker.example.com/x/scrape.php?passkey=k");
+
+        assert!(scrape_url("http://tracker.example.com/a", &[]).is_err());
+        assert!(scrape_url("http://tracker.example.com/announce/x", &[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_scrape_response() {
+        let mut body = b"d5:filesd20:".to_vec();
+        body.extend_from_slice(&[0xAB; 20]);
+        body.extend_from_slice(b"d8:completei5e10:downloadedi50e10:incompletei10eeee");
+
+        let stats = parse_scrape_response(&body).unwrap();
+
+        assert_eq!(stats.len(), 1);
+        assert_eq!(
+            stats[&[0xAB; 20]],
+            ScrapeStats {
+                complete: 5,
+                incomplete: 10,
+                downloaded: 50,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_scrape_failure() {
+        let result = parse_scrape_response(b"d14:failure reason9:forbiddene");
+        assert_eq!(result.unwrap_err(), "Tracker error: forbidden");
+    }
+}
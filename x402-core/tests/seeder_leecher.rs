@@ -0,0 +1,69 @@
+use std::sync::Arc;
+use std::thread;
+
+use serde_bytes::ByteBuf;
+use x402_core::torrent::verify::{hash_piece, verify_piece};
+use x402_core::{Bitfield, Info, Leecher, MemoryStore, Seeder, generate_peer_id};
+
+const PIECE_LENGTH: usize = 32 * 1024;
+
+/// Three pieces, the last one short
+fn test_content() -> (Vec<u8>, Info) {
+    let data: Vec<u8> = (0..PIECE_LENGTH * 2 + 5000)
+        .map(|i| (i * 7 % 256) as u8)
+        .collect();
+    let pieces: Vec<u8> = data.chunks(PIECE_LENGTH).flat_map(hash_piece).collect();
+    let info = Info {
+        name: "content.bin".to_string(),
+        plength: PIECE_LENGTH,
+        pieces: ByteBuf::from(pieces),
+        length: Some(data.len()),
+        ..Default::default()
+    };
+    (data, info)
+}
+
+#[test]
+fn test_leecher_downloads_piece_from_seeder() {
+    let (data, info) = test_content();
+    let info_hash = [0x42; 20];
+
+    // Port 0 avoids clashing with anything else listening on the host
+    let mut seeder = Seeder::new("127.0.0.1".to_string(), 0);
+    seeder.add_torrent_data(
+        info_hash,
+        Arc::new(MemoryStore::new(data.clone(), PIECE_LENGTH)),
+        Bitfield::full(info.num_pieces()),
+    );
+    let seeder_peer_id = seeder.peer_id();
+    let addr = seeder.bind().unwrap();
+    thread::spawn(move || seeder.listen());
+
+    let mut leecher = Leecher::connect(addr, info_hash, generate_peer_id(), info.num_pieces())
+        .expect("handshake with the seeder");
+    assert_eq!(leecher.remote_peer_id(), seeder_peer_id);
+
+    leecher.interested().unwrap();
+    assert!(leecher.bitfield().is_complete());
+
+    for index in [1, 2] {
+        let piece = leecher
+            .download_piece(index as u32, info.piece_size(index), 4)
+            .unwrap();
+
+        assert!(verify_piece(&info, index, &piece));
+        let start = index * PIECE_LENGTH;
+        assert_eq!(piece, data[start..start + piece.len()]);
+    }
+}
+
+#[test]
+fn test_seeder_rejects_unknown_torrent() {
+    let mut seeder = Seeder::new("127.0.0.1".to_string(), 0);
+    seeder.add_torrent([1u8; 20]);
+    let addr = seeder.bind().unwrap();
+    thread::spawn(move || seeder.listen());
+
+    let result = Leecher::connect(addr, [2u8; 20], generate_peer_id(), 1);
+    assert!(result.is_err());
+}